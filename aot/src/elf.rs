@@ -0,0 +1,140 @@
+use util::{
+    asm::{StandaloneProgram, TAPE_SIZE},
+    BfResult,
+};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+const ELF_HEADER_SIZE: u16 = 64;
+const PROGRAM_HEADER_SIZE: u16 = 56;
+const PROGRAM_HEADER_COUNT: u16 = 3;
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ELFOSABI_SYSV: u8 = 0;
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+struct ProgramHeader {
+    segment_type: u32,
+    flags: u32,
+    offset: u64,
+    address: u64,
+    file_size: u64,
+    memory_size: u64,
+    alignment: u64,
+}
+
+// Lays the program out as an ELF image with no sections, just the segments the
+// kernel needs to load it: one for the headers and code, one for the tape, and
+// one asking for a non-executable stack. Since the code is position-independent
+// and there's no interpreter, the kernel picks where to load it and jumps
+// straight to the code.
+pub fn write(program: &StandaloneProgram) -> BfResult<Vec<u8>> {
+    let code_offset = u64::from(ELF_HEADER_SIZE + PROGRAM_HEADER_COUNT * PROGRAM_HEADER_SIZE);
+    let code_end = code_offset + u64::try_from(program.code().len())?;
+    // The tape takes up no space in the file. The kernel maps zeroed pages for
+    // it, starting at the page after the code.
+    let tape_address = code_end.next_multiple_of(PAGE_SIZE);
+    let code = program.link((tape_address - code_offset).try_into()?)?;
+
+    let mut image = vec![];
+
+    image.extend_from_slice(b"\x7fELF");
+    image.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_SYSV]);
+    image.resize(16, 0);
+    image.extend_from_slice(&ET_DYN.to_le_bytes());
+    image.extend_from_slice(&EM_X86_64.to_le_bytes());
+    image.extend_from_slice(&u32::from(EV_CURRENT).to_le_bytes());
+    // The entry point, and where the program headers start.
+    image.extend_from_slice(&code_offset.to_le_bytes());
+    image.extend_from_slice(&u64::from(ELF_HEADER_SIZE).to_le_bytes());
+    // No section headers, and no flags.
+    image.extend_from_slice(&0_u64.to_le_bytes());
+    image.extend_from_slice(&0_u32.to_le_bytes());
+    image.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    image.extend_from_slice(&PROGRAM_HEADER_SIZE.to_le_bytes());
+    image.extend_from_slice(&PROGRAM_HEADER_COUNT.to_le_bytes());
+    image.extend_from_slice(&[0; 6]);
+
+    for header in [
+        ProgramHeader {
+            segment_type: PT_LOAD,
+            flags: PF_R | PF_X,
+            offset: 0,
+            address: 0,
+            file_size: code_end,
+            memory_size: code_end,
+            alignment: PAGE_SIZE,
+        },
+        ProgramHeader {
+            segment_type: PT_LOAD,
+            flags: PF_R | PF_W,
+            offset: 0,
+            address: tape_address,
+            file_size: 0,
+            memory_size: TAPE_SIZE.try_into()?,
+            alignment: PAGE_SIZE,
+        },
+        ProgramHeader {
+            segment_type: PT_GNU_STACK,
+            flags: PF_R | PF_W,
+            offset: 0,
+            address: 0,
+            file_size: 0,
+            memory_size: 0,
+            alignment: 16,
+        },
+    ] {
+        image.extend_from_slice(&header.segment_type.to_le_bytes());
+        image.extend_from_slice(&header.flags.to_le_bytes());
+        image.extend_from_slice(&header.offset.to_le_bytes());
+        // The virtual and physical addresses.
+        image.extend_from_slice(&header.address.to_le_bytes());
+        image.extend_from_slice(&header.address.to_le_bytes());
+        image.extend_from_slice(&header.file_size.to_le_bytes());
+        image.extend_from_slice(&header.memory_size.to_le_bytes());
+        image.extend_from_slice(&header.alignment.to_le_bytes());
+    }
+
+    image.extend_from_slice(&code);
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{write, PAGE_SIZE},
+        util::run::RunOptions,
+    };
+
+    fn read_u64(image: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(image[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn layout_test() {
+        let program = opjit::compile_standalone("+[>+.<-]", &RunOptions::default()).unwrap();
+        let image = write(&program).unwrap();
+
+        assert_eq!(&image[..4], b"\x7fELF");
+        // The entry point is the start of the code, right after the headers.
+        assert_eq!(read_u64(&image, 24), 64 + 3 * 56);
+        assert_eq!(image.len(), 64 + 3 * 56 + program.code().len());
+        // The code segment covers the whole file.
+        assert_eq!(read_u64(&image, 64 + 32), image.len() as u64);
+        // The tape starts on the next page.
+        let tape_address = read_u64(&image, 64 + 56 + 16);
+        assert_eq!(tape_address % PAGE_SIZE, 0);
+        assert!(tape_address >= image.len() as u64);
+        assert!(tape_address < image.len() as u64 + PAGE_SIZE);
+    }
+}
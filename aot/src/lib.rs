@@ -0,0 +1,170 @@
+use {
+    std::{
+        env,
+        fs::{self, OpenOptions},
+        io::{Read, Write},
+        path::{Path, PathBuf},
+        process::{self, Command, Stdio},
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    util::{run::RunOptions, BfError, BfResult},
+};
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod elf;
+pub mod wasm;
+
+// Compiles a program into a standalone x86-64 Linux executable, returning the
+// contents of the file.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn compile(source_code: &str, options: &RunOptions) -> BfResult<Vec<u8>> {
+    let program = opjit::compile_standalone(source_code, options)?;
+    elf::write(&program)
+}
+
+// The code is generated with the same assembler as opjit, which only targets
+// the machine it's running on.
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+pub fn compile(_source_code: &str, _options: &RunOptions) -> BfResult<Vec<u8>> {
+    Err(BfError::Bf(
+        "Executables can only be compiled on x86-64 Linux.".to_owned(),
+    ))
+}
+
+pub fn write_executable(path: &Path, contents: &[u8]) -> BfResult<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o755);
+
+    options.open(path)?.write_all(contents)?;
+
+    Ok(())
+}
+
+// Compiles the program, then runs the executable with the given input, so it
+// can be tested the same way as the VMs.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let path = temp_path();
+    write_executable(&path, &compile(source_code, &RunOptions::default())?)?;
+
+    let result = run_executable(&path, stdin, stdout);
+    fs::remove_file(&path)?;
+    result
+}
+
+// Compiles the program to a WebAssembly module, then validates and runs it with
+// the interpreter in wasm.rs.
+pub fn run_wasm(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let module = wasm::validate(&opjit::wasm_module(source_code, &RunOptions::default())?)?;
+    wasm::run(&module, stdin, stdout)?;
+    Ok(())
+}
+
+// Translates the program to C, then builds and runs it like run.
+#[cfg(unix)]
+pub fn run_c(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let path = temp_path();
+    build_c(source_code, &RunOptions::default(), &path)?;
+
+    let result = run_executable(&path, stdin, stdout);
+    fs::remove_file(&path)?;
+    result
+}
+
+// Translates the program to C and builds it into an executable at the given
+// path with `cc -O2`.
+#[cfg(unix)]
+pub fn build_c(source_code: &str, options: &RunOptions, path: &Path) -> BfResult<()> {
+    let source_path = path.with_extension("c");
+    fs::write(&source_path, opjit::c_source(source_code, options)?)?;
+
+    cc(&[source_path], path)
+}
+
+// Writes the program out as an assembler listing, then builds and runs it like
+// run.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn run_asm(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let path = temp_path();
+    build_asm(source_code, &RunOptions::default(), &path)?;
+
+    let result = run_executable(&path, stdin, stdout);
+    fs::remove_file(&path)?;
+    result
+}
+
+// Writes the program out as an assembler listing and builds it into an
+// executable at the given path along with opjit's runtime.c.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn build_asm(source_code: &str, options: &RunOptions, path: &Path) -> BfResult<()> {
+    let source_path = path.with_extension("s");
+    let runtime_path = path.with_extension("c");
+    fs::write(&source_path, opjit::assembly_listing(source_code, options)?)?;
+    fs::write(&runtime_path, opjit::LISTING_RUNTIME)?;
+
+    cc(&[source_path, runtime_path], path)
+}
+
+// Builds the sources into an executable at `path` with `cc -O2`, removing them
+// afterwards.
+#[cfg(unix)]
+fn cc(sources: &[PathBuf], path: &Path) -> BfResult<()> {
+    let status = Command::new("cc")
+        .arg("-O2")
+        .arg("-o")
+        .arg(path)
+        .args(sources)
+        .status();
+    for source in sources {
+        fs::remove_file(source)?;
+    }
+    let status = status?;
+
+    if !status.success() {
+        return Err(BfError::Bf(format!("cc failed with {status}.")));
+    }
+
+    Ok(())
+}
+
+// Runs an executable built from a program, passing it the given input and
+// copying out whatever it writes.
+pub fn run_executable(path: &Path, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let mut input = vec![];
+    stdin.read_to_end(&mut input)?;
+
+    let output = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut child_stdin) = child.stdin.take() {
+                child_stdin.write_all(&input)?;
+            }
+            child.wait_with_output()
+        })?;
+
+    stdout.write_all(&output.stdout)?;
+    if !output.status.success() {
+        return Err(BfError::Bf(format!(
+            "The executable failed with {}.",
+            output.status
+        )));
+    }
+
+    Ok(())
+}
+
+// A path in the temporary directory that no other executable built by this
+// process uses.
+pub fn temp_path() -> PathBuf {
+    static EXECUTABLE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    env::temp_dir().join(format!(
+        "bf-aot-{}-{}",
+        process::id(),
+        EXECUTABLE_COUNT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
@@ -0,0 +1,77 @@
+use {
+    std::{env, fs, path::Path},
+    util::{
+        run::{check_default_tape, parse_args, SupportedOptions},
+        BfError, BfResult,
+    },
+};
+
+enum Emit {
+    Executable,
+    C,
+    Rust,
+    Wasm,
+}
+
+fn main() -> BfResult<()> {
+    let mut output = None;
+    let mut emit = Emit::Executable;
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        if let Some(path) = arg.strip_prefix("--output=") {
+            output = Some(path.to_owned());
+        } else if let Some(kind) = arg.strip_prefix("--emit=") {
+            emit = match kind {
+                "executable" => Emit::Executable,
+                "c" => Emit::C,
+                "rust" => Emit::Rust,
+                "wasm" => Emit::Wasm,
+                _ => return Err(BfError::Bf(format!("Unknown output kind '{kind}'."))),
+            };
+        } else {
+            args.push(arg);
+        }
+    }
+
+    let supported = SupportedOptions {
+        passes: true,
+        report_passes: true,
+        ..SupportedOptions::NONE
+    };
+    let (filepath, options) = parse_args(args.into_iter(), &supported)?;
+    // Only generated source can change the shape of the tape.
+    if let Emit::Executable = emit {
+        check_default_tape(&options)?;
+    }
+    let source_code = fs::read_to_string(&filepath)?;
+    // Default to the program's name next to it, without the .bf for
+    // executables and with the format's extension otherwise.
+    let output = match (output, &emit) {
+        (Some(path), _) => path.into(),
+        (None, Emit::Executable) => Path::new(&filepath).with_extension(""),
+        (None, Emit::C) => Path::new(&filepath).with_extension("c"),
+        (None, Emit::Rust) => Path::new(&filepath).with_extension("rs"),
+        (None, Emit::Wasm) => Path::new(&filepath).with_extension("wasm"),
+    };
+    if output == Path::new(&filepath) {
+        return Err(BfError::Bf(format!(
+            "Can't write the output over '{filepath}'. Pass --output= to pick another path."
+        )));
+    }
+
+    match emit {
+        Emit::Executable => aot::write_executable(&output, &aot::compile(&source_code, &options)?),
+        Emit::C => {
+            fs::write(&output, opjit::c_source(&source_code, &options)?)?;
+            Ok(())
+        }
+        Emit::Rust => {
+            fs::write(&output, opjit::rust_source(&source_code, &options)?)?;
+            Ok(())
+        }
+        Emit::Wasm => {
+            fs::write(&output, opjit::wasm_module(&source_code, &options)?)?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,740 @@
+use {
+    opjit::wasm::{
+        op, IMPORT_MODULE, MAGIC, MEMORY_EXPORT, PAGE_SIZE, READ_IMPORT, RUN_EXPORT, VERSION,
+        WRITE_IMPORT,
+    },
+    std::io::{ErrorKind, Read, Write},
+    util::{BfError, BfResult},
+};
+
+// A small WebAssembly interpreter for the modules opjit::wasm_module writes, so
+// they can be checked and run without a runtime. Only the parts of the format
+// the writer uses are supported: functions taking and returning i32s, two
+// imported functions, one defined function, and one memory. Anything else is
+// rejected while validating rather than while running.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Import {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Width {
+    W8,
+    W16,
+    W32,
+}
+
+impl Width {
+    fn bytes(self) -> usize {
+        match self {
+            Width::W8 => 1,
+            Width::W16 => 2,
+            Width::W32 => 4,
+        }
+    }
+}
+
+// Control instructions know where their matching else and end are, so running
+// them doesn't need to scan ahead.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Unreachable,
+    Block { end: usize },
+    Loop,
+    If { else_: Option<usize>, end: usize },
+    Else { end: usize },
+    End,
+    Br { depth: u32 },
+    BrIf { depth: u32 },
+    Call { import: Import },
+    LocalGet { local: u32 },
+    LocalSet { local: u32 },
+    LocalTee { local: u32 },
+    Load { width: Width, offset: u32 },
+    Store { width: Width, offset: u32 },
+    Const { value: i32 },
+    Eqz,
+    Ne,
+    Add,
+    Sub,
+}
+
+#[derive(Debug)]
+pub struct Module {
+    memory_pages: u32,
+    local_count: u32,
+    code: Vec<Op>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FunctionType {
+    params: u32,
+    results: u32,
+}
+
+const READ_TYPE: FunctionType = FunctionType {
+    params: 0,
+    results: 1,
+};
+const WRITE_TYPE: FunctionType = FunctionType {
+    params: 1,
+    results: 0,
+};
+const RUN_TYPE: FunctionType = FunctionType {
+    params: 0,
+    results: 0,
+};
+
+// Checks that a module has the structure opjit::wasm_module gives it, and that
+// its code is well formed, decoding it ready to run.
+pub fn validate(bytes: &[u8]) -> BfResult<Module> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC || reader.take(VERSION.len())? != VERSION {
+        return invalid("Not a version 1 WebAssembly module.");
+    }
+
+    let mut types = vec![];
+    let mut imports = vec![];
+    let mut functions = vec![];
+    let mut memory_pages = None;
+    let mut run_function = None;
+    let mut exports_memory = false;
+    let mut body = None;
+
+    let mut last_id = 0;
+    while !reader.is_at_end() {
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let mut section = Reader {
+            bytes: reader.take(size)?,
+            position: 0,
+        };
+        // Custom sections can go anywhere and don't mean anything here.
+        if id == 0 {
+            continue;
+        }
+        if id <= last_id {
+            return invalid(&format!("Section {id} is out of order."));
+        }
+        last_id = id;
+
+        match id {
+            1 => {
+                for _ in 0..section.u32()? {
+                    types.push(section.function_type()?);
+                }
+            }
+            2 => {
+                for _ in 0..section.u32()? {
+                    let module = section.name()?;
+                    let name = section.name()?;
+                    if section.byte()? != 0x00 {
+                        return invalid("Only functions can be imported.");
+                    }
+                    let function_type = lookup(&types, section.u32()?)?;
+                    let import = match (module.as_str(), name.as_str()) {
+                        (IMPORT_MODULE, READ_IMPORT) if function_type == READ_TYPE => Import::Read,
+                        (IMPORT_MODULE, WRITE_IMPORT) if function_type == WRITE_TYPE => {
+                            Import::Write
+                        }
+                        _ => return invalid(&format!("Unknown import '{module}.{name}'.")),
+                    };
+                    imports.push(import);
+                }
+            }
+            3 => {
+                for _ in 0..section.u32()? {
+                    functions.push(lookup(&types, section.u32()?)?);
+                }
+            }
+            5 => {
+                if section.u32()? != 1 {
+                    return invalid("There must be exactly one memory.");
+                }
+                memory_pages = Some(match section.byte()? {
+                    0x00 => section.u32()?,
+                    0x01 => {
+                        let minimum = section.u32()?;
+                        section.u32()?;
+                        minimum
+                    }
+                    _ => return invalid("Unknown memory limits."),
+                });
+            }
+            7 => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    let kind = section.byte()?;
+                    let index = section.u32()?;
+                    match (name.as_str(), kind) {
+                        (RUN_EXPORT, 0x00) => run_function = Some(index),
+                        (MEMORY_EXPORT, 0x02) if index == 0 => exports_memory = true,
+                        _ => return invalid(&format!("Unexpected export '{name}'.")),
+                    }
+                }
+            }
+            10 => {
+                if section.u32()? as usize != functions.len() || functions.len() != 1 {
+                    return invalid("There must be exactly one function defined.");
+                }
+                let size = section.u32()? as usize;
+                body = Some(section.take(size)?);
+            }
+            _ => return invalid(&format!("Unsupported section {id}.")),
+        }
+
+        if !section.is_at_end() {
+            return invalid(&format!("Section {id} has trailing bytes."));
+        }
+    }
+
+    let mut sorted_imports = imports.clone();
+    sorted_imports.sort_by_key(|import| *import as u8);
+    sorted_imports.dedup();
+    if sorted_imports.len() != 2 || imports.len() != 2 {
+        return invalid("Both read and write must be imported once.");
+    }
+    if functions != [RUN_TYPE] || run_function != Some(imports.len() as u32) {
+        return invalid("The run function must be exported, taking and returning nothing.");
+    }
+    let (Some(memory_pages), true) = (memory_pages, exports_memory) else {
+        return invalid("The memory must be defined and exported.");
+    };
+    let Some(body) = body else {
+        return invalid("The run function has no code.");
+    };
+
+    let (local_count, code) = decode_body(body, &imports)?;
+
+    Ok(Module {
+        memory_pages,
+        local_count,
+        code,
+    })
+}
+
+// Runs a module's run function, returning its memory once it finishes. read
+// and write work on the given input and output.
+pub fn run(module: &Module, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<Vec<u8>> {
+    let mut memory = vec![0u8; module.memory_pages as usize * PAGE_SIZE];
+    let mut locals = vec![0i32; module.local_count as usize];
+    let mut stack: Vec<i32> = vec![];
+    // Where a branch to each enclosing label goes, and whether the label
+    // belongs to a loop, which stays open when branched to.
+    let mut labels: Vec<(usize, bool)> = vec![];
+
+    let mut pc = 0;
+    loop {
+        // Validation makes sure the stack and labels are never empty when
+        // popped.
+        match module.code[pc] {
+            Op::Unreachable => {
+                return Err(BfError::Bf(
+                    "Trapped at an unreachable instruction.".to_owned(),
+                ))
+            }
+            Op::Block { end } => labels.push((end + 1, false)),
+            Op::Loop => labels.push((pc + 1, true)),
+            Op::If { else_, end } => {
+                labels.push((end + 1, false));
+                if stack.pop().unwrap() == 0 {
+                    match else_ {
+                        Some(else_) => pc = else_,
+                        None => {
+                            labels.pop();
+                            pc = end;
+                        }
+                    }
+                }
+            }
+            Op::Else { end } => {
+                labels.pop();
+                pc = end;
+            }
+            Op::End => {
+                if labels.pop().is_none() {
+                    return Ok(memory);
+                }
+            }
+            Op::Br { depth } => {
+                pc = branch(&mut labels, depth);
+                continue;
+            }
+            Op::BrIf { depth } => {
+                if stack.pop().unwrap() != 0 {
+                    pc = branch(&mut labels, depth);
+                    continue;
+                }
+            }
+            Op::Call { import } => match import {
+                Import::Read => {
+                    let mut byte = [0];
+                    let value = match stdin.read_exact(&mut byte) {
+                        Ok(()) => byte[0].into(),
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => -1,
+                        Err(e) => return Err(e.into()),
+                    };
+                    stack.push(value);
+                }
+                Import::Write => {
+                    stdout.write_all(&[stack.pop().unwrap() as u8])?;
+                    stdout.flush()?;
+                }
+            },
+            Op::LocalGet { local } => stack.push(locals[local as usize]),
+            Op::LocalSet { local } => locals[local as usize] = stack.pop().unwrap(),
+            Op::LocalTee { local } => locals[local as usize] = *stack.last().unwrap(),
+            Op::Load { width, offset } => {
+                let address = stack.pop().unwrap();
+                let cell = access(&mut memory, address, offset, width)?;
+                let mut bytes = [0; 4];
+                bytes[..cell.len()].copy_from_slice(cell);
+                stack.push(i32::from_le_bytes(bytes));
+            }
+            Op::Store { width, offset } => {
+                let value = stack.pop().unwrap();
+                let address = stack.pop().unwrap();
+                let cell = access(&mut memory, address, offset, width)?;
+                let length = cell.len();
+                cell.copy_from_slice(&value.to_le_bytes()[..length]);
+            }
+            Op::Const { value } => stack.push(value),
+            Op::Eqz => {
+                let value = stack.pop().unwrap();
+                stack.push((value == 0).into());
+            }
+            Op::Ne | Op::Add | Op::Sub => {
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                stack.push(match module.code[pc] {
+                    Op::Ne => (left != right).into(),
+                    Op::Add => left.wrapping_add(right),
+                    _ => left.wrapping_sub(right),
+                });
+            }
+        }
+
+        pc += 1;
+    }
+}
+
+fn branch(labels: &mut Vec<(usize, bool)>, depth: u32) -> usize {
+    let index = labels.len() - 1 - depth as usize;
+    let (target, is_loop) = labels[index];
+    labels.truncate(if is_loop { index + 1 } else { index });
+    target
+}
+
+fn access(memory: &mut [u8], address: i32, offset: u32, width: Width) -> BfResult<&mut [u8]> {
+    // Addresses are unsigned, and adding the offset can't wrap.
+    let start = address as u32 as usize + offset as usize;
+    memory
+        .get_mut(start..start + width.bytes())
+        .ok_or_else(|| BfError::Bf(format!("Trapped accessing memory at {start}.")))
+}
+
+// Decodes the code of the run function, checking that every instruction has
+// the operands it needs and that blocks are properly nested and balanced.
+fn decode_body(body: &[u8], imports: &[Import]) -> BfResult<(u32, Vec<Op>)> {
+    struct Frame {
+        start: usize,
+        // The stack height the frame started at, which it must end at too.
+        height: usize,
+        // Code after a branch or unreachable can't be reached, so anything goes
+        // for the stack until the frame ends.
+        unreachable: bool,
+    }
+
+    // Pops operands, or fails if there aren't enough of them.
+    fn pop(frames: &[Frame], height: &mut usize, count: usize) -> BfResult<()> {
+        let frame = frames.last().unwrap();
+        if *height < frame.height + count {
+            if frame.unreachable {
+                *height = frame.height;
+                return Ok(());
+            }
+            return invalid("An instruction is missing an operand.");
+        }
+        *height -= count;
+        Ok(())
+    }
+
+    let mut reader = Reader {
+        bytes: body,
+        position: 0,
+    };
+
+    let mut local_count = 0u32;
+    for _ in 0..reader.u32()? {
+        let count = reader.u32()?;
+        if reader.byte()? != op::VALUE_TYPE_I32 {
+            return invalid("Only i32 locals are supported.");
+        }
+        local_count = local_count
+            .checked_add(count)
+            .ok_or_else(|| BfError::Bf("Too many locals.".to_owned()))?;
+    }
+
+    let mut code = vec![];
+    // The function body itself is the outermost frame.
+    let mut frames = vec![Frame {
+        start: 0,
+        height: 0,
+        unreachable: false,
+    }];
+    let mut height = 0usize;
+
+    while !frames.is_empty() {
+        let position = code.len();
+        let opcode = reader.byte()?;
+        let op = match opcode {
+            op::UNREACHABLE => {
+                let frame = frames.last_mut().unwrap();
+                frame.unreachable = true;
+                height = frame.height;
+                Op::Unreachable
+            }
+            op::BLOCK | op::LOOP | op::IF => {
+                if reader.byte()? != op::BLOCK_TYPE_EMPTY {
+                    return invalid("Only blocks without parameters or results are supported.");
+                }
+                if opcode == op::IF {
+                    pop(&frames, &mut height, 1)?;
+                }
+                frames.push(Frame {
+                    start: position,
+                    height,
+                    unreachable: false,
+                });
+                match opcode {
+                    op::BLOCK => Op::Block { end: 0 },
+                    op::LOOP => Op::Loop,
+                    _ => Op::If {
+                        else_: None,
+                        end: 0,
+                    },
+                }
+            }
+            op::ELSE => {
+                let frame = frames.last_mut().unwrap();
+                let Some(Op::If { else_, .. }) = code.get_mut(frame.start) else {
+                    return invalid("An else doesn't belong to an if.");
+                };
+                if else_.is_some() || (height != frame.height && !frame.unreachable) {
+                    return invalid("An if's then branch is malformed.");
+                }
+                *else_ = Some(position);
+                frame.unreachable = false;
+                height = frame.height;
+                Op::Else { end: 0 }
+            }
+            op::END => {
+                let frame = frames.pop().unwrap();
+                if height != frame.height && !frame.unreachable {
+                    return invalid("A block leaves values on the stack.");
+                }
+                height = frame.height;
+                if !frames.is_empty() {
+                    match &mut code[frame.start] {
+                        Op::Block { end } => *end = position,
+                        Op::If { else_, end } => {
+                            *end = position;
+                            if let Some(else_) = *else_ {
+                                code[else_] = Op::Else { end: position };
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Op::End
+            }
+            op::BR | op::BR_IF => {
+                let depth = reader.u32()?;
+                // The function body can't be branched to by depth.
+                if depth as usize >= frames.len() - 1 {
+                    return invalid(&format!("Branch depth {depth} is out of range."));
+                }
+                if opcode == op::BR {
+                    let frame = frames.last_mut().unwrap();
+                    frame.unreachable = true;
+                    height = frame.height;
+                    Op::Br { depth }
+                } else {
+                    pop(&frames, &mut height, 1)?;
+                    Op::BrIf { depth }
+                }
+            }
+            op::CALL => {
+                let function = reader.u32()?;
+                let Some(&import) = imports.get(function as usize) else {
+                    return invalid("Only imported functions can be called.");
+                };
+                match import {
+                    Import::Read => height += 1,
+                    Import::Write => pop(&frames, &mut height, 1)?,
+                }
+                Op::Call { import }
+            }
+            op::LOCAL_GET | op::LOCAL_SET | op::LOCAL_TEE => {
+                let local = reader.u32()?;
+                if local >= local_count {
+                    return invalid(&format!("Local {local} doesn't exist."));
+                }
+                match opcode {
+                    op::LOCAL_GET => {
+                        height += 1;
+                        Op::LocalGet { local }
+                    }
+                    op::LOCAL_SET => {
+                        pop(&frames, &mut height, 1)?;
+                        Op::LocalSet { local }
+                    }
+                    _ => {
+                        pop(&frames, &mut height, 1)?;
+                        height += 1;
+                        Op::LocalTee { local }
+                    }
+                }
+            }
+            op::I32_LOAD | op::I32_LOAD8_U | op::I32_LOAD16_U => {
+                let width = match opcode {
+                    op::I32_LOAD8_U => Width::W8,
+                    op::I32_LOAD16_U => Width::W16,
+                    _ => Width::W32,
+                };
+                let offset = reader.memarg(width)?;
+                pop(&frames, &mut height, 1)?;
+                height += 1;
+                Op::Load { width, offset }
+            }
+            op::I32_STORE | op::I32_STORE8 | op::I32_STORE16 => {
+                let width = match opcode {
+                    op::I32_STORE8 => Width::W8,
+                    op::I32_STORE16 => Width::W16,
+                    _ => Width::W32,
+                };
+                let offset = reader.memarg(width)?;
+                pop(&frames, &mut height, 2)?;
+                Op::Store { width, offset }
+            }
+            op::I32_CONST => {
+                height += 1;
+                Op::Const {
+                    value: reader.i32()?,
+                }
+            }
+            op::I32_EQZ => {
+                pop(&frames, &mut height, 1)?;
+                height += 1;
+                Op::Eqz
+            }
+            op::I32_NE | op::I32_ADD | op::I32_SUB => {
+                pop(&frames, &mut height, 2)?;
+                height += 1;
+                match opcode {
+                    op::I32_NE => Op::Ne,
+                    op::I32_ADD => Op::Add,
+                    _ => Op::Sub,
+                }
+            }
+            _ => return invalid(&format!("Unsupported opcode {opcode:#04x}.")),
+        };
+        code.push(op);
+    }
+
+    if !reader.is_at_end() {
+        return invalid("The run function has code after its end.");
+    }
+
+    Ok((local_count, code))
+}
+
+fn lookup(types: &[FunctionType], index: u32) -> BfResult<FunctionType> {
+    types
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| BfError::Bf(format!("Type {index} doesn't exist.")))
+}
+
+fn invalid<T>(message: &str) -> BfResult<T> {
+    Err(BfError::Bf(format!("Invalid module: {message}")))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> BfResult<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or_else(|| BfError::Bf("Invalid module: Unexpected end.".to_owned()))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> BfResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    // Unsigned LEB128, at most five bytes long.
+    fn u32(&mut self) -> BfResult<u32> {
+        let mut value = 0u64;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return value
+                    .try_into()
+                    .or_else(|_| invalid("An integer is too large."));
+            }
+        }
+        invalid("An integer is too long.")
+    }
+
+    // Signed LEB128, at most five bytes long.
+    fn i32(&mut self) -> BfResult<i32> {
+        let mut value = 0i64;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= i64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                if byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return value
+                    .try_into()
+                    .or_else(|_| invalid("An integer is too large."));
+            }
+        }
+        invalid("An integer is too long.")
+    }
+
+    fn name(&mut self) -> BfResult<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).or_else(|_| invalid("A name isn't UTF-8."))
+    }
+
+    fn function_type(&mut self) -> BfResult<FunctionType> {
+        if self.byte()? != op::FUNCTION_TYPE {
+            return invalid("Expected a function type.");
+        }
+        let params = self.value_types()?;
+        let results = self.value_types()?;
+        Ok(FunctionType { params, results })
+    }
+
+    fn value_types(&mut self) -> BfResult<u32> {
+        let count = self.u32()?;
+        for _ in 0..count {
+            if self.byte()? != op::VALUE_TYPE_I32 {
+                return invalid("Only i32 values are supported.");
+            }
+        }
+        Ok(count)
+    }
+
+    // The alignment of a memory access can't be more than its width.
+    fn memarg(&mut self, width: Width) -> BfResult<u32> {
+        let align = self.u32()?;
+        if 1usize
+            .checked_shl(align)
+            .is_none_or(|align| align > width.bytes())
+        {
+            return invalid("A memory access is overaligned.");
+        }
+        self.u32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{run, validate},
+        util::{
+            run::{CellType, EofPolicy, RunOptions, TapeOptions},
+            BfError,
+        },
+    };
+
+    fn run_with_tape(source_code: &str, tape: TapeOptions, input: &str) -> (Vec<u8>, Vec<u8>) {
+        let options = RunOptions {
+            tape,
+            ..Default::default()
+        };
+        let module = validate(&opjit::wasm_module(source_code, &options).unwrap()).unwrap();
+
+        let mut output = vec![];
+        let memory = run(&module, &mut input.as_bytes(), &mut output).unwrap();
+        (output, memory)
+    }
+
+    #[test]
+    fn run_test() {
+        let (output, memory) = run_with_tape(
+            ",[.,]>+++[<+>-]<[>>+>+<<<-]>>[<]>>.",
+            TapeOptions {
+                size: 10,
+                cell: CellType::U16,
+                eof: EofPolicy::Zero,
+            },
+            "ab",
+        );
+
+        assert_eq!(output, b"ab\x03");
+        // Cells are two bytes each.
+        assert_eq!(memory[..8], [0, 0, 0, 0, 3, 0, 3, 0]);
+    }
+
+    #[test]
+    fn eof_test() {
+        let tape = |eof| TapeOptions {
+            eof,
+            ..Default::default()
+        };
+
+        assert_eq!(run_with_tape("+,.", tape(EofPolicy::Zero), "").0, [0]);
+        assert_eq!(run_with_tape("+,.", tape(EofPolicy::Unchanged), "").0, [1]);
+        assert_eq!(run_with_tape("+,.", tape(EofPolicy::MinusOne), "").0, [255]);
+
+        let module = validate(&opjit::wasm_module(",", &RunOptions::default()).unwrap()).unwrap();
+        assert_eq!(
+            run(&module, &mut "".as_bytes(), &mut vec![]).unwrap_err(),
+            BfError::Bf("Trapped at an unreachable instruction.".to_owned())
+        );
+    }
+
+    #[test]
+    fn validate_test() {
+        let module = opjit::wasm_module("+[>+<-]", &RunOptions::default()).unwrap();
+        validate(&module).unwrap();
+
+        assert_eq!(
+            validate(&module[..module.len() - 1]).unwrap_err(),
+            BfError::Bf("Invalid module: Unexpected end.".to_owned())
+        );
+
+        // Swap the body's final end for an unsupported opcode.
+        let mut unsupported = module.clone();
+        *unsupported.last_mut().unwrap() = 0xff;
+        assert_eq!(
+            validate(&unsupported).unwrap_err(),
+            BfError::Bf("Invalid module: Unsupported opcode 0xff.".to_owned())
+        );
+
+        let mut bad_magic = module;
+        bad_magic[0] = 1;
+        assert_eq!(
+            validate(&bad_magic).unwrap_err(),
+            BfError::Bf("Invalid module: Not a version 1 WebAssembly module.".to_owned())
+        );
+    }
+}
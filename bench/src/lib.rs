@@ -3,6 +3,12 @@ use {
     util::{run::RunFunction, BfError},
 };
 
+#[cfg(unix)]
+use {
+    std::io::{Read, Write},
+    util::run::RunOptions,
+};
+
 #[cfg(target_os = "linux")]
 macro_rules! os_id {
     () => {
@@ -83,6 +89,8 @@ pub fn measure_results() -> Result<(), BfError> {
     for (short_title, title, input) in [
         ("mandelbrot", "mandelbrot generator", ""),
         ("factor", "factorization", "179424691\n"),
+        ("scan", "pointer scan", ""),
+        ("bounded", "bounded loops", ""),
     ] {
         let filepath = format!("corpus/{short_title}.bf");
         println!("Measuring file {filepath}...");
@@ -100,15 +108,39 @@ fn measure_results_for_file(
     input: &str,
     source_code: &str,
 ) -> Result<(), BfError> {
+    // Built once up front so that only running the program is timed, as with
+    // `cc -O2` outside of the benchmark.
+    #[cfg(unix)]
+    let c_executable = aot::temp_path();
+    #[cfg(unix)]
+    aot::build_c(source_code, &RunOptions::default(), &c_executable)?;
+
     let impl_infos = [
         ImplInfo::new("simpleinterp", &simpleinterp::run, source_code, input)?,
         ImplInfo::new("opinterp", &opinterp::run, source_code, input)?,
         ImplInfo::new("opinterp2", &opinterp2::run, source_code, input)?,
         ImplInfo::new("opinterp3", &opinterp3::run, source_code, input)?,
+        ImplInfo::new("threadedinterp", &threadedinterp::run, source_code, input)?,
+        ImplInfo::new("cachedinterp", &cachedinterp::run, source_code, input)?,
         ImplInfo::new("simplejit", &simplejit::run, source_code, input)?,
         ImplInfo::new("opjit", &opjit::run, source_code, input)?,
+        ImplInfo::new("tieredjit", &tieredjit::run, source_code, input)?,
+        ImplInfo::new("tracingjit", &tracingjit::run, source_code, input)?,
+        ImplInfo::new("craneliftjit", &craneliftjit::run, source_code, input)?,
+        #[cfg(unix)]
+        ImplInfo::new(
+            "c",
+            &|_source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+                aot::run_executable(&c_executable, stdin, stdout)
+            },
+            source_code,
+            input,
+        )?,
     ];
 
+    #[cfg(unix)]
+    fs::remove_file(&c_executable)?;
+
     output_data(title, short_title, impl_infos)?;
 
     Ok(())
@@ -0,0 +1,62 @@
+use {
+    proc_macro::TokenStream,
+    std::{env, fs, path::Path},
+    syn::{parse_macro_input, Error, LitStr},
+    util::{run::RunOptions, BfResult},
+};
+
+// Compiles a BF program given as a string literal into a function taking its
+// input and output, which returns the final tape:
+//
+//     let hello = bf!("++++++++[>++++++++<-]>+.");
+//     let tape = hello(&mut io::stdin(), &mut io::stdout())?;
+//
+// The function is generated by opjit::rust_source with the default options, so
+// nothing is interpreted or compiled at runtime.
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    expand(&literal, &literal.value(), None)
+}
+
+// Like bf!, but reads the program from a file, relative to the root of the
+// crate using it.
+#[proc_macro]
+pub fn include_bf(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    let path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap_or_default()).join(literal.value());
+    match fs::read_to_string(&path) {
+        Ok(source_code) => expand(&literal, &source_code, Some(&path)),
+        Err(e) => Error::new(
+            literal.span(),
+            format!("Couldn't read '{}': {e}", path.display()),
+        )
+        .to_compile_error()
+        .into(),
+    }
+}
+
+fn expand(literal: &LitStr, source_code: &str, path: Option<&Path>) -> TokenStream {
+    match generate(source_code, path) {
+        Ok(tokens) => tokens,
+        Err(e) => Error::new(literal.span(), e.to_string())
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn generate(source_code: &str, path: Option<&Path>) -> BfResult<TokenStream> {
+    let function = opjit::rust_source(source_code, &RunOptions::default())?;
+    // Including the file makes Cargo rebuild the crate whenever it changes.
+    let dependency = match path {
+        Some(path) => format!(
+            "const _: &str = include_str!({:?});",
+            path.display().to_string()
+        ),
+        None => String::new(),
+    };
+
+    Ok(format!("{{ {dependency} {function} run }}")
+        .parse()
+        .expect("Generated code should always be valid Rust."))
+}
@@ -0,0 +1,39 @@
+[
+  Counts down four nested loops which only ever move the data pointer by
+  fixed amounts and end where they started, so the range of the data pointer
+  can be worked out ahead of time and is always inside memory
+]
+
+                            Set c0 = 30 (using c1 as a temporary)
+>+++[<++++++++++>-]<
+
+[                           For each c0
+  >>++++++++++              Set c1 = 200 (using c2 as a temporary)
+  [<++++++++++++++++++++>-]<
+
+  [                         For each c1
+    >>++++++++++            Set c2 = 200 (using c3 as a temporary)
+    [<++++++++++++++++++++>-]<
+
+    [                       For each c2
+      >+++++ +++++ +++++ +++++ +++++
+      +++++ +++++ +++++ +++++ +++++
+                            Set c3 = 50
+
+      [                     For each c3
+        >+>-                Add one to c4 and take one from c5
+        >+>+                Add one to c6 and c7
+        <<<<-
+      ]
+
+      <-
+    ]
+
+    <-
+  ]
+
+  <-
+]
+
+                            Print an exclamation mark from c3
+>>>++++++++ ++++++++ ++++++++ ++++++++ +.
@@ -0,0 +1,71 @@
+[
+  Tests of loops which run at most once; this is the BF "if" idiom
+]
+
++++ >+<                     Set c0 = 3 and the else flag c1 = 1
+
+[                           If c0 is nonzero then print "y" from c2
+  >>
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  +.[-]
+  <[-]                      Clear the else flag
+  <[-]                      Clear c0 so the loop can't run again
+]
+>[                          Else print "n" from c2
+  >
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++.[-]
+  <[-]
+]<
+
+>+<                         Leave c0 = 0 and set the else flag c1 = 1
+
+[                           If c0 is nonzero then print "y" from c2
+  >>
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  +.[-]
+  <[-]                      Clear the else flag
+  <[-]                      Clear c0 so the loop can't run again
+]
+>[                          Else print "n" from c2
+  >
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++.[-]
+  <[-]
+]<
+
+++                          Set c0 = 2
+
+[                           If c0 is nonzero
+  >+                        Set c1 = 1
+  [                         If c1 is nonzero then print "A" from c2
+    >
+    ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+    +++++.[-]
+    <[-]
+  ]
+  <[-]                      Clear c0
+]
+
++>+>+<<                     Set c0 to c2 = 1
+
+[                           If c0 is nonzero then print "B" from c3
+  >>>
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  ++++++.[-]
+  <<<[>]                    Scan right to c3 which is zero
+]
+<[-]<[-]<[-]                Clear c2 to c0
+
++++++                       Set c0 = 5
+
+[                           If c0 is nonzero then print "C" from c2
+  >>
+  ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++ ++++++++++
+  +++++++.[-]
+  <<[->+<]                  Move c0 into c1
+]
+>                           Print c1 as a single ASCII digit
+++++++++ ++++++++ ++++++++ ++++++++ ++++++++ ++++++++.
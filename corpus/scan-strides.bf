@@ -0,0 +1,77 @@
+[
+  Tests of pointer scans with various strides and directions; each scan
+  lands on a zero cell followed by a letter which is then printed
+]
+
+Backward scan with a stride of 1
+>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+
+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+[<]>.>>>>>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+
+Forward scan with a stride of 1
+>>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>
++>+>+>+>+>+>+>+>+>+>+>>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>
++>+>+>+>+>+>+>+>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<<<[>]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+
+Forward scan with a stride of 2
+>>+>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>+>
++>+>+>+>>+>+>+>+>+>+>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>
++>+>+>+>+>+>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<[>>]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+
+Forward scan with a stride of 4
+>>+>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>
++>+>+>>+>+>+>+>>+>+>+>+>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+
+>+>+>+>+>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<[>>>>]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+
+Forward scan with a stride of 8
+>>+>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+
+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>>++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>+>+>+>+>+>+>+>+>
++>+>+>+>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<[>>>>>>>>]>.>>>>>>>>>>>>>>>>>>>>>>>>
+
+Forward scan with a stride of 3
+>>+>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>
++>+>+>+>+>+>+>+>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+
+>+>+>+>+>+>+>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<[>>>]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+
+Backward scan with a stride of 2
+>>>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>>++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++>+>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>+>
++>+>+>+>>+>+>+>+>+[<<]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>
+
+Backward scan with a stride of 4
+>>>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>>++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>+>+
+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>
++>+>+>>+>+>+>+>+[<<<<]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>>>>>
+
+Backward scan with a stride of 8
+>>>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>>++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>+>+>>+>+>+>+>>+>+>+>
++>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>+>+>>+>+>
++>+>>+>+>+>+>+[<<<<<<<<]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>>>
+
+Backward scan with a stride of 3
+>>>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>>++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+>>+>+>+>+>>+>+>+>+>+>+>+>+>+
+>>+>+>+>+>>+>+>+>+>+[<<<]>.>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>>>
+
+Scan starting on a zero cell
+>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++<[>]>.>>>>>>>>>>
@@ -0,0 +1,25 @@
+[
+  Grows a run of nonzero cells one cell at a time and scans across the whole
+  run in both directions after each step, so almost all of the runtime is spent
+  in [>] and [<] loops
+]
+
+                            Set c0 = 100 (using c1 as a temporary)
+>++++++++++[<++++++++++>-]<
+
+[                           For each c0
+  >>++++++++++              Set c1 = 200 (using c2 as a temporary)
+  [<++++++++++++++++++++>-]<
+
+  [                         For each c1
+    >>[>]+                  Extend the run which starts at c3 by one cell
+    [<]<-                   And scan back to the zero cell c2
+  ]
+
+  <-
+]
+
+>>>[>]<[<]                  Scan to the end of the run and back again
+
+                            Print an exclamation mark from c2
+++++++++ ++++++++ ++++++++ ++++++++ +.
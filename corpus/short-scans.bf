@@ -0,0 +1,32 @@
+[
+  Scans past a single nonzero cell over and over, so almost all of the runtime
+  is spent in [>] loops which stop after one step, where setting up vector loads
+  costs more than the scan itself
+]
+
+                            Set c0 = 250 (using c1 as a temporary)
+>+++++[<++++++++++++++++++++++++++++++++++++++++++++++++++>-]<
+
+>>>>+<<<<                   Set c4 = 1 for the scans to step over
+
+[                           For each c0
+  >>++++++++++              Set c1 = 200 (using c2 as a temporary)
+  [<++++++++++++++++++++>-]<
+
+  [                         For each c1
+    >>++++++++++            Set c2 = 200 (using c3 as a temporary)
+    [<++++++++++++++++++++>-]<
+
+    [                       For each c2
+      >>[>]                 Scan from c4 to c5
+      <<<-                  And go back to c2
+    ]
+
+    <-
+  ]
+
+  <-
+]
+
+                            Print an exclamation mark from c3
+>>>++++++++ ++++++++ ++++++++ ++++++++ +.
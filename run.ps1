@@ -1,6 +1,6 @@
 [CmdletBinding()]
 param(
-    [ValidateSet('simpleinterp', 'opinterp', 'opinterp2', 'opinterp3', 'simplejit', 'opjit')]
+    [ValidateSet('simpleinterp', 'opinterp', 'opinterp2', 'opinterp3', 'threadedinterp', 'cachedinterp', 'simplejit', 'opjit', 'tieredjit', 'tracingjit', 'craneliftjit')]
     [Parameter(Mandatory, Position = 0)]
     [String] $Bin,
 
@@ -0,0 +1,28 @@
+use bf_macro::{bf, include_bf};
+
+#[test]
+fn bf_test() {
+    let add_one = bf!(",+.>++[-<+>]");
+
+    let mut output = vec![];
+    let tape = add_one(&mut "A".as_bytes(), &mut output).unwrap();
+    assert_eq!(output, b"B");
+    assert_eq!(tape[..2], [68, 0]);
+}
+
+#[test]
+fn bf_eof_test() {
+    let read = bf!(",");
+
+    let error = read(&mut "".as_bytes(), &mut vec![]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn include_bf_test() {
+    let factor = include_bf!("../corpus/factor.bf");
+
+    let mut output = vec![];
+    factor(&mut "179424691\n".as_bytes(), &mut output).unwrap();
+    assert_eq!(output, b"179424691: 179424691\n");
+}
@@ -1,4 +1,13 @@
-use {std::fs, util::run::RunFunction};
+use {
+    std::{
+        fs,
+        io::{Read, Write},
+    },
+    util::{
+        passes::{PassSelection, SuperinstructionSelection},
+        run::{RunFunction, RunOptions},
+    },
+};
 
 const MANDELBROT_EXPECTED_OUTPUT: &str = concat!(
     "AAAAAAAAAAAAAAAABBBBBBBBBBBBBBBCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCDDDDDDDDDEGFFEEEEDDDDDDCCCCCCCCCBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB\n",
@@ -54,6 +63,7 @@ const MANDELBROT_EXPECTED_OUTPUT: &str = concat!(
 fn run_test(run_function: impl RunFunction) {
     for (name, input, expected_output) in [
         ("add-two-nums", "", "7"),
+        ("conditionals", "", "ynABC5"),
         ("count-1-to-5", "", "12345"),
         ("factor", "179424691\n", "179424691: 179424691\n"),
         ("hello-world", "", "Hello World!\n"),
@@ -66,6 +76,7 @@ fn run_test(run_function: impl RunFunction) {
         ("nested-loops", "", "?"),
         ("no-loops", "", "70"),
         ("optimizable-loops", "", "800"),
+        ("scan-strides", "", "abcdefghijk"),
         ("trivial-in", "X", "Y"),
         ("trivial-out", "", "!"),
     ] {
@@ -93,5 +104,310 @@ make_test!(simpleinterp, simpleinterp_test);
 make_test!(opinterp, opinterp_test);
 make_test!(opinterp2, opinterp2_test);
 make_test!(opinterp3, opinterp3_test);
+make_test!(threadedinterp, threadedinterp_test);
+make_test!(cachedinterp, cachedinterp_test);
 make_test!(simplejit, simplejit_test);
 make_test!(opjit, opjit_test);
+make_test!(tieredjit, tieredjit_test);
+make_test!(tracingjit, tracingjit_test);
+make_test!(craneliftjit, craneliftjit_test);
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+make_test!(aot, aot_test);
+
+// Runs everything twice, so that the second run of each program comes from the
+// cache.
+#[test]
+fn opjit_cached_test() {
+    let run_cached = |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+        opjit::run_cached(source_code, &RunOptions::default(), stdin, stdout)
+    };
+    run_test(run_cached);
+    run_test(run_cached);
+
+    assert!(opjit::cache_stats().hits > 0);
+}
+
+#[test]
+fn opjit_lazy_test() {
+    let options = RunOptions {
+        lazy: true,
+        ..Default::default()
+    };
+    run_test(
+        |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+            opjit::run_with_options(source_code, &options, stdin, stdout)
+        },
+    );
+}
+
+#[test]
+fn opjit_bounds_checks_test() {
+    let options = RunOptions {
+        bounds_checks: true,
+        ..Default::default()
+    };
+    run_test(
+        |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+            opjit::run_with_options(source_code, &options, stdin, stdout)
+        },
+    );
+
+    let run = |source_code: &str| {
+        opjit::run_with_options(source_code, &options, &mut "".as_bytes(), &mut vec![])
+    };
+    assert_eq!(
+        run("+[>+]"),
+        Err(util::BfError::Bf(
+            "Data pointer out of bounds in instruction 2, '>' at index 2.".to_owned()
+        ))
+    );
+    assert_eq!(
+        run("+[<]"),
+        Err(util::BfError::Bf(
+            "Data pointer out of bounds in instruction 1, '[<]' at index 1.".to_owned()
+        ))
+    );
+    assert_eq!(
+        run("+[-<+>]"),
+        Err(util::BfError::Bf(
+            "Data pointer out of bounds in instruction 1, '[-<+>]' at index 1.".to_owned()
+        ))
+    );
+}
+
+// Records a profile of each program with opinterp3, on the same input, then
+// runs it optimized with that profile.
+#[test]
+fn opjit_profile_test() {
+    run_test(
+        |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+            let mut input = vec![];
+            stdin.read_to_end(&mut input)?;
+            let profile =
+                opinterp3::run_with_profile(source_code, &mut input.as_slice(), &mut vec![])?;
+            opjit::run_with_profile(
+                source_code,
+                &RunOptions::default(),
+                &profile,
+                &mut input.as_slice(),
+                stdout,
+            )
+        },
+    );
+
+    let profile = opinterp3::run_with_profile("+[-]", &mut "".as_bytes(), &mut vec![]).unwrap();
+    assert_eq!(
+        opjit::run_with_profile(
+            "+[-]>",
+            &RunOptions::default(),
+            &profile,
+            &mut "".as_bytes(),
+            &mut vec![]
+        ),
+        Err(util::BfError::Bf(
+            "The profile was recorded from a different program.".to_owned()
+        ))
+    );
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[test]
+fn opjit_dump_options_test() {
+    let mut dump = vec![];
+    assert_eq!(
+        opjit::run_with_dump(
+            "+.",
+            &RunOptions {
+                lazy: true,
+                ..Default::default()
+            },
+            &mut "".as_bytes(),
+            &mut vec![],
+            &mut dump
+        ),
+        Err(util::BfError::Bf(
+            "Lazy compilation and syscall I/O can't be combined with dumping the compiled code."
+                .to_owned()
+        ))
+    );
+    assert!(dump.is_empty());
+}
+
+// Passes input and output through pipes, which the compiled code reads and
+// writes itself. Every input fits in a pipe's buffer, but the output is read
+// while the program runs, so as not to depend on it fitting too.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn opjit_syscall_io_test() {
+    use std::{io, os::unix::io::AsRawFd, thread};
+
+    run_test(
+        |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+            let (input_reader, mut input_writer) = io::pipe()?;
+            let (mut output_reader, output_writer) = io::pipe()?;
+            io::copy(stdin, &mut input_writer)?;
+            drop(input_writer);
+
+            let reader = thread::spawn(move || {
+                let mut output = vec![];
+                output_reader.read_to_end(&mut output).map(|_| output)
+            });
+            let result = opjit::run_with_fds(
+                source_code,
+                &RunOptions::default(),
+                input_reader.as_raw_fd(),
+                output_writer.as_raw_fd(),
+            );
+            drop(output_writer);
+
+            stdout.write_all(&reader.join().unwrap()?)?;
+            result
+        },
+    );
+}
+
+// Fills the output buffer a couple of times over, then reads past the end of
+// input.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn opjit_syscall_io_flush_test() {
+    use std::{io, os::unix::io::AsRawFd, thread};
+
+    let (input_reader, input_writer) = io::pipe().unwrap();
+    let (mut output_reader, output_writer) = io::pipe().unwrap();
+    drop(input_writer);
+
+    // The output is more than a pipe holds, so it's read while the program
+    // runs.
+    let reader = thread::spawn(move || {
+        let mut output = vec![];
+        output_reader.read_to_end(&mut output).unwrap();
+        output
+    });
+    let result = opjit::run_with_fds(
+        "-[>-[.-]<-],",
+        &RunOptions::default(),
+        input_reader.as_raw_fd(),
+        output_writer.as_raw_fd(),
+    );
+    drop(output_writer);
+    let output = reader.join().unwrap();
+
+    assert_eq!(output.len(), 255 * 255);
+    assert!(output
+        .chunks(255)
+        .all(|chunk| chunk[0] == 255 && chunk[254] == 1));
+    assert_eq!(
+        result,
+        Err(util::BfError::Io("unexpected end of file".to_owned()))
+    );
+}
+
+#[test]
+fn aot_wasm_test() {
+    run_test(aot::run_wasm);
+}
+
+#[cfg(unix)]
+#[test]
+fn aot_c_test() {
+    run_test(aot::run_c);
+}
+
+// Builds opjit's assembler listing of each program with runtime.c, so that the
+// listing is checked against what it should do rather than a copy of itself.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn opjit_listing_test() {
+    run_test(aot::run_asm);
+}
+
+macro_rules! make_opt_level_test {
+    ($vm_name:ident, $test_name:ident, $level:expr) => {
+        #[test]
+        fn $test_name() {
+            let options = RunOptions {
+                passes: PassSelection::Level($level),
+                ..Default::default()
+            };
+            run_test(
+                |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+                    $vm_name::run_with_options(source_code, &options, stdin, stdout)
+                },
+            );
+        }
+    };
+}
+
+make_opt_level_test!(opinterp3, opinterp3_o0_test, 0);
+make_opt_level_test!(opinterp3, opinterp3_o1_test, 1);
+make_opt_level_test!(opinterp3, opinterp3_o2_test, 2);
+make_opt_level_test!(opjit, opjit_o0_test, 0);
+make_opt_level_test!(opjit, opjit_o1_test, 1);
+make_opt_level_test!(opjit, opjit_o2_test, 2);
+
+macro_rules! make_superinstructions_test {
+    ($test_name:ident, $selection:expr) => {
+        #[test]
+        fn $test_name() {
+            let options = RunOptions {
+                superinstructions: $selection,
+                ..Default::default()
+            };
+            run_test(
+                |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+                    opinterp3::run_with_options(source_code, &options, stdin, stdout)
+                },
+            );
+        }
+    };
+}
+
+make_superinstructions_test!(
+    opinterp3_no_superinstructions_test,
+    SuperinstructionSelection::Named(vec![])
+);
+make_superinstructions_test!(
+    opinterp3_corpus_superinstructions_test,
+    SuperinstructionSelection::Corpus(vec!["../corpus/hello-world.bf".to_owned()])
+);
+
+#[test]
+fn opinterp3_bytecode_test() {
+    run_test(
+        |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+            let bytes = opinterp3::save_bytecode(source_code, &RunOptions::default())?;
+            opinterp3::run_bytecode(&bytes, stdin, stdout)
+        },
+    );
+}
+
+macro_rules! make_threshold_test {
+    ($vm_name:ident, $test_name:ident, $threshold:expr) => {
+        #[test]
+        fn $test_name() {
+            run_test(
+                |source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write| {
+                    $vm_name::run_with_threshold(
+                        source_code,
+                        &RunOptions::default(),
+                        $threshold,
+                        stdin,
+                        stdout,
+                    )
+                },
+            );
+        }
+    };
+}
+
+make_threshold_test!(tieredjit, tieredjit_compile_immediately_test, 1);
+make_threshold_test!(tieredjit, tieredjit_never_compile_test, u32::MAX);
+make_threshold_test!(tracingjit, tracingjit_trace_immediately_test, 1);
+make_threshold_test!(tracingjit, tracingjit_never_trace_test, u32::MAX);
@@ -0,0 +1,54 @@
+use {
+    std::fs,
+    util::passes::{PassSelection, MAX_OPT_LEVEL},
+    validate::{validate, Vm},
+};
+
+fn validation_test(vm: Vm) {
+    // The benchmark programs are left out or given smaller inputs since they
+    // take too long in the unoptimized reference interpreter.
+    for (name, input) in [
+        ("add-two-nums", ""),
+        ("conditionals", ""),
+        ("count-1-to-5", ""),
+        ("factor", "1234567\n"),
+        ("hello-world", ""),
+        ("move-data", ""),
+        ("move-data-left", ""),
+        ("move-data-loops", ""),
+        ("move-data-right", ""),
+        ("mult-nums", ""),
+        ("nested-loops", ""),
+        ("no-loops", ""),
+        ("optimizable-loops", ""),
+        ("scan-strides", ""),
+        ("trivial-in", "X"),
+        ("trivial-out", ""),
+    ] {
+        println!("Test: {}", name);
+
+        let filepath = format!("../corpus/{name}.bf");
+        let source_code = fs::read_to_string(filepath).unwrap();
+
+        for level in 0..=MAX_OPT_LEVEL {
+            let divergence = validate(
+                &source_code,
+                input.as_bytes(),
+                vm,
+                &PassSelection::Level(level),
+            )
+            .unwrap();
+            assert_eq!(divergence, None, "-O{level}");
+        }
+    }
+}
+
+#[test]
+fn opinterp3_validation_test() {
+    validation_test(Vm::Opinterp3);
+}
+
+#[test]
+fn opjit_validation_test() {
+    validation_test(Vm::Opjit);
+}
@@ -1,9 +1,12 @@
 use {
-    crate::error::{BfError, BfResult},
+    crate::{
+        error::{BfError, BfResult},
+        scan::{is_vectorizable_stride, lane_mask},
+    },
     dynasmrt::{dynasm, AssemblyOffset, DynamicLabel, DynasmApi, DynasmLabelApi, ExecutableBuffer},
     std::{
-        io::{Read, Write},
-        mem,
+        io::{self, Read, Write},
+        mem, process, ptr,
     },
 };
 
@@ -25,8 +28,12 @@ macro_rules! dasm {
             ; .alias reg_arg1, rdi
             ; .alias reg_arg2, rsi
             ; .alias reg_temp, r8
+            ; .alias reg_temp_dword, r8d
             ; .alias reg_temp_low, r8b
+            ; .alias reg_cell, r9b
+            ; .alias reg_cell_dword, r9d
             ; .alias reg_return, al
+            ; .alias reg_return_ptr, rax
             $($t)*
         )
     }
@@ -45,8 +52,12 @@ macro_rules! dasm {
             ; .alias reg_arg1, ecx
             ; .alias reg_arg2, edx
             ; .alias reg_temp, eax
+            ; .alias reg_temp_dword, eax
             ; .alias reg_temp_low, al
+            ; .alias reg_cell, dl
+            ; .alias reg_cell_dword, edx
             ; .alias reg_return, al
+            ; .alias reg_return_ptr, eax
             $($t)*
         )
     }
@@ -68,12 +79,15 @@ macro_rules! dasm {
             ; .alias reg_link, x30 // The lr alias isn't supported by default.
             ; .alias reg_data_ptr, x19
             ; .alias reg_arg1, x0
+            ; .alias reg_arg2, x1
             ; .alias reg_arg2_low, w1
             ; .alias reg_temp, x9
             ; .alias reg_temp_low, w9
             ; .alias reg_temp2_low, w10
             ; .alias reg_temp3, x11
+            ; .alias reg_cell, w12
             ; .alias reg_return, w0
+            ; .alias reg_return_ptr, x0
             $($t)*
         )
     }
@@ -95,8 +109,12 @@ macro_rules! dasm {
             ; .alias reg_arg1, rcx
             ; .alias reg_arg2, rdx
             ; .alias reg_temp, r8
+            ; .alias reg_temp_dword, r8d
             ; .alias reg_temp_low, r8b
+            ; .alias reg_cell, r9b
+            ; .alias reg_cell_dword, r9d
             ; .alias reg_return, al
+            ; .alias reg_return_ptr, rax
             $($t)*
         )
     }
@@ -150,12 +168,52 @@ macro_rules! add_sub_u64 {
     };
 }
 
+// Where to read the current cell from when branching on it. Code which caches
+// the current cell keeps it in reg_cell, which on aarch64 may have junk above
+// the low byte.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CellLocation {
+    Memory,
+    Register,
+}
+
 pub struct LabelPair {
     begin_label: DynamicLabel,
     end_label: DynamicLabel,
 }
 
 pub fn prologue(assembler: &mut Assembler, runtime: &mut Runtime) {
+    save_registers(assembler);
+
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        // Reinterpret as i64, using the same bytes as before.
+        ; mov reg_data_ptr, QWORD runtime.memory_ptr() as i64
+    );
+    #[cfg(target_arch = "x86")]
+    dasm!(assembler
+        // Reinterpret as i32, using the same bytes as before.
+        ; mov reg_data_ptr, DWORD runtime.memory_ptr() as i32
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        // Reinterpret as u64, using the same bytes as before.
+        ;; mov_u64!(assembler, reg_data_ptr, runtime.memory_ptr() as u64)
+    );
+}
+
+// Rather than starting at the beginning of memory, code compiled with
+// loop_prologue and loop_epilogue takes the data pointer as its argument and
+// returns wherever the data pointer ended up. See Runtime::run_loop.
+pub fn loop_prologue(assembler: &mut Assembler) {
+    save_registers(assembler);
+
+    dasm!(assembler
+        ; mov reg_data_ptr, reg_arg1
+    );
+}
+
+fn save_registers(assembler: &mut Assembler) {
     if STACK_OFFSET > 0 {
         #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
         dasm!(assembler
@@ -184,22 +242,14 @@ pub fn prologue(assembler: &mut Assembler, runtime: &mut Runtime) {
     dasm!(assembler
         ; str reg_data_ptr, [reg_stack_ptr, 0x8]
     );
+}
 
-    #[cfg(target_arch = "x86_64")]
+pub fn loop_epilogue(assembler: &mut Assembler) {
     dasm!(assembler
-        // Reinterpret as i64, using the same bytes as before.
-        ; mov reg_data_ptr, QWORD runtime.memory_ptr() as i64
-    );
-    #[cfg(target_arch = "x86")]
-    dasm!(assembler
-        // Reinterpret as i32, using the same bytes as before.
-        ; mov reg_data_ptr, DWORD runtime.memory_ptr() as i32
-    );
-    #[cfg(target_arch = "aarch64")]
-    dasm!(assembler
-        // Reinterpret as u64, using the same bytes as before.
-        ;; mov_u64!(assembler, reg_data_ptr, runtime.memory_ptr() as u64)
+        ; mov reg_return_ptr, reg_data_ptr
     );
+
+    epilogue(assembler);
 }
 
 pub fn epilogue(assembler: &mut Assembler) {
@@ -234,11 +284,120 @@ pub fn epilogue(assembler: &mut Assembler) {
     );
 }
 
-pub fn call_read(assembler: &mut Assembler, runtime: &mut Runtime) {
+// Where compiled code sends the bytes it reads and writes.
+pub enum Io<'r, 'a> {
+    // Calls back into a Runtime in the same process.
+    Runtime(&'r mut Runtime<'a>),
+    // Calls back into whichever Runtime is in the slot when the code runs, so
+    // that the same code can be run with a new Runtime each time. See
+    // Runtime::run_in_slot.
+    Slot(&'r RuntimeSlot),
+    // Makes read and write syscalls on stdin and stdout directly, for code
+    // which runs on its own. Failed calls, and reaching the end of input, jump
+    // to `error`.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    Syscalls {
+        error: DynamicLabel,
+    },
+    // Calls bf_read and bf_write from runtime.c, for code written out as an
+    // assembler listing to be linked with it. The calls go to these labels,
+    // which the listing names after the functions.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    Extern {
+        read: DynamicLabel,
+        write: DynamicLabel,
+    },
+    // Makes read syscalls inline, and appends output to the buffer in `io`,
+    // which `flush` writes out. See SyscallIo.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    Buffered {
+        io: &'r SyscallIo,
+        flush: DynamicLabel,
+        error: DynamicLabel,
+    },
+}
+
+pub fn call_read(assembler: &mut Assembler, io: &Io) {
+    match io {
+        Io::Runtime(runtime) => {
+            call_runtime_read(assembler, *runtime as *const Runtime as usize, false)
+        }
+        Io::Slot(slot) => call_runtime_read(assembler, slot.address(), true),
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        Io::Syscalls { error } => syscall(assembler, SYS_READ, STDIN, *error),
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        Io::Extern { read, .. } => dasm!(assembler
+            ; call =>*read
+            ; mov BYTE [reg_data_ptr], reg_return
+        ),
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        Io::Buffered { io, flush, error } => buffered_read(assembler, io, *flush, *error),
+    }
+}
+
+pub fn call_write(assembler: &mut Assembler, io: &Io) {
+    match io {
+        Io::Runtime(runtime) => {
+            call_runtime_write(assembler, *runtime as *const Runtime as usize, false)
+        }
+        Io::Slot(slot) => call_runtime_write(assembler, slot.address(), true),
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        Io::Syscalls { error } => syscall(assembler, SYS_WRITE, STDOUT, *error),
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        Io::Extern { write, .. } => dasm!(assembler
+            ; movzx reg_arg1, BYTE [reg_data_ptr]
+            ; call =>*write
+        ),
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        Io::Buffered { io, flush, error } => buffered_write(assembler, io, *flush, *error),
+    }
+}
+
+// Puts the Runtime to call back into in reg_arg1. `address` is either the
+// Runtime's own, or that of a slot holding it when `in_slot` is set.
+fn load_runtime(assembler: &mut Assembler, address: usize, in_slot: bool) {
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        // Reinterpret as i64, using the same bytes as before.
+        ; mov reg_arg1, QWORD address as i64
+    );
+    #[cfg(target_arch = "x86")]
+    dasm!(assembler
+        // Reinterpret as i32, using the same bytes as before.
+        ; mov reg_arg1, DWORD address as i32
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ;; mov_u64!(assembler, reg_arg1, address as u64)
+    );
+
+    if in_slot {
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        dasm!(assembler
+            ; mov reg_arg1, [reg_arg1]
+        );
+        #[cfg(target_arch = "aarch64")]
+        dasm!(assembler
+            ; ldr reg_arg1, [reg_arg1]
+        );
+    }
+}
+
+fn call_runtime_read(assembler: &mut Assembler, address: usize, in_slot: bool) {
+    load_runtime(assembler, address, in_slot);
+
     #[cfg(target_arch = "x86_64")]
     dasm!(assembler
         // Reinterpret as i64, using the same bytes as before.
-        ; mov reg_arg1, QWORD runtime as *const Runtime as i64
         ; mov reg_temp, QWORD Runtime::read as *const () as i64
         ; call reg_temp
         ; mov BYTE [reg_data_ptr], reg_return
@@ -246,7 +405,6 @@ pub fn call_read(assembler: &mut Assembler, runtime: &mut Runtime) {
     #[cfg(target_arch = "x86")]
     dasm!(assembler
         // Reinterpret as i32, using the same bytes as before.
-        ; mov reg_arg1, DWORD runtime as *const Runtime as i32
         ; mov reg_temp, DWORD Runtime::read as *const () as i32
         ; call reg_temp
         ; mov BYTE [reg_data_ptr], reg_return
@@ -254,18 +412,18 @@ pub fn call_read(assembler: &mut Assembler, runtime: &mut Runtime) {
     #[cfg(target_arch = "aarch64")]
     dasm!(assembler
         // Reinterpret as u64, using the same bytes as before.
-        ;; mov_u64!(assembler, reg_arg1, runtime as *const Runtime as u64)
         ;; mov_u64!(assembler, reg_temp, Runtime::read as *const () as u64)
         ; blr reg_temp
         ; strb reg_return, [reg_data_ptr]
     );
 }
 
-pub fn call_write(assembler: &mut Assembler, runtime: &mut Runtime) {
+fn call_runtime_write(assembler: &mut Assembler, address: usize, in_slot: bool) {
+    load_runtime(assembler, address, in_slot);
+
     #[cfg(target_arch = "x86_64")]
     dasm!(assembler
         // Reinterpret as i64, using the same bytes as before.
-        ; mov reg_arg1, QWORD runtime as *const Runtime as i64
         ; mov reg_arg2, [reg_data_ptr]
         ; mov reg_temp, QWORD Runtime::write as *const () as i64
         ; call reg_temp
@@ -273,7 +431,6 @@ pub fn call_write(assembler: &mut Assembler, runtime: &mut Runtime) {
     #[cfg(target_arch = "x86")]
     dasm!(assembler
         // Reinterpret as i32, using the same bytes as before.
-        ; mov reg_arg1, DWORD runtime as *const Runtime as i32
         ; mov reg_arg2, [reg_data_ptr]
         ; mov reg_temp, DWORD Runtime::write as *const () as i32
         ; call reg_temp
@@ -281,14 +438,358 @@ pub fn call_write(assembler: &mut Assembler, runtime: &mut Runtime) {
     #[cfg(target_arch = "aarch64")]
     dasm!(assembler
         // Reinterpret as u64, using the same bytes as before.
-        ;; mov_u64!(assembler, reg_arg1, runtime as *const Runtime as u64)
         ; ldrb reg_arg2_low, [reg_data_ptr]
         ;; mov_u64!(assembler, reg_temp, Runtime::write as *const () as u64)
         ; blr reg_temp
     );
 }
 
-pub fn jump_begin(assembler: &mut Assembler, open_bracket_stack: &mut Vec<LabelPair>) {
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYS_READ: i32 = 0;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYS_WRITE: i32 = 1;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYS_EXIT: i32 = 60;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const SYS_READ: i32 = 63;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const SYS_WRITE: i32 = 64;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const STDIN: i32 = 0;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const STDOUT: i32 = 1;
+
+// Reads or writes the current cell through `fd`. Besides the registers it
+// passes arguments in, the kernel clobbers rcx and r11, neither of which we
+// keep anything in.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn syscall(assembler: &mut Assembler, number: i32, fd: i32, error: DynamicLabel) {
+    dasm!(assembler
+        ; mov eax, number
+        ; mov edi, fd
+        ; mov rsi, reg_data_ptr
+        ; mov edx, 1
+        ; syscall
+        // Anything other than one byte means an error, or the end of input.
+        ; cmp rax, 1
+        ; jne =>error
+    );
+}
+
+// Code compiled with standalone_prologue and standalone_epilogue makes up a
+// whole executable on its own. It starts at the entry point, finds the tape
+// relative to itself so it can be loaded anywhere, and exits the process when
+// it's done. Returns the offset just past the tape's displacement, which
+// StandaloneProgram::link fills in.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn standalone_prologue(assembler: &mut Assembler) -> AssemblyOffset {
+    dasm!(assembler
+        ; lea reg_data_ptr, [rip + 0]
+    );
+
+    assembler.offset()
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn standalone_epilogue(assembler: &mut Assembler, error: DynamicLabel) {
+    dasm!(assembler
+        ; mov eax, SYS_EXIT
+        ; xor edi, edi
+        ; syscall
+        ; =>error
+        ; mov eax, SYS_EXIT
+        ; mov edi, 1
+        ; syscall
+    );
+}
+
+// How many bytes of output code compiled with Io::Buffered holds on to before
+// writing them out.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub const OUTPUT_BUFFER_SIZE: usize = 32 * 1024;
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const EINTR: i32 = 4;
+
+// What code compiled with Io::Buffered reads and writes through. Output is
+// appended to the buffer inline, and only written out with a write syscall when
+// the buffer fills up, before each read, and at the end of the program. Reads
+// are read syscalls made inline. The first syscall to fail, or a read at the
+// end of input, ends the program, and is recorded for result to report. The
+// code refers to it by its address, so it's always boxed to keep it in one
+// place.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[repr(C)]
+pub struct SyscallIo {
+    // How much of buffer is waiting to be written.
+    len: usize,
+    // Which syscall ended the program, if any, and what it returned.
+    failure: u64,
+    result: i64,
+    input_fd: i32,
+    output_fd: i32,
+    buffer: [u8; OUTPUT_BUFFER_SIZE],
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+impl SyscallIo {
+    const LEN: u32 = mem::offset_of!(SyscallIo, len) as u32;
+    const FAILURE: u32 = mem::offset_of!(SyscallIo, failure) as u32;
+    const RESULT: u32 = mem::offset_of!(SyscallIo, result) as u32;
+    const BUFFER: u32 = mem::offset_of!(SyscallIo, buffer) as u32;
+
+    const READ_FAILED: u32 = 1;
+    const WRITE_FAILED: u32 = 2;
+
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(input_fd: i32, output_fd: i32) -> Box<Self> {
+        Box::new(SyscallIo {
+            len: 0,
+            failure: 0,
+            result: 0,
+            input_fd,
+            output_fd,
+            buffer: [0; OUTPUT_BUFFER_SIZE],
+        })
+    }
+
+    fn address(&self) -> u64 {
+        self as *const Self as u64
+    }
+
+    // Whatever ended the program early, once it's run.
+    pub fn result(&self) -> BfResult<()> {
+        let error = match (self.failure as u32, self.result) {
+            (0, _) => return Ok(()),
+            (Self::READ_FAILED, 0) => std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
+            (Self::WRITE_FAILED, 0) => std::io::Error::from(std::io::ErrorKind::WriteZero),
+            (_, result) => std::io::Error::from_raw_os_error(-result as i32),
+        };
+
+        Err(error.into())
+    }
+}
+
+// Reads into the current cell, after writing out any output so that it's seen
+// before the program waits on input.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn buffered_read(
+    assembler: &mut Assembler,
+    io: &SyscallIo,
+    flush: DynamicLabel,
+    error: DynamicLabel,
+) {
+    call_flush(assembler, flush, error);
+
+    let retry = assembler.new_dynamic_label();
+    let done = assembler.new_dynamic_label();
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        ; =>retry
+        ; mov eax, SYS_READ
+        ; mov edi, io.input_fd
+        ; mov rsi, reg_data_ptr
+        ; mov edx, 1
+        ; syscall
+        ; cmp rax, 1
+        ; je =>done
+        ; cmp rax, -EINTR
+        ; je =>retry
+        // Reinterpret as i64, using the same bytes as before.
+        ; mov reg_temp, QWORD io.address() as i64
+        ; mov [reg_temp + SyscallIo::RESULT as i32], rax
+        ; mov QWORD [reg_temp + SyscallIo::FAILURE as i32], SyscallIo::READ_FAILED as i32
+        ; jmp =>error
+        ; =>done
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; =>retry
+        ; movz x8, SYS_READ as u32
+        ; movz x0, io.input_fd as u32
+        ; mov x1, reg_data_ptr
+        ; movz x2, 1
+        ; svc 0
+        ; cmp x0, 1
+        ; b.eq =>done
+        ; cmn x0, EINTR as u32
+        ; b.eq =>retry
+        ;; mov_u64!(assembler, x11, io.address())
+        ; str x0, [x11, SyscallIo::RESULT]
+        ; movz x9, SyscallIo::READ_FAILED
+        ; str x9, [x11, SyscallIo::FAILURE]
+        ; b =>error
+        ; =>done
+    );
+}
+
+// Appends the current cell to the buffer, and writes the buffer out if that
+// fills it.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn buffered_write(
+    assembler: &mut Assembler,
+    io: &SyscallIo,
+    flush: DynamicLabel,
+    error: DynamicLabel,
+) {
+    let not_full = assembler.new_dynamic_label();
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        // Reinterpret as i64, using the same bytes as before.
+        ; mov reg_temp, QWORD io.address() as i64
+        ; mov rax, [reg_temp + SyscallIo::LEN as i32]
+        ; movzx ecx, BYTE [reg_data_ptr]
+        ; mov [reg_temp + rax + SyscallIo::BUFFER as i32], cl
+        ; add rax, 1
+        ; mov [reg_temp + SyscallIo::LEN as i32], rax
+        ; cmp rax, OUTPUT_BUFFER_SIZE as i32
+        ; jb =>not_full
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ;; mov_u64!(assembler, x11, io.address())
+        ; ldr x9, [x11, SyscallIo::LEN]
+        ; ldrb w10, [reg_data_ptr]
+        ; add x8, x11, x9
+        ; strb w10, [x8, SyscallIo::BUFFER]
+        ; add x9, x9, 1
+        ; str x9, [x11, SyscallIo::LEN]
+        ; movz x10, OUTPUT_BUFFER_SIZE as u32
+        ; cmp x9, x10
+        ; b.lo =>not_full
+    );
+    call_flush(assembler, flush, error);
+    dasm!(assembler
+        ; =>not_full
+    );
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn call_flush(assembler: &mut Assembler, flush: DynamicLabel, error: DynamicLabel) {
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        ; call =>flush
+        ; test eax, eax
+        ; jnz =>error
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; bl =>flush
+        ; cbnz x0, =>error
+    );
+}
+
+// Ends a program compiled with Io::Buffered, writing out whatever output is
+// left, followed by the routine which writes out the buffer. Every syscall that
+// fails jumps to `error` here, having recorded why.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn buffered_epilogue(
+    assembler: &mut Assembler,
+    io: &SyscallIo,
+    flush: DynamicLabel,
+    error: DynamicLabel,
+) {
+    call_flush(assembler, flush, error);
+    dasm!(assembler
+        ; =>error
+    );
+    epilogue(assembler);
+
+    // Writes until the whole buffer is written, and returns zero once it is.
+    // Nothing is kept on the stack, so the return address is all that's there.
+    let next = assembler.new_dynamic_label();
+    let done = assembler.new_dynamic_label();
+    let failed = assembler.new_dynamic_label();
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        ; =>flush
+        // Reinterpret as i64, using the same bytes as before.
+        ; mov reg_temp, QWORD io.address() as i64
+        ; mov rdx, [reg_temp + SyscallIo::LEN as i32]
+        ; lea rsi, [reg_temp + SyscallIo::BUFFER as i32]
+        ; =>next
+        ; test rdx, rdx
+        ; jz =>done
+        ; mov eax, SYS_WRITE
+        ; mov edi, io.output_fd
+        ; syscall
+        ; cmp rax, -EINTR
+        ; je =>next
+        ; test rax, rax
+        ; jle =>failed
+        ; add rsi, rax
+        ; sub rdx, rax
+        ; jmp =>next
+        ; =>done
+        ; mov QWORD [reg_temp + SyscallIo::LEN as i32], 0
+        ; xor eax, eax
+        ; ret
+        ; =>failed
+        ; mov [reg_temp + SyscallIo::RESULT as i32], rax
+        ; mov QWORD [reg_temp + SyscallIo::FAILURE as i32], SyscallIo::WRITE_FAILED as i32
+        ; mov eax, 1
+        ; ret
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; =>flush
+        ;; mov_u64!(assembler, x11, io.address())
+        ; ldr x2, [x11, SyscallIo::LEN]
+        ; add x1, x11, SyscallIo::BUFFER
+        ; =>next
+        ; cbz x2, =>done
+        ; movz x8, SYS_WRITE as u32
+        ; movz x0, io.output_fd as u32
+        ; svc 0
+        ; cmn x0, EINTR as u32
+        ; b.eq =>next
+        ; cmp x0, 0
+        ; b.le =>failed
+        ; add x1, x1, x0
+        ; sub x2, x2, x0
+        ; b =>next
+        ; =>done
+        ; str xzr, [x11, SyscallIo::LEN]
+        ; movz x0, 0
+        ; ret
+        ; =>failed
+        ; str x0, [x11, SyscallIo::RESULT]
+        ; movz x9, SyscallIo::WRITE_FAILED
+        ; str x9, [x11, SyscallIo::FAILURE]
+        ; movz x0, 1
+        ; ret
+    );
+}
+
+pub fn jump_begin(
+    assembler: &mut Assembler,
+    open_bracket_stack: &mut Vec<LabelPair>,
+    location: CellLocation,
+) {
     let begin_label = assembler.new_dynamic_label();
     let end_label = assembler.new_dynamic_label();
     open_bracket_stack.push(LabelPair {
@@ -296,16 +797,14 @@ pub fn jump_begin(assembler: &mut Assembler, open_bracket_stack: &mut Vec<LabelP
         end_label,
     });
 
+    test_cell(assembler, location);
     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
     dasm!(assembler
-        ; cmp BYTE [reg_data_ptr], 0
         ; jz =>end_label
         ; =>begin_label
     );
     #[cfg(target_arch = "aarch64")]
     dasm!(assembler
-        ; ldrb reg_temp_low, [reg_data_ptr]
-        ; cmp reg_temp_low, 0
         ; b.eq =>end_label
         ; =>begin_label
     );
@@ -314,6 +813,7 @@ pub fn jump_begin(assembler: &mut Assembler, open_bracket_stack: &mut Vec<LabelP
 pub fn jump_end(
     assembler: &mut Assembler,
     open_bracket_stack: &mut Vec<LabelPair>,
+    location: CellLocation,
     instruction_index: usize,
 ) -> BfResult<()> {
     let LabelPair {
@@ -325,16 +825,14 @@ pub fn jump_end(
         ))
     })?;
 
+    test_cell(assembler, location);
     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
     dasm!(assembler
-        ; cmp BYTE [reg_data_ptr], 0
         ; jnz =>begin_label
         ; =>end_label
     );
     #[cfg(target_arch = "aarch64")]
     dasm!(assembler
-        ; ldrb reg_temp_low, [reg_data_ptr]
-        ; cmp reg_temp_low, 0
         ; b.ne =>begin_label
         ; =>end_label
     );
@@ -342,6 +840,522 @@ pub fn jump_end(
     Ok(())
 }
 
+// A conditional is a loop whose body is known to run at most once, so unlike
+// jump_begin and jump_end there's no back edge, only a branch past the body.
+pub fn conditional_begin(
+    assembler: &mut Assembler,
+    open_conditional_stack: &mut Vec<DynamicLabel>,
+    location: CellLocation,
+) {
+    let end_label = assembler.new_dynamic_label();
+    open_conditional_stack.push(end_label);
+
+    test_cell(assembler, location);
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    dasm!(assembler
+        ; jz =>end_label
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; b.eq =>end_label
+    );
+}
+
+pub fn conditional_end(
+    assembler: &mut Assembler,
+    open_conditional_stack: &mut Vec<DynamicLabel>,
+    instruction_index: usize,
+) -> BfResult<()> {
+    let end_label = open_conditional_stack.pop().ok_or_else(|| {
+        BfError::Bf(format!(
+            "Unmatched conditional end at position {instruction_index}."
+        ))
+    })?;
+
+    dasm!(assembler
+        ; =>end_label
+    );
+
+    Ok(())
+}
+
+// Branches to exit_label unless the current cell is zero when expect_zero is
+// true, or nonzero when it's false.
+pub fn guard(assembler: &mut Assembler, expect_zero: bool, exit_label: DynamicLabel) {
+    test_cell(assembler, CellLocation::Memory);
+    if expect_zero {
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        dasm!(assembler
+            ; jnz =>exit_label
+        );
+        #[cfg(target_arch = "aarch64")]
+        dasm!(assembler
+            ; b.ne =>exit_label
+        );
+    } else {
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        dasm!(assembler
+            ; jz =>exit_label
+        );
+        #[cfg(target_arch = "aarch64")]
+        dasm!(assembler
+            ; b.eq =>exit_label
+        );
+    }
+}
+
+// Sets the zero flag if the current cell is zero.
+fn test_cell(assembler: &mut Assembler, location: CellLocation) {
+    match location {
+        CellLocation::Memory => {
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            dasm!(assembler
+                ; cmp BYTE [reg_data_ptr], 0
+            );
+            #[cfg(target_arch = "aarch64")]
+            dasm!(assembler
+                ; ldrb reg_temp_low, [reg_data_ptr]
+                ; cmp reg_temp_low, 0
+            );
+        }
+        CellLocation::Register => {
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            dasm!(assembler
+                ; test reg_cell, reg_cell
+            );
+            #[cfg(target_arch = "aarch64")]
+            dasm!(assembler
+                ; tst reg_cell, 0xff
+            );
+        }
+    }
+}
+
+pub fn jump(assembler: &mut Assembler, label: DynamicLabel) {
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    dasm!(assembler
+        ; jmp =>label
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; b =>label
+    );
+}
+
+// Stores a value at a fixed address, for compiled code to report which way it
+// left.
+pub fn store_u32(assembler: &mut Assembler, address: *mut u32, value: u32) {
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        // Reinterpret as i64 and i32, using the same bytes as before.
+        ; mov reg_temp, QWORD address as i64
+        ; mov DWORD [reg_temp], value as i32
+    );
+    #[cfg(target_arch = "x86")]
+    dasm!(assembler
+        // Reinterpret as i32, using the same bytes as before.
+        ; mov reg_temp, DWORD address as i32
+        ; mov DWORD [reg_temp], value as i32
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ;; mov_u64!(assembler, reg_temp3, address as u64)
+        ;; mov_u64!(assembler, reg_temp, value.into())
+        ; str reg_temp_low, [reg_temp3]
+    );
+}
+
+// Calls code compiled with loop_prologue and loop_epilogue from the current
+// data pointer, and carries on from wherever it leaves the data pointer. The
+// address called is a pointer-sized word in the code, which starts out as zero
+// and must be filled in with CompiledProgram::patch before it's reached. If
+// the call returns a null data pointer, jumps to `failed` instead. Returns the
+// offset of that word.
+pub fn patchable_loop_call(assembler: &mut Assembler, failed: DynamicLabel) -> AssemblyOffset {
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        ; mov reg_arg1, reg_data_ptr
+        ; mov reg_temp, QWORD 0
+    );
+    #[cfg(target_arch = "x86")]
+    dasm!(assembler
+        ; mov reg_arg1, reg_data_ptr
+        ; mov reg_temp, DWORD 0
+    );
+    // There's no instruction taking the whole address as an immediate, so it's
+    // loaded from a literal placed in between.
+    #[cfg(target_arch = "aarch64")]
+    let call = {
+        let literal = assembler.new_dynamic_label();
+        let call = assembler.new_dynamic_label();
+        dasm!(assembler
+            ; mov reg_arg1, reg_data_ptr
+            ; ldr reg_temp, =>literal
+            ; b =>call
+            ; =>literal
+            ; .u64 0
+        );
+        call
+    };
+    let target = AssemblyOffset(assembler.offset().0 - mem::size_of::<usize>());
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    dasm!(assembler
+        ; call reg_temp
+        ; mov reg_data_ptr, reg_return_ptr
+        ; test reg_data_ptr, reg_data_ptr
+        ; jz =>failed
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; =>call
+        ; blr reg_temp
+        ; mov reg_data_ptr, reg_return_ptr
+        ; cbz reg_data_ptr, =>failed
+    );
+
+    target
+}
+
+// Stands in for code compiled with loop_prologue and loop_epilogue, by calling
+// function(argument, data pointer) and returning whatever it returns as the
+// new data pointer. function has to follow the calling convention the code
+// would have.
+pub fn loop_stub(assembler: &mut Assembler, argument: *const (), function: *const ()) {
+    // The function returns straight to whoever called the stub.
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        // Reinterpret as i64, using the same bytes as before.
+        ; mov reg_arg2, reg_arg1
+        ; mov reg_arg1, QWORD argument as i64
+        ; mov reg_temp, QWORD function as i64
+        ; jmp reg_temp
+    );
+    #[cfg(target_arch = "x86")]
+    dasm!(assembler
+        // Reinterpret as i32, using the same bytes as before.
+        ; mov reg_arg2, reg_arg1
+        ; mov reg_arg1, DWORD argument as i32
+        ; mov reg_temp, DWORD function as i32
+        ; jmp reg_temp
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        // Reinterpret as u64, using the same bytes as before.
+        ; mov reg_arg2, reg_arg1
+        ;; mov_u64!(assembler, reg_arg1, argument as u64)
+        ;; mov_u64!(assembler, reg_temp, function as u64)
+        ; br reg_temp
+    );
+}
+
+// Jumps to `fail` unless the cell `offset` cells from the data pointer is one
+// of the MEMORY_SIZE cells starting at `memory`, as returned by
+// Runtime::memory_ptr. Only reg_temp is clobbered, along with reg_temp3 on
+// aarch64.
+pub fn bounds_check(assembler: &mut Assembler, memory: *const u8, offset: i64, fail: DynamicLabel) {
+    // One unsigned comparison of how far the cell is from the start of memory
+    // catches it being either side.
+    let displacement = offset.wrapping_sub(memory as i64);
+    #[cfg(target_arch = "x86_64")]
+    dasm!(assembler
+        ; mov reg_temp, QWORD displacement
+        ; add reg_temp, reg_data_ptr
+        ; cmp reg_temp, MEMORY_SIZE as i32
+        ; jae =>fail
+    );
+    #[cfg(target_arch = "x86")]
+    dasm!(assembler
+        // Truncate to i32, which is all an address takes up.
+        ; mov reg_temp, DWORD displacement as i32
+        ; add reg_temp, reg_data_ptr
+        ; cmp reg_temp, MEMORY_SIZE as i32
+        ; jae =>fail
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        // Reinterpret as u64, using the same bytes as before.
+        ;; mov_u64!(assembler, reg_temp, displacement as u64)
+        ; add reg_temp, reg_temp, reg_data_ptr
+        ; movz reg_temp3, MEMORY_SIZE as u32
+        ; cmp reg_temp, reg_temp3
+        ; b.hs =>fail
+    );
+}
+
+// The vectors scans compare cells with. Code run in this process can use the
+// widest the host supports, but code written out to run on other machines has
+// to stick to what every CPU of the architecture has.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VectorWidth {
+    // 16 bytes, with SSE2 on x86 and NEON on aarch64.
+    Baseline,
+    // 32 bytes, with AVX2. Only used on x86.
+    Avx2,
+}
+
+impl VectorWidth {
+    // The widest vectors the machine this is running on supports.
+    pub fn host() -> Self {
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        if is_x86_feature_detected!("avx2") {
+            return VectorWidth::Avx2;
+        }
+
+        VectorWidth::Baseline
+    }
+}
+
+pub fn move_ptr_until_zero(
+    assembler: &mut Assembler,
+    forward: bool,
+    amount: u32,
+    width: VectorWidth,
+) {
+    if is_vectorizable_stride(amount as usize) {
+        vector_move_ptr_until_zero(assembler, forward, amount, width);
+        return;
+    }
+
+    scalar_move_ptr_until_zero(assembler, forward, amount, None);
+}
+
+// Like move_ptr_until_zero, but checks the data pointer is still in memory
+// after every step, and jumps to `fail` as soon as it isn't. See bounds_check.
+// Scans always go one step at a time, since vector loads can reach past the
+// cells they visit.
+pub fn checked_move_ptr_until_zero(
+    assembler: &mut Assembler,
+    forward: bool,
+    amount: u32,
+    memory: *const u8,
+    fail: DynamicLabel,
+) {
+    scalar_move_ptr_until_zero(assembler, forward, amount, Some((memory, fail)));
+}
+
+// Scans one step at a time, which beats setting up vector loads for scans
+// which only go a few cells. With `check`, the data pointer is checked after
+// every step, as in checked_move_ptr_until_zero.
+pub fn scalar_move_ptr_until_zero(
+    assembler: &mut Assembler,
+    forward: bool,
+    amount: u32,
+    check: Option<(*const u8, DynamicLabel)>,
+) {
+    let begin_loop = assembler.new_dynamic_label();
+    let end_loop = assembler.new_dynamic_label();
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    dasm!(assembler
+        ; =>begin_loop
+        ; cmp BYTE [reg_data_ptr], 0
+        ; jz =>end_loop
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; =>begin_loop
+        ; ldrb reg_temp_low, [reg_data_ptr]
+        ; cmp reg_temp_low, 0
+        ; b.eq =>end_loop
+    );
+
+    if forward {
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        dasm!(assembler
+            // Reinterpret as i32, using the same bytes as before.
+            ; add reg_data_ptr, DWORD amount as i32
+        );
+        #[cfg(target_arch = "aarch64")]
+        add_sub_u64!(assembler, add, reg_data_ptr, reg_data_ptr, amount.into());
+    } else {
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        dasm!(assembler
+            // Reinterpret as i32, using the same bytes as before.
+            ; sub reg_data_ptr, DWORD amount as i32
+        );
+        #[cfg(target_arch = "aarch64")]
+        add_sub_u64!(assembler, sub, reg_data_ptr, reg_data_ptr, amount.into());
+    }
+    if let Some((memory, fail)) = check {
+        bounds_check(assembler, memory, 0, fail);
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    dasm!(assembler
+        ; jmp =>begin_loop
+        ; =>end_loop
+    );
+    #[cfg(target_arch = "aarch64")]
+    dasm!(assembler
+        ; b =>begin_loop
+        ; =>end_loop
+    );
+}
+
+// Compares a whole vector of cells against zero at a time, masking out the
+// lanes the stride skips over. Forward scans load the vector starting at the
+// data pointer and backward scans load the vector ending at it, so loads can
+// reach up to a vector's width outside of the cells actually visited. That's
+// what MEMORY_PADDING is for.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn vector_move_ptr_until_zero(
+    assembler: &mut Assembler,
+    forward: bool,
+    amount: u32,
+    width: VectorWidth,
+) {
+    let use_avx2 = width == VectorWidth::Avx2;
+    let lanes: i32 = if use_avx2 { 32 } else { 16 };
+    // Reinterpret as i32, using the same bytes as before.
+    let lane_mask = lane_mask(lanes as usize, forward, amount as usize) as i32;
+    let load_offset = if forward { 0 } else { 1 - lanes };
+
+    let begin_loop = assembler.new_dynamic_label();
+    let found_zero = assembler.new_dynamic_label();
+    let end_loop = assembler.new_dynamic_label();
+
+    dasm!(assembler
+        ; cmp BYTE [reg_data_ptr], 0
+        ; jz =>end_loop
+    );
+
+    if use_avx2 {
+        dasm!(assembler
+            ; vpxor ymm1, ymm1, ymm1
+            ; =>begin_loop
+            ; vmovdqu ymm0, [reg_data_ptr + load_offset]
+            ; vpcmpeqb ymm0, ymm0, ymm1
+        );
+        // dynasm doesn't support vpmovmskb with a ymm operand, so encode
+        // `vpmovmskb reg_temp_dword, ymm0` by hand.
+        #[cfg(target_arch = "x86_64")]
+        dasm!(assembler
+            ; .bytes [0xc5, 0x7d, 0xd7, 0xc0]
+        );
+        #[cfg(target_arch = "x86")]
+        dasm!(assembler
+            ; .bytes [0xc5, 0xfd, 0xd7, 0xc0]
+        );
+    } else {
+        dasm!(assembler
+            ; pxor xmm1, xmm1
+            ; =>begin_loop
+            ; movdqu xmm0, [reg_data_ptr + load_offset]
+            ; pcmpeqb xmm0, xmm1
+            ; pmovmskb reg_temp_dword, xmm0
+        );
+    }
+
+    dasm!(assembler
+        ; and reg_temp_dword, DWORD lane_mask
+        ; jnz =>found_zero
+    );
+
+    if forward {
+        dasm!(assembler
+            ; add reg_data_ptr, lanes
+            ; jmp =>begin_loop
+            ; =>found_zero
+            ; bsf reg_temp_dword, reg_temp_dword
+            ; add reg_data_ptr, reg_temp
+        );
+    } else {
+        dasm!(assembler
+            ; sub reg_data_ptr, lanes
+            ; jmp =>begin_loop
+            ; =>found_zero
+            ; bsr reg_temp_dword, reg_temp_dword
+            ; add reg_data_ptr, reg_temp
+            ; add reg_data_ptr, load_offset
+        );
+    }
+
+    dasm!(assembler
+        ; =>end_loop
+    );
+
+    // Avoid the penalty for mixing AVX and SSE code in anything we call
+    // afterwards.
+    if use_avx2 {
+        dasm!(assembler
+            ; vzeroupper
+        );
+    }
+}
+
+// NEON has no equivalent of movemask. Instead we narrow the comparison result
+// so that each lane becomes a nibble of a 64-bit general purpose register, and
+// then count trailing or leading zeros to find the first zero cell.
+#[cfg(target_arch = "aarch64")]
+fn vector_move_ptr_until_zero(
+    assembler: &mut Assembler,
+    forward: bool,
+    amount: u32,
+    _width: VectorWidth,
+) {
+    const LANES: u32 = 16;
+    let lane_mask = lane_mask(LANES as usize, forward, amount as usize);
+    let nibble_mask = (0..LANES)
+        .filter(|lane| lane_mask & (1 << lane) != 0)
+        .fold(0_u64, |mask, lane| mask | (0xf << (lane * 4)));
+
+    let begin_loop = assembler.new_dynamic_label();
+    let found_zero = assembler.new_dynamic_label();
+    let end_loop = assembler.new_dynamic_label();
+
+    dasm!(assembler
+        ; ldrb reg_temp_low, [reg_data_ptr]
+        ; cmp reg_temp_low, 0
+        ; b.eq =>end_loop
+        ;; mov_u64!(assembler, reg_temp3, nibble_mask)
+        ; =>begin_loop
+    );
+
+    if forward {
+        dasm!(assembler
+            ; ld1 {v0.b16}, [reg_data_ptr]
+        );
+    } else {
+        dasm!(assembler
+            ; sub reg_temp, reg_data_ptr, LANES - 1
+            ; ld1 {v0.b16}, [reg_temp]
+        );
+    }
+
+    dasm!(assembler
+        ; cmeq v0.b16, v0.b16, 0
+        ; shrn v0.b8, v0.h8, 4
+        ; fmov reg_temp, d0
+        ; ands reg_temp, reg_temp, reg_temp3
+        ; b.ne =>found_zero
+    );
+
+    if forward {
+        dasm!(assembler
+            ; add reg_data_ptr, reg_data_ptr, LANES
+            ; b =>begin_loop
+            ; =>found_zero
+            ; rbit reg_temp, reg_temp
+            ; clz reg_temp, reg_temp
+            ; add reg_data_ptr, reg_data_ptr, reg_temp, lsr 2
+        );
+    } else {
+        // The highest set nibble is lane 15 - clz / 4 of the vector, which
+        // started 15 cells before the data pointer.
+        dasm!(assembler
+            ; sub reg_data_ptr, reg_data_ptr, LANES
+            ; b =>begin_loop
+            ; =>found_zero
+            ; clz reg_temp, reg_temp
+            ; sub reg_data_ptr, reg_data_ptr, reg_temp, lsr 2
+        );
+    }
+
+    dasm!(assembler
+        ; =>end_loop
+    );
+}
+
 pub struct CompiledProgram {
     buffer: ExecutableBuffer,
     start: AssemblyOffset,
@@ -355,6 +1369,69 @@ impl CompiledProgram {
     pub fn function_ptr(&self) -> *const () {
         self.buffer.ptr(self.start) as *const ()
     }
+
+    // The whole buffer, which is mapped at address().
+    pub fn code(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn address(&self) -> u64 {
+        self.buffer.ptr(AssemblyOffset(0)) as u64
+    }
+
+    // Overwrites part of the code in place, such as the word a
+    // patchable_loop_call calls. The buffer is only writable while this
+    // happens, and stays at the same address, so the code can be running
+    // further up the stack but not on another thread. Changing the buffer's
+    // protection gives it up if it fails, and the code running further up the
+    // stack would then be unmapped, so this aborts instead.
+    pub fn patch(&mut self, offset: AssemblyOffset, bytes: &[u8]) {
+        let mut buffer = mem::take(&mut self.buffer)
+            .make_mut()
+            .unwrap_or_else(|error| abort_patch(&error));
+        buffer[offset.0..offset.0 + bytes.len()].copy_from_slice(bytes);
+        self.buffer = buffer
+            .make_exec()
+            .unwrap_or_else(|error| abort_patch(&error));
+    }
+}
+
+fn abort_patch(error: &io::Error) -> ! {
+    eprintln!("Failed to patch compiled code while it was running: {error}");
+    process::abort()
+}
+
+pub struct StandaloneProgram {
+    code: Vec<u8>,
+    tape_displacement: AssemblyOffset,
+}
+
+impl StandaloneProgram {
+    pub fn new(buffer: ExecutableBuffer, tape_displacement: AssemblyOffset) -> Self {
+        StandaloneProgram {
+            code: buffer.to_vec(),
+            tape_displacement,
+        }
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    // Returns the code ready to be loaded with a TAPE_SIZE region of zeroes
+    // `tape_offset` bytes after its start.
+    pub fn link(&self, tape_offset: usize) -> BfResult<Vec<u8>> {
+        let end = self.tape_displacement.0;
+        // The displacement is relative to the end of the instruction, and the
+        // data pointer starts past the padding.
+        let displacement =
+            i32::try_from(i64::try_from(tape_offset + MEMORY_PADDING)? - i64::try_from(end)?)?;
+
+        let mut code = self.code.clone();
+        code[end - 4..end].copy_from_slice(&displacement.to_le_bytes());
+
+        Ok(code)
+    }
 }
 
 #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
@@ -362,10 +1439,45 @@ type AsmEntryPoint = extern "C" fn();
 #[cfg(target_arch = "x86")]
 type AsmEntryPoint = extern "fastcall" fn();
 
-const MEMORY_SIZE: usize = 30000;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+type AsmLoopEntryPoint = extern "C" fn(*mut u8) -> *mut u8;
+#[cfg(target_arch = "x86")]
+type AsmLoopEntryPoint = extern "fastcall" fn(*mut u8) -> *mut u8;
+
+// The number of cells in a Runtime's memory.
+pub const MEMORY_SIZE: usize = 30000;
+// Vectorized scans may load up to a vector's width of bytes on either side of
+// the cells they visit, so keep some zeroed padding around the tape.
+pub const MEMORY_PADDING: usize = 32;
+// The size of the tape along with its padding, for code which sets up its own.
+pub const TAPE_SIZE: usize = MEMORY_SIZE + 2 * MEMORY_PADDING;
+
+// Holds the Runtime which code compiled with Io::Slot calls back into while it
+// runs, and nothing otherwise. The code refers to the slot by its address, so
+// it's always boxed to keep it in one place.
+pub struct RuntimeSlot {
+    runtime: *const (),
+}
+
+// The slot only holds a Runtime for the duration of Runtime::run_in_slot, which
+// borrows the slot mutably, so moving it to another thread in between is fine.
+unsafe impl Send for RuntimeSlot {}
+
+impl RuntimeSlot {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> Box<Self> {
+        Box::new(RuntimeSlot {
+            runtime: ptr::null(),
+        })
+    }
+
+    fn address(&self) -> usize {
+        &self.runtime as *const *const () as usize
+    }
+}
 
 pub struct Runtime<'a> {
-    memory: [u8; MEMORY_SIZE],
+    memory: [u8; TAPE_SIZE],
     stdin: &'a mut dyn Read,
     stdout: &'a mut dyn Write,
 }
@@ -373,14 +1485,22 @@ pub struct Runtime<'a> {
 impl<'a> Runtime<'a> {
     pub fn new(stdin: &'a mut dyn Read, stdout: &'a mut dyn Write) -> Self {
         Self {
-            memory: [0; MEMORY_SIZE],
+            memory: [0; TAPE_SIZE],
             stdin,
             stdout,
         }
     }
 
     pub fn memory_ptr(&mut self) -> *mut u8 {
-        self.memory.as_mut_ptr()
+        self.memory[MEMORY_PADDING..].as_mut_ptr()
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[MEMORY_PADDING..MEMORY_PADDING + MEMORY_SIZE]
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory[MEMORY_PADDING..MEMORY_PADDING + MEMORY_SIZE]
     }
 
     pub fn run(&self, compiled_program: CompiledProgram) -> BfResult<()> {
@@ -392,16 +1512,47 @@ impl<'a> Runtime<'a> {
         Ok(())
     }
 
-    fn read_inner(&mut self) -> u8 {
+    // Runs code compiled with loop_prologue and loop_epilogue starting from the
+    // given cell, and returns the cell it finished on.
+    pub fn run_loop(&mut self, compiled_loop: &CompiledProgram, data_pointer: usize) -> usize {
+        let entry_point_pointer = compiled_loop.function_ptr();
+        let entry_point =
+            unsafe { mem::transmute::<*const (), AsmLoopEntryPoint>(entry_point_pointer) };
+
+        let memory = self.memory_ptr();
+        let end = entry_point(memory.wrapping_add(data_pointer));
+        (end as usize).wrapping_sub(memory as usize)
+    }
+
+    // Runs code compiled with loop_prologue, loop_epilogue and Io::Slot(slot)
+    // from the start of memory, with the slot holding this runtime until the
+    // code returns.
+    pub fn run_in_slot(&mut self, compiled_program: &CompiledProgram, slot: &mut RuntimeSlot) {
+        slot.runtime = self as *const Self as *const ();
+        self.run_loop(compiled_program, 0);
+        slot.runtime = ptr::null();
+    }
+
+    pub fn read_byte(&mut self) -> BfResult<u8> {
         let mut c = [0; 1];
-        self.stdin.read_exact(&mut c).unwrap();
+        self.stdin.read_exact(&mut c)?;
 
-        c[0]
+        Ok(c[0])
+    }
+
+    pub fn write_byte(&mut self, byte: u8) -> BfResult<()> {
+        self.stdout.write_all(&[byte])?;
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    fn read_inner(&mut self) -> u8 {
+        self.read_byte().unwrap()
     }
 
     fn write_inner(&mut self, byte: u8) {
-        self.stdout.write_all(&[byte]).unwrap();
-        self.stdout.flush().unwrap();
+        self.write_byte(byte).unwrap()
     }
 
     #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
@@ -0,0 +1,801 @@
+use {
+    crate::{asm::CompiledProgram, error::BfResult, perf::Symbol},
+    dynasmrt::AssemblyOffset,
+    std::{path::Path, ptr, sync::Mutex},
+};
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+use crate::error::BfError;
+
+// GDB's JIT interface, as described in the "JIT Compilation Interface" section
+// of the GDB manual. GDB sets a breakpoint on __jit_debug_register_code, and
+// when it's hit, reads the object file described by the relevant entry of
+// __jit_debug_descriptor as if it had been loaded from disk.
+#[repr(C)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+#[repr(C)]
+pub struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+// GDB finds both of these by name.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: 0,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn __jit_debug_register_code() {
+    // An empty asm! block counts as having side effects, so calls to this
+    // aren't optimized away.
+    unsafe { std::arch::asm!("") };
+}
+
+// Guards the list of entries, which GDB only reads while we're stopped in
+// __jit_debug_register_code.
+static ENTRIES: Mutex<()> = Mutex::new(());
+
+// Keeps a program registered with GDB until it's dropped.
+pub struct Registration {
+    entry: *mut JitCodeEntry,
+    // GDB reads the object file from here, so it has to outlive the entry.
+    _object: Box<[u8]>,
+}
+
+impl Registration {
+    fn new(object: Box<[u8]>) -> Self {
+        let entry = Box::into_raw(Box::new(JitCodeEntry {
+            next_entry: ptr::null_mut(),
+            prev_entry: ptr::null_mut(),
+            symfile_addr: object.as_ptr(),
+            symfile_size: object.len() as u64,
+        }));
+
+        let _lock = ENTRIES.lock().unwrap();
+        unsafe {
+            let descriptor = &raw mut __jit_debug_descriptor;
+            let next = (*descriptor).first_entry;
+            (*entry).next_entry = next;
+            if !next.is_null() {
+                (*next).prev_entry = entry;
+            }
+            (*descriptor).first_entry = entry;
+            (*descriptor).relevant_entry = entry;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+        }
+        __jit_debug_register_code();
+
+        Registration {
+            entry,
+            _object: object,
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _lock = ENTRIES.lock().unwrap();
+        unsafe {
+            let descriptor = &raw mut __jit_debug_descriptor;
+            let entry = self.entry;
+            let (prev, next) = ((*entry).prev_entry, (*entry).next_entry);
+            if prev.is_null() {
+                (*descriptor).first_entry = next;
+            } else {
+                (*prev).next_entry = next;
+            }
+            if !next.is_null() {
+                (*next).prev_entry = prev;
+            }
+            (*descriptor).relevant_entry = entry;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+
+            drop(Box::from_raw(entry));
+        }
+    }
+}
+
+// Registers a compiled program with GDB, so that breakpoints can be set on
+// lines of its source file and backtraces through it show which loop it's in.
+// The symbols are the ones perf::loop_symbols gives, lines holds where the code
+// for each part of the source starts, and the code for the source ends where
+// the epilogue starts.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn register(
+    source_path: &Path,
+    source_code: &str,
+    program: &CompiledProgram,
+    symbols: &[Symbol],
+    lines: &[(AssemblyOffset, usize)],
+    epilogue: AssemblyOffset,
+) -> BfResult<Registration> {
+    let object = object::write(
+        source_path,
+        source_code,
+        program.address(),
+        program.code().len(),
+        symbols,
+        lines,
+        epilogue,
+    );
+    Ok(Registration::new(object.into_boxed_slice()))
+}
+
+// The object file describes how to unwind the code, which is only done for
+// x86-64.
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+pub fn register(
+    _source_path: &Path,
+    _source_code: &str,
+    _program: &CompiledProgram,
+    _symbols: &[Symbol],
+    _lines: &[(AssemblyOffset, usize)],
+    _epilogue: AssemblyOffset,
+) -> BfResult<Registration> {
+    Err(BfError::Bf(
+        "Registering with GDB is only supported on x86-64 Linux.".to_owned(),
+    ))
+}
+
+// Writes a relocatable ELF object whose .text section is placed where the code
+// already is. It has no contents, since GDB reads the code from memory. The
+// symbols go in .symtab, and the DWARF sections describe the source file, where
+// the code for each line is, and how to unwind it.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod object {
+    use {
+        crate::perf::{line_column, Symbol},
+        dynasmrt::AssemblyOffset,
+        std::path::Path,
+    };
+
+    const ELF_HEADER_SIZE: u16 = 64;
+    const SECTION_HEADER_SIZE: u16 = 64;
+    const SYMBOL_SIZE: u64 = 24;
+
+    const ELFCLASS64: u8 = 2;
+    const ELFDATA2LSB: u8 = 1;
+    const EV_CURRENT: u8 = 1;
+    const ELFOSABI_SYSV: u8 = 0;
+    const ET_REL: u16 = 1;
+    const EM_X86_64: u16 = 62;
+
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_SYMTAB: u32 = 2;
+    const SHT_STRTAB: u32 = 3;
+    const SHT_NOBITS: u32 = 8;
+    const SHF_ALLOC: u64 = 2;
+    const SHF_EXECINSTR: u64 = 4;
+
+    const STB_LOCAL: u8 = 0;
+    const STT_FUNC: u8 = 2;
+    const STT_FILE: u8 = 4;
+    const SHN_ABS: u16 = 0xfff1;
+
+    // The index of each section.
+    const TEXT: u16 = 1;
+    const STRTAB: u16 = 3;
+    const SHSTRTAB: u16 = 4;
+
+    const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+    const DW_TAG_SUBPROGRAM: u8 = 0x2e;
+    const DW_CHILDREN_NO: u8 = 0;
+    const DW_CHILDREN_YES: u8 = 1;
+    const DW_AT_NAME: u8 = 0x03;
+    const DW_AT_STMT_LIST: u8 = 0x10;
+    const DW_AT_LOW_PC: u8 = 0x11;
+    const DW_AT_HIGH_PC: u8 = 0x12;
+    const DW_FORM_ADDR: u8 = 0x01;
+    const DW_FORM_DATA4: u8 = 0x06;
+    const DW_FORM_STRING: u8 = 0x08;
+
+    const DW_LNS_COPY: u8 = 1;
+    const DW_LNS_ADVANCE_PC: u8 = 2;
+    const DW_LNS_ADVANCE_LINE: u8 = 3;
+    const DW_LNS_SET_COLUMN: u8 = 5;
+    const DW_LNE_END_SEQUENCE: u8 = 1;
+    const DW_LNE_SET_ADDRESS: u8 = 2;
+    // Only the standard opcodes are used, so the special opcode parameters are
+    // just the usual ones.
+    const LINE_BASE: i8 = -5;
+    const LINE_RANGE: u8 = 14;
+    const OPCODE_BASE: u8 = 13;
+    const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+    const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+    const DW_CFA_OFFSET: u8 = 0x80;
+    const DW_CFA_RESTORE: u8 = 0xc0;
+    const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+    const DW_CFA_DEF_CFA: u8 = 0x0c;
+    const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+    const CIE_ID: u32 = 0xffff_ffff;
+    // DWARF's numbers for rsp, r13, and the return address.
+    const RSP: u8 = 7;
+    const R13: u8 = 13;
+    const RETURN_ADDRESS: u8 = 16;
+    // asm::save_registers starts the code by pushing r13 with a two byte
+    // instruction, and asm::epilogue pops it the same way before returning.
+    const PUSH_SIZE: usize = 2;
+
+    struct Section {
+        name: &'static str,
+        section_type: u32,
+        flags: u64,
+        address: u64,
+        contents: Vec<u8>,
+        // Only differs from the length of the contents for .text.
+        size: u64,
+        link: u32,
+        info: u32,
+        alignment: u64,
+        entry_size: u64,
+    }
+
+    impl Section {
+        fn new(name: &'static str, section_type: u32, contents: Vec<u8>) -> Self {
+            Section {
+                name,
+                section_type,
+                flags: 0,
+                address: 0,
+                size: contents.len() as u64,
+                contents,
+                link: 0,
+                info: 0,
+                alignment: 1,
+                entry_size: 0,
+            }
+        }
+    }
+
+    pub fn write(
+        source_path: &Path,
+        source_code: &str,
+        address: u64,
+        code_len: usize,
+        symbols: &[Symbol],
+        lines: &[(AssemblyOffset, usize)],
+        epilogue: AssemblyOffset,
+    ) -> Vec<u8> {
+        let source_path = source_path.to_string_lossy();
+        let (symtab, strtab) = symbol_table(&source_path, symbols);
+        let symbol_count = symtab.len() as u64 / SYMBOL_SIZE;
+
+        let sections = [
+            Section::new("", 0, vec![]),
+            Section {
+                flags: SHF_ALLOC | SHF_EXECINSTR,
+                address,
+                size: code_len as u64,
+                alignment: 16,
+                ..Section::new(".text", SHT_NOBITS, vec![])
+            },
+            Section {
+                link: STRTAB.into(),
+                // Every symbol is local.
+                info: symbol_count as u32,
+                alignment: 8,
+                entry_size: SYMBOL_SIZE,
+                ..Section::new(".symtab", SHT_SYMTAB, symtab)
+            },
+            Section::new(".strtab", SHT_STRTAB, strtab),
+            Section::new(".shstrtab", SHT_STRTAB, vec![]),
+            Section::new(
+                ".debug_info",
+                SHT_PROGBITS,
+                debug_info(&source_path, address, code_len, symbols),
+            ),
+            Section::new(".debug_abbrev", SHT_PROGBITS, debug_abbrev()),
+            Section::new(
+                ".debug_line",
+                SHT_PROGBITS,
+                debug_line(&source_path, source_code, address, lines, epilogue),
+            ),
+            Section::new(
+                ".debug_frame",
+                SHT_PROGBITS,
+                debug_frame(address, code_len, epilogue),
+            ),
+        ];
+
+        let mut shstrtab = vec![];
+        let mut names = vec![];
+        for section in &sections {
+            names.push(shstrtab.len() as u32);
+            shstrtab.extend_from_slice(section.name.as_bytes());
+            shstrtab.push(0);
+        }
+
+        let mut image = vec![0; ELF_HEADER_SIZE.into()];
+        let mut offsets = vec![];
+        for section in &sections {
+            let contents = if section.name == ".shstrtab" {
+                &shstrtab
+            } else {
+                &section.contents
+            };
+            image.resize(image.len().next_multiple_of(8), 0);
+            offsets.push(image.len() as u64);
+            image.extend_from_slice(contents);
+        }
+        image.resize(image.len().next_multiple_of(8), 0);
+        let section_headers_offset = image.len() as u64;
+
+        for ((section, name), offset) in sections.iter().zip(names).zip(offsets) {
+            let size = if section.name == ".shstrtab" {
+                shstrtab.len() as u64
+            } else {
+                section.size
+            };
+            image.extend_from_slice(&name.to_le_bytes());
+            image.extend_from_slice(&section.section_type.to_le_bytes());
+            image.extend_from_slice(&section.flags.to_le_bytes());
+            image.extend_from_slice(&section.address.to_le_bytes());
+            image.extend_from_slice(&offset.to_le_bytes());
+            image.extend_from_slice(&size.to_le_bytes());
+            image.extend_from_slice(&section.link.to_le_bytes());
+            image.extend_from_slice(&section.info.to_le_bytes());
+            image.extend_from_slice(&section.alignment.to_le_bytes());
+            image.extend_from_slice(&section.entry_size.to_le_bytes());
+        }
+
+        let mut header = vec![];
+        header.extend_from_slice(b"\x7fELF");
+        header.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_SYSV]);
+        header.resize(16, 0);
+        header.extend_from_slice(&ET_REL.to_le_bytes());
+        header.extend_from_slice(&EM_X86_64.to_le_bytes());
+        header.extend_from_slice(&u32::from(EV_CURRENT).to_le_bytes());
+        // No entry point or program headers.
+        header.extend_from_slice(&0_u64.to_le_bytes());
+        header.extend_from_slice(&0_u64.to_le_bytes());
+        header.extend_from_slice(&section_headers_offset.to_le_bytes());
+        // No flags.
+        header.extend_from_slice(&0_u32.to_le_bytes());
+        header.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+        header.extend_from_slice(&0_u16.to_le_bytes());
+        header.extend_from_slice(&0_u16.to_le_bytes());
+        header.extend_from_slice(&SECTION_HEADER_SIZE.to_le_bytes());
+        header.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+        header.extend_from_slice(&SHSTRTAB.to_le_bytes());
+        image[..header.len()].copy_from_slice(&header);
+
+        image
+    }
+
+    // A file symbol followed by a function symbol for each of the symbols, whose
+    // values are offsets into .text.
+    fn symbol_table(source_path: &str, symbols: &[Symbol]) -> (Vec<u8>, Vec<u8>) {
+        let mut symtab = vec![0; SYMBOL_SIZE as usize];
+        let mut strtab = vec![0];
+
+        let mut push = |name: &str, info: u8, section: u16, value: u64, size: u64| {
+            symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            symtab.push(info);
+            // Default visibility.
+            symtab.push(0);
+            symtab.extend_from_slice(&section.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+        };
+
+        push(source_path, STB_LOCAL << 4 | STT_FILE, SHN_ABS, 0, 0);
+        for symbol in symbols {
+            push(
+                &symbol.name,
+                STB_LOCAL << 4 | STT_FUNC,
+                TEXT,
+                symbol.start as u64,
+                (symbol.end - symbol.start) as u64,
+            );
+        }
+
+        (symtab, strtab)
+    }
+
+    fn debug_abbrev() -> Vec<u8> {
+        vec![
+            1,
+            DW_TAG_COMPILE_UNIT,
+            DW_CHILDREN_YES,
+            DW_AT_NAME,
+            DW_FORM_STRING,
+            DW_AT_LOW_PC,
+            DW_FORM_ADDR,
+            DW_AT_HIGH_PC,
+            DW_FORM_ADDR,
+            DW_AT_STMT_LIST,
+            DW_FORM_DATA4,
+            0,
+            0,
+            2,
+            DW_TAG_SUBPROGRAM,
+            DW_CHILDREN_NO,
+            DW_AT_NAME,
+            DW_FORM_STRING,
+            DW_AT_LOW_PC,
+            DW_FORM_ADDR,
+            DW_AT_HIGH_PC,
+            DW_FORM_ADDR,
+            0,
+            0,
+            0,
+        ]
+    }
+
+    // A DWARF 2 compilation unit for the whole program, with a subprogram for
+    // each symbol so that backtraces name the loop being run.
+    fn debug_info(source_path: &str, address: u64, code_len: usize, symbols: &[Symbol]) -> Vec<u8> {
+        let mut unit = vec![];
+        // The version, where the abbreviations start, and the size of an
+        // address.
+        unit.extend_from_slice(&2_u16.to_le_bytes());
+        unit.extend_from_slice(&0_u32.to_le_bytes());
+        unit.push(8);
+
+        unit.push(1);
+        push_string(&mut unit, source_path);
+        unit.extend_from_slice(&address.to_le_bytes());
+        unit.extend_from_slice(&(address + code_len as u64).to_le_bytes());
+        // The line table is the only one in .debug_line.
+        unit.extend_from_slice(&0_u32.to_le_bytes());
+
+        for symbol in symbols {
+            unit.push(2);
+            push_string(&mut unit, &symbol.name);
+            unit.extend_from_slice(&(address + symbol.start as u64).to_le_bytes());
+            unit.extend_from_slice(&(address + symbol.end as u64).to_le_bytes());
+        }
+        unit.push(0);
+
+        with_length(unit)
+    }
+
+    // A DWARF 2 line table with a row for each part of the source that has
+    // code, ending where the epilogue starts.
+    fn debug_line(
+        source_path: &str,
+        source_code: &str,
+        address: u64,
+        lines: &[(AssemblyOffset, usize)],
+        epilogue: AssemblyOffset,
+    ) -> Vec<u8> {
+        let mut header = vec![1, 1, LINE_BASE as u8, LINE_RANGE, OPCODE_BASE];
+        header.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+        // No include directories, then the source file with no directory,
+        // modification time, or length.
+        header.push(0);
+        push_string(&mut header, source_path);
+        header.extend_from_slice(&[0, 0, 0]);
+        header.push(0);
+
+        let mut program = vec![0, 9, DW_LNE_SET_ADDRESS];
+        program.extend_from_slice(&address.to_le_bytes());
+        let (mut row_offset, mut row_line, mut row_column) = (0, 1, 0);
+
+        for (i, (offset, position)) in lines.iter().enumerate() {
+            let end = lines.get(i + 1).map_or(epilogue, |(end, _)| *end);
+            if end.0 <= offset.0 {
+                continue;
+            }
+
+            let (line, column) = line_column(source_code, *position);
+            if offset.0 != row_offset {
+                program.push(DW_LNS_ADVANCE_PC);
+                push_uleb(&mut program, (offset.0 - row_offset) as u64);
+            }
+            if line != row_line {
+                program.push(DW_LNS_ADVANCE_LINE);
+                push_sleb(&mut program, line as i64 - row_line as i64);
+            }
+            if column != row_column {
+                program.push(DW_LNS_SET_COLUMN);
+                push_uleb(&mut program, column as u64);
+            }
+            program.push(DW_LNS_COPY);
+            (row_offset, row_line, row_column) = (offset.0, line, column);
+        }
+
+        if epilogue.0 != row_offset {
+            program.push(DW_LNS_ADVANCE_PC);
+            push_uleb(&mut program, (epilogue.0 - row_offset) as u64);
+        }
+        program.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+
+        let mut table = 2_u16.to_le_bytes().to_vec();
+        table.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        table.extend_from_slice(&header);
+        table.extend_from_slice(&program);
+
+        with_length(table)
+    }
+
+    // Call frame information for the code: on entry the return address is at
+    // the top of the stack, then r13 is pushed on top of it for everything but
+    // the last two instructions.
+    fn debug_frame(address: u64, code_len: usize, epilogue: AssemblyOffset) -> Vec<u8> {
+        let mut cie = CIE_ID.to_le_bytes().to_vec();
+        // The version, an empty augmentation, and the code and data alignment
+        // factors.
+        cie.extend_from_slice(&[1, 0, 1]);
+        push_sleb(&mut cie, -8);
+        cie.push(RETURN_ADDRESS);
+        cie.extend_from_slice(&[DW_CFA_DEF_CFA, RSP, 8]);
+        cie.extend_from_slice(&[DW_CFA_OFFSET | RETURN_ADDRESS, 1]);
+        let mut frame = with_length(padded(cie));
+
+        // Points back to the CIE at the start of the section.
+        let mut fde = 0_u32.to_le_bytes().to_vec();
+        fde.extend_from_slice(&address.to_le_bytes());
+        fde.extend_from_slice(&(code_len as u64).to_le_bytes());
+        fde.push(DW_CFA_ADVANCE_LOC | PUSH_SIZE as u8);
+        fde.extend_from_slice(&[DW_CFA_DEF_CFA_OFFSET, 16]);
+        fde.extend_from_slice(&[DW_CFA_OFFSET | R13, 2]);
+        // From just after the push to just after the pop.
+        fde.push(DW_CFA_ADVANCE_LOC4);
+        fde.extend_from_slice(&(epilogue.0 as u32).to_le_bytes());
+        fde.extend_from_slice(&[DW_CFA_DEF_CFA_OFFSET, 8]);
+        fde.push(DW_CFA_RESTORE | R13);
+        frame.extend(with_length(padded(fde)));
+
+        frame
+    }
+
+    // Pads call frame information with DW_CFA_nop so that, along with its
+    // length, it ends on an address boundary.
+    fn padded(mut entry: Vec<u8>) -> Vec<u8> {
+        entry.resize((entry.len() + 4).next_multiple_of(8) - 4, 0);
+        entry
+    }
+
+    fn with_length(contents: Vec<u8>) -> Vec<u8> {
+        let mut entry = (contents.len() as u32).to_le_bytes().to_vec();
+        entry.extend(contents);
+        entry
+    }
+
+    fn push_string(bytes: &mut Vec<u8>, string: &str) {
+        bytes.extend_from_slice(string.as_bytes());
+        bytes.push(0);
+    }
+
+    fn push_uleb(bytes: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn push_sleb(bytes: &mut Vec<u8>, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                bytes.push(byte);
+                return;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use {
+            super::{push_sleb, push_uleb, write},
+            crate::perf::Symbol,
+            dynasmrt::AssemblyOffset,
+            std::path::Path,
+        };
+
+        fn read_u16(image: &[u8], offset: usize) -> u16 {
+            u16::from_le_bytes(image[offset..offset + 2].try_into().unwrap())
+        }
+
+        fn read_u32(image: &[u8], offset: usize) -> u32 {
+            u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+        }
+
+        fn read_u64(image: &[u8], offset: usize) -> u64 {
+            u64::from_le_bytes(image[offset..offset + 8].try_into().unwrap())
+        }
+
+        fn read_string(image: &[u8], offset: usize) -> &str {
+            let end = offset + image[offset..].iter().position(|b| *b == 0).unwrap();
+            std::str::from_utf8(&image[offset..end]).unwrap()
+        }
+
+        // The header of each section, by name, as (address, offset, size).
+        fn sections(image: &[u8]) -> Vec<(&str, u64, u64, u64)> {
+            let headers = read_u64(image, 40) as usize;
+            let count = read_u16(image, 60) as usize;
+            let names = read_u64(image, headers + read_u16(image, 62) as usize * 64 + 24);
+            (0..count)
+                .map(|i| {
+                    let header = headers + i * 64;
+                    (
+                        read_string(image, names as usize + read_u32(image, header) as usize),
+                        read_u64(image, header + 16),
+                        read_u64(image, header + 24),
+                        read_u64(image, header + 32),
+                    )
+                })
+                .collect()
+        }
+
+        #[test]
+        fn write_test() {
+            let symbols = [
+                Symbol {
+                    start: 0,
+                    end: 12,
+                    name: "bf_program".to_owned(),
+                },
+                Symbol {
+                    start: 12,
+                    end: 30,
+                    name: "bf_loop_2_1".to_owned(),
+                },
+            ];
+            let image = write(
+                Path::new("/a.bf"),
+                "+\n[-]",
+                0x1000,
+                32,
+                &symbols,
+                &[
+                    (AssemblyOffset(8), 0),
+                    (AssemblyOffset(12), 2),
+                    (AssemblyOffset(20), 3),
+                    (AssemblyOffset(26), 4),
+                ],
+                AssemblyOffset(30),
+            );
+
+            assert_eq!(&image[..4], b"\x7fELF");
+            // A relocatable x86-64 object.
+            assert_eq!(read_u16(&image, 16), 1);
+            assert_eq!(read_u16(&image, 18), 62);
+
+            let sections = sections(&image);
+            let names = sections.iter().map(|s| s.0).collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                [
+                    "",
+                    ".text",
+                    ".symtab",
+                    ".strtab",
+                    ".shstrtab",
+                    ".debug_info",
+                    ".debug_abbrev",
+                    ".debug_line",
+                    ".debug_frame"
+                ]
+            );
+            // .text is placed over the code.
+            assert_eq!((sections[1].1, sections[1].3), (0x1000, 32));
+
+            // The null symbol and the file symbol, then the function symbols
+            // with offsets into .text.
+            let (_, _, symtab, symtab_size) = sections[2];
+            let (_, _, strtab, _) = sections[3];
+            assert_eq!(symtab_size, 4 * 24);
+            let symbol = |i: u64| {
+                let offset = (symtab + i * 24) as usize;
+                (
+                    read_string(&image, strtab as usize + read_u32(&image, offset) as usize),
+                    image[offset + 4],
+                    read_u64(&image, offset + 8),
+                    read_u64(&image, offset + 16),
+                )
+            };
+            assert_eq!(symbol(1), ("/a.bf", 4, 0, 0));
+            assert_eq!(symbol(2), ("bf_program", 2, 0, 12));
+            assert_eq!(symbol(3), ("bf_loop_2_1", 2, 12, 18));
+
+            // The line program, after the header: the + at line 1, the [ at
+            // line 2, then the - and ] in the next columns.
+            let (_, _, line, line_size) = sections[7];
+            let table = &image[line as usize..(line + line_size) as usize];
+            assert_eq!(read_u32(table, 0) as usize, table.len() - 4);
+            let program = &table[10 + read_u32(table, 6) as usize..];
+            assert_eq!(
+                program,
+                [
+                    &[0, 9, 2][..],
+                    &0x1000_u64.to_le_bytes(),
+                    &[2, 8, 5, 1, 1],
+                    &[2, 4, 3, 1, 1],
+                    &[2, 8, 5, 2, 1],
+                    &[2, 6, 5, 3, 1],
+                    &[2, 4, 0, 1, 1],
+                ]
+                .concat()
+            );
+        }
+
+        #[test]
+        fn leb128_test() {
+            let mut bytes = vec![];
+            push_uleb(&mut bytes, 624_485);
+            assert_eq!(bytes, [0xe5, 0x8e, 0x26]);
+
+            bytes.clear();
+            push_sleb(&mut bytes, -123_456);
+            assert_eq!(bytes, [0xc0, 0xbb, 0x78]);
+
+            bytes.clear();
+            push_sleb(&mut bytes, 63);
+            push_sleb(&mut bytes, 64);
+            push_sleb(&mut bytes, -64);
+            assert_eq!(bytes, [0x3f, 0xc0, 0x00, 0x40]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{__jit_debug_descriptor, Registration, JIT_REGISTER_FN, JIT_UNREGISTER_FN},
+        std::ptr,
+    };
+
+    #[test]
+    fn registration_test() {
+        let descriptor = &raw const __jit_debug_descriptor;
+
+        let first = Registration::new(vec![1, 2, 3].into_boxed_slice());
+        let second = Registration::new(vec![4].into_boxed_slice());
+        unsafe {
+            assert_eq!((*descriptor).version, 1);
+            assert_eq!((*descriptor).action_flag, JIT_REGISTER_FN);
+            assert_eq!((*descriptor).relevant_entry, second.entry);
+            assert_eq!((*descriptor).first_entry, second.entry);
+            assert_eq!((*second.entry).next_entry, first.entry);
+            assert_eq!((*first.entry).prev_entry, second.entry);
+            assert_eq!(
+                *(*first.entry).symfile_addr.add(2),
+                3,
+                "GDB reads the object from the entry"
+            );
+            assert_eq!((*first.entry).symfile_size, 3);
+        }
+
+        drop(second);
+        unsafe {
+            assert_eq!((*descriptor).action_flag, JIT_UNREGISTER_FN);
+            assert_eq!((*descriptor).first_entry, first.entry);
+            assert_eq!((*first.entry).prev_entry, ptr::null_mut());
+        }
+
+        drop(first);
+        unsafe {
+            assert_eq!((*descriptor).first_entry, ptr::null_mut());
+        }
+    }
+}
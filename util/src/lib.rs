@@ -1,6 +1,11 @@
 pub mod asm;
 mod error;
+pub mod gdb;
 pub mod math;
+pub mod passes;
+pub mod perf;
+pub mod profile;
 pub mod run;
+pub mod scan;
 
 pub use error::{BfError, BfResult};
@@ -0,0 +1,45 @@
+use std::fmt;
+
+pub const MAX_OPT_LEVEL: u8 = 3;
+
+// Which optimization passes to run, either as one of the predefined levels or
+// as an explicit list of pass names to run in order.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum PassSelection {
+    Level(u8),
+    Passes(Vec<String>),
+}
+
+impl Default for PassSelection {
+    fn default() -> Self {
+        Self::Level(MAX_OPT_LEVEL)
+    }
+}
+
+// Which superinstructions to fuse instruction sequences into: the built-in
+// list, an explicit list of names in priority order, or whichever turn up most
+// often in a corpus of BF source files.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SuperinstructionSelection {
+    #[default]
+    Builtin,
+    Named(Vec<String>),
+    Corpus(Vec<String>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PassReport {
+    pub name: &'static str,
+    pub instructions_before: usize,
+    pub instructions_after: usize,
+}
+
+impl fmt::Display for PassReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} instructions",
+            self.name, self.instructions_before, self.instructions_after
+        )
+    }
+}
@@ -0,0 +1,360 @@
+use {
+    crate::{asm::CompiledProgram, error::BfResult, run::PerfOptions},
+    dynasmrt::AssemblyOffset,
+    std::{fmt::Write as _, fs::OpenOptions, io::Write, process},
+};
+
+// A named range of a compiled program's code, relative to the start of its
+// buffer.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LoopMarker {
+    // Where the code for a loop starts, along with where in the source code its
+    // [ is.
+    Begin { position: usize },
+    // Where the code for a loop ends, just after its ].
+    End,
+}
+
+// Splits a program's code into symbols, so that perf attributes the time spent
+// in each loop to that loop. Code is named after the innermost loop it belongs
+// to, as bf_loop_<line>_<column> of the loop's [, and code outside of any loop
+// is named bf_program. Symbols can't overlap, so a loop with loops inside it
+// gets a symbol for each piece of code between them, all with the same name.
+pub fn loop_symbols(
+    source_code: &str,
+    markers: &[(AssemblyOffset, LoopMarker)],
+    code_len: usize,
+) -> Vec<Symbol> {
+    let mut symbols = vec![];
+    let mut names = vec!["bf_program".to_owned()];
+    let mut start = 0;
+
+    for (offset, marker) in markers {
+        push_symbol(&mut symbols, start, offset.0, names.last().unwrap());
+        start = offset.0;
+
+        match marker {
+            LoopMarker::Begin { position } => {
+                let (line, column) = line_column(source_code, *position);
+                names.push(format!("bf_loop_{line}_{column}"));
+            }
+            LoopMarker::End => {
+                if names.len() > 1 {
+                    names.pop();
+                }
+            }
+        }
+    }
+    push_symbol(&mut symbols, start, code_len, names.last().unwrap());
+
+    symbols
+}
+
+fn push_symbol(symbols: &mut Vec<Symbol>, start: usize, end: usize, name: &str) {
+    if end > start {
+        symbols.push(Symbol {
+            start,
+            end,
+            name: name.to_owned(),
+        });
+    }
+}
+
+// Both start at 1, and columns count characters rather than bytes.
+pub(crate) fn line_column(source_code: &str, position: usize) -> (usize, usize) {
+    let before = &source_code[..position];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+// Tells perf about a compiled program's symbols in whichever ways the options
+// ask for. perf reads a perf map when reporting, so nothing more is needed to
+// see the symbols in perf report. A jitdump also carries the code itself, which
+// perf inject --jit turns into objects that perf annotate can disassemble, but
+// perf record must be given -k mono for it to line up.
+pub fn register(
+    options: &PerfOptions,
+    program: &CompiledProgram,
+    symbols: &[Symbol],
+) -> BfResult<()> {
+    if options.map {
+        write_perf_map(program.address(), symbols)?;
+    }
+    if options.jitdump {
+        #[cfg(target_os = "linux")]
+        jitdump::write(program.address(), program.code(), symbols)?;
+        #[cfg(not(target_os = "linux"))]
+        return Err(crate::error::BfError::Bf(
+            "jitdump is only supported on Linux.".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Each line is the symbol's address and size in hex, followed by its name.
+// Other programs compiled by the same process are added to the same file.
+fn write_perf_map(address: u64, symbols: &[Symbol]) -> BfResult<()> {
+    let mut map = String::new();
+    for symbol in symbols {
+        writeln!(
+            map,
+            "{:x} {:x} {}",
+            address + symbol.start as u64,
+            symbol.end - symbol.start,
+            symbol.name
+        )
+        .unwrap();
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("/tmp/perf-{}.map", process::id()))?
+        .write_all(map.as_bytes())?;
+
+    Ok(())
+}
+
+// Writes the jitdump format described in tools/perf/Documentation/jitdump-specification.txt
+// in the Linux source: a header, then a code load record for each symbol.
+#[cfg(target_os = "linux")]
+mod jitdump {
+    use {
+        super::Symbol,
+        crate::error::BfResult,
+        std::{
+            fs::{File, OpenOptions},
+            io::{self, Write},
+            os::unix::io::AsRawFd,
+            process, ptr,
+            sync::Mutex,
+        },
+    };
+
+    const MAGIC: u32 = 0x4a69_5444;
+    const VERSION: u32 = 1;
+    const HEADER_SIZE: u32 = 40;
+    const CODE_LOAD: u32 = 0;
+    // The size of a code load record before its name and code.
+    const CODE_LOAD_SIZE: u32 = 56;
+
+    #[cfg(target_arch = "x86_64")]
+    const ELF_MACHINE: u32 = 62;
+    #[cfg(target_arch = "x86")]
+    const ELF_MACHINE: u32 = 3;
+    #[cfg(target_arch = "aarch64")]
+    const ELF_MACHINE: u32 = 183;
+
+    struct JitDump {
+        file: File,
+        // Every code load record needs an index of its own.
+        next_index: u64,
+    }
+
+    // One file is shared by every program a process compiles.
+    static JITDUMP: Mutex<Option<JitDump>> = Mutex::new(None);
+
+    pub fn write(address: u64, code: &[u8], symbols: &[Symbol]) -> BfResult<()> {
+        let mut jitdump = JITDUMP.lock().unwrap();
+        if jitdump.is_none() {
+            *jitdump = Some(open()?);
+        }
+        let jitdump = jitdump.as_mut().unwrap();
+
+        let pid = process::id();
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+        for symbol in symbols {
+            let record = code_load_record(
+                timestamp(),
+                pid,
+                tid,
+                address + symbol.start as u64,
+                &code[symbol.start..symbol.end],
+                jitdump.next_index,
+                &symbol.name,
+            );
+            jitdump.file.write_all(&record)?;
+            jitdump.next_index += 1;
+        }
+        jitdump.file.flush()?;
+
+        Ok(())
+    }
+
+    fn open() -> BfResult<JitDump> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("/tmp/jit-{}.dump", process::id()))?;
+        file.write_all(&header(timestamp(), process::id()))?;
+
+        // perf record finds the file by seeing it mapped as executable. The
+        // mapping is never used, and is left in place until the process exits.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mapping = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if mapping == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(JitDump {
+            file,
+            next_index: 0,
+        })
+    }
+
+    // Timestamps are in nanoseconds on the monotonic clock, which is what -k
+    // mono has perf record use.
+    fn timestamp() -> u64 {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+        time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+    }
+
+    fn header(timestamp: u64, pid: u32) -> Vec<u8> {
+        [
+            &MAGIC.to_ne_bytes()[..],
+            &VERSION.to_ne_bytes(),
+            &HEADER_SIZE.to_ne_bytes(),
+            &ELF_MACHINE.to_ne_bytes(),
+            // Padding.
+            &0u32.to_ne_bytes(),
+            &pid.to_ne_bytes(),
+            &timestamp.to_ne_bytes(),
+            // Flags.
+            &0u64.to_ne_bytes(),
+        ]
+        .concat()
+    }
+
+    fn code_load_record(
+        timestamp: u64,
+        pid: u32,
+        tid: u32,
+        address: u64,
+        code: &[u8],
+        index: u64,
+        name: &str,
+    ) -> Vec<u8> {
+        let size = CODE_LOAD_SIZE as usize + name.len() + 1 + code.len();
+        [
+            &CODE_LOAD.to_ne_bytes()[..],
+            &(size as u32).to_ne_bytes(),
+            &timestamp.to_ne_bytes(),
+            &pid.to_ne_bytes(),
+            &tid.to_ne_bytes(),
+            // Where the code is mapped and where it was loaded from, which are
+            // the same here.
+            &address.to_ne_bytes(),
+            &address.to_ne_bytes(),
+            &(code.len() as u64).to_ne_bytes(),
+            &index.to_ne_bytes(),
+            name.as_bytes(),
+            &[0],
+            code,
+        ]
+        .concat()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{code_load_record, header};
+
+        #[test]
+        fn header_test() {
+            let header = header(7, 42);
+            assert_eq!(header.len(), 40);
+            assert_eq!(&header[..4], &0x4a69_5444u32.to_ne_bytes());
+            assert_eq!(&header[20..24], &42u32.to_ne_bytes());
+            assert_eq!(&header[24..32], &7u64.to_ne_bytes());
+        }
+
+        #[test]
+        fn code_load_record_test() {
+            let record = code_load_record(7, 42, 43, 0x1000, &[0xc3], 2, "bf_program");
+            assert_eq!(record.len(), 56 + 11 + 1);
+            assert_eq!(&record[..4], &0u32.to_ne_bytes());
+            assert_eq!(&record[4..8], &68u32.to_ne_bytes());
+            assert_eq!(&record[8..16], &7u64.to_ne_bytes());
+            assert_eq!(&record[16..20], &42u32.to_ne_bytes());
+            assert_eq!(&record[20..24], &43u32.to_ne_bytes());
+            assert_eq!(&record[24..32], &0x1000u64.to_ne_bytes());
+            assert_eq!(&record[32..40], &0x1000u64.to_ne_bytes());
+            assert_eq!(&record[40..48], &1u64.to_ne_bytes());
+            assert_eq!(&record[48..56], &2u64.to_ne_bytes());
+            assert_eq!(&record[56..], b"bf_program\0\xc3");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{line_column, loop_symbols, LoopMarker, Symbol},
+        dynasmrt::AssemblyOffset,
+    };
+
+    fn symbol(start: usize, end: usize, name: &str) -> Symbol {
+        Symbol {
+            start,
+            end,
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn loop_symbols_test() {
+        let source_code = "+[\n  ->[-]<\n]é[]";
+        assert_eq!(
+            loop_symbols(
+                source_code,
+                &[
+                    (AssemblyOffset(10), LoopMarker::Begin { position: 1 }),
+                    (AssemblyOffset(20), LoopMarker::Begin { position: 7 }),
+                    (AssemblyOffset(30), LoopMarker::End),
+                    (AssemblyOffset(40), LoopMarker::End),
+                    (AssemblyOffset(40), LoopMarker::Begin { position: 15 }),
+                    (AssemblyOffset(50), LoopMarker::End),
+                ],
+                60,
+            ),
+            vec![
+                symbol(0, 10, "bf_program"),
+                symbol(10, 20, "bf_loop_1_2"),
+                symbol(20, 30, "bf_loop_2_5"),
+                symbol(30, 40, "bf_loop_1_2"),
+                symbol(40, 50, "bf_loop_3_3"),
+                symbol(50, 60, "bf_program"),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_column_test() {
+        assert_eq!(line_column("", 0), (1, 1));
+        assert_eq!(line_column("ab\ncd", 4), (2, 2));
+        assert_eq!(line_column("é\n\nx", 4), (3, 1));
+    }
+}
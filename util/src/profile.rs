@@ -0,0 +1,240 @@
+use {
+    crate::error::{BfError, BfResult},
+    std::collections::BTreeMap,
+};
+
+// A profile is text, starting with the format and a hash of the source it was
+// recorded from, then a line for each loop that was reached:
+//
+//     bf-profile 1
+//     source 5d4c0e1b2a3f6789
+//     loop <position> <entries> <iterations> <zero trips>
+const MAGIC: &str = "bf-profile";
+pub const FORMAT_VERSION: u32 = 1;
+
+// How often one loop ran in a profiled run.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LoopCounts {
+    // How many times the loop was reached.
+    pub entries: u64,
+    // How many times its body ran, over all of the entries.
+    pub iterations: u64,
+    // How many entries skipped the body because the cell was already zero.
+    pub zero_trips: u64,
+}
+
+impl LoopCounts {
+    // The fraction of entries which skipped the body, or 1 for loops which
+    // were never reached.
+    pub fn zero_trip_frequency(&self) -> f64 {
+        if self.entries == 0 {
+            1.0
+        } else {
+            self.zero_trips as f64 / self.entries as f64
+        }
+    }
+
+    // How many times the body ran on average for entries which didn't skip
+    // it.
+    pub fn average_trips(&self) -> f64 {
+        let taken = self.entries - self.zero_trips;
+        if taken == 0 {
+            0.0
+        } else {
+            self.iterations as f64 / taken as f64
+        }
+    }
+}
+
+// Per-loop counts from running a program under an interpreter, for the JITs to
+// optimize with. Loops are keyed by the index of their '[' in the source, so
+// the profile doesn't depend on how either side optimized the program.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Profile {
+    pub source_hash: u64,
+    pub loops: BTreeMap<usize, LoopCounts>,
+}
+
+impl Profile {
+    pub fn new(source_code: &str) -> Self {
+        Profile {
+            source_hash: source_hash(source_code),
+            loops: BTreeMap::new(),
+        }
+    }
+
+    // Counts for the loop starting at that index in the source, if it was
+    // reached.
+    pub fn get(&self, position: usize) -> Option<&LoopCounts> {
+        self.loops.get(&position)
+    }
+
+    // Profiles only make sense for the program they were recorded from.
+    pub fn check_source(&self, source_code: &str) -> BfResult<()> {
+        if self.source_hash != source_hash(source_code) {
+            return Err(BfError::Bf(
+                "The profile was recorded from a different program.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = format!(
+            "{MAGIC} {FORMAT_VERSION}\nsource {:016x}\n",
+            self.source_hash
+        );
+        for (position, counts) in &self.loops {
+            text += &format!(
+                "loop {position} {} {} {}\n",
+                counts.entries, counts.iterations, counts.zero_trips
+            );
+        }
+
+        text
+    }
+
+    pub fn decode(text: &str) -> BfResult<Self> {
+        let mut lines = text.lines();
+
+        let version = lines
+            .next()
+            .and_then(|line| line.strip_prefix(MAGIC))
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or_else(|| BfError::Bf("Not a profile.".to_owned()))?;
+        if version != FORMAT_VERSION {
+            return Err(BfError::Bf(format!(
+                "Unsupported profile format version {version}, expected {FORMAT_VERSION}."
+            )));
+        }
+
+        let source_hash = lines
+            .next()
+            .and_then(|line| line.strip_prefix("source "))
+            .and_then(|hash| u64::from_str_radix(hash, 16).ok())
+            .ok_or_else(|| corrupted("missing source hash"))?;
+
+        let mut loops = BTreeMap::new();
+        for line in lines {
+            let fields = line
+                .strip_prefix("loop ")
+                .ok_or_else(|| corrupted("expected a loop"))?
+                .split(' ')
+                .map(|field| field.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| corrupted("loop counts aren't numbers"))?;
+            let [position, entries, iterations, zero_trips] = fields[..] else {
+                return Err(corrupted("expected four numbers for a loop"));
+            };
+            if zero_trips > entries {
+                return Err(corrupted("more zero trips than entries"));
+            }
+
+            let counts = LoopCounts {
+                entries,
+                iterations,
+                zero_trips,
+            };
+            if loops.insert(position as usize, counts).is_some() {
+                return Err(corrupted("loop counted twice"));
+            }
+        }
+
+        Ok(Profile { source_hash, loops })
+    }
+}
+
+fn corrupted(reason: &str) -> BfError {
+    BfError::Bf(format!("Corrupted profile: {reason}."))
+}
+
+// FNV-1a, which unlike the standard library's hasher stays the same between
+// builds, so profiles keep working.
+fn source_hash(source_code: &str) -> u64 {
+    source_code
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{LoopCounts, Profile},
+        crate::BfError,
+    };
+
+    #[test]
+    fn encode_test() {
+        let mut profile = Profile::new("+[>[-]<-]");
+        profile.loops.insert(
+            1,
+            LoopCounts {
+                entries: 1,
+                iterations: 1,
+                zero_trips: 0,
+            },
+        );
+        profile.loops.insert(
+            3,
+            LoopCounts {
+                entries: 1,
+                iterations: 0,
+                zero_trips: 1,
+            },
+        );
+
+        let text = profile.encode();
+        assert!(text.starts_with("bf-profile 1\nsource "));
+        assert!(text.ends_with("\nloop 1 1 1 0\nloop 3 1 0 1\n"));
+        assert_eq!(Profile::decode(&text), Ok(profile.clone()));
+
+        assert_eq!(profile.check_source("+[>[-]<-]"), Ok(()));
+        assert!(profile.check_source("+[>[-]<-]>").is_err());
+    }
+
+    #[test]
+    fn decode_error_test() {
+        let error = |text: &str| match Profile::decode(text) {
+            Err(BfError::Bf(message)) => message,
+            result => panic!("Unexpected {result:?}"),
+        };
+
+        assert_eq!(error(""), "Not a profile.");
+        assert_eq!(
+            error("bf-profile 2\n"),
+            "Unsupported profile format version 2, expected 1."
+        );
+        assert_eq!(
+            error("bf-profile 1\n"),
+            "Corrupted profile: missing source hash."
+        );
+        assert_eq!(
+            error("bf-profile 1\nsource 0\nloop 1 2\n"),
+            "Corrupted profile: expected four numbers for a loop."
+        );
+        assert_eq!(
+            error("bf-profile 1\nsource 0\nloop 1 1 0 2\n"),
+            "Corrupted profile: more zero trips than entries."
+        );
+        assert_eq!(
+            error("bf-profile 1\nsource 0\nloop 1 1 0 1\nloop 1 1 0 1\n"),
+            "Corrupted profile: loop counted twice."
+        );
+    }
+
+    #[test]
+    fn counts_test() {
+        let counts = LoopCounts {
+            entries: 4,
+            iterations: 30,
+            zero_trips: 1,
+        };
+        assert_eq!(counts.zero_trip_frequency(), 0.25);
+        assert_eq!(counts.average_trips(), 10.0);
+        assert_eq!(LoopCounts::default().zero_trip_frequency(), 1.0);
+        assert_eq!(LoopCounts::default().average_trips(), 0.0);
+    }
+}
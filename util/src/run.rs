@@ -1,14 +1,159 @@
 use {
-    crate::error::BfResult,
+    crate::{
+        error::{BfError, BfResult},
+        passes::{PassReport, PassSelection, SuperinstructionSelection},
+    },
     std::{
         env, fs,
         io::{self, Read, Write},
+        path::{self, PathBuf},
     },
 };
 
 pub trait RunFunction: Fn(&str, &mut dyn Read, &mut dyn Write) -> BfResult<()> {}
 impl<T> RunFunction for T where T: Fn(&str, &mut dyn Read, &mut dyn Write) -> BfResult<()> {}
 
+pub trait RunWithOptionsFunction:
+    Fn(&str, &RunOptions, &mut dyn Read, &mut dyn Write) -> BfResult<()>
+{
+}
+impl<T> RunWithOptionsFunction for T where
+    T: Fn(&str, &RunOptions, &mut dyn Read, &mut dyn Write) -> BfResult<()>
+{
+}
+
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RunOptions {
+    pub passes: PassSelection,
+    pub superinstructions: SuperinstructionSelection,
+    pub report_passes: bool,
+    pub tape: TapeOptions,
+    pub perf: PerfOptions,
+    // Register compiled code with GDB, with line tables pointing into this
+    // source file. See the gdb module.
+    pub gdb: Option<PathBuf>,
+    // Compile each top-level loop the first time it's entered rather than up
+    // front, for the JITs which support it.
+    pub lazy: bool,
+    // Read and write with syscalls made by the compiled code itself, with
+    // output buffered, for the JITs which support it.
+    pub syscall_io: bool,
+    // Check that the data pointer stays inside memory, stopping with an error
+    // at the instruction which takes it outside, for the JITs which support it.
+    pub bounds_checks: bool,
+    // Optimize with the loop counts in this profile, recorded from the same
+    // program by an interpreter, for the JITs which support it. See the
+    // profile module.
+    pub profile: Option<PathBuf>,
+}
+
+// Which of the run options a VM makes use of, and so accepts on its command
+// line. The tape options are always accepted, since check_default_tape rejects
+// any the VM can't follow.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SupportedOptions {
+    // -O and --passes=.
+    pub passes: bool,
+    pub superinstructions: bool,
+    pub report_passes: bool,
+    // --perf-map and --jitdump.
+    pub perf: bool,
+    pub gdb: bool,
+    pub lazy: bool,
+    pub syscall_io: bool,
+    pub bounds_checks: bool,
+    pub profile: bool,
+}
+
+impl SupportedOptions {
+    pub const NONE: Self = SupportedOptions {
+        passes: false,
+        superinstructions: false,
+        report_passes: false,
+        perf: false,
+        gdb: false,
+        lazy: false,
+        syscall_io: false,
+        bounds_checks: false,
+        profile: false,
+    };
+    pub const ALL: Self = SupportedOptions {
+        passes: true,
+        superinstructions: true,
+        report_passes: true,
+        perf: true,
+        gdb: true,
+        lazy: true,
+        syscall_io: true,
+        bounds_checks: true,
+        profile: true,
+    };
+}
+
+// What to tell Linux perf about compiled code, for the JITs which support it.
+// See the perf module.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct PerfOptions {
+    // Append symbols to /tmp/perf-<pid>.map.
+    pub map: bool,
+    // Write the code and its symbols to /tmp/jit-<pid>.dump.
+    pub jitdump: bool,
+}
+
+impl PerfOptions {
+    pub fn enabled(&self) -> bool {
+        self.map || self.jitdump
+    }
+}
+
+// The shape of the tape and what reading past the end of input does. The VMs
+// only support the default, but generated code follows whatever is given.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TapeOptions {
+    pub size: usize,
+    pub cell: CellType,
+    pub eof: EofPolicy,
+}
+
+impl Default for TapeOptions {
+    fn default() -> Self {
+        Self {
+            size: 30000,
+            cell: CellType::U8,
+            eof: EofPolicy::Error,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CellType {
+    U8,
+    U16,
+    U32,
+}
+
+impl CellType {
+    pub fn bits(self) -> u32 {
+        match self {
+            CellType::U8 => 8,
+            CellType::U16 => 16,
+            CellType::U32 => 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EofPolicy {
+    // Fail the program.
+    Error,
+    // Set the current cell to zero.
+    Zero,
+    // Leave the current cell as it was.
+    Unchanged,
+    // Set the current cell to -1, wrapped to the cell type.
+    MinusOne,
+}
+
 pub fn run_main(run_function: impl RunFunction) -> BfResult<()> {
     let args = env::args().collect::<Vec<String>>();
     let filepath = &args[1];
@@ -16,3 +161,292 @@ pub fn run_main(run_function: impl RunFunction) -> BfResult<()> {
 
     run_function(&source_code, &mut io::stdin(), &mut io::stdout())
 }
+
+pub fn run_main_with_options(
+    run_function: impl RunWithOptionsFunction,
+    supported: &SupportedOptions,
+) -> BfResult<()> {
+    let (filepath, options) = parse_args(env::args().skip(1), supported)?;
+    check_default_tape(&options)?;
+    let source_code = fs::read_to_string(filepath)?;
+
+    run_function(&source_code, &options, &mut io::stdin(), &mut io::stdout())
+}
+
+// For everything other than code generation, which can't change the tape yet.
+pub fn check_default_tape(options: &RunOptions) -> BfResult<()> {
+    if options.tape == TapeOptions::default() {
+        Ok(())
+    } else {
+        Err(BfError::Bf(
+            "Only the default tape of 30000 u8 cells, with reads failing at the end of input, is supported."
+                .to_owned(),
+        ))
+    }
+}
+
+pub fn report_passes(reports: &[PassReport]) {
+    for report in reports {
+        eprintln!("{report}");
+    }
+}
+
+// Options a VM doesn't support are rejected as unknown rather than ignored.
+pub fn parse_args(
+    args: impl Iterator<Item = String>,
+    supported: &SupportedOptions,
+) -> BfResult<(String, RunOptions)> {
+    let mut filepath = None;
+    let mut options = RunOptions::default();
+    let mut gdb = false;
+
+    for arg in args {
+        if let Some(level) = arg.strip_prefix("-O").filter(|_| supported.passes) {
+            let level = level
+                .parse()
+                .map_err(|_| BfError::Bf(format!("Invalid optimization level '{level}'.")))?;
+            options.passes = PassSelection::Level(level);
+        } else if let Some(passes) = arg.strip_prefix("--passes=").filter(|_| supported.passes) {
+            options.passes = PassSelection::Passes(split_list(passes));
+        } else if let Some(names) = arg
+            .strip_prefix("--superinstructions=")
+            .filter(|_| supported.superinstructions)
+        {
+            options.superinstructions = SuperinstructionSelection::Named(split_list(names));
+        } else if let Some(filepaths) = arg
+            .strip_prefix("--superinstructions-from=")
+            .filter(|_| supported.superinstructions)
+        {
+            options.superinstructions = SuperinstructionSelection::Corpus(split_list(filepaths));
+        } else if let Some(size) = arg.strip_prefix("--tape-size=") {
+            options.tape.size = match size.parse() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(BfError::Bf(format!("Invalid tape size '{size}'."))),
+            };
+        } else if let Some(cell) = arg.strip_prefix("--cell=") {
+            options.tape.cell = match cell {
+                "u8" => CellType::U8,
+                "u16" => CellType::U16,
+                "u32" => CellType::U32,
+                _ => return Err(BfError::Bf(format!("Unknown cell type '{cell}'."))),
+            };
+        } else if let Some(eof) = arg.strip_prefix("--eof=") {
+            options.tape.eof = match eof {
+                "error" => EofPolicy::Error,
+                "zero" => EofPolicy::Zero,
+                "unchanged" => EofPolicy::Unchanged,
+                "minus-one" => EofPolicy::MinusOne,
+                _ => return Err(BfError::Bf(format!("Unknown EOF policy '{eof}'."))),
+            };
+        } else if arg == "--report-passes" && supported.report_passes {
+            options.report_passes = true;
+        } else if arg == "--perf-map" && supported.perf {
+            options.perf.map = true;
+        } else if arg == "--jitdump" && supported.perf {
+            options.perf.jitdump = true;
+        } else if arg == "--gdb" && supported.gdb {
+            gdb = true;
+        } else if arg == "--lazy" && supported.lazy {
+            options.lazy = true;
+        } else if arg == "--syscall-io" && supported.syscall_io {
+            options.syscall_io = true;
+        } else if arg == "--bounds-checks" && supported.bounds_checks {
+            options.bounds_checks = true;
+        } else if let Some(path) = arg.strip_prefix("--profile=").filter(|_| supported.profile) {
+            options.profile = Some(PathBuf::from(path));
+        } else if arg.starts_with('-') {
+            return Err(BfError::Bf(format!("Unknown option '{arg}'.")));
+        } else if filepath.replace(arg).is_some() {
+            return Err(BfError::Bf("Only one source file may be given.".to_owned()));
+        }
+    }
+
+    let filepath = filepath.ok_or_else(|| BfError::Bf("No source file given.".to_owned()))?;
+    // GDB looks for the source file itself, possibly from somewhere else.
+    if gdb {
+        options.gdb = Some(path::absolute(&filepath)?);
+    }
+    Ok((filepath, options))
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            parse_args, CellType, EofPolicy, PerfOptions, RunOptions, SupportedOptions, TapeOptions,
+        },
+        crate::{
+            error::BfError,
+            passes::{PassSelection, SuperinstructionSelection},
+        },
+        std::{env, path::PathBuf},
+    };
+
+    fn parse(args: &[&str]) -> Result<(String, RunOptions), BfError> {
+        parse_args(
+            args.iter().map(|arg| (*arg).to_owned()),
+            &SupportedOptions::ALL,
+        )
+    }
+
+    #[test]
+    fn parse_args_test() {
+        assert_eq!(
+            parse(&["a.bf"]).unwrap(),
+            ("a.bf".to_owned(), RunOptions::default())
+        );
+        assert_eq!(
+            parse(&["-O1", "a.bf", "--report-passes"]).unwrap(),
+            (
+                "a.bf".to_owned(),
+                RunOptions {
+                    passes: PassSelection::Level(1),
+                    report_passes: true,
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse(&["--passes=combine-repeats,move-data", "a.bf"]).unwrap(),
+            (
+                "a.bf".to_owned(),
+                RunOptions {
+                    passes: PassSelection::Passes(vec![
+                        "combine-repeats".to_owned(),
+                        "move-data".to_owned()
+                    ]),
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            parse(&["--passes=", "a.bf"]).unwrap().1.passes,
+            PassSelection::Passes(vec![])
+        );
+        assert_eq!(
+            parse(&["--superinstructions=ptr-data,data-ptr", "a.bf"])
+                .unwrap()
+                .1
+                .superinstructions,
+            SuperinstructionSelection::Named(vec!["ptr-data".to_owned(), "data-ptr".to_owned()])
+        );
+        assert_eq!(
+            parse(&["--superinstructions-from=b.bf,c.bf", "a.bf"])
+                .unwrap()
+                .1
+                .superinstructions,
+            SuperinstructionSelection::Corpus(vec!["b.bf".to_owned(), "c.bf".to_owned()])
+        );
+        assert_eq!(
+            parse(&["--tape-size=65536", "--cell=u16", "--eof=unchanged", "a.bf"])
+                .unwrap()
+                .1
+                .tape,
+            TapeOptions {
+                size: 65536,
+                cell: CellType::U16,
+                eof: EofPolicy::Unchanged,
+            }
+        );
+        assert_eq!(
+            parse(&["--gdb", "a.bf"]).unwrap().1.gdb,
+            Some(env::current_dir().unwrap().join("a.bf"))
+        );
+        assert!(parse(&["--lazy", "a.bf"]).unwrap().1.lazy);
+        assert!(parse(&["--syscall-io", "a.bf"]).unwrap().1.syscall_io);
+        assert!(parse(&["--bounds-checks", "a.bf"]).unwrap().1.bounds_checks);
+        assert_eq!(
+            parse(&["--profile=a.profile", "a.bf"]).unwrap().1.profile,
+            Some(PathBuf::from("a.profile"))
+        );
+        assert_eq!(
+            parse(&["--perf-map", "--jitdump", "a.bf"]).unwrap().1.perf,
+            PerfOptions {
+                map: true,
+                jitdump: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_args_error_test() {
+        assert_eq!(
+            parse(&[]).unwrap_err(),
+            BfError::Bf("No source file given.".to_owned())
+        );
+        assert_eq!(
+            parse(&["a.bf", "b.bf"]).unwrap_err(),
+            BfError::Bf("Only one source file may be given.".to_owned())
+        );
+        assert_eq!(
+            parse(&["-Ofast", "a.bf"]).unwrap_err(),
+            BfError::Bf("Invalid optimization level 'fast'.".to_owned())
+        );
+        assert_eq!(
+            parse(&["--fast", "a.bf"]).unwrap_err(),
+            BfError::Bf("Unknown option '--fast'.".to_owned())
+        );
+        assert_eq!(
+            parse(&["--tape-size=0", "a.bf"]).unwrap_err(),
+            BfError::Bf("Invalid tape size '0'.".to_owned())
+        );
+        assert_eq!(
+            parse(&["--cell=u64", "a.bf"]).unwrap_err(),
+            BfError::Bf("Unknown cell type 'u64'.".to_owned())
+        );
+        assert_eq!(
+            parse(&["--eof=ignore", "a.bf"]).unwrap_err(),
+            BfError::Bf("Unknown EOF policy 'ignore'.".to_owned())
+        );
+    }
+
+    #[test]
+    fn unsupported_options_test() {
+        let supported = SupportedOptions {
+            passes: true,
+            ..SupportedOptions::NONE
+        };
+        let parse =
+            |args: &[&str]| parse_args(args.iter().map(|arg| (*arg).to_owned()), &supported);
+
+        assert_eq!(
+            parse(&["-O1", "--tape-size=100", "a.bf"]).unwrap().1,
+            RunOptions {
+                passes: PassSelection::Level(1),
+                tape: TapeOptions {
+                    size: 100,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        );
+        for option in [
+            "--superinstructions=ptr-data",
+            "--superinstructions-from=b.bf",
+            "--report-passes",
+            "--perf-map",
+            "--jitdump",
+            "--gdb",
+            "--lazy",
+            "--syscall-io",
+            "--bounds-checks",
+            "--profile=a.profile",
+        ] {
+            assert_eq!(
+                parse(&[option, "a.bf"]).unwrap_err(),
+                BfError::Bf(format!("Unknown option '{option}'."))
+            );
+        }
+        assert_eq!(
+            parse_args(["-O1".to_owned()].into_iter(), &SupportedOptions::NONE).unwrap_err(),
+            BfError::Bf("Unknown option '-O1'.".to_owned())
+        );
+    }
+}
@@ -0,0 +1,241 @@
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::{vaddv_u8, vandq_u8, vceqzq_u8, vget_high_u8, vget_low_u8, vld1q_u8};
+#[cfg(all(target_arch = "x86", target_feature = "sse2"))]
+use std::arch::x86::{
+    __m128i, __m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8,
+    _mm256_setzero_si256, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_setzero_si128,
+};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+    __m128i, __m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8,
+    _mm256_setzero_si256, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_setzero_si128,
+};
+
+// Strides which evenly divide the width of a vector select the same lanes of
+// every vector as we step through memory, so only those can be scanned a
+// vector at a time.
+pub fn is_vectorizable_stride(amount: usize) -> bool {
+    matches!(amount, 1 | 2 | 4 | 8)
+}
+
+// Returns a bitmask of the lanes in a vector of the given width which a scan
+// with the given stride actually visits. Forward scans load the vector starting
+// at the current cell, and backward scans load the vector ending at it.
+pub fn lane_mask(lanes: usize, forward: bool, amount: usize) -> u32 {
+    (0..lanes)
+        .filter(|lane| {
+            let distance = if forward { *lane } else { lanes - 1 - lane };
+            distance % amount == 0
+        })
+        .fold(0, |mask, lane| mask | (1 << lane))
+}
+
+pub fn move_ptr_until_zero(
+    memory: &[u8],
+    data_pointer: usize,
+    forward: bool,
+    amount: usize,
+) -> usize {
+    let mut data_pointer = data_pointer;
+    if memory[data_pointer] == 0 {
+        return data_pointer;
+    }
+
+    if is_vectorizable_stride(amount) {
+        data_pointer = vector_scan(memory, data_pointer, forward, amount);
+    }
+
+    // Finish off whatever the vectorized scan couldn't cover, since it only
+    // loads whole vectors that fit inside memory.
+    while memory[data_pointer] != 0 {
+        if forward {
+            data_pointer += amount
+        } else {
+            data_pointer -= amount
+        }
+    }
+
+    data_pointer
+}
+
+#[inline(always)]
+fn scan_chunks<const LANES: usize>(
+    memory: &[u8],
+    data_pointer: usize,
+    forward: bool,
+    amount: usize,
+    zero_mask: impl Fn(&[u8; LANES]) -> u32,
+) -> usize {
+    let mut data_pointer = data_pointer;
+    let lane_mask = lane_mask(LANES, forward, amount);
+
+    if forward {
+        while let Some(chunk) = memory.get(data_pointer..data_pointer + LANES) {
+            let mask = zero_mask(chunk.try_into().unwrap()) & lane_mask;
+            if mask != 0 {
+                return data_pointer + mask.trailing_zeros() as usize;
+            }
+
+            data_pointer += LANES;
+        }
+    } else {
+        while data_pointer + 1 >= LANES {
+            let start = data_pointer + 1 - LANES;
+            let chunk = &memory[start..=data_pointer];
+            let mask = zero_mask(chunk.try_into().unwrap()) & lane_mask;
+            if mask != 0 {
+                return start + (31 - mask.leading_zeros()) as usize;
+            }
+
+            data_pointer -= LANES;
+        }
+    }
+
+    data_pointer
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2")
+))]
+fn vector_scan(memory: &[u8], data_pointer: usize, forward: bool, amount: usize) -> usize {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: We just checked that AVX2 is supported.
+        unsafe { avx2_scan(memory, data_pointer, forward, amount) }
+    } else {
+        scan_chunks::<16>(memory, data_pointer, forward, amount, sse2_zero_mask)
+    }
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2")
+))]
+fn sse2_zero_mask(chunk: &[u8; 16]) -> u32 {
+    // SAFETY: SSE2 is enabled for this target, and an unaligned load of 16
+    // bytes from a 16 byte array is in bounds.
+    unsafe {
+        let vector = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+        _mm_movemask_epi8(_mm_cmpeq_epi8(vector, _mm_setzero_si128())) as u32
+    }
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2")
+))]
+#[target_feature(enable = "avx2")]
+unsafe fn avx2_scan(memory: &[u8], data_pointer: usize, forward: bool, amount: usize) -> usize {
+    scan_chunks::<32>(memory, data_pointer, forward, amount, |chunk| {
+        // SAFETY: Our caller has checked that AVX2 is supported, and an
+        // unaligned load of 32 bytes from a 32 byte array is in bounds.
+        unsafe {
+            let vector = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            _mm256_movemask_epi8(_mm256_cmpeq_epi8(vector, _mm256_setzero_si256())) as u32
+        }
+    })
+}
+
+#[cfg(target_arch = "aarch64")]
+fn vector_scan(memory: &[u8], data_pointer: usize, forward: bool, amount: usize) -> usize {
+    scan_chunks::<16>(memory, data_pointer, forward, amount, neon_zero_mask)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn neon_zero_mask(chunk: &[u8; 16]) -> u32 {
+    // NEON has no equivalent of movemask, so weight each lane by its bit in
+    // the final mask and sum up each half of the vector instead.
+    const LANE_BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+    // SAFETY: NEON is always available on aarch64, and both loads are of 16
+    // bytes from 16 byte arrays.
+    unsafe {
+        let zeros = vceqzq_u8(vld1q_u8(chunk.as_ptr()));
+        let bits = vandq_u8(zeros, vld1q_u8(LANE_BITS.as_ptr()));
+        let low = vaddv_u8(vget_low_u8(bits)) as u32;
+        let high = vaddv_u8(vget_high_u8(bits)) as u32;
+        low | (high << 8)
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2"),
+    target_arch = "aarch64"
+)))]
+fn vector_scan(_memory: &[u8], data_pointer: usize, _forward: bool, _amount: usize) -> usize {
+    data_pointer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lane_mask, move_ptr_until_zero};
+
+    // Returns None rather than running off either end of memory.
+    fn scalar_move_ptr_until_zero(
+        memory: &[u8],
+        mut data_pointer: usize,
+        forward: bool,
+        amount: usize,
+    ) -> Option<usize> {
+        while *memory.get(data_pointer)? != 0 {
+            if forward {
+                data_pointer += amount
+            } else {
+                data_pointer = data_pointer.checked_sub(amount)?
+            }
+        }
+
+        Some(data_pointer)
+    }
+
+    #[test]
+    fn lane_mask_test() {
+        assert_eq!(lane_mask(16, true /*forward*/, 1), 0xffff);
+        assert_eq!(lane_mask(16, true /*forward*/, 2), 0x5555);
+        assert_eq!(lane_mask(16, true /*forward*/, 4), 0x1111);
+        assert_eq!(lane_mask(16, true /*forward*/, 8), 0x0101);
+        assert_eq!(lane_mask(16, false /*forward*/, 1), 0xffff);
+        assert_eq!(lane_mask(16, false /*forward*/, 2), 0xaaaa);
+        assert_eq!(lane_mask(16, false /*forward*/, 4), 0x8888);
+        assert_eq!(lane_mask(16, false /*forward*/, 8), 0x8080);
+
+        assert_eq!(lane_mask(32, true /*forward*/, 1), 0xffffffff);
+        assert_eq!(lane_mask(32, true /*forward*/, 4), 0x11111111);
+        assert_eq!(lane_mask(32, false /*forward*/, 1), 0xffffffff);
+        assert_eq!(lane_mask(32, false /*forward*/, 4), 0x88888888);
+    }
+
+    #[test]
+    fn move_ptr_until_zero_test() {
+        const MEMORY_SIZE: usize = 200;
+
+        for zero in [0, 1, 15, 16, 17, 31, 32, 33, 63, 64, 100, 198, 199] {
+            let mut memory = [1; MEMORY_SIZE];
+            memory[zero] = 0;
+            // Put some zeros in between that a scan with a larger stride
+            // should skip over.
+            for i in (zero % 2 + 1..MEMORY_SIZE).step_by(2) {
+                if i.abs_diff(zero) % 8 != 0 {
+                    memory[i] = 0;
+                }
+            }
+
+            for amount in 1..=9 {
+                for start in 0..MEMORY_SIZE {
+                    for forward in [true, false] {
+                        if let Some(expected) =
+                            scalar_move_ptr_until_zero(&memory, start, forward, amount)
+                        {
+                            assert_eq!(
+                                move_ptr_until_zero(&memory, start, forward, amount),
+                                expected,
+                                "zero: {zero}, amount: {amount}, start: {start}, forward: {forward}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use {
+    std::fmt,
+    util::{passes::PassSelection, run::RunOptions, BfError, BfResult},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Vm {
+    Opinterp3,
+    Opjit,
+}
+
+impl Vm {
+    pub fn from_name(name: &str) -> BfResult<Self> {
+        match name {
+            "opinterp3" => Ok(Self::Opinterp3),
+            "opjit" => Ok(Self::Opjit),
+            _ => Err(BfError::Bf(format!("Unknown VM '{name}'."))),
+        }
+    }
+
+    fn pass_names(self, selection: &PassSelection) -> BfResult<Vec<&'static str>> {
+        match self {
+            Self::Opinterp3 => opinterp3::pass_names(selection),
+            Self::Opjit => opjit::pass_names(selection),
+        }
+    }
+
+    fn run(self, source_code: &str, passes: &[&str], input: &[u8]) -> Execution {
+        let options = RunOptions {
+            passes: PassSelection::Passes(passes.iter().map(|pass| (*pass).to_owned()).collect()),
+            ..Default::default()
+        };
+        let mut output = vec![];
+        let tape = match self {
+            Self::Opinterp3 => {
+                opinterp3::run_with_tape(source_code, &options, &mut &input[..], &mut output)
+            }
+            Self::Opjit => {
+                opjit::run_with_tape(source_code, &options, &mut &input[..], &mut output)
+            }
+        };
+
+        Execution { output, tape }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Execution {
+    output: Vec<u8>,
+    // The final tape, or the error the run stopped with.
+    tape: BfResult<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mismatch {
+    // None stands for the end of the output.
+    Output {
+        offset: usize,
+        expected: Option<u8>,
+        actual: Option<u8>,
+    },
+    Tape {
+        cell: usize,
+        expected: u8,
+        actual: u8,
+    },
+    // One run stopped with an error and the other didn't. None stands for
+    // finishing without one.
+    Error {
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn byte(byte: &Option<u8>) -> String {
+            match byte {
+                Some(byte) => format!("{byte:#04x}"),
+                None => "end of output".to_owned(),
+            }
+        }
+
+        match self {
+            Self::Output {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Output byte {offset} differs: expected {}, got {}",
+                byte(expected),
+                byte(actual)
+            ),
+            Self::Tape {
+                cell,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Tape cell {cell} differs: expected {expected:#04x}, got {actual:#04x}"
+            ),
+            Self::Error {
+                expected: Some(expected),
+                ..
+            } => write!(
+                f,
+                "The program finished, but should have stopped with '{expected}'"
+            ),
+            Self::Error { actual, .. } => write!(
+                f,
+                "The program stopped with '{}', but should have finished",
+                actual.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    pub mismatch: Mismatch,
+    // The pass after which the program first diverges, or None if it diverges
+    // even with every pass disabled.
+    pub pass: Option<&'static str>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pass {
+            Some(pass) => write!(f, "{} after the '{pass}' pass.", self.mismatch),
+            None => write!(f, "{} with no passes enabled.", self.mismatch),
+        }
+    }
+}
+
+// Runs the program through the unoptimized opinterp and through the given VM
+// with the selected passes, and compares the output and the final tape.
+pub fn validate(
+    source_code: &str,
+    input: &[u8],
+    vm: Vm,
+    passes: &PassSelection,
+) -> BfResult<Option<Divergence>> {
+    let pass_names = vm.pass_names(passes)?;
+    let mut output = vec![];
+    let tape = opinterp::run_with_tape(source_code, &mut &input[..], &mut output);
+    let expected = Execution { output, tape };
+
+    Ok(find_divergence(&expected, &pass_names, |passes| {
+        vm.run(source_code, passes, input)
+    }))
+}
+
+fn find_divergence(
+    expected: &Execution,
+    pass_names: &[&'static str],
+    run: impl Fn(&[&'static str]) -> Execution,
+) -> Option<Divergence> {
+    let mismatch = first_mismatch(expected, &run(pass_names))?;
+
+    // Enable one more pass at a time to find the first one which breaks the
+    // program. The full pipeline already diverged, so if no shorter prefix of
+    // it does then the last pass is to blame.
+    for end in 0..pass_names.len() {
+        if let Some(mismatch) = first_mismatch(expected, &run(&pass_names[..end])) {
+            return Some(Divergence {
+                mismatch,
+                pass: end.checked_sub(1).map(|last| pass_names[last]),
+            });
+        }
+    }
+
+    Some(Divergence {
+        mismatch,
+        pass: pass_names.last().copied(),
+    })
+}
+
+fn first_mismatch(expected: &Execution, actual: &Execution) -> Option<Mismatch> {
+    let output_length = expected.output.len().max(actual.output.len());
+    if let Some(offset) =
+        (0..output_length).find(|&offset| expected.output.get(offset) != actual.output.get(offset))
+    {
+        return Some(Mismatch::Output {
+            offset,
+            expected: expected.output.get(offset).copied(),
+            actual: actual.output.get(offset).copied(),
+        });
+    }
+
+    // Runs which both stop with an error agree, even if the VMs word it
+    // differently, and leave no tape to compare.
+    let (expected_tape, actual_tape) = match (&expected.tape, &actual.tape) {
+        (Ok(expected), Ok(actual)) => (expected, actual),
+        (Err(_), Err(_)) => return None,
+        (expected, actual) => {
+            return Some(Mismatch::Error {
+                expected: expected.as_ref().err().map(ToString::to_string),
+                actual: actual.as_ref().err().map(ToString::to_string),
+            })
+        }
+    };
+
+    // Cells past the end of the shorter tape were never touched, so count as
+    // zero.
+    let cell = |tape: &[u8], cell: usize| tape.get(cell).copied().unwrap_or(0);
+    let tape_length = expected_tape.len().max(actual_tape.len());
+    (0..tape_length)
+        .find(|&index| cell(expected_tape, index) != cell(actual_tape, index))
+        .map(|index| Mismatch::Tape {
+            cell: index,
+            expected: cell(expected_tape, index),
+            actual: cell(actual_tape, index),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{find_divergence, first_mismatch, Divergence, Execution, Mismatch},
+        util::BfError,
+    };
+
+    fn execution(output: &str, tape: &[u8]) -> Execution {
+        Execution {
+            output: output.as_bytes().to_vec(),
+            tape: Ok(tape.to_vec()),
+        }
+    }
+
+    fn failed(output: &str, message: &str) -> Execution {
+        Execution {
+            output: output.as_bytes().to_vec(),
+            tape: Err(BfError::Bf(message.to_owned())),
+        }
+    }
+
+    #[test]
+    fn first_mismatch_test() {
+        let expected = execution("abc", &[1, 2, 0]);
+        assert_eq!(first_mismatch(&expected, &expected), None);
+        assert_eq!(first_mismatch(&expected, &execution("abc", &[1, 2])), None);
+        assert_eq!(
+            first_mismatch(&expected, &execution("abd", &[0, 0, 0])),
+            Some(Mismatch::Output {
+                offset: 2,
+                expected: Some(b'c'),
+                actual: Some(b'd'),
+            })
+        );
+        assert_eq!(
+            first_mismatch(&expected, &execution("ab", &[1, 2, 0])),
+            Some(Mismatch::Output {
+                offset: 2,
+                expected: Some(b'c'),
+                actual: None,
+            })
+        );
+        assert_eq!(
+            first_mismatch(&expected, &execution("abcd", &[1, 2, 0])),
+            Some(Mismatch::Output {
+                offset: 3,
+                expected: None,
+                actual: Some(b'd'),
+            })
+        );
+        assert_eq!(
+            first_mismatch(&expected, &execution("abc", &[1, 2, 0, 4])),
+            Some(Mismatch::Tape {
+                cell: 3,
+                expected: 0,
+                actual: 4,
+            })
+        );
+        assert_eq!(
+            first_mismatch(&expected, &failed("abc", "Out of memory.")),
+            Some(Mismatch::Error {
+                expected: None,
+                actual: Some("Out of memory.".to_owned()),
+            })
+        );
+        assert_eq!(
+            first_mismatch(&failed("abc", "Out of memory."), &expected),
+            Some(Mismatch::Error {
+                expected: Some("Out of memory.".to_owned()),
+                actual: None,
+            })
+        );
+        // VMs word their errors differently, so only the output is compared.
+        assert_eq!(
+            first_mismatch(
+                &failed("ab", "unmatched '['"),
+                &failed("ab", "Unmatched '['.")
+            ),
+            None
+        );
+        assert_eq!(
+            first_mismatch(
+                &failed("ab", "Out of memory."),
+                &failed("a", "Out of memory.")
+            ),
+            Some(Mismatch::Output {
+                offset: 1,
+                expected: Some(b'b'),
+                actual: None,
+            })
+        );
+    }
+
+    #[test]
+    fn find_divergence_test() {
+        let expected = execution("ok", &[]);
+        let pass_names = ["first", "second", "third"];
+        // Pretends that the given pass breaks the program.
+        let run_breaking = |broken: &'static str| {
+            move |passes: &[&str]| {
+                if passes.contains(&broken) {
+                    execution("no", &[])
+                } else {
+                    execution("ok", &[])
+                }
+            }
+        };
+        let mismatch = Mismatch::Output {
+            offset: 0,
+            expected: Some(b'o'),
+            actual: Some(b'n'),
+        };
+
+        assert_eq!(
+            find_divergence(&expected, &pass_names, run_breaking("none")),
+            None
+        );
+        for broken in pass_names {
+            assert_eq!(
+                find_divergence(&expected, &pass_names, run_breaking(broken)),
+                Some(Divergence {
+                    mismatch: mismatch.clone(),
+                    pass: Some(broken),
+                })
+            );
+        }
+        assert_eq!(
+            find_divergence(&expected, &pass_names, |_: &[&str]| execution("no", &[])),
+            Some(Divergence {
+                mismatch,
+                pass: None,
+            })
+        );
+        // A pass which makes the program stop with an error.
+        assert_eq!(
+            find_divergence(&expected, &pass_names, |passes: &[&str]| {
+                if passes.contains(&"second") {
+                    failed("ok", "Out of memory.")
+                } else {
+                    execution("ok", &[])
+                }
+            }),
+            Some(Divergence {
+                mismatch: Mismatch::Error {
+                    expected: None,
+                    actual: Some("Out of memory.".to_owned()),
+                },
+                pass: Some("second"),
+            })
+        );
+    }
+
+    #[test]
+    fn divergence_display_test() {
+        assert_eq!(
+            Divergence {
+                mismatch: Mismatch::Output {
+                    offset: 4,
+                    expected: Some(b'A'),
+                    actual: None,
+                },
+                pass: Some("move-data"),
+            }
+            .to_string(),
+            "Output byte 4 differs: expected 0x41, got end of output after the 'move-data' pass."
+        );
+        assert_eq!(
+            Divergence {
+                mismatch: Mismatch::Tape {
+                    cell: 7,
+                    expected: 0,
+                    actual: 255,
+                },
+                pass: None,
+            }
+            .to_string(),
+            "Tape cell 7 differs: expected 0x00, got 0xff with no passes enabled."
+        );
+        assert_eq!(
+            Divergence {
+                mismatch: Mismatch::Error {
+                    expected: None,
+                    actual: Some("Out of memory.".to_owned()),
+                },
+                pass: Some("move-data"),
+            }
+            .to_string(),
+            "The program stopped with 'Out of memory.', but should have finished after the \
+             'move-data' pass."
+        );
+        assert_eq!(
+            Divergence {
+                mismatch: Mismatch::Error {
+                    expected: Some("Out of memory.".to_owned()),
+                    actual: None,
+                },
+                pass: None,
+            }
+            .to_string(),
+            "The program finished, but should have stopped with 'Out of memory.' with no passes \
+             enabled."
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use {
+    std::{
+        env, fs,
+        io::{self, Read},
+        process,
+    },
+    util::{
+        run::{check_default_tape, parse_args, SupportedOptions},
+        BfError,
+    },
+    validate::{validate, Vm},
+};
+
+fn main() -> Result<(), BfError> {
+    let mut vm = Vm::Opinterp3;
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--vm=") {
+            Some(name) => vm = Vm::from_name(name)?,
+            None => args.push(arg),
+        }
+    }
+
+    let supported = SupportedOptions {
+        passes: true,
+        ..SupportedOptions::NONE
+    };
+    let (filepath, options) = parse_args(args.into_iter(), &supported)?;
+    check_default_tape(&options)?;
+    let source_code = fs::read_to_string(filepath)?;
+    let mut input = vec![];
+    io::stdin().read_to_end(&mut input)?;
+
+    match validate(&source_code, &input, vm, &options.passes)? {
+        Some(divergence) => {
+            println!("{divergence}");
+            process::exit(1);
+        }
+        None => println!("No divergence found."),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use opinterp3::{Instruction, Program};
+
+// Bounds past this are treated as unbounded, which also keeps the arithmetic
+// below well clear of overflowing.
+const UNBOUNDED: i128 = 1 << 80;
+
+// The number of times the pointer range at an instruction may grow before it's
+// widened to unbounded, so that loops which keep moving the pointer in one
+// direction still reach a fixed point.
+const MAX_UPDATES: usize = 2;
+
+// The range of values the data pointer might have at some point in the program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Interval {
+    min: i128,
+    max: i128,
+}
+
+impl Interval {
+    fn shift(self, offset: i128) -> Self {
+        Self {
+            min: (self.min + offset).clamp(-UNBOUNDED, UNBOUNDED),
+            max: (self.max + offset).clamp(-UNBOUNDED, UNBOUNDED),
+        }
+    }
+
+    fn join(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    // Gives up on whichever bound is still moving.
+    fn widen(self, next: Self) -> Self {
+        Self {
+            min: if next.min < self.min {
+                -UNBOUNDED
+            } else {
+                self.min
+            },
+            max: if next.max > self.max {
+                UNBOUNDED
+            } else {
+                self.max
+            },
+        }
+    }
+
+    fn within(self, memory_size: usize) -> bool {
+        self.min >= 0 && self.max < memory_size as i128
+    }
+}
+
+// Works out whether the data pointer provably stays inside memory for the whole
+// program, by tracking the range of values it can have at each instruction. This
+// counts every position the pointer passes through, and not just the cells
+// which are read or written, since the interpreter loads the new current cell
+// whenever the pointer moves.
+pub fn pointer_in_bounds(program: &Program, memory_size: usize) -> bool {
+    let instructions = &program.instructions;
+    let mut states: Vec<Option<Interval>> = vec![None; instructions.len()];
+    let mut updates = vec![0; instructions.len()];
+    let mut worklist = vec![];
+
+    if !instructions.is_empty() {
+        states[0] = Some(Interval { min: 0, max: 0 });
+        worklist.push(0);
+    }
+
+    let mut positions = vec![];
+    while let Some(pc) = worklist.pop() {
+        let Some(pointer) = states[pc] else {
+            continue;
+        };
+
+        positions.clear();
+        let next = step(&instructions[pc], pointer, &mut positions);
+
+        for successor in successors(&instructions[pc], pc) {
+            let Some(state) = states.get_mut(successor) else {
+                continue;
+            };
+
+            let updated = match *state {
+                None => next,
+                Some(current) => {
+                    let joined = current.join(next);
+                    if joined == current {
+                        continue;
+                    }
+
+                    updates[successor] += 1;
+                    if updates[successor] > MAX_UPDATES {
+                        current.widen(joined)
+                    } else {
+                        joined
+                    }
+                }
+            };
+
+            *state = Some(updated);
+            worklist.push(successor);
+        }
+    }
+
+    instructions
+        .iter()
+        .zip(&states)
+        .all(|(instruction, state)| {
+            let Some(pointer) = *state else {
+                // Never run.
+                return true;
+            };
+
+            positions.clear();
+            step(instruction, pointer, &mut positions);
+            positions
+                .iter()
+                .all(|position| position.within(memory_size))
+        })
+}
+
+// Returns the range of the pointer after the instruction, and adds every range
+// of cells the instruction touches on the way to `positions`.
+fn step(instruction: &Instruction, pointer: Interval, positions: &mut Vec<Interval>) -> Interval {
+    positions.push(pointer);
+
+    match *instruction {
+        Instruction::IncPtr { count } => move_by(pointer, count as i128, positions),
+        Instruction::DecPtr { count } => move_by(pointer, -(count as i128), positions),
+        Instruction::IncData { .. }
+        | Instruction::DecData { .. }
+        | Instruction::Read { .. }
+        | Instruction::Write { .. }
+        | Instruction::JumpBegin { .. }
+        | Instruction::JumpEnd { .. }
+        | Instruction::ConditionalBegin { .. }
+        | Instruction::SetDataToZero => pointer,
+        // There's no telling how far a scan goes.
+        Instruction::MovePtrUntilZero { forward, .. } => {
+            let pointer = if forward {
+                Interval {
+                    min: pointer.min,
+                    max: UNBOUNDED,
+                }
+            } else {
+                Interval {
+                    min: -UNBOUNDED,
+                    max: pointer.max,
+                }
+            };
+            positions.push(pointer);
+            pointer
+        }
+        Instruction::MoveData {
+            forward, amount, ..
+        } => {
+            move_data_target(pointer, forward, amount, positions);
+            pointer
+        }
+        Instruction::MovePtrAddData { offset, .. }
+        | Instruction::MovePtrSetDataToZero { offset }
+        | Instruction::MovePtrJumpIfZero { offset, .. }
+        | Instruction::MovePtrJumpEnd { offset, .. }
+        | Instruction::AddDataMovePtr { offset, .. } => move_by(pointer, offset as i128, positions),
+        Instruction::MovePtrAddDataMovePtr {
+            offset,
+            second_offset,
+            ..
+        } => {
+            let pointer = move_by(pointer, offset as i128, positions);
+            move_by(pointer, second_offset as i128, positions)
+        }
+        Instruction::MovePtrMoveData {
+            offset,
+            forward,
+            amount,
+            ..
+        } => {
+            let pointer = move_by(pointer, offset as i128, positions);
+            move_data_target(pointer, forward, amount, positions);
+            pointer
+        }
+        Instruction::MovePtrMoveDataMovePtr {
+            offset,
+            forward,
+            amount,
+            second_offset,
+            ..
+        } => {
+            let pointer = move_by(pointer, offset as i128, positions);
+            move_data_target(pointer, forward, amount, positions);
+            move_by(pointer, second_offset as i128, positions)
+        }
+    }
+}
+
+fn move_by(pointer: Interval, offset: i128, positions: &mut Vec<Interval>) -> Interval {
+    let pointer = pointer.shift(offset);
+    positions.push(pointer);
+    pointer
+}
+
+fn move_data_target(
+    pointer: Interval,
+    forward: bool,
+    amount: usize,
+    positions: &mut Vec<Interval>,
+) {
+    let amount = amount as i128;
+    positions.push(pointer.shift(if forward { amount } else { -amount }));
+}
+
+// Jump destinations point at the instruction before the one execution carries
+// on from.
+fn successors(instruction: &Instruction, pc: usize) -> impl Iterator<Item = usize> {
+    let destination = match *instruction {
+        Instruction::JumpBegin { destination }
+        | Instruction::JumpEnd { destination }
+        | Instruction::ConditionalBegin { destination }
+        | Instruction::MovePtrJumpIfZero { destination, .. }
+        | Instruction::MovePtrJumpEnd { destination, .. } => Some(destination + 1),
+        _ => None,
+    };
+
+    [Some(pc + 1), destination].into_iter().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::pointer_in_bounds, util::run::RunOptions};
+
+    fn in_bounds(source_code: &str, memory_size: usize) -> bool {
+        let program = opinterp3::compile(source_code, &RunOptions::default()).unwrap();
+        pointer_in_bounds(&program, memory_size)
+    }
+
+    #[test]
+    fn straight_line_test() {
+        assert!(in_bounds("", 1));
+        assert!(in_bounds("+>+>+<<-.", 3));
+        assert!(!in_bounds("+>+>+<<-.", 2));
+        assert!(!in_bounds("<+", 30000));
+        // Moving the pointer past the end counts, even without touching the
+        // cell there.
+        assert!(!in_bounds(">><<", 2));
+        assert!(in_bounds(&">".repeat(29999), 30000));
+        assert!(!in_bounds(&">".repeat(30000), 30000));
+    }
+
+    #[test]
+    fn loop_test() {
+        // Loops which leave the pointer where they found it.
+        assert!(in_bounds("++[>+++[>++<-]<-]>>.", 3));
+        assert!(in_bounds(">+[-<+>]<[>>+<<-]", 3));
+        assert!(!in_bounds(">+[-<+>]<[>>+<<-]", 2));
+        // Loops which don't.
+        assert!(!in_bounds("+[>+]", 30000));
+        assert!(!in_bounds("+[<+>-<]", 30000));
+        // Conditionals only run once, so are fine either way.
+        assert!(in_bounds("+[>[-]<[-]]", 2));
+    }
+
+    #[test]
+    fn scan_test() {
+        assert!(!in_bounds("+[>]", 30000));
+        assert!(!in_bounds(">>+[<]", 30000));
+    }
+}
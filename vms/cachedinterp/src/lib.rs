@@ -0,0 +1,32 @@
+use {
+    std::io::{Read, Write},
+    util::{run::RunOptions, BfResult},
+};
+
+mod analysis;
+mod vm;
+
+pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    let program = opinterp3::compile(source_code, options)?;
+
+    if analysis::pointer_in_bounds(&program, vm::MEMORY_SIZE) {
+        if options.report_passes {
+            eprintln!("bounds checks: removed, the data pointer stays in memory");
+        }
+        vm::run::<false>(&program, stdin, stdout)
+    } else {
+        if options.report_passes {
+            eprintln!("bounds checks: kept, the data pointer may leave memory");
+        }
+        vm::run::<true>(&program, stdin, stdout)
+    }
+}
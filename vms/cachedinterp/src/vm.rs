@@ -0,0 +1,231 @@
+use {
+    opinterp3::{Instruction, Program},
+    std::io::{Read, Write},
+    util::{
+        math::{unbalanced_wrapping_add, unbalanced_wrapping_sub},
+        scan::move_ptr_until_zero,
+        BfResult,
+    },
+};
+
+pub const MEMORY_SIZE: usize = 30000;
+
+struct Memory<const CHECKED: bool> {
+    cells: [u8; MEMORY_SIZE],
+}
+
+impl<const CHECKED: bool> Memory<CHECKED> {
+    fn load(&self, data_pointer: usize) -> u8 {
+        if CHECKED {
+            self.cells[data_pointer]
+        } else {
+            // SAFETY: Memory is only unchecked once the analysis has proven
+            // that the data pointer never leaves it.
+            unsafe { *self.cells.get_unchecked(data_pointer) }
+        }
+    }
+
+    fn store(&mut self, data_pointer: usize, value: u8) {
+        if CHECKED {
+            self.cells[data_pointer] = value;
+        } else {
+            // SAFETY: As above.
+            unsafe { *self.cells.get_unchecked_mut(data_pointer) = value }
+        }
+    }
+
+    // Writes back the current cell, which is always in bounds: the data pointer
+    // only ever changes by moving to a cell which is then loaded, and a checked
+    // load would have panicked if it had left memory.
+    fn store_current(&mut self, data_pointer: usize, value: u8) {
+        // SAFETY: As above.
+        unsafe { *self.cells.get_unchecked_mut(data_pointer) = value }
+    }
+
+    fn add(&mut self, data_pointer: usize, value: u8) {
+        self.store(data_pointer, self.load(data_pointer).wrapping_add(value));
+    }
+}
+
+// Runs the program with the value of the current cell kept in a local, only
+// writing it back to memory when the data pointer moves. CHECKED should only
+// be false if the data pointer has been proven to stay inside memory.
+pub fn run<const CHECKED: bool>(
+    program: &Program,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    let mut memory = Memory::<CHECKED> {
+        cells: [0; MEMORY_SIZE],
+    };
+    let mut pc = 0;
+    let mut data_pointer = 0;
+    let mut cell = 0;
+
+    macro_rules! move_ptr {
+        ($data_pointer:expr) => {{
+            memory.store_current(data_pointer, cell);
+            data_pointer = $data_pointer;
+            cell = memory.load(data_pointer);
+        }};
+    }
+
+    while pc < program.instructions.len() {
+        match program.instructions[pc] {
+            Instruction::IncPtr { count } => move_ptr!(data_pointer + count),
+            Instruction::DecPtr { count } => move_ptr!(data_pointer - count),
+            Instruction::IncData { count } => cell = unbalanced_wrapping_add(cell, count),
+            Instruction::DecData { count } => cell = unbalanced_wrapping_sub(cell, count),
+            Instruction::Read { count } => {
+                for _ in 0..count {
+                    cell = read(stdin)?;
+                }
+            }
+            Instruction::Write { count } => {
+                for _ in 0..count {
+                    write(stdout, cell)?;
+                }
+            }
+            Instruction::JumpBegin { destination }
+            | Instruction::ConditionalBegin { destination } => {
+                if cell == 0 {
+                    pc = destination;
+                }
+            }
+            Instruction::JumpEnd { destination } => {
+                if cell != 0 {
+                    pc = destination;
+                }
+            }
+            Instruction::SetDataToZero => cell = 0,
+            Instruction::MovePtrUntilZero {
+                count,
+                forward,
+                amount,
+            } => {
+                // The scan reads memory directly, so it needs to see the
+                // current cell.
+                memory.store_current(data_pointer, cell);
+                for _ in 0..count {
+                    data_pointer =
+                        move_ptr_until_zero(&memory.cells, data_pointer, forward, amount);
+                }
+                cell = memory.load(data_pointer);
+            }
+            Instruction::MoveData {
+                count,
+                forward,
+                amount,
+            } => move_data(&mut memory, data_pointer, &mut cell, count, forward, amount),
+            Instruction::MovePtrAddData { offset, delta } => {
+                move_ptr!(data_pointer.wrapping_add_signed(offset));
+                cell = cell.wrapping_add(delta);
+            }
+            Instruction::AddDataMovePtr { delta, offset } => {
+                cell = cell.wrapping_add(delta);
+                move_ptr!(data_pointer.wrapping_add_signed(offset));
+            }
+            Instruction::MovePtrAddDataMovePtr {
+                offset,
+                delta,
+                second_offset,
+            } => {
+                // The cell in the middle is never the current cell for long
+                // enough to be worth loading.
+                let middle = data_pointer.wrapping_add_signed(offset);
+                if offset == 0 {
+                    cell = cell.wrapping_add(delta);
+                } else {
+                    memory.add(middle, delta);
+                }
+                move_ptr!(middle.wrapping_add_signed(second_offset));
+            }
+            Instruction::MovePtrSetDataToZero { offset } => {
+                // No need to load a cell which is about to be cleared, but it
+                // still has to go through a checked access.
+                memory.store_current(data_pointer, cell);
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                memory.store(data_pointer, 0);
+                cell = 0;
+            }
+            Instruction::MovePtrMoveData {
+                offset,
+                count,
+                forward,
+                amount,
+            } => {
+                move_ptr!(data_pointer.wrapping_add_signed(offset));
+                move_data(&mut memory, data_pointer, &mut cell, count, forward, amount);
+            }
+            Instruction::MovePtrMoveDataMovePtr {
+                offset,
+                count,
+                forward,
+                amount,
+                second_offset,
+            } => {
+                move_ptr!(data_pointer.wrapping_add_signed(offset));
+                move_data(&mut memory, data_pointer, &mut cell, count, forward, amount);
+                move_ptr!(data_pointer.wrapping_add_signed(second_offset));
+            }
+            Instruction::MovePtrJumpIfZero {
+                offset,
+                destination,
+            } => {
+                move_ptr!(data_pointer.wrapping_add_signed(offset));
+                if cell == 0 {
+                    pc = destination;
+                }
+            }
+            Instruction::MovePtrJumpEnd {
+                offset,
+                destination,
+            } => {
+                move_ptr!(data_pointer.wrapping_add_signed(offset));
+                if cell != 0 {
+                    pc = destination;
+                }
+            }
+        }
+
+        pc += 1;
+    }
+
+    Ok(())
+}
+
+fn move_data<const CHECKED: bool>(
+    memory: &mut Memory<CHECKED>,
+    data_pointer: usize,
+    cell: &mut u8,
+    count: usize,
+    forward: bool,
+    amount: usize,
+) {
+    for _ in 0..count {
+        if *cell != 0 {
+            let move_to_ptr = if forward {
+                data_pointer + amount
+            } else {
+                data_pointer - amount
+            };
+
+            memory.add(move_to_ptr, *cell);
+            *cell = 0;
+        }
+    }
+}
+
+fn read(stdin: &mut dyn Read) -> BfResult<u8> {
+    let mut c = [0; 1];
+    stdin.read_exact(&mut c)?;
+
+    Ok(c[0])
+}
+
+fn write(stdout: &mut dyn Write, byte: u8) -> BfResult<()> {
+    stdout.write_all(&[byte])?;
+    stdout.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,468 @@
+use {
+    crate::runtime::{read, write},
+    cranelift_codegen::{
+        ir::{condcodes::IntCC, types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Value},
+        settings::{self, Configurable},
+    },
+    cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable},
+    cranelift_jit::{JITBuilder, JITModule},
+    cranelift_module::{default_libcall_names, Linkage, Module},
+    opinterp3::{Instruction, Program},
+    std::fmt::Display,
+    util::{
+        scan::{is_vectorizable_stride, lane_mask},
+        BfError, BfResult,
+    },
+};
+
+pub struct CompiledProgram {
+    // Owns the memory the code lives in.
+    _module: JITModule,
+    code: *const u8,
+}
+
+impl CompiledProgram {
+    pub fn code(&self) -> *const u8 {
+        self.code
+    }
+}
+
+// Translates the program into a single Cranelift function and compiles it for
+// the host. Cranelift takes care of register allocation and instruction
+// selection, so unlike opjit there's nothing here specific to an architecture.
+pub fn compile(program: &Program) -> BfResult<CompiledProgram> {
+    let mut flag_builder = settings::builder();
+    flag_builder
+        .set("opt_level", "speed")
+        .map_err(codegen_error)?;
+    let isa = cranelift_native::builder()
+        .map_err(codegen_error)?
+        .finish(settings::Flags::new(flag_builder))
+        .map_err(codegen_error)?;
+
+    let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
+    jit_builder.symbol("bf_read", read as *const u8);
+    jit_builder.symbol("bf_write", write as *const u8);
+    let mut module = JITModule::new(jit_builder);
+    let pointer_type = module.target_config().pointer_type();
+
+    let mut read_signature = module.make_signature();
+    read_signature.params.push(AbiParam::new(pointer_type));
+    read_signature.returns.push(AbiParam::new(types::I32));
+    let read_id = module
+        .declare_function("bf_read", Linkage::Import, &read_signature)
+        .map_err(codegen_error)?;
+
+    let mut write_signature = module.make_signature();
+    write_signature.params.push(AbiParam::new(pointer_type));
+    write_signature.params.push(AbiParam::new(types::I8).uext());
+    write_signature
+        .returns
+        .push(AbiParam::new(types::I8).uext());
+    let write_id = module
+        .declare_function("bf_write", Linkage::Import, &write_signature)
+        .map_err(codegen_error)?;
+
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer_type));
+    signature.params.push(AbiParam::new(pointer_type));
+    signature.returns.push(AbiParam::new(types::I8).uext());
+    let main_id = module
+        .declare_function("main", Linkage::Local, &signature)
+        .map_err(codegen_error)?;
+
+    let mut context = module.make_context();
+    context.func.signature = signature;
+    let mut function_builder_context = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut function_builder_context);
+        let read = module.declare_func_in_func(read_id, builder.func);
+        let write = module.declare_func_in_func(write_id, builder.func);
+
+        let entry_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.switch_to_block(entry_block);
+        let memory = builder.block_params(entry_block)[0];
+        let io = builder.block_params(entry_block)[1];
+
+        let data_pointer = Variable::from_u32(0);
+        builder.declare_var(data_pointer, pointer_type);
+        builder.def_var(data_pointer, memory);
+
+        let mut translator = Translator {
+            error_block: builder.create_block(),
+            builder,
+            data_pointer,
+            io,
+            read,
+            write,
+        };
+        translator.translate(&program.instructions)?;
+        translator.builder.finalize();
+    }
+
+    module
+        .define_function(main_id, &mut context)
+        .map_err(codegen_error)?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().map_err(codegen_error)?;
+
+    Ok(CompiledProgram {
+        code: module.get_finalized_function(main_id),
+        _module: module,
+    })
+}
+
+fn codegen_error(err: impl Display) -> BfError {
+    BfError::Assembler(format!("{err}"))
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    data_pointer: Variable,
+    io: Value,
+    read: FuncRef,
+    write: FuncRef,
+    // Returns 1 from the program after a failed read or write.
+    error_block: Block,
+}
+
+impl Translator<'_> {
+    fn translate(&mut self, instructions: &[Instruction]) -> BfResult<()> {
+        // The start and end of the body of each loop currently open.
+        let mut open_loops = vec![];
+        // The last instruction of each conditional currently open, and the
+        // block which carries on after it.
+        let mut open_conditionals = vec![];
+
+        for (position, instruction) in instructions.iter().enumerate() {
+            match *instruction {
+                Instruction::IncPtr { count } => self.move_ptr(count.try_into()?),
+                Instruction::DecPtr { count } => self.move_ptr(-i64::try_from(count)?),
+                Instruction::IncData { count } => self.add((count % 256) as i64),
+                Instruction::DecData { count } => self.add(-((count % 256) as i64)),
+                Instruction::Read { count } => {
+                    for _ in 0..count {
+                        self.read();
+                    }
+                }
+                Instruction::Write { count } => {
+                    for _ in 0..count {
+                        self.write();
+                    }
+                }
+                Instruction::JumpBegin { .. } => open_loops.push(self.jump_begin()),
+                Instruction::JumpEnd { .. } => self.jump_end(&mut open_loops, position)?,
+                Instruction::ConditionalBegin { destination } => {
+                    open_conditionals.push((destination, self.conditional_begin()));
+                }
+                Instruction::SetDataToZero => self.set(0),
+                Instruction::MovePtrUntilZero {
+                    count,
+                    forward,
+                    amount,
+                } => {
+                    for _ in 0..count {
+                        self.move_ptr_until_zero(forward, amount)?;
+                    }
+                }
+                Instruction::MoveData {
+                    count,
+                    forward,
+                    amount,
+                } => self.move_data(count, forward, amount)?,
+                Instruction::MovePtrAddData { offset, delta } => {
+                    self.move_ptr(offset.try_into()?);
+                    self.add(delta.into());
+                }
+                Instruction::AddDataMovePtr { delta, offset } => {
+                    self.add(delta.into());
+                    self.move_ptr(offset.try_into()?);
+                }
+                Instruction::MovePtrAddDataMovePtr {
+                    offset,
+                    delta,
+                    second_offset,
+                } => {
+                    self.move_ptr(offset.try_into()?);
+                    self.add(delta.into());
+                    self.move_ptr(second_offset.try_into()?);
+                }
+                Instruction::MovePtrSetDataToZero { offset } => {
+                    self.move_ptr(offset.try_into()?);
+                    self.set(0);
+                }
+                Instruction::MovePtrMoveData {
+                    offset,
+                    count,
+                    forward,
+                    amount,
+                } => {
+                    self.move_ptr(offset.try_into()?);
+                    self.move_data(count, forward, amount)?;
+                }
+                Instruction::MovePtrMoveDataMovePtr {
+                    offset,
+                    count,
+                    forward,
+                    amount,
+                    second_offset,
+                } => {
+                    self.move_ptr(offset.try_into()?);
+                    self.move_data(count, forward, amount)?;
+                    self.move_ptr(second_offset.try_into()?);
+                }
+                Instruction::MovePtrJumpIfZero {
+                    offset,
+                    destination,
+                } => {
+                    self.move_ptr(offset.try_into()?);
+                    if is_loop_begin(instructions, position, destination) {
+                        open_loops.push(self.jump_begin());
+                    } else {
+                        open_conditionals.push((destination, self.conditional_begin()));
+                    }
+                }
+                Instruction::MovePtrJumpEnd { offset, .. } => {
+                    self.move_ptr(offset.try_into()?);
+                    self.jump_end(&mut open_loops, position)?;
+                }
+            }
+
+            // Conditionals which end together were opened outermost first.
+            while let Some(&(end, after)) = open_conditionals.last() {
+                if end != position {
+                    break;
+                }
+
+                open_conditionals.pop();
+                self.builder.ins().jump(after, &[]);
+                self.builder.switch_to_block(after);
+            }
+        }
+
+        if !open_loops.is_empty() || !open_conditionals.is_empty() {
+            return Err(BfError::Bf(
+                "Unmatched opening '[' at the end of the program.".to_owned(),
+            ));
+        }
+
+        let success = self.builder.ins().iconst(types::I8, 0);
+        self.builder.ins().return_(&[success]);
+
+        self.builder.switch_to_block(self.error_block);
+        let failure = self.builder.ins().iconst(types::I8, 1);
+        self.builder.ins().return_(&[failure]);
+
+        self.builder.seal_all_blocks();
+
+        Ok(())
+    }
+
+    fn data_pointer(&mut self) -> Value {
+        self.builder.use_var(self.data_pointer)
+    }
+
+    fn move_ptr(&mut self, offset: i64) {
+        if offset != 0 {
+            let data_pointer = self.data_pointer();
+            let moved = self.builder.ins().iadd_imm(data_pointer, offset);
+            self.builder.def_var(self.data_pointer, moved);
+        }
+    }
+
+    fn load(&mut self, offset: i32) -> Value {
+        let data_pointer = self.data_pointer();
+        self.builder
+            .ins()
+            .load(types::I8, MemFlags::trusted(), data_pointer, offset)
+    }
+
+    fn store(&mut self, value: Value, offset: i32) {
+        let data_pointer = self.data_pointer();
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, data_pointer, offset);
+    }
+
+    fn add(&mut self, delta: i64) {
+        let cell = self.load(0);
+        let sum = self.builder.ins().iadd_imm(cell, delta);
+        self.store(sum, 0);
+    }
+
+    fn set(&mut self, value: i64) {
+        let value = self.builder.ins().iconst(types::I8, value);
+        self.store(value, 0);
+    }
+
+    fn read(&mut self) {
+        let call = self.builder.ins().call(self.read, &[self.io]);
+        let result = self.builder.inst_results(call)[0];
+
+        let failed = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::SignedLessThan, result, 0);
+        self.branch_to_error_block(failed);
+
+        let byte = self.builder.ins().ireduce(types::I8, result);
+        self.store(byte, 0);
+    }
+
+    fn write(&mut self) {
+        let cell = self.load(0);
+        let call = self.builder.ins().call(self.write, &[self.io, cell]);
+        let failed = self.builder.inst_results(call)[0];
+        self.branch_to_error_block(failed);
+    }
+
+    fn branch_to_error_block(&mut self, condition: Value) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, self.error_block, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    // Returns the first block of the loop's body and the block after it.
+    fn jump_begin(&mut self) -> (Block, Block) {
+        let body = self.builder.create_block();
+        let after = self.builder.create_block();
+
+        let cell = self.load(0);
+        self.builder.ins().brif(cell, body, &[], after, &[]);
+        self.builder.switch_to_block(body);
+
+        (body, after)
+    }
+
+    fn jump_end(&mut self, open_loops: &mut Vec<(Block, Block)>, position: usize) -> BfResult<()> {
+        let (body, after) = open_loops
+            .pop()
+            .ok_or_else(|| BfError::Bf(format!("Unmatched closing ']' at position {position}.")))?;
+
+        let cell = self.load(0);
+        self.builder.ins().brif(cell, body, &[], after, &[]);
+        self.builder.switch_to_block(after);
+
+        Ok(())
+    }
+
+    // Returns the block after the conditional.
+    fn conditional_begin(&mut self) -> Block {
+        let body = self.builder.create_block();
+        let after = self.builder.create_block();
+
+        let cell = self.load(0);
+        self.builder.ins().brif(cell, body, &[], after, &[]);
+        self.builder.switch_to_block(body);
+
+        after
+    }
+
+    fn move_ptr_until_zero(&mut self, forward: bool, amount: usize) -> BfResult<()> {
+        if is_vectorizable_stride(amount) {
+            self.vector_move_ptr_until_zero(forward, amount);
+            return Ok(());
+        }
+
+        let amount = i64::try_from(amount)?;
+        let check = self.builder.create_block();
+        let step = self.builder.create_block();
+        let after = self.builder.create_block();
+
+        self.builder.ins().jump(check, &[]);
+        self.builder.switch_to_block(check);
+        let cell = self.load(0);
+        self.builder.ins().brif(cell, step, &[], after, &[]);
+
+        self.builder.switch_to_block(step);
+        self.move_ptr(if forward { amount } else { -amount });
+        self.builder.ins().jump(check, &[]);
+
+        self.builder.switch_to_block(after);
+
+        Ok(())
+    }
+
+    // Compares 16 cells against zero at a time like opjit does, masking out
+    // the lanes the stride skips over. Loads reach up to 15 cells outside of
+    // the ones visited, which the tape's padding allows for. Lane 0 is the
+    // lowest bit of the mask, so forward scans find the first zero by counting
+    // trailing zeros, and backward scans the last one by counting leading
+    // zeros.
+    fn vector_move_ptr_until_zero(&mut self, forward: bool, amount: usize) {
+        const LANES: i64 = 16;
+        let load_offset = if forward { 0 } else { 1 - LANES };
+        let lane_mask = lane_mask(LANES as usize, forward, amount);
+        let pointer_type = self.builder.func.dfg.value_type(self.io);
+
+        let check = self.builder.create_block();
+        let step = self.builder.create_block();
+        let found = self.builder.create_block();
+
+        self.builder.ins().jump(check, &[]);
+        self.builder.switch_to_block(check);
+        let data_pointer = self.data_pointer();
+        let cells = self.builder.ins().load(
+            types::I8X16,
+            MemFlags::new().with_notrap(),
+            data_pointer,
+            load_offset as i32,
+        );
+        let zero = self.builder.ins().iconst(types::I8, 0);
+        let zeros = self.builder.ins().splat(types::I8X16, zero);
+        let zero_lanes = self.builder.ins().icmp(IntCC::Equal, cells, zeros);
+        let bits = self.builder.ins().vhigh_bits(types::I32, zero_lanes);
+        let bits = self.builder.ins().band_imm(bits, i64::from(lane_mask));
+        self.builder.ins().brif(bits, found, &[], step, &[]);
+
+        self.builder.switch_to_block(step);
+        self.move_ptr(if forward { LANES } else { -LANES });
+        self.builder.ins().jump(check, &[]);
+
+        self.builder.switch_to_block(found);
+        let lane = if forward {
+            self.builder.ins().ctz(bits)
+        } else {
+            let leading_zeros = self.builder.ins().clz(bits);
+            self.builder.ins().irsub_imm(leading_zeros, 31)
+        };
+        let lane = self.builder.ins().uextend(pointer_type, lane);
+        let data_pointer = self.data_pointer();
+        let moved = self.builder.ins().iadd(data_pointer, lane);
+        self.builder.def_var(self.data_pointer, moved);
+        self.move_ptr(load_offset);
+    }
+
+    // Adding a zero cell and then clearing it changes nothing, so there's no
+    // need to branch on it. Repeating the move does nothing either, since the
+    // cell is zero afterwards.
+    fn move_data(&mut self, count: usize, forward: bool, amount: usize) -> BfResult<()> {
+        if count > 0 {
+            let amount = i32::try_from(amount)?;
+            let offset = if forward { amount } else { -amount };
+
+            let cell = self.load(0);
+            let target = self.load(offset);
+            let sum = self.builder.ins().iadd(target, cell);
+            self.store(sum, offset);
+            self.set(0);
+        }
+
+        Ok(())
+    }
+}
+
+// A fused pointer move and jump starts a loop if the instruction it jumps to
+// jumps back to it, and a conditional otherwise.
+fn is_loop_begin(instructions: &[Instruction], position: usize, destination: usize) -> bool {
+    matches!(
+        instructions.get(destination),
+        Some(
+            Instruction::JumpEnd { destination: begin }
+                | Instruction::MovePtrJumpEnd { destination: begin, .. }
+        ) if *begin == position
+    )
+}
@@ -0,0 +1,74 @@
+use {
+    crate::compiler::CompiledProgram,
+    std::{
+        io::{Read, Write},
+        mem,
+    },
+    util::{
+        asm::{MEMORY_PADDING, TAPE_SIZE},
+        BfError, BfResult,
+    },
+};
+
+// Compiled programs take the start of memory and the program's I/O, and return
+// nonzero if reading or writing failed.
+type EntryPoint = extern "C" fn(*mut u8, *mut Io) -> u8;
+
+pub struct Io<'a> {
+    stdin: &'a mut dyn Read,
+    stdout: &'a mut dyn Write,
+    // Set when reading or writing fails, for run to return once the compiled
+    // program has stopped.
+    error: Option<BfError>,
+}
+
+pub fn run(
+    compiled_program: &CompiledProgram,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    // Laid out like a Runtime's tape, padded for vectorized scans.
+    let mut tape = vec![0; TAPE_SIZE];
+    let mut io = Io {
+        stdin,
+        stdout,
+        error: None,
+    };
+
+    let entry_point = unsafe { mem::transmute::<*const u8, EntryPoint>(compiled_program.code()) };
+    if entry_point(tape[MEMORY_PADDING..].as_mut_ptr(), &mut io) != 0 {
+        return Err(io
+            .error
+            .take()
+            .unwrap_or_else(|| BfError::Bf("The program stopped without an error.".to_owned())));
+    }
+
+    Ok(())
+}
+
+// Returns the byte read, or -1 if reading failed.
+pub extern "C" fn read(io: &mut Io) -> i32 {
+    let mut c = [0; 1];
+    match io.stdin.read_exact(&mut c) {
+        Ok(()) => c[0].into(),
+        Err(err) => {
+            io.error = Some(err.into());
+            -1
+        }
+    }
+}
+
+// Returns nonzero if writing failed.
+pub extern "C" fn write(io: &mut Io, byte: u8) -> u8 {
+    match io
+        .stdout
+        .write_all(&[byte])
+        .and_then(|()| io.stdout.flush())
+    {
+        Ok(()) => 0,
+        Err(err) => {
+            io.error = Some(err.into());
+            1
+        }
+    }
+}
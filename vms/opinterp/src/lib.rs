@@ -7,6 +7,16 @@ mod parser;
 mod vm;
 
 pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    run_with_tape(source_code, stdin, stdout)?;
+    Ok(())
+}
+
+// Also returns the contents of the tape once the program finishes.
+pub fn run_with_tape(
+    source_code: &str,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<Vec<u8>> {
     let program = parser::parse(source_code)?;
     vm::run(program, stdin, stdout)
 }
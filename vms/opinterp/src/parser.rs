@@ -43,7 +43,7 @@ pub fn parse(source_code: &str) -> BfResult<Program> {
     })
 }
 
-fn create_jump_table(instructions: &Vec<Instruction>) -> BfResult<Vec<usize>> {
+fn create_jump_table(instructions: &[Instruction]) -> BfResult<Vec<usize>> {
     let mut pc = 0;
     let mut jump_table = vec![0; instructions.len()];
 
@@ -6,7 +6,7 @@ use {
 
 const MEMORY_SIZE: usize = 30000;
 
-pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<Vec<u8>> {
     let mut memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
     let mut pc = 0;
     let mut data_pointer = 0;
@@ -30,7 +30,7 @@ pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> Bf
         pc += 1;
     }
 
-    Ok(())
+    Ok(memory.to_vec())
 }
 
 fn read(stdin: &mut dyn Read) -> BfResult<u8> {
@@ -0,0 +1,686 @@
+use {
+    crate::parser::{Instruction, Program},
+    util::{BfError, BfResult},
+};
+
+// A .bfc file starts with the magic bytes and format version, then a CRC-32 of
+// everything after it: the header and the encoded instructions.
+const MAGIC: &[u8; 4] = b"BFC\0";
+pub const FORMAT_VERSION: u16 = 1;
+const PREAMBLE_LENGTH: usize = MAGIC.len() + 2 + 4;
+
+// The optimizations the program was compiled with, kept around for reporting.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Header {
+    pub passes: Vec<String>,
+    pub superinstructions: Vec<String>,
+}
+
+pub fn encode(header: &Header, program: &Program) -> Vec<u8> {
+    let mut body = Encoder { bytes: vec![] };
+    body.strings(&header.passes);
+    body.strings(&header.superinstructions);
+    body.usize(program.instructions.len());
+    for instruction in &program.instructions {
+        body.instruction(instruction);
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(crc32(&body.bytes).to_le_bytes());
+    bytes.extend(body.bytes);
+
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> BfResult<(Header, Program)> {
+    if bytes.len() < PREAMBLE_LENGTH || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(BfError::Bf("Not a .bfc file.".to_owned()));
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(BfError::Bf(format!(
+            "Unsupported .bfc format version {version}, expected {FORMAT_VERSION}."
+        )));
+    }
+
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let body = &bytes[PREAMBLE_LENGTH..];
+    if crc32(body) != checksum {
+        return Err(corrupted("checksum mismatch"));
+    }
+
+    let mut decoder = Decoder { bytes: body };
+    let header = Header {
+        passes: decoder.strings()?,
+        superinstructions: decoder.strings()?,
+    };
+
+    let count = decoder.usize()?;
+    // Every instruction takes at least one byte, so don't trust the count with
+    // an allocation bigger than the file.
+    let mut instructions = Vec::with_capacity(count.min(decoder.bytes.len()));
+    for _ in 0..count {
+        instructions.push(decoder.instruction()?);
+    }
+
+    if !decoder.bytes.is_empty() {
+        return Err(corrupted("trailing data after the last instruction"));
+    }
+
+    let program = Program { instructions };
+    validate_jumps(&program)?;
+
+    Ok((header, program))
+}
+
+fn corrupted(reason: &str) -> BfError {
+    BfError::Bf(format!("Corrupted .bfc file: {reason}."))
+}
+
+// The VM trusts jump destinations completely, so make sure each one is in
+// bounds and that loops still match up before handing the program over.
+fn validate_jumps(program: &Program) -> BfResult<()> {
+    let instructions = &program.instructions;
+    let invalid = |position: usize| {
+        corrupted(&format!(
+            "invalid jump destination at instruction {position}"
+        ))
+    };
+
+    for (position, instruction) in instructions.iter().enumerate() {
+        match *instruction {
+            Instruction::JumpBegin { destination } => match instructions.get(destination) {
+                Some(
+                    Instruction::JumpEnd { destination: begin }
+                    | Instruction::MovePtrJumpEnd {
+                        destination: begin, ..
+                    },
+                ) if destination > position && *begin == position => (),
+                _ => return Err(invalid(position)),
+            },
+            Instruction::JumpEnd { destination }
+            | Instruction::MovePtrJumpEnd { destination, .. } => {
+                match instructions.get(destination) {
+                    Some(
+                        Instruction::JumpBegin { destination: end }
+                        | Instruction::MovePtrJumpIfZero {
+                            destination: end, ..
+                        },
+                    ) if destination < position && *end == position => (),
+                    _ => return Err(invalid(position)),
+                }
+            }
+            // These either start a loop, in which case the matching end has
+            // already been checked above, or a conditional, which just has to
+            // branch forward.
+            Instruction::ConditionalBegin { destination }
+            | Instruction::MovePtrJumpIfZero { destination, .. }
+                if destination <= position || destination >= instructions.len() =>
+            {
+                return Err(invalid(position));
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value.into());
+    }
+
+    // LEB128, since almost every count and offset fits in a byte or two.
+    fn usize(&mut self, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.u8(byte);
+                return;
+            }
+
+            self.u8(byte | 0x80);
+        }
+    }
+
+    // Zigzag encoded so small negative offsets stay small.
+    fn isize(&mut self, value: isize) {
+        self.usize(((value << 1) ^ (value >> (isize::BITS - 1))) as usize);
+    }
+
+    fn strings(&mut self, strings: &[String]) {
+        self.usize(strings.len());
+        for string in strings {
+            self.usize(string.len());
+            self.bytes.extend(string.as_bytes());
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        match *instruction {
+            Instruction::IncPtr { count } => {
+                self.u8(0);
+                self.usize(count);
+            }
+            Instruction::DecPtr { count } => {
+                self.u8(1);
+                self.usize(count);
+            }
+            Instruction::IncData { count } => {
+                self.u8(2);
+                self.usize(count);
+            }
+            Instruction::DecData { count } => {
+                self.u8(3);
+                self.usize(count);
+            }
+            Instruction::Read { count } => {
+                self.u8(4);
+                self.usize(count);
+            }
+            Instruction::Write { count } => {
+                self.u8(5);
+                self.usize(count);
+            }
+            Instruction::JumpBegin { destination } => {
+                self.u8(6);
+                self.usize(destination);
+            }
+            Instruction::JumpEnd { destination } => {
+                self.u8(7);
+                self.usize(destination);
+            }
+            Instruction::ConditionalBegin { destination } => {
+                self.u8(8);
+                self.usize(destination);
+            }
+            Instruction::SetDataToZero => self.u8(9),
+            Instruction::MovePtrUntilZero {
+                count,
+                forward,
+                amount,
+            } => {
+                self.u8(10);
+                self.usize(count);
+                self.bool(forward);
+                self.usize(amount);
+            }
+            Instruction::MoveData {
+                count,
+                forward,
+                amount,
+            } => {
+                self.u8(11);
+                self.usize(count);
+                self.bool(forward);
+                self.usize(amount);
+            }
+            Instruction::MovePtrAddData { offset, delta } => {
+                self.u8(12);
+                self.isize(offset);
+                self.u8(delta);
+            }
+            Instruction::AddDataMovePtr { delta, offset } => {
+                self.u8(13);
+                self.u8(delta);
+                self.isize(offset);
+            }
+            Instruction::MovePtrAddDataMovePtr {
+                offset,
+                delta,
+                second_offset,
+            } => {
+                self.u8(14);
+                self.isize(offset);
+                self.u8(delta);
+                self.isize(second_offset);
+            }
+            Instruction::MovePtrSetDataToZero { offset } => {
+                self.u8(15);
+                self.isize(offset);
+            }
+            Instruction::MovePtrMoveData {
+                offset,
+                count,
+                forward,
+                amount,
+            } => {
+                self.u8(16);
+                self.isize(offset);
+                self.usize(count);
+                self.bool(forward);
+                self.usize(amount);
+            }
+            Instruction::MovePtrMoveDataMovePtr {
+                offset,
+                count,
+                forward,
+                amount,
+                second_offset,
+            } => {
+                self.u8(17);
+                self.isize(offset);
+                self.usize(count);
+                self.bool(forward);
+                self.usize(amount);
+                self.isize(second_offset);
+            }
+            Instruction::MovePtrJumpIfZero {
+                offset,
+                destination,
+            } => {
+                self.u8(18);
+                self.isize(offset);
+                self.usize(destination);
+            }
+            Instruction::MovePtrJumpEnd {
+                offset,
+                destination,
+            } => {
+                self.u8(19);
+                self.isize(offset);
+                self.usize(destination);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn u8(&mut self) -> BfResult<u8> {
+        let (first, rest) = self
+            .bytes
+            .split_first()
+            .ok_or_else(|| corrupted("unexpected end of data"))?;
+        self.bytes = rest;
+
+        Ok(*first)
+    }
+
+    fn bool(&mut self) -> BfResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(corrupted(&format!("invalid boolean {byte}"))),
+        }
+    }
+
+    fn usize(&mut self) -> BfResult<usize> {
+        let mut value = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            let bits = usize::from(byte & 0x7f);
+            if shift >= usize::BITS || (bits << shift) >> shift != bits {
+                return Err(corrupted("integer out of range"));
+            }
+
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+
+            shift += 7;
+        }
+    }
+
+    fn isize(&mut self) -> BfResult<isize> {
+        let value = self.usize()?;
+        Ok((value >> 1) as isize ^ -((value & 1) as isize))
+    }
+
+    fn strings(&mut self) -> BfResult<Vec<String>> {
+        let count = self.usize()?;
+        let mut strings = vec![];
+        for _ in 0..count {
+            let length = self.usize()?;
+            if length > self.bytes.len() {
+                return Err(corrupted("unexpected end of data"));
+            }
+
+            let (string, rest) = self.bytes.split_at(length);
+            self.bytes = rest;
+            strings
+                .push(String::from_utf8(string.to_vec()).map_err(|_| corrupted("invalid string"))?);
+        }
+
+        Ok(strings)
+    }
+
+    fn instruction(&mut self) -> BfResult<Instruction> {
+        Ok(match self.u8()? {
+            0 => Instruction::IncPtr {
+                count: self.usize()?,
+            },
+            1 => Instruction::DecPtr {
+                count: self.usize()?,
+            },
+            2 => Instruction::IncData {
+                count: self.usize()?,
+            },
+            3 => Instruction::DecData {
+                count: self.usize()?,
+            },
+            4 => Instruction::Read {
+                count: self.usize()?,
+            },
+            5 => Instruction::Write {
+                count: self.usize()?,
+            },
+            6 => Instruction::JumpBegin {
+                destination: self.usize()?,
+            },
+            7 => Instruction::JumpEnd {
+                destination: self.usize()?,
+            },
+            8 => Instruction::ConditionalBegin {
+                destination: self.usize()?,
+            },
+            9 => Instruction::SetDataToZero,
+            10 => Instruction::MovePtrUntilZero {
+                count: self.usize()?,
+                forward: self.bool()?,
+                amount: self.usize()?,
+            },
+            11 => Instruction::MoveData {
+                count: self.usize()?,
+                forward: self.bool()?,
+                amount: self.usize()?,
+            },
+            12 => Instruction::MovePtrAddData {
+                offset: self.isize()?,
+                delta: self.u8()?,
+            },
+            13 => Instruction::AddDataMovePtr {
+                delta: self.u8()?,
+                offset: self.isize()?,
+            },
+            14 => Instruction::MovePtrAddDataMovePtr {
+                offset: self.isize()?,
+                delta: self.u8()?,
+                second_offset: self.isize()?,
+            },
+            15 => Instruction::MovePtrSetDataToZero {
+                offset: self.isize()?,
+            },
+            16 => Instruction::MovePtrMoveData {
+                offset: self.isize()?,
+                count: self.usize()?,
+                forward: self.bool()?,
+                amount: self.usize()?,
+            },
+            17 => Instruction::MovePtrMoveDataMovePtr {
+                offset: self.isize()?,
+                count: self.usize()?,
+                forward: self.bool()?,
+                amount: self.usize()?,
+                second_offset: self.isize()?,
+            },
+            18 => Instruction::MovePtrJumpIfZero {
+                offset: self.isize()?,
+                destination: self.usize()?,
+            },
+            19 => Instruction::MovePtrJumpEnd {
+                offset: self.isize()?,
+                destination: self.usize()?,
+            },
+            opcode => return Err(corrupted(&format!("unknown opcode {opcode}"))),
+        })
+    }
+}
+
+// The standard CRC-32 used by zip and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{crc32, decode, encode, Header, FORMAT_VERSION},
+        crate::parser::{parse_with_passes, Instruction, Program},
+        util::{passes::PassSelection, BfError},
+    };
+
+    fn header() -> Header {
+        Header {
+            passes: vec!["combine-repeats".to_owned(), "move-data".to_owned()],
+            superinstructions: vec!["ptr-data".to_owned()],
+        }
+    }
+
+    // Covers every kind of instruction, with large and negative operands.
+    fn program() -> Program {
+        Program {
+            instructions: vec![
+                Instruction::IncPtr { count: 1 },
+                Instruction::DecPtr { count: 300 },
+                Instruction::IncData { count: 127 },
+                Instruction::DecData { count: 128 },
+                Instruction::Read { count: 2 },
+                Instruction::Write { count: usize::MAX },
+                Instruction::JumpBegin { destination: 20 },
+                Instruction::MovePtrJumpIfZero {
+                    offset: -1,
+                    destination: 19,
+                },
+                Instruction::ConditionalBegin { destination: 9 },
+                Instruction::SetDataToZero,
+                Instruction::MovePtrUntilZero {
+                    count: 1,
+                    forward: false,
+                    amount: 4,
+                },
+                Instruction::MoveData {
+                    count: 2,
+                    forward: true,
+                    amount: 1,
+                },
+                Instruction::MovePtrAddData {
+                    offset: isize::MIN,
+                    delta: 255,
+                },
+                Instruction::AddDataMovePtr {
+                    delta: 0,
+                    offset: isize::MAX,
+                },
+                Instruction::MovePtrAddDataMovePtr {
+                    offset: 64,
+                    delta: 1,
+                    second_offset: -65,
+                },
+                Instruction::MovePtrSetDataToZero { offset: 0 },
+                Instruction::MovePtrMoveData {
+                    offset: 3,
+                    count: 1,
+                    forward: false,
+                    amount: 2,
+                },
+                Instruction::MovePtrMoveDataMovePtr {
+                    offset: -3,
+                    count: 1,
+                    forward: true,
+                    amount: 2,
+                    second_offset: 3,
+                },
+                Instruction::IncPtr { count: 1 },
+                Instruction::MovePtrJumpEnd {
+                    offset: 1,
+                    destination: 7,
+                },
+                Instruction::JumpEnd { destination: 6 },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let bytes = encode(&header(), &program());
+        assert_eq!(decode(&bytes).unwrap(), (header(), program()));
+
+        let (program, _reports) = parse_with_passes(
+            &std::fs::read_to_string("../../corpus/mandelbrot.bf").unwrap(),
+            &PassSelection::default(),
+        )
+        .unwrap();
+        let bytes = encode(&Header::default(), &program);
+        assert_eq!(decode(&bytes).unwrap(), (Header::default(), program));
+    }
+
+    #[test]
+    fn preamble_error_test() {
+        let bytes = encode(&header(), &program());
+
+        assert_eq!(
+            decode(b"+[>.]").unwrap_err(),
+            BfError::Bf("Not a .bfc file.".to_owned())
+        );
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode(&newer).unwrap_err(),
+            BfError::Bf(format!(
+                "Unsupported .bfc format version {}, expected {FORMAT_VERSION}.",
+                FORMAT_VERSION + 1
+            ))
+        );
+
+        // Flipping any single bit of the body is caught by the checksum.
+        for i in 10..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 1 << (i % 8);
+            assert_eq!(
+                decode(&corrupted).unwrap_err(),
+                BfError::Bf("Corrupted .bfc file: checksum mismatch.".to_owned())
+            );
+        }
+
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1]).unwrap_err(),
+            BfError::Bf("Corrupted .bfc file: checksum mismatch.".to_owned())
+        );
+    }
+
+    #[test]
+    fn body_error_test() {
+        // Rewrites the body and fixes up the checksum, as if the file had been
+        // written by something broken rather than corrupted afterwards.
+        fn with_body(body: &[u8]) -> Vec<u8> {
+            let mut bytes = encode(
+                &Header::default(),
+                &Program {
+                    instructions: vec![],
+                },
+            )[..6]
+                .to_vec();
+            bytes.extend(crc32(body).to_le_bytes());
+            bytes.extend(body);
+            bytes
+        }
+
+        for (body, reason) in [
+            (&[0, 0, 1, 0][..], "unexpected end of data"),
+            (&[0, 0, 1, 20], "unknown opcode 20"),
+            (&[0, 0, 1, 10, 1, 2, 1], "invalid boolean 2"),
+            (&[0, 0, 1, 9, 9], "trailing data after the last instruction"),
+            (
+                &[
+                    0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+                ],
+                "integer out of range",
+            ),
+            (&[1, 2, 0xc3, 0x28, 0, 0], "invalid string"),
+        ] {
+            assert_eq!(
+                decode(&with_body(body)).unwrap_err(),
+                BfError::Bf(format!("Corrupted .bfc file: {reason}.")),
+                "{body:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn jump_error_test() {
+        let decode_program =
+            |instructions| decode(&encode(&Header::default(), &Program { instructions }));
+        let invalid = |position: usize| {
+            BfError::Bf(format!(
+                "Corrupted .bfc file: invalid jump destination at instruction {position}."
+            ))
+        };
+
+        // Out of bounds.
+        assert_eq!(
+            decode_program(vec![
+                Instruction::JumpBegin { destination: 5 },
+                Instruction::JumpEnd { destination: 0 },
+            ])
+            .unwrap_err(),
+            invalid(0)
+        );
+        assert_eq!(
+            decode_program(vec![Instruction::ConditionalBegin {
+                destination: usize::MAX
+            }])
+            .unwrap_err(),
+            invalid(0)
+        );
+        // Pointing at the wrong kind of instruction.
+        assert_eq!(
+            decode_program(vec![
+                Instruction::JumpBegin { destination: 1 },
+                Instruction::SetDataToZero,
+            ])
+            .unwrap_err(),
+            invalid(0)
+        );
+        // Loops which don't match up.
+        assert_eq!(
+            decode_program(vec![
+                Instruction::JumpBegin { destination: 3 },
+                Instruction::JumpBegin { destination: 3 },
+                Instruction::JumpEnd { destination: 1 },
+                Instruction::JumpEnd { destination: 1 },
+            ])
+            .unwrap_err(),
+            invalid(0)
+        );
+        // Branching backward.
+        assert_eq!(
+            decode_program(vec![
+                Instruction::SetDataToZero,
+                Instruction::MovePtrJumpIfZero {
+                    offset: 1,
+                    destination: 0,
+                },
+            ])
+            .unwrap_err(),
+            invalid(1)
+        );
+    }
+}
@@ -1,12 +1,184 @@
 use {
     std::io::{Read, Write},
-    util::BfResult,
+    util::{
+        passes::{PassReport, PassSelection},
+        profile::Profile,
+        run::{report_passes, RunOptions, SupportedOptions},
+        BfResult,
+    },
 };
 
+mod bytecode;
 mod parser;
+mod superinstructions;
 mod vm;
 
+pub use {
+    bytecode::{Header as BytecodeHeader, FORMAT_VERSION as BYTECODE_FORMAT_VERSION},
+    parser::{Instruction, Program},
+};
+
+// The options which compile makes use of, and so which every VM running its
+// program accepts.
+pub const SUPPORTED_OPTIONS: SupportedOptions = SupportedOptions {
+    passes: true,
+    superinstructions: true,
+    report_passes: true,
+    ..SupportedOptions::NONE
+};
+
 pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
-    let program = parser::parse(source_code)?;
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    run_with_tape(source_code, options, stdin, stdout)?;
+    Ok(())
+}
+
+// Also returns the contents of the tape once the program finishes.
+pub fn run_with_tape(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<Vec<u8>> {
+    let program = compile(source_code, options)?;
     vm::run(program, stdin, stdout)
 }
+
+// Runs the program, counting how often each of its loops runs, for opjit to
+// optimize with. Only repeats are combined, and there are no superinstructions,
+// so every loop in the source is still a loop when it's counted, whatever the
+// JIT later turns it into.
+pub fn run_with_profile(
+    source_code: &str,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<Profile> {
+    let selection = PassSelection::Passes(vec![parser::Pass::CombineRepeats.name().to_owned()]);
+    let (program, _reports) = parser::parse_with_passes(source_code, &selection)?;
+    let loop_starts = program
+        .instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| matches!(instruction, Instruction::JumpBegin { .. }))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    let (_memory, counts) = vm::run_with_counts(program, stdin, stdout)?;
+
+    // Each loop left starts at the next '[' in the source.
+    let positions = source_code
+        .char_indices()
+        .filter(|(_, c)| *c == '[')
+        .map(|(i, _)| i);
+    let mut profile = Profile::new(source_code);
+    for (position, start) in positions.zip(loop_starts) {
+        if counts[start].entries > 0 {
+            profile.loops.insert(position, counts[start]);
+        }
+    }
+
+    Ok(profile)
+}
+
+// Parses the program and runs the selected passes over it, then fuses
+// superinstructions.
+pub fn compile(source_code: &str, options: &RunOptions) -> BfResult<Program> {
+    let superinstructions = superinstructions::select(&options.superinstructions, &options.passes)?;
+    compile_with_superinstructions(source_code, options, &superinstructions)
+}
+
+fn compile_with_superinstructions(
+    source_code: &str,
+    options: &RunOptions,
+    superinstructions: &[superinstructions::Superinstruction],
+) -> BfResult<Program> {
+    let (program, mut reports) = parser::parse_with_passes(source_code, &options.passes)?;
+
+    let instructions_before = program.instructions.len();
+    let program = superinstructions::fuse(program, superinstructions);
+    reports.push(PassReport {
+        name: "superinstructions",
+        instructions_before,
+        instructions_after: program.instructions.len(),
+    });
+
+    if options.report_passes {
+        report_passes(&reports);
+    }
+
+    Ok(program)
+}
+
+// Compiles the program and encodes it as a .bfc file, which can be run later
+// without parsing or optimizing it again.
+pub fn save_bytecode(source_code: &str, options: &RunOptions) -> BfResult<Vec<u8>> {
+    let superinstructions = superinstructions::select(&options.superinstructions, &options.passes)?;
+    let program = compile_with_superinstructions(source_code, options, &superinstructions)?;
+    let header = BytecodeHeader {
+        passes: pass_names(&options.passes)?
+            .into_iter()
+            .map(str::to_owned)
+            .collect(),
+        superinstructions: superinstructions
+            .into_iter()
+            .map(|superinstruction| superinstruction.name().to_owned())
+            .collect(),
+    };
+
+    Ok(bytecode::encode(&header, &program))
+}
+
+// Checks the file is intact and that every jump stays within the program.
+pub fn load_bytecode(bytes: &[u8]) -> BfResult<(BytecodeHeader, Program)> {
+    bytecode::decode(bytes)
+}
+
+pub fn run_bytecode(bytes: &[u8], stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let (_header, program) = load_bytecode(bytes)?;
+    vm::run(program, stdin, stdout)?;
+    Ok(())
+}
+
+// The names of the passes the selection resolves to, in the order they run.
+pub fn pass_names(selection: &PassSelection) -> BfResult<Vec<&'static str>> {
+    Ok(parser::Pass::pipeline(selection)?
+        .into_iter()
+        .map(parser::Pass::name)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::run_with_profile,
+        util::profile::{LoopCounts, Profile},
+    };
+
+    #[test]
+    fn run_with_profile_test() {
+        // The last loop is skipped, so the one inside it is never reached.
+        let source_code = "++[>+++[>+<-]<-]>>>[-][[+]]";
+        let mut output = vec![];
+        let profile = run_with_profile(source_code, &mut "".as_bytes(), &mut output).unwrap();
+
+        let counts = |entries, iterations, zero_trips| LoopCounts {
+            entries,
+            iterations,
+            zero_trips,
+        };
+        let mut expected = Profile::new(source_code);
+        expected.loops.insert(2, counts(1, 2, 0));
+        expected.loops.insert(7, counts(2, 6, 0));
+        expected.loops.insert(19, counts(1, 0, 1));
+        expected.loops.insert(22, counts(1, 0, 1));
+        assert_eq!(profile, expected);
+    }
+}
@@ -1,5 +1,62 @@
-use util::{run::run_main, BfResult};
+use {
+    std::{env, fs, io},
+    util::{
+        run::{check_default_tape, parse_args},
+        BfError, BfResult,
+    },
+};
 
 fn main() -> BfResult<()> {
-    run_main(opinterp3::run)
+    let mut emit_bytecode = None;
+    let mut emit_profile = None;
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        if let Some(path) = arg.strip_prefix("--emit-bytecode=") {
+            emit_bytecode = Some(path.to_owned());
+        } else if let Some(path) = arg.strip_prefix("--emit-profile=") {
+            emit_profile = Some(path.to_owned());
+        } else {
+            args.push(arg);
+        }
+    }
+
+    let (filepath, options) = parse_args(args.into_iter(), &opinterp3::SUPPORTED_OPTIONS)?;
+    check_default_tape(&options)?;
+
+    // Compiled programs are run as they are, ignoring any optimization
+    // options given.
+    if filepath.ends_with(".bfc") {
+        if emit_profile.is_some() {
+            return Err(BfError::Bf(
+                "Profiles can only be recorded from source files.".to_owned(),
+            ));
+        }
+        let bytes = fs::read(&filepath)?;
+        if options.report_passes {
+            let (header, program) = opinterp3::load_bytecode(&bytes)?;
+            eprintln!("passes: {}", header.passes.join(","));
+            eprintln!("superinstructions: {}", header.superinstructions.join(","));
+            eprintln!("instructions: {}", program.instructions.len());
+        }
+
+        return opinterp3::run_bytecode(&bytes, &mut io::stdin(), &mut io::stdout());
+    }
+
+    let source_code = fs::read_to_string(&filepath)?;
+    match (emit_bytecode, emit_profile) {
+        (Some(path), _) => {
+            fs::write(path, opinterp3::save_bytecode(&source_code, &options)?)?;
+            Ok(())
+        }
+        // Profiled runs choose their own passes, ignoring any given.
+        (None, Some(path)) => {
+            let profile =
+                opinterp3::run_with_profile(&source_code, &mut io::stdin(), &mut io::stdout())?;
+            fs::write(path, profile.encode())?;
+            Ok(())
+        }
+        (None, None) => {
+            opinterp3::run_with_options(&source_code, &options, &mut io::stdin(), &mut io::stdout())
+        }
+    }
 }
@@ -1,9 +1,12 @@
 use {
     std::{iter::Peekable, slice, str::CharIndices},
-    util::{BfError, BfResult},
+    util::{
+        passes::{PassReport, PassSelection},
+        BfError, BfResult,
+    },
 };
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 enum AstNode {
     IncPtr,
     DecPtr,
@@ -12,17 +15,18 @@ enum AstNode {
     Read,
     Write,
     Loop { seq: AstSeq },
+    Conditional { seq: AstSeq },
     SetDataToZero,
     MovePtrUntilZero { forward: bool, amount: usize },
     MoveData { forward: bool, amount: usize },
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 struct AstSeq {
     children: Vec<AstNode>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Instruction {
     IncPtr {
         count: usize,
@@ -48,6 +52,9 @@ pub enum Instruction {
     JumpEnd {
         destination: usize,
     },
+    ConditionalBegin {
+        destination: usize,
+    },
     SetDataToZero,
     MovePtrUntilZero {
         count: usize,
@@ -59,6 +66,46 @@ pub enum Instruction {
         forward: bool,
         amount: usize,
     },
+    // Superinstructions, which are fused from short sequences of the
+    // instructions above. Pointer offsets and data deltas are signed (or wrap
+    // around) so that each covers both directions.
+    MovePtrAddData {
+        offset: isize,
+        delta: u8,
+    },
+    AddDataMovePtr {
+        delta: u8,
+        offset: isize,
+    },
+    MovePtrAddDataMovePtr {
+        offset: isize,
+        delta: u8,
+        second_offset: isize,
+    },
+    MovePtrSetDataToZero {
+        offset: isize,
+    },
+    MovePtrMoveData {
+        offset: isize,
+        count: usize,
+        forward: bool,
+        amount: usize,
+    },
+    MovePtrMoveDataMovePtr {
+        offset: isize,
+        count: usize,
+        forward: bool,
+        amount: usize,
+        second_offset: isize,
+    },
+    MovePtrJumpIfZero {
+        offset: isize,
+        destination: usize,
+    },
+    MovePtrJumpEnd {
+        offset: isize,
+        destination: usize,
+    },
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -66,13 +113,100 @@ pub struct Program {
     pub instructions: Vec<Instruction>,
 }
 
-pub fn parse(source_code: &str) -> BfResult<Program> {
-    let ast = create_ast(source_code)?;
-    let ast = optimize_loops(ast);
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pass {
+    CombineRepeats,
+    SetDataToZero,
+    MovePtrUntilZero,
+    MoveData,
+    ConditionalLoops,
+}
 
-    let instructions = compile(ast);
+impl Pass {
+    const ALL: [Pass; 5] = [
+        Pass::CombineRepeats,
+        Pass::SetDataToZero,
+        Pass::MovePtrUntilZero,
+        Pass::MoveData,
+        Pass::ConditionalLoops,
+    ];
 
-    Ok(Program { instructions })
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::CombineRepeats => "combine-repeats",
+            Pass::SetDataToZero => "set-data-to-zero",
+            Pass::MovePtrUntilZero => "move-ptr-until-zero",
+            Pass::MoveData => "move-data",
+            Pass::ConditionalLoops => "conditional-loops",
+        }
+    }
+
+    fn from_name(name: &str) -> BfResult<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|pass| pass.name() == name)
+            .ok_or_else(|| BfError::Bf(format!("Unknown optimization pass '{name}'.")))
+    }
+
+    fn for_level(level: u8) -> BfResult<Vec<Self>> {
+        match level {
+            0 => Ok(vec![]),
+            1 => Ok(vec![Pass::CombineRepeats]),
+            2 => Ok(vec![
+                Pass::CombineRepeats,
+                Pass::SetDataToZero,
+                Pass::MovePtrUntilZero,
+            ]),
+            3 => Ok(Self::ALL.to_vec()),
+            _ => Err(BfError::Bf(format!("Unknown optimization level {level}."))),
+        }
+    }
+
+    pub fn pipeline(selection: &PassSelection) -> BfResult<Vec<Self>> {
+        match selection {
+            PassSelection::Level(level) => Self::for_level(*level),
+            PassSelection::Passes(names) => {
+                names.iter().map(|name| Self::from_name(name)).collect()
+            }
+        }
+    }
+
+    fn run(self, seq: AstSeq) -> AstSeq {
+        match self {
+            // Repeated instructions are combined while lowering the AST rather
+            // than in the AST itself.
+            Pass::CombineRepeats => seq,
+            _ => optimize_loops(seq, self),
+        }
+    }
+}
+
+pub fn parse_with_passes(
+    source_code: &str,
+    selection: &PassSelection,
+) -> BfResult<(Program, Vec<PassReport>)> {
+    let passes = Pass::pipeline(selection)?;
+    let mut ast = create_ast(source_code)?;
+
+    let mut combine_repeats = false;
+    let mut instruction_count = count_instructions(&ast, combine_repeats);
+    let mut reports = vec![];
+    for pass in passes {
+        ast = pass.run(ast);
+        combine_repeats |= pass == Pass::CombineRepeats;
+
+        let new_instruction_count = count_instructions(&ast, combine_repeats);
+        reports.push(PassReport {
+            name: pass.name(),
+            instructions_before: instruction_count,
+            instructions_after: new_instruction_count,
+        });
+        instruction_count = new_instruction_count;
+    }
+
+    let instructions = compile(ast, combine_repeats);
+
+    Ok((Program { instructions }, reports))
 }
 
 fn create_ast(source_code: &str) -> BfResult<AstSeq> {
@@ -130,7 +264,7 @@ fn create_ast(source_code: &str) -> BfResult<AstSeq> {
     )
 }
 
-fn optimize_loops(seq: AstSeq) -> AstSeq {
+fn optimize_loops(seq: AstSeq, pass: Pass) -> AstSeq {
     fn check_next_node<'a>(
         iter: &'a mut Peekable<slice::Iter<AstNode>>,
         expected_node: &AstNode,
@@ -203,36 +337,85 @@ fn optimize_loops(seq: AstSeq) -> AstSeq {
         }
     }
 
-    fn optimize_node(node: AstNode) -> AstNode {
-        if let AstNode::Loop { seq } = node {
-            if let Some((forward, amount)) = check_move_ptr_until_zero_pattern(&seq.children) {
-                AstNode::MovePtrUntilZero { forward, amount }
-            } else if check_set_data_to_zero_pattern(&seq.children) {
-                AstNode::SetDataToZero
-            } else if let Some((forward, amount)) = check_move_data_pattern(&seq.children) {
-                AstNode::MoveData { forward, amount }
-            } else {
-                AstNode::Loop {
-                    seq: optimize_seq(seq),
-                }
+    // Every loop-like node only finishes once the current cell is zero, so a
+    // loop whose body ends with one can never jump back to its start.
+    fn check_conditional_pattern(nodes: &[AstNode]) -> bool {
+        matches!(
+            nodes.last(),
+            Some(
+                AstNode::Loop { .. }
+                    | AstNode::Conditional { .. }
+                    | AstNode::SetDataToZero
+                    | AstNode::MovePtrUntilZero { .. }
+                    | AstNode::MoveData { .. }
+            )
+        )
+    }
+
+    fn optimize_loop(seq: &AstSeq, pass: Pass) -> Option<AstNode> {
+        match pass {
+            Pass::CombineRepeats | Pass::ConditionalLoops => None,
+            Pass::SetDataToZero => {
+                check_set_data_to_zero_pattern(&seq.children).then_some(AstNode::SetDataToZero)
             }
-        } else {
-            node
+            Pass::MovePtrUntilZero => check_move_ptr_until_zero_pattern(&seq.children)
+                .map(|(forward, amount)| AstNode::MovePtrUntilZero { forward, amount }),
+            Pass::MoveData => check_move_data_pattern(&seq.children)
+                .map(|(forward, amount)| AstNode::MoveData { forward, amount }),
         }
     }
 
-    fn optimize_seq(seq: AstSeq) -> AstSeq {
+    fn optimize_node(node: AstNode, pass: Pass) -> AstNode {
+        match node {
+            AstNode::Loop { seq } => optimize_loop(&seq, pass).unwrap_or_else(|| {
+                let seq = optimize_seq(seq, pass);
+                if pass == Pass::ConditionalLoops && check_conditional_pattern(&seq.children) {
+                    AstNode::Conditional { seq }
+                } else {
+                    AstNode::Loop { seq }
+                }
+            }),
+            AstNode::Conditional { seq } => AstNode::Conditional {
+                seq: optimize_seq(seq, pass),
+            },
+            _ => node,
+        }
+    }
+
+    fn optimize_seq(seq: AstSeq, pass: Pass) -> AstSeq {
         AstSeq {
-            children: seq.children.into_iter().map(optimize_node).collect(),
+            children: seq
+                .children
+                .into_iter()
+                .map(|node| optimize_node(node, pass))
+                .collect(),
         }
     }
 
-    optimize_seq(seq)
+    optimize_seq(seq, pass)
 }
 
-fn compile(seq: AstSeq) -> Vec<Instruction> {
-    fn compile_loop(seq: AstSeq, position: usize) -> Vec<Instruction> {
-        let children = compile_seq(seq, position + 1);
+// The number of instructions compile would lower the AST to, counted without
+// lowering it, for reporting what each pass did.
+fn count_instructions(seq: &AstSeq, combine_repeats: bool) -> usize {
+    let mut count = 0;
+    let mut previous = None;
+    for node in &seq.children {
+        match node {
+            AstNode::Loop { seq } => count += 2 + count_instructions(seq, combine_repeats),
+            AstNode::Conditional { seq } => count += 1 + count_instructions(seq, combine_repeats),
+            _ if combine_repeats && previous == Some(node) => (),
+            _ => count += 1,
+        }
+        previous = Some(node);
+    }
+
+    count
+}
+
+fn compile(seq: AstSeq, combine_repeats: bool) -> Vec<Instruction> {
+    fn compile_loop(seq: AstSeq, position: usize, combine_repeats: bool) -> Vec<Instruction> {
+        let children = compile_seq(seq, position + 1, combine_repeats);
 
         let jump_begin = Instruction::JumpBegin {
             destination: position + children.len() + 1,
@@ -248,6 +431,26 @@ fn compile(seq: AstSeq) -> Vec<Instruction> {
         instructions
     }
 
+    // Conditionals run their body at most once, so they only need a forward
+    // branch over it and no back edge. The branch lands on the last instruction
+    // of the body, just like a loop's lands on its closing jump.
+    fn compile_conditional(
+        seq: AstSeq,
+        position: usize,
+        combine_repeats: bool,
+    ) -> Vec<Instruction> {
+        let children = compile_seq(seq, position + 1, combine_repeats);
+
+        let conditional_begin = Instruction::ConditionalBegin {
+            destination: position + children.len(),
+        };
+
+        let mut instructions = vec![conditional_begin];
+        instructions.extend(children);
+
+        instructions
+    }
+
     fn compile_node(node: AstNode, count: usize) -> Instruction {
         match node {
             AstNode::IncPtr => Instruction::IncPtr { count },
@@ -271,18 +474,25 @@ fn compile(seq: AstSeq) -> Vec<Instruction> {
         }
     }
 
-    fn compile_seq(seq: AstSeq, position: usize) -> Vec<Instruction> {
+    fn compile_seq(seq: AstSeq, position: usize, combine_repeats: bool) -> Vec<Instruction> {
         let mut instructions = vec![];
 
         let mut iter = seq.children.into_iter().peekable();
         while let Some(node) = iter.next() {
             match node {
-                AstNode::Loop { seq } => {
-                    instructions.extend(compile_loop(seq, position + instructions.len()))
-                }
+                AstNode::Loop { seq } => instructions.extend(compile_loop(
+                    seq,
+                    position + instructions.len(),
+                    combine_repeats,
+                )),
+                AstNode::Conditional { seq } => instructions.extend(compile_conditional(
+                    seq,
+                    position + instructions.len(),
+                    combine_repeats,
+                )),
                 _ => {
                     let mut count = 1;
-                    while iter.peek() == Some(&node) {
+                    while combine_repeats && iter.peek() == Some(&node) {
                         count += 1;
                         debug_assert_eq!(iter.peek(), Some(&node));
                         iter.next();
@@ -296,16 +506,26 @@ fn compile(seq: AstSeq) -> Vec<Instruction> {
         instructions
     }
 
-    compile_seq(seq, 0 /*position*/)
+    compile_seq(seq, 0 /*position*/, combine_repeats)
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::{parse, Instruction, Program},
-        util::BfError,
+        super::{
+            compile, count_instructions, create_ast, parse_with_passes, Instruction, Pass, Program,
+        },
+        util::{
+            passes::{PassReport, PassSelection},
+            BfError, BfResult,
+        },
     };
 
+    fn parse(source_code: &str) -> BfResult<Program> {
+        let (program, _reports) = parse_with_passes(source_code, &PassSelection::default())?;
+        Ok(program)
+    }
+
     #[test]
     fn parse_test() {
         let program = parse(">a<+bcde-,_.[]._1[.]234567890か").unwrap();
@@ -630,6 +850,92 @@ mod tests {
         );
     }
 
+    #[test]
+    fn conditional_loops_test() {
+        assert_eq!(
+            parse("[>+<[-]]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin { destination: 4 },
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::IncData { count: 1 },
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::SetDataToZero,
+                ],
+            }
+        );
+        assert_eq!(
+            parse("[.[>]]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin { destination: 2 },
+                    Instruction::Write { count: 1 },
+                    Instruction::MovePtrUntilZero {
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            parse("[[.[-]]]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin { destination: 3 },
+                    Instruction::ConditionalBegin { destination: 3 },
+                    Instruction::Write { count: 1 },
+                    Instruction::SetDataToZero,
+                ],
+            }
+        );
+
+        // The cell isn't necessarily zero at the end of these bodies.
+        assert_eq!(
+            parse("[-[-].]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::JumpBegin { destination: 4 },
+                    Instruction::DecData { count: 1 },
+                    Instruction::SetDataToZero,
+                    Instruction::Write { count: 1 },
+                    Instruction::JumpEnd { destination: 0 },
+                ],
+            }
+        );
+        assert_eq!(
+            parse("[>[-]<]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::JumpBegin { destination: 4 },
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::SetDataToZero,
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::JumpEnd { destination: 0 },
+                ],
+            }
+        );
+
+        // Loops which are still around when the pass runs end conditionals
+        // too.
+        let passes = PassSelection::Passes(vec!["conditional-loops".to_owned()]);
+        let (program, _reports) = parse_with_passes("[>+<[-]]", &passes).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin { destination: 6 },
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::IncData { count: 1 },
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::JumpBegin { destination: 6 },
+                    Instruction::DecData { count: 1 },
+                    Instruction::JumpEnd { destination: 4 },
+                ],
+            }
+        );
+    }
+
     #[test]
     fn parse_error_test() {
         let err = parse("..[...").unwrap_err();
@@ -638,4 +944,151 @@ mod tests {
         let err = parse("...]...").unwrap_err();
         assert_eq!(err, BfError::Bf("Unmatched ']' at index 3.".to_owned()));
     }
+
+    #[test]
+    fn opt_level_test() {
+        let source_code = ">>[-][->+<]";
+
+        let (program, reports) = parse_with_passes(source_code, &PassSelection::Level(0)).unwrap();
+        assert_eq!(program.instructions.len(), 11);
+        assert_eq!(reports, vec![]);
+
+        let (program, _reports) = parse_with_passes(source_code, &PassSelection::Level(1)).unwrap();
+        assert_eq!(program.instructions.len(), 10);
+        assert_eq!(program.instructions[0], Instruction::IncPtr { count: 2 });
+
+        let (program, _reports) = parse_with_passes(source_code, &PassSelection::Level(2)).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::IncPtr { count: 2 },
+                    Instruction::SetDataToZero,
+                    Instruction::JumpBegin { destination: 7 },
+                    Instruction::DecData { count: 1 },
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::IncData { count: 1 },
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::JumpEnd { destination: 2 },
+                ],
+            }
+        );
+
+        let (program, reports) = parse_with_passes(source_code, &PassSelection::Level(3)).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::IncPtr { count: 2 },
+                    Instruction::SetDataToZero,
+                    Instruction::MoveData {
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            reports,
+            vec![
+                PassReport {
+                    name: "combine-repeats",
+                    instructions_before: 11,
+                    instructions_after: 10,
+                },
+                PassReport {
+                    name: "set-data-to-zero",
+                    instructions_before: 10,
+                    instructions_after: 8,
+                },
+                PassReport {
+                    name: "move-ptr-until-zero",
+                    instructions_before: 8,
+                    instructions_after: 8,
+                },
+                PassReport {
+                    name: "move-data",
+                    instructions_before: 8,
+                    instructions_after: 3,
+                },
+                PassReport {
+                    name: "conditional-loops",
+                    instructions_before: 3,
+                    instructions_after: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_passes_test() {
+        let passes = PassSelection::Passes(vec!["move-data".to_owned()]);
+        let (program, reports) = parse_with_passes("[-][->+<]", &passes).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::JumpBegin { destination: 2 },
+                    Instruction::DecData { count: 1 },
+                    Instruction::JumpEnd { destination: 0 },
+                    Instruction::MoveData {
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            reports,
+            vec![PassReport {
+                name: "move-data",
+                instructions_before: 9,
+                instructions_after: 4,
+            }]
+        );
+
+        let passes = PassSelection::Passes(vec![]);
+        let (program, reports) = parse_with_passes("++", &passes).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::IncData { count: 1 },
+                    Instruction::IncData { count: 1 },
+                ],
+            }
+        );
+        assert_eq!(reports, vec![]);
+    }
+
+    #[test]
+    fn count_instructions_test() {
+        for source_code in ["++>>[-]<<", "[>+<[-]]+[.[>]]", "[[.[-]]]--[->+<]", ",[.,]"] {
+            let mut ast = create_ast(source_code).unwrap();
+            for pass in Pass::pipeline(&PassSelection::default()).unwrap() {
+                ast = pass.run(ast);
+                for combine_repeats in [false, true] {
+                    assert_eq!(
+                        count_instructions(&ast, combine_repeats),
+                        compile(ast.clone(), combine_repeats).len()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn passes_error_test() {
+        let err = parse_with_passes("+", &PassSelection::Level(4)).unwrap_err();
+        assert_eq!(err, BfError::Bf("Unknown optimization level 4.".to_owned()));
+
+        let passes = PassSelection::Passes(vec!["move-data".to_owned(), "bogus".to_owned()]);
+        let err = parse_with_passes("+", &passes).unwrap_err();
+        assert_eq!(
+            err,
+            BfError::Bf("Unknown optimization pass 'bogus'.".to_owned())
+        );
+    }
 }
@@ -0,0 +1,416 @@
+use {
+    crate::parser::{parse_with_passes, Instruction, Program},
+    std::{cmp::Reverse, fs, iter},
+    util::{
+        passes::{PassSelection, SuperinstructionSelection},
+        BfError, BfResult,
+    },
+};
+
+// Each instruction inside a loop is assumed to run this many times more often
+// than one just outside it when ranking superinstructions by a corpus.
+const LOOP_WEIGHT: u64 = 16;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Superinstruction {
+    PtrData,
+    DataPtr,
+    PtrDataPtr,
+    PtrSetZero,
+    PtrMoveData,
+    PtrMoveDataPtr,
+    PtrJumpIfZero,
+    PtrJumpEnd,
+}
+
+impl Superinstruction {
+    const ALL: [Superinstruction; 8] = [
+        Superinstruction::PtrData,
+        Superinstruction::DataPtr,
+        Superinstruction::PtrDataPtr,
+        Superinstruction::PtrSetZero,
+        Superinstruction::PtrMoveData,
+        Superinstruction::PtrMoveDataPtr,
+        Superinstruction::PtrJumpIfZero,
+        Superinstruction::PtrJumpEnd,
+    ];
+
+    // The order rank() picks for factor.bf and mandelbrot.bf, which is what
+    // --superinstructions-from=corpus/factor.bf,corpus/mandelbrot.bf gives.
+    const BUILTIN: [Superinstruction; 8] = [
+        Superinstruction::PtrDataPtr,
+        Superinstruction::DataPtr,
+        Superinstruction::PtrData,
+        Superinstruction::PtrJumpEnd,
+        Superinstruction::PtrMoveDataPtr,
+        Superinstruction::PtrMoveData,
+        Superinstruction::PtrJumpIfZero,
+        Superinstruction::PtrSetZero,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Superinstruction::PtrData => "ptr-data",
+            Superinstruction::DataPtr => "data-ptr",
+            Superinstruction::PtrDataPtr => "ptr-data-ptr",
+            Superinstruction::PtrSetZero => "ptr-set-zero",
+            Superinstruction::PtrMoveData => "ptr-move-data",
+            Superinstruction::PtrMoveDataPtr => "ptr-move-data-ptr",
+            Superinstruction::PtrJumpIfZero => "ptr-jump-if-zero",
+            Superinstruction::PtrJumpEnd => "ptr-jump-end",
+        }
+    }
+
+    fn from_name(name: &str) -> BfResult<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|superinstruction| superinstruction.name() == name)
+            .ok_or_else(|| BfError::Bf(format!("Unknown superinstruction '{name}'.")))
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Superinstruction::PtrDataPtr | Superinstruction::PtrMoveDataPtr => 3,
+            _ => 2,
+        }
+    }
+
+    // Returns the superinstruction for the start of the given instructions, if
+    // they match it.
+    fn fuse(self, instructions: &[Instruction]) -> Option<Instruction> {
+        match (self, instructions) {
+            (Superinstruction::PtrData, [first, second, ..]) => Some(Instruction::MovePtrAddData {
+                offset: ptr_offset(first)?,
+                delta: data_delta(second)?,
+            }),
+            (Superinstruction::DataPtr, [first, second, ..]) => Some(Instruction::AddDataMovePtr {
+                delta: data_delta(first)?,
+                offset: ptr_offset(second)?,
+            }),
+            (Superinstruction::PtrDataPtr, [first, second, third, ..]) => {
+                Some(Instruction::MovePtrAddDataMovePtr {
+                    offset: ptr_offset(first)?,
+                    delta: data_delta(second)?,
+                    second_offset: ptr_offset(third)?,
+                })
+            }
+            (Superinstruction::PtrSetZero, [first, Instruction::SetDataToZero, ..]) => {
+                Some(Instruction::MovePtrSetDataToZero {
+                    offset: ptr_offset(first)?,
+                })
+            }
+            (
+                Superinstruction::PtrMoveData,
+                [first, Instruction::MoveData {
+                    count,
+                    forward,
+                    amount,
+                }, ..],
+            ) => Some(Instruction::MovePtrMoveData {
+                offset: ptr_offset(first)?,
+                count: *count,
+                forward: *forward,
+                amount: *amount,
+            }),
+            (
+                Superinstruction::PtrMoveDataPtr,
+                [first, Instruction::MoveData {
+                    count,
+                    forward,
+                    amount,
+                }, third, ..],
+            ) => Some(Instruction::MovePtrMoveDataMovePtr {
+                offset: ptr_offset(first)?,
+                count: *count,
+                forward: *forward,
+                amount: *amount,
+                second_offset: ptr_offset(third)?,
+            }),
+            // Loops and conditionals both jump past their body if the current
+            // cell is zero.
+            (
+                Superinstruction::PtrJumpIfZero,
+                [first, Instruction::JumpBegin { destination }
+                | Instruction::ConditionalBegin { destination }, ..],
+            ) => Some(Instruction::MovePtrJumpIfZero {
+                offset: ptr_offset(first)?,
+                destination: *destination,
+            }),
+            (Superinstruction::PtrJumpEnd, [first, Instruction::JumpEnd { destination }, ..]) => {
+                Some(Instruction::MovePtrJumpEnd {
+                    offset: ptr_offset(first)?,
+                    destination: *destination,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn select(
+    selection: &SuperinstructionSelection,
+    passes: &PassSelection,
+) -> BfResult<Vec<Superinstruction>> {
+    match selection {
+        SuperinstructionSelection::Builtin => Ok(Superinstruction::BUILTIN.to_vec()),
+        SuperinstructionSelection::Named(names) => names
+            .iter()
+            .map(|name| Superinstruction::from_name(name))
+            .collect(),
+        SuperinstructionSelection::Corpus(filepaths) => {
+            let programs = filepaths
+                .iter()
+                .map(|filepath| {
+                    let source_code = fs::read_to_string(filepath)?;
+                    let (program, _reports) = parse_with_passes(&source_code, passes)?;
+                    Ok(program)
+                })
+                .collect::<BfResult<Vec<Program>>>()?;
+
+            Ok(rank(&programs))
+        }
+    }
+}
+
+// Orders the superinstructions which appear in the programs by how many
+// dispatches they would save, dropping any which never appear.
+fn rank(programs: &[Program]) -> Vec<Superinstruction> {
+    let mut savings = Superinstruction::ALL.map(|superinstruction| (superinstruction, 0u64));
+
+    for program in programs {
+        let mut depth = 0;
+        for (i, instruction) in program.instructions.iter().enumerate() {
+            match instruction {
+                Instruction::JumpBegin { .. } => depth += 1,
+                Instruction::JumpEnd { .. } => depth -= 1,
+                _ => (),
+            }
+
+            let weight = LOOP_WEIGHT.saturating_pow(depth);
+            for (superinstruction, saving) in &mut savings {
+                if superinstruction.fuse(&program.instructions[i..]).is_some() {
+                    let saved = weight.saturating_mul(superinstruction.len() as u64 - 1);
+                    *saving = saving.saturating_add(saved);
+                }
+            }
+        }
+    }
+
+    // The sort is stable, so ties keep the order of ALL.
+    savings.sort_by_key(|(_, saving)| Reverse(*saving));
+    savings
+        .into_iter()
+        .filter(|(_, saving)| *saving > 0)
+        .map(|(superinstruction, _)| superinstruction)
+        .collect()
+}
+
+pub fn fuse(program: Program, superinstructions: &[Superinstruction]) -> Program {
+    let instructions = program.instructions;
+
+    // Jumps carry on from the instruction after their destination, so that one
+    // has to be the start of a superinstruction rather than in the middle of
+    // one.
+    let mut is_jump_target = vec![false; instructions.len() + 1];
+    for mut instruction in instructions.iter().copied() {
+        if let Some(destination) = destination_mut(&mut instruction) {
+            is_jump_target[*destination + 1] = true;
+        }
+    }
+
+    let mut fused = vec![];
+    // Maps the position of each original instruction to the position of the
+    // instruction it ends up in.
+    let mut positions = Vec::with_capacity(instructions.len());
+    let mut i = 0;
+    while i < instructions.len() {
+        let (instruction, length) = superinstructions
+            .iter()
+            .find_map(|superinstruction| {
+                let end = i + superinstruction.len();
+                if end > instructions.len() || is_jump_target[i + 1..end].contains(&true) {
+                    return None;
+                }
+
+                let instruction = superinstruction.fuse(&instructions[i..end])?;
+                Some((instruction, superinstruction.len()))
+            })
+            .unwrap_or((instructions[i], 1));
+
+        positions.extend(iter::repeat_n(fused.len(), length));
+        fused.push(instruction);
+        i += length;
+    }
+
+    for instruction in &mut fused {
+        if let Some(destination) = destination_mut(instruction) {
+            *destination = positions[*destination];
+        }
+    }
+
+    Program {
+        instructions: fused,
+    }
+}
+
+fn destination_mut(instruction: &mut Instruction) -> Option<&mut usize> {
+    match instruction {
+        Instruction::JumpBegin { destination }
+        | Instruction::JumpEnd { destination }
+        | Instruction::ConditionalBegin { destination }
+        | Instruction::MovePtrJumpIfZero { destination, .. }
+        | Instruction::MovePtrJumpEnd { destination, .. } => Some(destination),
+        _ => None,
+    }
+}
+
+fn ptr_offset(instruction: &Instruction) -> Option<isize> {
+    match instruction {
+        Instruction::IncPtr { count } => Some(*count as isize),
+        Instruction::DecPtr { count } => Some(-(*count as isize)),
+        _ => None,
+    }
+}
+
+fn data_delta(instruction: &Instruction) -> Option<u8> {
+    // Only the count mod 256 matters, since the data wraps around.
+    match instruction {
+        Instruction::IncData { count } => Some((*count % 256) as u8),
+        Instruction::DecData { count } => Some(((*count % 256) as u8).wrapping_neg()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{fuse, rank, select, Superinstruction},
+        crate::parser::{parse_with_passes, Instruction, Program},
+        util::{
+            passes::{PassSelection, SuperinstructionSelection},
+            BfError,
+        },
+    };
+
+    fn parse(source_code: &str) -> Program {
+        let (program, _reports) =
+            parse_with_passes(source_code, &PassSelection::default()).unwrap();
+        program
+    }
+
+    #[test]
+    fn fuse_test() {
+        let superinstructions = Superinstruction::BUILTIN;
+
+        assert_eq!(
+            fuse(parse(">+<<-->"), &superinstructions),
+            Program {
+                instructions: vec![
+                    Instruction::MovePtrAddDataMovePtr {
+                        offset: 1,
+                        delta: 1,
+                        second_offset: -2,
+                    },
+                    Instruction::AddDataMovePtr {
+                        delta: 254,
+                        offset: 1,
+                    },
+                ],
+            }
+        );
+
+        // Jumps into and out of the loop are remapped to the fused
+        // instructions.
+        assert_eq!(
+            fuse(parse(">>[+>]"), &superinstructions),
+            Program {
+                instructions: vec![
+                    Instruction::MovePtrJumpIfZero {
+                        offset: 2,
+                        destination: 2,
+                    },
+                    Instruction::AddDataMovePtr {
+                        delta: 1,
+                        offset: 1,
+                    },
+                    Instruction::JumpEnd { destination: 0 },
+                ],
+            }
+        );
+
+        // The IncPtr after the conditional is where it carries on from when
+        // it's skipped, so it can't be fused into the end of its body.
+        assert_eq!(
+            fuse(parse("[<[->+<]]>"), &superinstructions),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin { destination: 1 },
+                    Instruction::MovePtrMoveData {
+                        offset: -1,
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                    Instruction::IncPtr { count: 1 },
+                ],
+            }
+        );
+
+        assert_eq!(fuse(parse(">+<"), &[]), parse(">+<"));
+    }
+
+    #[test]
+    fn select_test() {
+        assert_eq!(
+            select(
+                &SuperinstructionSelection::Named(vec![
+                    "ptr-jump-end".to_owned(),
+                    "ptr-data".to_owned()
+                ]),
+                &PassSelection::default()
+            )
+            .unwrap(),
+            vec![Superinstruction::PtrJumpEnd, Superinstruction::PtrData]
+        );
+        assert_eq!(
+            select(
+                &SuperinstructionSelection::Named(vec!["bogus".to_owned()]),
+                &PassSelection::default()
+            )
+            .unwrap_err(),
+            BfError::Bf("Unknown superinstruction 'bogus'.".to_owned())
+        );
+    }
+
+    #[test]
+    fn builtin_test() {
+        let corpus = vec![
+            "../../corpus/factor.bf".to_owned(),
+            "../../corpus/mandelbrot.bf".to_owned(),
+        ];
+        assert_eq!(
+            select(
+                &SuperinstructionSelection::Corpus(corpus),
+                &PassSelection::default()
+            )
+            .unwrap(),
+            Superinstruction::BUILTIN
+        );
+    }
+
+    #[test]
+    fn rank_test() {
+        // The loop body counts for more than the straight-line code around
+        // it, and the triple saves twice as many dispatches as a pair.
+        assert_eq!(
+            rank(&[parse("+>+>+>+>[>-<-]")]),
+            vec![
+                Superinstruction::PtrDataPtr,
+                Superinstruction::PtrData,
+                Superinstruction::DataPtr,
+                Superinstruction::PtrJumpIfZero,
+            ]
+        );
+        assert_eq!(rank(&[parse("+.")]), vec![]);
+    }
+}
@@ -3,13 +3,37 @@ use {
     std::io::{Read, Write},
     util::{
         math::{unbalanced_wrapping_add, unbalanced_wrapping_sub},
+        profile::LoopCounts,
+        scan::move_ptr_until_zero,
         BfResult,
     },
 };
 
 const MEMORY_SIZE: usize = 30000;
 
-pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<Vec<u8>> {
+    execute::<false>(program, stdin, stdout, &mut [])
+}
+
+// Like run, but also counts how often each loop ran. The counts are indexed by
+// the position of the instruction that starts the loop.
+pub fn run_with_counts(
+    program: Program,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<(Vec<u8>, Vec<LoopCounts>)> {
+    let mut counts = vec![LoopCounts::default(); program.instructions.len()];
+    let memory = execute::<true>(program, stdin, stdout, &mut counts)?;
+    Ok((memory, counts))
+}
+
+// Counting is a const parameter so that unprofiled runs don't pay for it.
+fn execute<const PROFILE: bool>(
+    program: Program,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+    counts: &mut [LoopCounts],
+) -> BfResult<Vec<u8>> {
     let mut memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
     let mut pc = 0;
     let mut data_pointer = 0;
@@ -34,7 +58,11 @@ pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> Bf
                     write(stdout, memory[data_pointer])?
                 }
             }
-            Instruction::JumpBegin { destination } => {
+            Instruction::JumpBegin { destination }
+            | Instruction::ConditionalBegin { destination } => {
+                if PROFILE {
+                    count_entry(&mut counts[pc], memory[data_pointer]);
+                }
                 pc = jump(
                     true, /*eq_zero*/
                     &memory,
@@ -44,6 +72,9 @@ pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> Bf
                 )
             }
             Instruction::JumpEnd { destination } => {
+                if PROFILE && memory[data_pointer] != 0 {
+                    counts[destination].iterations += 1;
+                }
                 pc = jump(
                     false, /*eq_zero*/
                     &memory,
@@ -59,40 +90,123 @@ pub fn run(program: Program, stdin: &mut dyn Read, stdout: &mut dyn Write) -> Bf
                 amount,
             } => {
                 for _ in 0..count {
-                    while memory[data_pointer] != 0 {
-                        if forward {
-                            data_pointer += amount
-                        } else {
-                            data_pointer -= amount
-                        }
-                    }
+                    data_pointer = move_ptr_until_zero(&memory, data_pointer, forward, amount);
                 }
             }
             Instruction::MoveData {
                 count,
                 forward,
                 amount,
+            } => move_data(&mut memory, data_pointer, count, forward, amount),
+            Instruction::MovePtrAddData { offset, delta } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                memory[data_pointer] = memory[data_pointer].wrapping_add(delta);
+            }
+            Instruction::AddDataMovePtr { delta, offset } => {
+                memory[data_pointer] = memory[data_pointer].wrapping_add(delta);
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+            }
+            Instruction::MovePtrAddDataMovePtr {
+                offset,
+                delta,
+                second_offset,
             } => {
-                for _ in 0..count {
-                    if memory[data_pointer] != 0 {
-                        let move_to_ptr = if forward {
-                            data_pointer + amount
-                        } else {
-                            data_pointer - amount
-                        };
-
-                        memory[move_to_ptr] =
-                            memory[move_to_ptr].wrapping_add(memory[data_pointer]);
-                        memory[data_pointer] = 0;
-                    }
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                memory[data_pointer] = memory[data_pointer].wrapping_add(delta);
+                data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            }
+            Instruction::MovePtrSetDataToZero { offset } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                memory[data_pointer] = 0;
+            }
+            Instruction::MovePtrMoveData {
+                offset,
+                count,
+                forward,
+                amount,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                move_data(&mut memory, data_pointer, count, forward, amount);
+            }
+            Instruction::MovePtrMoveDataMovePtr {
+                offset,
+                count,
+                forward,
+                amount,
+                second_offset,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                move_data(&mut memory, data_pointer, count, forward, amount);
+                data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            }
+            Instruction::MovePtrJumpIfZero {
+                offset,
+                destination,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                if PROFILE {
+                    count_entry(&mut counts[pc], memory[data_pointer]);
+                }
+                pc = jump(
+                    true, /*eq_zero*/
+                    &memory,
+                    data_pointer,
+                    destination,
+                    pc,
+                )
+            }
+            Instruction::MovePtrJumpEnd {
+                offset,
+                destination,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                if PROFILE && memory[data_pointer] != 0 {
+                    counts[destination].iterations += 1;
                 }
+                pc = jump(
+                    false, /*eq_zero*/
+                    &memory,
+                    data_pointer,
+                    destination,
+                    pc,
+                )
             }
         }
 
         pc += 1;
     }
 
-    Ok(())
+    Ok(memory.to_vec())
+}
+
+fn count_entry(counts: &mut LoopCounts, cell: u8) {
+    counts.entries += 1;
+    if cell == 0 {
+        counts.zero_trips += 1;
+    } else {
+        counts.iterations += 1;
+    }
+}
+
+fn move_data(
+    memory: &mut [u8; MEMORY_SIZE],
+    data_pointer: usize,
+    count: usize,
+    forward: bool,
+    amount: usize,
+) {
+    for _ in 0..count {
+        if memory[data_pointer] != 0 {
+            let move_to_ptr = if forward {
+                data_pointer + amount
+            } else {
+                data_pointer - amount
+            };
+
+            memory[move_to_ptr] = memory[move_to_ptr].wrapping_add(memory[data_pointer]);
+            memory[data_pointer] = 0;
+        }
+    }
 }
 
 fn read(stdin: &mut dyn Read) -> BfResult<u8> {
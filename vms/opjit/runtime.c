@@ -0,0 +1,33 @@
+// A minimal runtime for programs written out with `opjit --emit-asm=<path>`.
+// Build one with `cc -o program program.s runtime.c`.
+#include <stdio.h>
+#include <stdlib.h>
+
+#define MEMORY_SIZE 30000
+// Vectorized scans may load up to a vector's width of bytes on either side of
+// the cells they visit.
+#define MEMORY_PADDING 32
+
+void bf_main(unsigned char *tape);
+
+static unsigned char memory[MEMORY_SIZE + 2 * MEMORY_PADDING];
+
+unsigned char bf_read(void) {
+    int c = getchar();
+    if (c == EOF) {
+        exit(1);
+    }
+
+    return (unsigned char)c;
+}
+
+void bf_write(unsigned char byte) {
+    if (putchar(byte) == EOF) {
+        exit(1);
+    }
+}
+
+int main(void) {
+    bf_main(memory + MEMORY_PADDING);
+    return 0;
+}
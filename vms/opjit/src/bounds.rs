@@ -0,0 +1,324 @@
+use {
+    crate::parser::{partners, Instruction},
+    dynasmrt::{dynasm, DynamicLabel, DynasmLabelApi},
+    util::{
+        asm::{bounds_check, jump, store_u32, Assembler, MEMORY_SIZE},
+        dasm,
+    },
+};
+
+// The number of times the pointer range at the start of a loop may grow before
+// it's widened to the whole of memory, so that loops which keep moving the
+// pointer in one direction still reach a fixed point.
+const MAX_UPDATES: usize = 2;
+
+// What's left of failed_at when no check fails.
+const NOT_FAILED: u32 = u32::MAX;
+
+// The checks compiled code makes that the data pointer stays inside memory, for
+// sandboxing programs without relying on guard pages. Pointer moves are checked
+// as they happen, along with every step of a scan and the cell MoveData adds
+// to. A failed check stops the program, and records which instruction it was
+// in. Checks the range analysis proves can't fail are left out.
+pub struct BoundsChecks {
+    memory: *const u8,
+    needed: Vec<bool>,
+    // Where each instruction's code jumps when a check fails, for the
+    // instructions which have any.
+    failures: Vec<(DynamicLabel, usize)>,
+    // Boxed so that the code can store to it.
+    failed_at: Box<u32>,
+}
+
+impl BoundsChecks {
+    // `memory` is where the runtime's memory starts, as returned by
+    // Runtime::memory_ptr.
+    pub fn new(instructions: &[Instruction], memory: *const u8) -> Self {
+        BoundsChecks {
+            memory,
+            needed: needed_checks(instructions),
+            failures: vec![],
+            failed_at: Box::new(NOT_FAILED),
+        }
+    }
+
+    // Checks the cell `offset` cells from the data pointer, if the check for
+    // the instruction at `index` is needed.
+    pub fn check(&mut self, assembler: &mut Assembler, index: usize, offset: i64) {
+        if let Some(fail) = self.failure(assembler, index) {
+            bounds_check(assembler, self.memory, offset, fail);
+        }
+    }
+
+    // Where a failed check in the instruction at `index` jumps to, if it needs
+    // checking at all.
+    pub fn failure(&mut self, assembler: &mut Assembler, index: usize) -> Option<DynamicLabel> {
+        if !self.needed[index] {
+            return None;
+        }
+
+        match self.failures.last() {
+            Some((label, failed_index)) if *failed_index == index => Some(*label),
+            _ => {
+                let label = assembler.new_dynamic_label();
+                self.failures.push((label, index));
+                Some(label)
+            }
+        }
+    }
+
+    pub fn memory(&self) -> *const u8 {
+        self.memory
+    }
+
+    // Writes the code each failed check jumps to, which records where it
+    // failed before leaving through `exit`.
+    pub fn write_failures(&self, assembler: &mut Assembler, exit: DynamicLabel) {
+        let failed_at = &*self.failed_at as *const u32 as *mut u32;
+        for (label, index) in &self.failures {
+            dasm!(assembler
+                ; =>*label
+            );
+            store_u32(assembler, failed_at, *index as u32);
+            jump(assembler, exit);
+        }
+    }
+
+    // The index of the instruction whose check failed, once the program has
+    // run.
+    pub fn failed_at(&self) -> Option<usize> {
+        (*self.failed_at != NOT_FAILED).then_some(*self.failed_at as usize)
+    }
+
+    // How many instructions are checked, out of how many could take the data
+    // pointer out of memory without the analysis.
+    pub fn counts(&self, instructions: &[Instruction]) -> (usize, usize) {
+        let checked = self.needed.iter().filter(|needed| **needed).count();
+        let checkable = instructions
+            .iter()
+            .filter(|instruction| {
+                matches!(
+                    instruction,
+                    Instruction::IncPtr { .. }
+                        | Instruction::DecPtr { .. }
+                        | Instruction::MovePtrUntilZero { .. }
+                        | Instruction::MoveData { .. }
+                )
+            })
+            .count();
+        (checked, checkable)
+    }
+}
+
+// The range of values the data pointer might have at some point in the
+// program, relative to the start of memory. Every check passed so far leaves
+// it inside memory, so the ranges the analysis keeps are too.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Interval {
+    min: i64,
+    max: i64,
+}
+
+impl Interval {
+    fn shift(self, offset: i64) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    fn join(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    // Gives up on whichever bound is still moving, leaving it at that end of
+    // memory.
+    fn widen(self, next: Self) -> Self {
+        Self {
+            min: if next.min < self.min { 0 } else { self.min },
+            max: if next.max > self.max {
+                MEMORY_SIZE as i64 - 1
+            } else {
+                self.max
+            },
+        }
+    }
+
+    fn within_memory(self) -> bool {
+        self.min >= 0 && self.max < MEMORY_SIZE as i64
+    }
+
+    // What's left of the range once a check has passed, if anything.
+    fn clamp_to_memory(self) -> Option<Self> {
+        let clamped = Self {
+            min: self.min.max(0),
+            max: self.max.min(MEMORY_SIZE as i64 - 1),
+        };
+        (clamped.min <= clamped.max).then_some(clamped)
+    }
+}
+
+// Works out which instructions need their checks, by tracking the range of
+// values the data pointer can have at each instruction. Code after a check can
+// rely on it having passed, so a loop which keeps moving the pointer only needs
+// the one check in it rather than one for everything after it too.
+fn needed_checks(instructions: &[Instruction]) -> Vec<bool> {
+    let partners = partners(instructions);
+    let mut states: Vec<Option<Interval>> = vec![None; instructions.len()];
+    let mut updates = vec![0; instructions.len()];
+    let mut worklist = vec![];
+
+    if !instructions.is_empty() {
+        states[0] = Some(Interval { min: 0, max: 0 });
+        worklist.push(0);
+    }
+
+    while let Some(index) = worklist.pop() {
+        let Some(pointer) = states[index] else {
+            continue;
+        };
+        let (Some(next), _) = step(&instructions[index], pointer) else {
+            // Every check here fails.
+            continue;
+        };
+
+        for successor in successors(&instructions[index], index, &partners) {
+            let Some(state) = states.get_mut(successor) else {
+                continue;
+            };
+
+            let updated = match *state {
+                None => next,
+                Some(current) => {
+                    let joined = current.join(next);
+                    if joined == current {
+                        continue;
+                    }
+
+                    // Only widen going back round a loop, so that the rest of
+                    // the loop still gets to narrow the range with its checks.
+                    updates[successor] += 1;
+                    if successor <= index && updates[successor] > MAX_UPDATES {
+                        current.widen(joined)
+                    } else {
+                        joined
+                    }
+                }
+            };
+
+            *state = Some(updated);
+            worklist.push(successor);
+        }
+    }
+
+    instructions
+        .iter()
+        .zip(&states)
+        .map(|(instruction, state)| match *state {
+            Some(pointer) => step(instruction, pointer).1,
+            // Never run.
+            None => false,
+        })
+        .collect()
+}
+
+// Returns the range of the pointer after the instruction once its checks have
+// passed, along with whether they might fail.
+fn step(instruction: &Instruction, pointer: Interval) -> (Option<Interval>, bool) {
+    let moved = match *instruction {
+        Instruction::IncPtr { count } => pointer.shift(count.into()),
+        Instruction::DecPtr { count } => pointer.shift(-i64::from(count)),
+        // There's no telling how far a scan goes, so it's always checked.
+        Instruction::MovePtrUntilZero { forward, .. } => {
+            let scanned = if forward {
+                Interval {
+                    min: pointer.min,
+                    max: MEMORY_SIZE as i64 - 1,
+                }
+            } else {
+                Interval {
+                    min: 0,
+                    max: pointer.max,
+                }
+            };
+            return (Some(scanned), true);
+        }
+        // The check is on the cell added to, rather than the pointer.
+        Instruction::MoveData {
+            forward, amount, ..
+        } => {
+            let amount = i64::from(amount);
+            let target = pointer.shift(if forward { amount } else { -amount });
+            return (Some(pointer), !target.within_memory());
+        }
+        _ => return (Some(pointer), false),
+    };
+
+    (moved.clamp_to_memory(), !moved.within_memory())
+}
+
+// Loops skip past their end when the cell is zero, and go back to just after
+// their beginning when it isn't. Conditionals skip to their end.
+fn successors(
+    instruction: &Instruction,
+    index: usize,
+    partners: &[usize],
+) -> impl Iterator<Item = usize> {
+    let destination = match instruction {
+        Instruction::JumpBegin | Instruction::JumpEnd => Some(partners[index] + 1),
+        Instruction::ConditionalBegin => Some(partners[index]),
+        _ => None,
+    };
+
+    [Some(index + 1), destination].into_iter().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::needed_checks, crate::parser::parse_with_passes, util::passes::PassSelection};
+
+    fn checks(source_code: &str) -> Vec<bool> {
+        let (program, _) = parse_with_passes(source_code, &PassSelection::default()).unwrap();
+        needed_checks(&program.instructions)
+    }
+
+    #[test]
+    fn needed_checks_test() {
+        assert_eq!(checks(""), vec![]);
+        assert_eq!(checks(">>+<<-"), vec![false, false, false, false]);
+        assert_eq!(checks("<+"), vec![true, false]);
+        assert_eq!(checks(&">".repeat(29999)), vec![false]);
+        assert_eq!(checks(&">".repeat(30000)), vec![true]);
+        // The pointer only moves one way in the loop, so it's checked. The
+        // loop may not run at all, so moving back afterwards is too.
+        assert_eq!(
+            checks("+[>+]<"),
+            vec![false, false, true, false, false, true]
+        );
+        // Only the move further right each time round needs checking, since
+        // the loop never goes back past where it started.
+        assert_eq!(
+            checks(">>+[<->>>+<]"),
+            vec![false, false, false, false, false, true, false, false, false]
+        );
+        // The loop comes back to where it started every time.
+        assert_eq!(
+            checks(">+[<.>-]<"),
+            vec![false, false, false, false, false, false, false, false, false]
+        );
+        assert_eq!(checks(">[<]"), vec![false, true]);
+    }
+
+    #[test]
+    fn move_data_checks_test() {
+        assert_eq!(checks(">[-<+>]"), vec![false, false]);
+        assert_eq!(checks("[-<+>]"), vec![true]);
+        assert_eq!(
+            checks(&format!("[-{}+{}]", ">".repeat(30000), "<".repeat(30000))),
+            vec![true]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use {
+    crate::{
+        parser::{Instruction, Program},
+        source::Source,
+    },
+    util::{
+        run::{CellType, EofPolicy, TapeOptions},
+        BfError, BfResult,
+    },
+};
+
+// Writes a program as a self-contained C program, with the tape and reads
+// following the given options. Loops become while loops and conditionals
+// become if statements, so the C compiler sees the program's structure rather
+// than a sequence of jumps. Like the JIT, nothing checks that the pointer stays
+// on the tape.
+pub fn write_c(program: Program, tape: &TapeOptions) -> BfResult<String> {
+    let mut source = Source::default();
+
+    source.line("#include <stdint.h>");
+    source.line("#include <stdio.h>");
+    source.line("#include <stdlib.h>");
+    source.line("");
+    source.line(&format!("#define TAPE_SIZE {}", tape.size));
+    source.line("");
+    source.line(&format!("typedef {} cell;", cell_type_name(tape.cell)));
+    source.line("");
+    source.line("static cell tape[TAPE_SIZE];");
+    source.line("");
+    source.line("static void bf_read(cell *p) {");
+    source.indent += 1;
+    source.line("int c = getchar();");
+    source.line("if (c == EOF) {");
+    source.indent += 1;
+    match tape.eof {
+        EofPolicy::Error => source.line("exit(1);"),
+        EofPolicy::Zero => {
+            source.line("*p = 0;");
+            source.line("return;");
+        }
+        EofPolicy::Unchanged => source.line("return;"),
+        EofPolicy::MinusOne => {
+            source.line("*p = (cell)-1;");
+            source.line("return;");
+        }
+    }
+    source.indent -= 1;
+    source.line("}");
+    source.line("*p = (cell)c;");
+    source.indent -= 1;
+    source.line("}");
+    source.line("");
+    source.line("static void bf_write(cell value) {");
+    source.indent += 1;
+    source.line("if (putchar((unsigned char)value) == EOF) {");
+    source.indent += 1;
+    source.line("exit(1);");
+    source.indent -= 1;
+    source.line("}");
+    source.indent -= 1;
+    source.line("}");
+    source.line("");
+    source.line("int main(void) {");
+    source.indent += 1;
+    source.line("cell *p = tape;");
+    source.line("");
+
+    // Data changes are written modulo the cell size, which is how they wrap
+    // anyway.
+    let modulus = 1u64 << tape.cell.bits();
+    let mut open_blocks = 0usize;
+
+    for (i, instruction) in program.instructions.into_iter().enumerate() {
+        match instruction {
+            Instruction::IncPtr { count } => source.line(&format!("p += {count};")),
+            Instruction::DecPtr { count } => source.line(&format!("p -= {count};")),
+            Instruction::IncData { count } => {
+                source.line(&format!("*p += {};", u64::from(count) % modulus));
+            }
+            Instruction::DecData { count } => {
+                source.line(&format!("*p -= {};", u64::from(count) % modulus));
+            }
+            Instruction::Read { count } => {
+                for _ in 0..count {
+                    source.line("bf_read(p);");
+                }
+            }
+            Instruction::Write { count } => {
+                for _ in 0..count {
+                    source.line("bf_write(*p);");
+                }
+            }
+            Instruction::JumpBegin => {
+                source.line("while (*p) {");
+                source.indent += 1;
+                open_blocks += 1;
+            }
+            Instruction::ConditionalBegin => {
+                source.line("if (*p) {");
+                source.indent += 1;
+                open_blocks += 1;
+            }
+            Instruction::JumpEnd | Instruction::ConditionalEnd => {
+                open_blocks = open_blocks
+                    .checked_sub(1)
+                    .ok_or_else(|| BfError::Bf(format!("Unmatched block end at position {i}.")))?;
+                source.indent -= 1;
+                source.line("}");
+            }
+            Instruction::SetDataToZero => source.line("*p = 0;"),
+            // Every repeat after the first finds the current cell already zero
+            // and does nothing, so only one is written.
+            Instruction::MovePtrUntilZero {
+                count: _,
+                forward,
+                amount,
+            } => {
+                let step = if forward { "+=" } else { "-=" };
+                source.line("while (*p) {");
+                source.indent += 1;
+                source.line(&format!("p {step} {amount};"));
+                source.indent -= 1;
+                source.line("}");
+            }
+            Instruction::MoveData {
+                count: _,
+                forward,
+                amount,
+            } => {
+                let offset = if forward {
+                    format!("{amount}")
+                } else {
+                    format!("-{amount}")
+                };
+                // Like the loop it replaces, nothing is touched when the
+                // current cell is zero, which matters at the ends of the tape.
+                source.line("if (*p) {");
+                source.indent += 1;
+                source.line(&format!("p[{offset}] += *p;"));
+                source.line("*p = 0;");
+                source.indent -= 1;
+                source.line("}");
+            }
+        }
+    }
+
+    if open_blocks != 0 {
+        return Err(BfError::Bf("Unmatched block begin.".to_owned()));
+    }
+
+    source.line("");
+    source.line("return fflush(stdout) == EOF;");
+    source.indent -= 1;
+    source.line("}");
+
+    Ok(source.text)
+}
+
+fn cell_type_name(cell: CellType) -> &'static str {
+    match cell {
+        CellType::U8 => "uint8_t",
+        CellType::U16 => "uint16_t",
+        CellType::U32 => "uint32_t",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::write_c,
+        crate::parser::parse_with_passes,
+        util::{
+            passes::PassSelection,
+            run::{CellType, EofPolicy, TapeOptions},
+        },
+    };
+
+    #[test]
+    fn write_c_test() {
+        let (program, _reports) =
+            parse_with_passes(",[->+<]>[-[-]]+++[<]-.", &PassSelection::default()).unwrap();
+        let tape = TapeOptions {
+            size: 100,
+            cell: CellType::U16,
+            eof: EofPolicy::MinusOne,
+        };
+
+        assert_eq!(
+            write_c(program, &tape).unwrap(),
+            concat!(
+                "#include <stdint.h>\n",
+                "#include <stdio.h>\n",
+                "#include <stdlib.h>\n",
+                "\n",
+                "#define TAPE_SIZE 100\n",
+                "\n",
+                "typedef uint16_t cell;\n",
+                "\n",
+                "static cell tape[TAPE_SIZE];\n",
+                "\n",
+                "static void bf_read(cell *p) {\n",
+                "    int c = getchar();\n",
+                "    if (c == EOF) {\n",
+                "        *p = (cell)-1;\n",
+                "        return;\n",
+                "    }\n",
+                "    *p = (cell)c;\n",
+                "}\n",
+                "\n",
+                "static void bf_write(cell value) {\n",
+                "    if (putchar((unsigned char)value) == EOF) {\n",
+                "        exit(1);\n",
+                "    }\n",
+                "}\n",
+                "\n",
+                "int main(void) {\n",
+                "    cell *p = tape;\n",
+                "\n",
+                "    bf_read(p);\n",
+                "    if (*p) {\n",
+                "        p[1] += *p;\n",
+                "        *p = 0;\n",
+                "    }\n",
+                "    p += 1;\n",
+                "    if (*p) {\n",
+                "        *p -= 1;\n",
+                "        *p = 0;\n",
+                "    }\n",
+                "    *p += 3;\n",
+                "    while (*p) {\n",
+                "        p -= 1;\n",
+                "    }\n",
+                "    *p -= 1;\n",
+                "    bf_write(*p);\n",
+                "\n",
+                "    return fflush(stdout) == EOF;\n",
+                "}\n",
+            )
+        );
+    }
+}
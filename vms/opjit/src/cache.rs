@@ -0,0 +1,368 @@
+use {
+    crate::{compiler, parser},
+    std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::{Hash, Hasher},
+        io::{Read, Write},
+        sync::{Arc, Mutex},
+    },
+    util::{
+        asm::{CompiledProgram, Runtime, RuntimeSlot},
+        passes::PassSelection,
+        run::{report_passes, RunOptions},
+        BfResult,
+    },
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    // What the cache holds right now.
+    pub programs: usize,
+    pub code_size: usize,
+}
+
+// Everything which changes the code compiled for a program. The rest of the
+// run options either don't affect opjit or bypass the cache.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Key {
+    source_hash: u64,
+    passes: PassSelection,
+}
+
+struct Entry {
+    // Kept to tell apart programs whose hashes collide.
+    source_code: String,
+    program: CompiledProgram,
+    // The Runtime the program calls back into. Runs of the same program take
+    // turns with it.
+    slot: Mutex<Box<RuntimeSlot>>,
+}
+
+struct CachedProgram {
+    entry: Arc<Entry>,
+    // The clock when the program was last run, for finding the least recently
+    // used one.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Programs {
+    programs: HashMap<Key, CachedProgram>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+// Keeps compiled programs around so that running the same program again skips
+// parsing and compiling it. Once the programs' code adds up to more than
+// max_code_size bytes, the least recently used ones are evicted. Programs are
+// compiled outside of the lock, so a slow compile doesn't hold up hits on other
+// programs.
+pub struct ProgramCache {
+    max_code_size: usize,
+    programs: Mutex<Programs>,
+}
+
+impl ProgramCache {
+    pub fn new(max_code_size: usize) -> Self {
+        ProgramCache {
+            max_code_size,
+            programs: Mutex::default(),
+        }
+    }
+
+    // Like run_with_options, but with the compiled program cached. Passes are
+    // only reported when the program is compiled. Registering code with perf
+    // or GDB needs it compiled for the run, as do compiling it lazily,
+    // checking bounds, optimizing it with a profile and syscall I/O, so those
+    // runs bypass the cache.
+    pub fn run(
+        &self,
+        source_code: &str,
+        options: &RunOptions,
+        stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+    ) -> BfResult<()> {
+        if options.perf.enabled()
+            || options.gdb.is_some()
+            || options.lazy
+            || options.bounds_checks
+            || options.profile.is_some()
+            || options.syscall_io
+        {
+            return crate::run_with_options(source_code, options, stdin, stdout);
+        }
+
+        let entry = self.get_or_compile(source_code, options)?;
+        let mut runtime = Runtime::new(stdin, stdout);
+        let mut slot = entry.slot.lock().unwrap();
+        runtime.run_in_slot(&entry.program, &mut slot);
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.programs.lock().unwrap().stats
+    }
+
+    // Evicts every program, leaving the hit and miss counts alone. Programs
+    // which are running stay alive until they finish.
+    pub fn clear(&self) {
+        let mut programs = self.programs.lock().unwrap();
+        let evicted = programs.programs.len() as u64;
+        programs.programs.clear();
+        programs.stats.evictions += evicted;
+        programs.stats.programs = 0;
+        programs.stats.code_size = 0;
+    }
+
+    fn get_or_compile(&self, source_code: &str, options: &RunOptions) -> BfResult<Arc<Entry>> {
+        let key = Key {
+            source_hash: hash(source_code),
+            passes: options.passes.clone(),
+        };
+        if let Some(entry) = self.programs.lock().unwrap().get(&key, source_code) {
+            return Ok(entry);
+        }
+
+        let (program, reports) = parser::parse_with_passes(source_code, &options.passes)?;
+        if options.report_passes {
+            report_passes(&reports);
+        }
+        let slot = RuntimeSlot::new();
+        let program = compiler::compile_in_slot(program, &slot)?;
+        let entry = Arc::new(Entry {
+            source_code: source_code.to_owned(),
+            program,
+            slot: Mutex::new(slot),
+        });
+
+        self.programs
+            .lock()
+            .unwrap()
+            .insert(key, Arc::clone(&entry), self.max_code_size);
+
+        Ok(entry)
+    }
+}
+
+impl Programs {
+    fn get(&mut self, key: &Key, source_code: &str) -> Option<Arc<Entry>> {
+        self.clock += 1;
+        match self.programs.get_mut(key) {
+            Some(cached) if cached.entry.source_code == source_code => {
+                cached.last_used = self.clock;
+                self.stats.hits += 1;
+                Some(Arc::clone(&cached.entry))
+            }
+            _ => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    // Programs bigger than the whole cache are run without being kept.
+    fn insert(&mut self, key: Key, entry: Arc<Entry>, max_code_size: usize) {
+        let size = entry.program.code().len();
+        if size > max_code_size {
+            return;
+        }
+
+        // Another thread may have compiled the same program in the meantime,
+        // or it may be a different program whose hash collides.
+        if let Some(replaced) = self.programs.remove(&key) {
+            self.stats.code_size -= replaced.entry.program.code().len();
+        }
+        while self.stats.code_size + size > max_code_size {
+            self.evict_least_recently_used();
+        }
+
+        self.clock += 1;
+        self.programs.insert(
+            key,
+            CachedProgram {
+                entry,
+                last_used: self.clock,
+            },
+        );
+        self.stats.code_size += size;
+        self.stats.programs = self.programs.len();
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let key = self
+            .programs
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(key, _)| key.clone())
+            .expect("Code size left over with no programs.");
+        let evicted = self.programs.remove(&key).unwrap();
+
+        self.stats.code_size -= evicted.entry.program.code().len();
+        self.stats.programs = self.programs.len();
+        self.stats.evictions += 1;
+    }
+}
+
+fn hash(source_code: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source_code.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{CacheStats, ProgramCache},
+        std::{sync::Arc, thread},
+        util::{passes::PassSelection, run::RunOptions, BfError},
+    };
+
+    const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.";
+    const ECHO: &str = ",[.,]";
+
+    fn run(cache: &ProgramCache, source_code: &str, options: &RunOptions, input: &str) -> String {
+        let mut output = vec![];
+        cache
+            .run(source_code, options, &mut input.as_bytes(), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn code_size(source_code: &str) -> usize {
+        let cache = ProgramCache::new(usize::MAX);
+        run(&cache, source_code, &RunOptions::default(), "\0");
+        cache.stats().code_size
+    }
+
+    #[test]
+    fn hit_test() {
+        let cache = ProgramCache::new(usize::MAX);
+        let options = RunOptions::default();
+
+        assert_eq!(run(&cache, HELLO, &options, ""), "Hello");
+        assert_eq!(run(&cache, HELLO, &options, ""), "Hello");
+        assert_eq!(run(&cache, ECHO, &options, "ab\0"), "ab");
+        assert_eq!(run(&cache, ECHO, &options, "cd\0"), "cd");
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.evictions, 0);
+        assert_eq!(stats.programs, 2);
+        assert_eq!(stats.code_size, code_size(HELLO) + code_size(ECHO));
+    }
+
+    #[test]
+    fn options_test() {
+        let cache = ProgramCache::new(usize::MAX);
+        let o0 = RunOptions {
+            passes: PassSelection::Level(0),
+            ..Default::default()
+        };
+
+        assert_eq!(run(&cache, HELLO, &RunOptions::default(), ""), "Hello");
+        assert_eq!(run(&cache, HELLO, &o0, ""), "Hello");
+        assert_eq!(run(&cache, HELLO, &o0, ""), "Hello");
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.programs), (1, 2, 2));
+    }
+
+    #[test]
+    fn eviction_test() {
+        let options = RunOptions::default();
+        let cache = ProgramCache::new(code_size(HELLO) + code_size(ECHO));
+
+        run(&cache, HELLO, &options, "");
+        run(&cache, ECHO, &options, "\0");
+        // Make the echo program the least recently used.
+        run(&cache, HELLO, &options, "");
+        run(&cache, "+.", &options, "");
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.programs, 2);
+        assert_eq!(stats.code_size, code_size(HELLO) + code_size("+."));
+
+        run(&cache, HELLO, &options, "");
+        run(&cache, ECHO, &options, "\0");
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.stats().misses, 4);
+    }
+
+    #[test]
+    fn too_big_test() {
+        let cache = ProgramCache::new(code_size(HELLO) - 1);
+
+        assert_eq!(run(&cache, HELLO, &RunOptions::default(), ""), "Hello");
+        assert_eq!(run(&cache, HELLO, &RunOptions::default(), ""), "Hello");
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                misses: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn syscall_io_test() {
+        let cache = ProgramCache::new(usize::MAX);
+        let options = RunOptions {
+            syscall_io: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            cache.run(HELLO, &options, &mut "".as_bytes(), &mut vec![]),
+            Err(BfError::Bf(
+                "Syscall I/O reads and writes file descriptors, so it needs run_with_fds."
+                    .to_owned()
+            ))
+        );
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn clear_test() {
+        let cache = ProgramCache::new(usize::MAX);
+
+        run(&cache, HELLO, &RunOptions::default(), "");
+        cache.clear();
+        run(&cache, HELLO, &RunOptions::default(), "");
+
+        let stats = cache.stats();
+        assert_eq!((stats.misses, stats.evictions, stats.programs), (2, 1, 1));
+    }
+
+    #[test]
+    fn threads_test() {
+        let cache = Arc::new(ProgramCache::new(usize::MAX));
+
+        let threads = (0..8)
+            .map(|i| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    let input = format!("{i}\0");
+                    for _ in 0..10 {
+                        assert_eq!(
+                            run(&cache, ECHO, &RunOptions::default(), &input),
+                            i.to_string()
+                        );
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, 80);
+        assert_eq!(stats.programs, 1);
+    }
+}
@@ -1,10 +1,16 @@
 use {
-    crate::parser::{Instruction, Program},
-    dynasmrt::{dynasm, DynasmApi, DynasmLabelApi},
+    crate::{
+        bounds::BoundsChecks,
+        parser::{Instruction, Program},
+        pgo::Hint,
+    },
+    dynasmrt::{dynasm, AssemblyOffset, DynamicLabel, DynasmApi, DynasmLabelApi},
     util::{
         asm::{
-            call_read, call_write, epilogue, jump_begin, jump_end, prologue, Assembler,
-            CompiledProgram, Runtime,
+            call_read, call_write, checked_move_ptr_until_zero, conditional_begin, conditional_end,
+            epilogue, jump_begin, jump_end, loop_epilogue, loop_prologue, move_ptr_until_zero,
+            prologue, scalar_move_ptr_until_zero, Assembler, CellLocation, CompiledProgram, Io,
+            LabelPair, Runtime, RuntimeSlot, VectorWidth,
         },
         dasm, BfResult,
     },
@@ -12,18 +18,236 @@ use {
 
 #[cfg(target_arch = "aarch64")]
 use util::add_sub_u64;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use util::asm::{buffered_epilogue, SyscallIo};
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use util::asm::{standalone_epilogue, standalone_prologue, StandaloneProgram};
+
+// What else goes into compiling a whole program, beyond its instructions.
+#[derive(Default)]
+pub struct CompileOptions<'a> {
+    // With bounds checks, a failed check ends the program early, leaving the
+    // checks to say where.
+    pub checks: Option<&'a mut BoundsChecks>,
+    // How to compile each instruction, going by a profile. Empty without one.
+    pub hints: Vec<Hint>,
+}
 
-pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProgram> {
+// Also returns where each instruction's code starts, followed by where the
+// epilogue starts.
+pub fn compile(
+    program: Program,
+    runtime: &mut Runtime,
+    options: CompileOptions,
+) -> BfResult<(CompiledProgram, Vec<AssemblyOffset>)> {
+    let CompileOptions { mut checks, hints } = options;
     let mut assembler = Assembler::new()?;
+    let exit = assembler.new_dynamic_label();
     let start = assembler.offset();
 
     prologue(&mut assembler, runtime);
+    let mut offsets = compile_program_instructions(
+        &mut assembler,
+        program.instructions,
+        &Io::Runtime(runtime),
+        VectorWidth::host(),
+        checks.as_deref_mut(),
+        &hints,
+    )?;
+    offsets.push(assembler.offset());
+    dasm!(assembler
+        ; =>exit
+    );
+    epilogue(&mut assembler);
+    if let Some(checks) = checks {
+        checks.write_failures(&mut assembler, exit);
+    }
+
+    Ok((CompiledProgram::new(assembler.finalize()?, start), offsets))
+}
 
-    let mut open_bracket_stack = vec![];
+// Compiles a fragment of a program, such as a single loop, to be run from
+// wherever the data pointer currently is with Runtime::run_loop.
+pub fn compile_loop(
+    instructions: Vec<Instruction>,
+    runtime: &mut Runtime,
+) -> BfResult<CompiledProgram> {
+    let mut assembler = Assembler::new()?;
+    let start = assembler.offset();
+
+    loop_prologue(&mut assembler);
+    compile_instructions(
+        &mut assembler,
+        instructions,
+        &Io::Runtime(runtime),
+        VectorWidth::host(),
+    )?;
+    loop_epilogue(&mut assembler);
+
+    Ok(CompiledProgram::new(assembler.finalize()?, start))
+}
 
-    for (i, instruction) in program.instructions.into_iter().enumerate() {
-        match instruction {
+// Compiles a program which can be run any number of times, each with a new
+// Runtime, with Runtime::run_in_slot. It calls back into whichever Runtime is
+// in the slot, so that's the one it must be run with.
+pub fn compile_in_slot(program: Program, slot: &RuntimeSlot) -> BfResult<CompiledProgram> {
+    let mut assembler = Assembler::new()?;
+    let start = assembler.offset();
+
+    loop_prologue(&mut assembler);
+    compile_instructions(
+        &mut assembler,
+        program.instructions,
+        &Io::Slot(slot),
+        VectorWidth::host(),
+    )?;
+    loop_epilogue(&mut assembler);
+
+    Ok(CompiledProgram::new(assembler.finalize()?, start))
+}
+
+// Compiles a program which reads and writes through `io` rather than calling
+// back into the runtime. Once it's run, io.result() says whether it finished.
+// Output is still flushed when a bounds check fails.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn compile_with_syscalls(
+    program: Program,
+    runtime: &mut Runtime,
+    io: &SyscallIo,
+    options: CompileOptions,
+) -> BfResult<CompiledProgram> {
+    let CompileOptions { mut checks, hints } = options;
+    let mut assembler = Assembler::new()?;
+    let flush = assembler.new_dynamic_label();
+    let error = assembler.new_dynamic_label();
+    let exit = assembler.new_dynamic_label();
+    let start = assembler.offset();
+
+    prologue(&mut assembler, runtime);
+    compile_program_instructions(
+        &mut assembler,
+        program.instructions,
+        &Io::Buffered { io, flush, error },
+        VectorWidth::host(),
+        checks.as_deref_mut(),
+        &hints,
+    )?;
+    dasm!(assembler
+        ; =>exit
+    );
+    buffered_epilogue(&mut assembler, io, flush, error);
+    if let Some(checks) = checks {
+        checks.write_failures(&mut assembler, exit);
+    }
+
+    Ok(CompiledProgram::new(assembler.finalize()?, start))
+}
+
+// Compiles a program into position-independent code which runs as an
+// executable of its own, making syscalls rather than calling into a Runtime.
+// The executable may run on another machine, so scans only use SSE2.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn compile_standalone(program: Program) -> BfResult<StandaloneProgram> {
+    let mut assembler = Assembler::new()?;
+    let error = assembler.new_dynamic_label();
+
+    let tape_displacement = standalone_prologue(&mut assembler);
+    compile_instructions(
+        &mut assembler,
+        program.instructions,
+        &Io::Syscalls { error },
+        VectorWidth::Baseline,
+    )?;
+    standalone_epilogue(&mut assembler, error);
+
+    Ok(StandaloneProgram::new(
+        assembler.finalize()?,
+        tape_displacement,
+    ))
+}
+
+// Compiles instructions into an assembler which the caller sets up and
+// finishes, for code which isn't just a program or a loop. Scans use vectors of
+// the given width. Returns where each instruction's code starts.
+pub fn compile_instructions(
+    assembler: &mut Assembler,
+    instructions: Vec<Instruction>,
+    io: &Io,
+    vectors: VectorWidth,
+) -> BfResult<Vec<AssemblyOffset>> {
+    compile_program_instructions(assembler, instructions, io, vectors, None, &[])
+}
+
+// Compiles a whole program's instructions, with the data pointer checked
+// wherever the checks say it needs to be, and scans compiled as the hints say.
+// The caller writes the code failed checks jump to.
+fn compile_program_instructions(
+    assembler: &mut Assembler,
+    instructions: Vec<Instruction>,
+    io: &Io,
+    vectors: VectorWidth,
+    checks: Option<&mut BoundsChecks>,
+    hints: &[Hint],
+) -> BfResult<Vec<AssemblyOffset>> {
+    let mut compiler = InstructionCompiler {
+        assembler,
+        io,
+        vectors,
+        checks,
+        hints,
+        open_bracket_stack: vec![],
+        open_conditional_stack: vec![],
+        cell: CellCache::default(),
+        offsets: Vec::with_capacity(instructions.len()),
+    };
+    for i in 0..instructions.len() {
+        compiler.instruction(&instructions, i)?;
+    }
+
+    // Whatever runs next expects the tape to be up to date.
+    compiler.cell.store(compiler.assembler);
+
+    Ok(compiler.offsets)
+}
+
+// The state of compiling a program's instructions, one after another.
+struct InstructionCompiler<'a, 'r, 's> {
+    assembler: &'a mut Assembler,
+    io: &'a Io<'r, 's>,
+    vectors: VectorWidth,
+    checks: Option<&'a mut BoundsChecks>,
+    hints: &'a [Hint],
+    open_bracket_stack: Vec<LabelPair>,
+    open_conditional_stack: Vec<DynamicLabel>,
+    cell: CellCache,
+    // Where each instruction's code starts.
+    offsets: Vec<AssemblyOffset>,
+}
+
+impl InstructionCompiler<'_, '_, '_> {
+    fn instruction(&mut self, instructions: &[Instruction], i: usize) -> BfResult<()> {
+        let InstructionCompiler {
+            assembler,
+            io,
+            vectors,
+            checks,
+            hints,
+            open_bracket_stack,
+            open_conditional_stack,
+            cell,
+            offsets,
+        } = self;
+        offsets.push(assembler.offset());
+
+        match instructions[i] {
             Instruction::IncPtr { count } => {
+                cell.forget(assembler);
                 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                 dasm!(assembler
                     // Reinterpret as i32, using the same bytes as before.
@@ -31,8 +255,12 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                 );
                 #[cfg(target_arch = "aarch64")]
                 add_sub_u64!(assembler, add, reg_data_ptr, reg_data_ptr, count.into());
+                if let Some(checks) = checks.as_deref_mut() {
+                    checks.check(assembler, i, 0);
+                }
             }
             Instruction::DecPtr { count } => {
+                cell.forget(assembler);
                 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                 dasm!(assembler
                     // Reinterpret as i32, using the same bytes as before.
@@ -40,8 +268,12 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                 );
                 #[cfg(target_arch = "aarch64")]
                 add_sub_u64!(assembler, sub, reg_data_ptr, reg_data_ptr, count.into());
+                if let Some(checks) = checks.as_deref_mut() {
+                    checks.check(assembler, i, 0);
+                }
             }
             Instruction::IncData { count } => {
+                cell.load(assembler);
                 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                 {
                     // Adding 256 is effectively a nop, since it will wrap around to
@@ -50,17 +282,15 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                     let wrapped_count = (count % 256) as u8;
                     dasm!(assembler
                         // Reinterpret as i8, using the same bytes as before.
-                        ; add BYTE [reg_data_ptr], BYTE wrapped_count as i8
+                        ; add reg_cell, BYTE wrapped_count as i8
                     );
                 }
                 #[cfg(target_arch = "aarch64")]
-                dasm!(assembler
-                    ; ldrb reg_temp_low, [reg_data_ptr]
-                    ;; add_sub_u64!(assembler, add, reg_temp_low, reg_temp_low, count.into())
-                    ; strb reg_temp_low, [reg_data_ptr]
-                );
+                add_sub_u64!(assembler, add, reg_cell, reg_cell, (count % 256).into());
+                cell.dirty = true;
             }
             Instruction::DecData { count } => {
+                cell.load(assembler);
                 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                 {
                     // Subtracting 256 is effectively a nop, since it will wrap
@@ -69,92 +299,74 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                     let wrapped_count = (count % 256) as u8;
                     dasm!(assembler
                         // Reinterpret as i8, using the same bytes as before.
-                        ; sub BYTE [reg_data_ptr], BYTE wrapped_count as i8
+                        ; sub reg_cell, BYTE wrapped_count as i8
                     );
                 }
                 #[cfg(target_arch = "aarch64")]
-                dasm!(assembler
-                    ; ldrb reg_temp_low, [reg_data_ptr]
-                    ;; add_sub_u64!(assembler, sub, reg_temp_low, reg_temp_low, count.into())
-                    ; strb reg_temp_low, [reg_data_ptr]
-                );
+                add_sub_u64!(assembler, sub, reg_cell, reg_cell, (count % 256).into());
+                cell.dirty = true;
             }
             Instruction::Read { count } => {
+                // The calls clobber reg_cell, and memory ends up with the byte
+                // read.
+                cell.forget(assembler);
                 for _ in 0..count {
-                    call_read(&mut assembler, runtime);
+                    call_read(assembler, io);
                 }
             }
             Instruction::Write { count } => {
+                cell.forget(assembler);
                 for _ in 0..count {
-                    call_write(&mut assembler, runtime);
+                    call_write(assembler, io);
                 }
             }
             Instruction::JumpBegin => {
-                jump_begin(&mut assembler, &mut open_bracket_stack);
+                cell.sync(assembler);
+                jump_begin(assembler, open_bracket_stack, CellLocation::Register);
             }
             Instruction::JumpEnd => {
-                jump_end(&mut assembler, &mut open_bracket_stack, i)?;
+                cell.sync(assembler);
+                jump_end(assembler, open_bracket_stack, CellLocation::Register, i)?;
+            }
+            Instruction::ConditionalBegin => {
+                cell.sync(assembler);
+                conditional_begin(assembler, open_conditional_stack, CellLocation::Register);
+            }
+            Instruction::ConditionalEnd => {
+                cell.sync(assembler);
+                conditional_end(assembler, open_conditional_stack, i)?;
             }
             Instruction::SetDataToZero => {
                 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                 dasm!(assembler
-                    ; mov BYTE [reg_data_ptr], 0
+                    ; xor reg_cell_dword, reg_cell_dword
                 );
                 #[cfg(target_arch = "aarch64")]
                 dasm!(assembler
-                    ; strb wzr, [reg_data_ptr]
+                    ; mov reg_cell, wzr
                 );
+                cell.loaded = true;
+                cell.dirty = true;
             }
             Instruction::MovePtrUntilZero {
                 count,
                 forward,
                 amount,
             } => {
+                cell.forget(assembler);
+                let fail = checks
+                    .as_deref_mut()
+                    .and_then(|checks| Some((checks.memory(), checks.failure(assembler, i)?)));
                 for _ in 0..count {
-                    let begin_loop = assembler.new_dynamic_label();
-                    let end_loop = assembler.new_dynamic_label();
-                    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-                    dasm!(assembler
-                        ; =>begin_loop
-                        ; cmp BYTE [reg_data_ptr], 0
-                        ; jz =>end_loop
-                    );
-                    #[cfg(target_arch = "aarch64")]
-                    dasm!(assembler
-                        ; =>begin_loop
-                        ; ldrb reg_temp_low, [reg_data_ptr]
-                        ; cmp reg_temp_low, 0
-                        ; b.eq =>end_loop
-                    );
-
-                    if forward {
-                        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-                        dasm!(assembler
-                            // Reinterpret as i32, using the same bytes as before.
-                            ; add reg_data_ptr, DWORD amount as i32
-                        );
-                        #[cfg(target_arch = "aarch64")]
-                        add_sub_u64!(assembler, add, reg_data_ptr, reg_data_ptr, amount.into());
-                    } else {
-                        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-                        dasm!(assembler
-                            // Reinterpret as i32, using the same bytes as before.
-                            ; sub reg_data_ptr, DWORD amount as i32
-                        );
-                        #[cfg(target_arch = "aarch64")]
-                        add_sub_u64!(assembler, sub, reg_data_ptr, reg_data_ptr, amount.into());
+                    match fail {
+                        Some((memory, fail)) => {
+                            checked_move_ptr_until_zero(assembler, forward, amount, memory, fail)
+                        }
+                        None if hints.get(i) == Some(&Hint::ShortScan) => {
+                            scalar_move_ptr_until_zero(assembler, forward, amount, None)
+                        }
+                        None => move_ptr_until_zero(assembler, forward, amount, *vectors),
                     }
-
-                    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-                    dasm!(assembler
-                        ; jmp =>begin_loop
-                        ; =>end_loop
-                    );
-                    #[cfg(target_arch = "aarch64")]
-                    dasm!(assembler
-                        ; b =>begin_loop
-                        ; =>end_loop
-                    );
                 }
             }
             Instruction::MoveData {
@@ -162,29 +374,32 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                 forward,
                 amount,
             } => {
+                cell.load(assembler);
                 for _ in 0..count {
                     let skip_move = assembler.new_dynamic_label();
 
                     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                     dasm!(assembler
-                        ; cmp BYTE [reg_data_ptr], 0
+                        ; test reg_cell, reg_cell
                         ; jz =>skip_move
-                        ; mov reg_temp_low, BYTE [reg_data_ptr]
                     );
                     #[cfg(target_arch = "aarch64")]
                     dasm!(assembler
-                        ; ldrb reg_temp2_low, [reg_data_ptr]
-                        ; cmp reg_temp2_low, 0
+                        ; tst reg_cell, 0xff
                         ; b.eq =>skip_move
-                        ; ldrb reg_temp_low, [reg_data_ptr]
                     );
 
+                    if let Some(checks) = checks.as_deref_mut() {
+                        let offset = i64::from(amount);
+                        checks.check(assembler, i, if forward { offset } else { -offset });
+                    }
+
                     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                     let amount_i32: i32 = amount.try_into()?;
                     if forward {
                         #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                         dasm!(assembler
-                            ; add BYTE [reg_data_ptr + amount_i32], reg_temp_low
+                            ; add BYTE [reg_data_ptr + amount_i32], reg_cell
                         );
                         #[cfg(target_arch = "aarch64")]
                         dasm!(assembler
@@ -192,13 +407,13 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                             // can actually have a u32 of offset.
                             ;; add_sub_u64!(assembler, add, reg_temp3, reg_data_ptr, amount.into())
                             ; ldrb reg_temp2_low, [reg_temp3]
-                            ; add reg_temp2_low, reg_temp2_low, reg_temp_low
+                            ; add reg_temp2_low, reg_temp2_low, reg_cell
                             ; strb reg_temp2_low, [reg_temp3]
                         );
                     } else {
                         #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                         dasm!(assembler
-                            ; add BYTE [reg_data_ptr - amount_i32], reg_temp_low
+                            ; add BYTE [reg_data_ptr - amount_i32], reg_cell
                         );
                         #[cfg(target_arch = "aarch64")]
                         dasm!(assembler
@@ -206,27 +421,117 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                             // can actually have a u32 of offset.
                             ;; add_sub_u64!(assembler, sub, reg_temp3, reg_data_ptr, amount.into())
                             ; ldrb reg_temp2_low, [reg_temp3]
-                            ; add reg_temp2_low, reg_temp2_low, reg_temp_low
+                            ; add reg_temp2_low, reg_temp2_low, reg_cell
                             ; strb reg_temp2_low, [reg_temp3]
                         );
                     }
 
                     #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
                     dasm!(assembler
-                        ; mov BYTE [reg_data_ptr], 0
+                        ; xor reg_cell_dword, reg_cell_dword
                         ; =>skip_move
                     );
                     #[cfg(target_arch = "aarch64")]
                     dasm!(assembler
-                        ; strb wzr, [reg_data_ptr]
+                        ; mov reg_cell, wzr
                         ; =>skip_move
                     );
                 }
+                cell.dirty = true;
             }
         }
+
+        Ok(())
     }
 
-    epilogue(&mut assembler);
+    // Compiles the loop from begin to end with its body copied `factor` times,
+    // leaving the loop if the cell is zero after each copy, and only jumping
+    // back after the last. The body is straight-line code.
+}
 
-    Ok(CompiledProgram::new(assembler.finalize()?, start))
+// Tracks the copy of the current cell kept in reg_cell. The cell is loaded at
+// most once per basic block, and only stored back when the data pointer moves,
+// before calls, and at the ends of blocks. Every label is reached with reg_cell
+// holding the current cell and memory agreeing with it, so each block starts
+// out with the cell already loaded.
+#[derive(Default)]
+struct CellCache {
+    loaded: bool,
+    // Whether memory is behind reg_cell.
+    dirty: bool,
+}
+
+impl CellCache {
+    fn load(&mut self, assembler: &mut Assembler) {
+        if !self.loaded {
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            dasm!(assembler
+                ; movzx reg_cell_dword, BYTE [reg_data_ptr]
+            );
+            #[cfg(target_arch = "aarch64")]
+            dasm!(assembler
+                ; ldrb reg_cell, [reg_data_ptr]
+            );
+            self.loaded = true;
+        }
+    }
+
+    fn store(&mut self, assembler: &mut Assembler) {
+        if self.dirty {
+            #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+            dasm!(assembler
+                ; mov BYTE [reg_data_ptr], reg_cell
+            );
+            #[cfg(target_arch = "aarch64")]
+            dasm!(assembler
+                ; strb reg_cell, [reg_data_ptr]
+            );
+            self.dirty = false;
+        }
+    }
+
+    // Gets reg_cell and memory agreeing at a block boundary.
+    fn sync(&mut self, assembler: &mut Assembler) {
+        self.load(assembler);
+        self.store(assembler);
+    }
+
+    // Stores the cell back before the data pointer moves or reg_cell is
+    // clobbered.
+    fn forget(&mut self, assembler: &mut Assembler) {
+        self.store(assembler);
+        self.loaded = false;
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use {
+        super::compile_standalone,
+        crate::parser::parse_with_passes,
+        iced_x86::{Decoder, DecoderOptions, EncodingKind, Mnemonic},
+        util::passes::PassSelection,
+    };
+
+    // Standalone executables can be run on any x86-64 CPU, so their scans
+    // stick to SSE2 whatever the machine compiling them supports.
+    #[test]
+    fn standalone_scan_test() {
+        let (program, _) = parse_with_passes("+[>]+[<<]", &PassSelection::default()).unwrap();
+        let standalone = compile_standalone(program).unwrap();
+
+        let instructions = Decoder::new(64, standalone.code(), DecoderOptions::NONE)
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert!(instructions
+            .iter()
+            .all(|instruction| instruction.encoding() == EncodingKind::Legacy));
+        assert_eq!(
+            instructions
+                .iter()
+                .filter(|instruction| instruction.mnemonic() == Mnemonic::Pcmpeqb)
+                .count(),
+            2
+        );
+    }
 }
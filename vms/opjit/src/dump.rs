@@ -0,0 +1,127 @@
+use {
+    dynasmrt::AssemblyOffset,
+    std::{fmt::Write, ops::Range},
+};
+
+// Writes the code compiled for a program as a disassembly, with each
+// instruction's code headed by the instruction and the BF it was compiled from.
+// offsets holds where each instruction's code starts followed by where the
+// epilogue starts, as returned by compiler::compile. Addresses are
+// shown as the code is mapped, so they match what a debugger would show.
+pub fn write_dump(
+    descriptions: &[String],
+    spans: &[Range<usize>],
+    source_code: &str,
+    code: &[u8],
+    address: u64,
+    offsets: &[AssemblyOffset],
+) -> String {
+    let mut dump = String::new();
+
+    let (epilogue, instruction_offsets) = offsets.split_last().expect("Missing epilogue offset.");
+    let prologue_end = instruction_offsets.first().unwrap_or(epilogue);
+
+    writeln!(dump, "prologue:").unwrap();
+    disassemble(&mut dump, code, address, 0..prologue_end.0);
+
+    for (i, ((description, span), start)) in descriptions
+        .iter()
+        .zip(spans)
+        .zip(instruction_offsets)
+        .enumerate()
+    {
+        let snippet = source_code[span.clone()]
+            .chars()
+            .filter(|c| "><+-,.[]".contains(*c))
+            .collect::<String>();
+        let end = offsets[i + 1];
+        writeln!(dump, "{i}: {description} ; {snippet}").unwrap();
+        disassemble(&mut dump, code, address, start.0..end.0);
+    }
+
+    writeln!(dump, "epilogue:").unwrap();
+    disassemble(&mut dump, code, address, epilogue.0..code.len());
+
+    dump
+}
+
+fn disassemble(dump: &mut String, code: &[u8], address: u64, range: Range<usize>) {
+    use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, IntelFormatter};
+
+    #[cfg(target_arch = "x86_64")]
+    const BITNESS: u32 = 64;
+    #[cfg(target_arch = "x86")]
+    const BITNESS: u32 = 32;
+
+    let start = range.start;
+    let mut decoder = Decoder::with_ip(
+        BITNESS,
+        &code[range],
+        address + start as u64,
+        DecoderOptions::NONE,
+    );
+    let mut formatter = IntelFormatter::new();
+    let mut instruction = Instruction::default();
+    let mut text = String::new();
+
+    while decoder.can_decode() {
+        decoder.decode_out(&mut instruction);
+        text.clear();
+        formatter.format(&instruction, &mut text);
+
+        let offset = (instruction.ip() - address) as usize;
+        let bytes = &code[offset..offset + instruction.len()];
+        write_line(dump, instruction.ip(), bytes, &text);
+    }
+}
+
+fn write_line(dump: &mut String, address: u64, bytes: &[u8], text: &str) {
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    writeln!(dump, "    {address:016x}  {hex:<20}  {text}").unwrap();
+}
+
+// The expected disassembly is only right for x86-64.
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use {super::write_dump, dynasmrt::AssemblyOffset};
+
+    #[test]
+    fn write_dump_test() {
+        let code = [
+            0x41, 0x55, // push r13
+            0x49, 0x83, 0xc5, 0x02, // add r13,2
+            0x49, 0x83, 0xed, 0x01, // sub r13,1
+            0x41, 0x5d, // pop r13
+            0xc3, // ret
+        ];
+        let descriptions = [
+            "IncPtr { count: 2 }".to_owned(),
+            "DecPtr { count: 1 }".to_owned(),
+        ];
+
+        assert_eq!(
+            write_dump(
+                &descriptions,
+                &[0..2, 3..4],
+                ">>\n<",
+                &code,
+                0x1000,
+                &[AssemblyOffset(2), AssemblyOffset(6), AssemblyOffset(10)],
+            ),
+            concat!(
+                "prologue:\n",
+                "    0000000000001000  4155                  push r13\n",
+                "0: IncPtr { count: 2 } ; >>\n",
+                "    0000000000001002  4983c502              add r13,2\n",
+                "1: DecPtr { count: 1 } ; <\n",
+                "    0000000000001006  4983ed01              sub r13,1\n",
+                "epilogue:\n",
+                "    000000000000100a  415d                  pop r13\n",
+                "    000000000000100c  c3                    ret\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use {
+    crate::{
+        compiler::{compile_instructions, compile_loop},
+        parser::{Instruction, Program},
+    },
+    dynasmrt::{AssemblyOffset, DynasmApi, DynasmLabelApi},
+    std::{mem, ptr},
+    util::{
+        asm::{
+            conditional_begin, conditional_end, loop_epilogue, loop_prologue, loop_stub,
+            patchable_loop_call, Assembler, CellLocation, CompiledProgram, Io, Runtime,
+            VectorWidth,
+        },
+        BfError, BfResult,
+    },
+};
+
+// A run of instructions at the top level of a program, outside of any loop, or
+// one of its top-level loops.
+#[derive(Debug, Eq, PartialEq)]
+enum Piece {
+    Code(Vec<Instruction>),
+    Loop(usize),
+}
+
+struct Loop {
+    // Taken once the loop is compiled.
+    instructions: Option<Vec<Instruction>>,
+    code: Option<CompiledProgram>,
+    // Where the address the loop's call site calls is.
+    target: AssemblyOffset,
+}
+
+struct LazyProgram<'a> {
+    runtime: *mut Runtime<'a>,
+    program: Option<CompiledProgram>,
+    loops: Vec<Loop>,
+    // Why a loop failed to compile, which stops the program.
+    error: Option<BfError>,
+}
+
+// What each loop's stub passes to enter.
+struct Stub<'a> {
+    program: *mut LazyProgram<'a>,
+    index: usize,
+}
+
+// Runs a program with only the code outside of its loops compiled up front.
+// Each top-level loop starts out as a call to a stub, which compiles the loop
+// the first time it's entered, patches the call to go straight to the compiled
+// loop from then on, and runs it. Loops which are skipped over never call the
+// stub, so are never compiled. Loops inside of conditionals are compiled
+// along with the conditional. Returns how many of the top-level loops were
+// compiled, out of how many there are.
+pub fn run(program: Program, runtime: &mut Runtime) -> BfResult<(usize, usize)> {
+    let (pieces, loops) = split(program.instructions);
+
+    let mut lazy = Box::new(LazyProgram {
+        runtime,
+        program: None,
+        loops: loops
+            .into_iter()
+            .map(|instructions| Loop {
+                instructions: Some(instructions),
+                code: None,
+                target: AssemblyOffset(0),
+            })
+            .collect(),
+        error: None,
+    });
+    let lazy_pointer: *mut LazyProgram = &mut *lazy;
+    let stubs = (0..lazy.loops.len())
+        .map(|index| Stub {
+            program: lazy_pointer,
+            index,
+        })
+        .collect::<Vec<_>>();
+    // Nothing else uses the runtime until the program has run.
+    let runtime = unsafe { &mut *lazy.runtime };
+
+    let mut assembler = Assembler::new()?;
+    let failed = assembler.new_dynamic_label();
+    let start = assembler.offset();
+
+    loop_prologue(&mut assembler);
+    for piece in pieces {
+        match piece {
+            Piece::Code(instructions) => {
+                compile_instructions(
+                    &mut assembler,
+                    instructions,
+                    &Io::Runtime(runtime),
+                    VectorWidth::host(),
+                )?;
+            }
+            Piece::Loop(index) => {
+                // Loops which are skipped aren't compiled.
+                let mut skip_loop = vec![];
+                conditional_begin(&mut assembler, &mut skip_loop, CellLocation::Memory);
+                lazy.loops[index].target = patchable_loop_call(&mut assembler, failed);
+                conditional_end(&mut assembler, &mut skip_loop, index)?;
+            }
+        }
+    }
+    assembler.dynamic_label(failed);
+    loop_epilogue(&mut assembler);
+
+    let stub_offsets = stubs
+        .iter()
+        .map(|stub| {
+            let offset = assembler.offset();
+            loop_stub(
+                &mut assembler,
+                stub as *const Stub as *const (),
+                enter as *const (),
+            );
+            offset
+        })
+        .collect::<Vec<_>>();
+
+    let mut program = CompiledProgram::new(assembler.finalize()?, start);
+    for (lazy_loop, stub_offset) in lazy.loops.iter().zip(stub_offsets) {
+        let stub_address = program.address() as usize + stub_offset.0;
+        program.patch(lazy_loop.target, &stub_address.to_ne_bytes());
+    }
+
+    let program = &*lazy.program.insert(program) as *const CompiledProgram;
+    // The stubs patch the program while it runs, so it can't be borrowed for
+    // the whole run.
+    runtime.run_loop(unsafe { &*program }, 0);
+    if let Some(error) = lazy.error.take() {
+        return Err(error);
+    }
+
+    let compiled = lazy.loops.iter().filter(|l| l.code.is_some()).count();
+    Ok((compiled, lazy.loops.len()))
+}
+
+// Splits the top-level loops out of the instructions, with the loops in the
+// order they appear.
+fn split(instructions: Vec<Instruction>) -> (Vec<Piece>, Vec<Vec<Instruction>>) {
+    let mut pieces = vec![];
+    let mut loops = vec![];
+    let mut current = vec![];
+    let mut depth = 0;
+    let mut in_loop = false;
+
+    for instruction in instructions {
+        if depth == 0 && instruction == Instruction::JumpBegin {
+            if !current.is_empty() {
+                pieces.push(Piece::Code(mem::take(&mut current)));
+            }
+            in_loop = true;
+        }
+        match instruction {
+            Instruction::JumpBegin | Instruction::ConditionalBegin => depth += 1,
+            Instruction::JumpEnd | Instruction::ConditionalEnd => depth -= 1,
+            _ => {}
+        }
+        current.push(instruction);
+
+        if depth == 0 && in_loop {
+            pieces.push(Piece::Loop(loops.len()));
+            loops.push(mem::take(&mut current));
+            in_loop = false;
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(Piece::Code(current));
+    }
+
+    (pieces, loops)
+}
+
+impl LazyProgram<'_> {
+    // Compiles a loop, patches its call site, then runs it from where its stub
+    // was called. Each call site is patched the first time through, so this
+    // only ever happens once for each loop.
+    fn enter(&mut self, index: usize, data_pointer: *mut u8) -> BfResult<*mut u8> {
+        let runtime = unsafe { &mut *self.runtime };
+        let lazy_loop = &mut self.loops[index];
+
+        let instructions = lazy_loop
+            .instructions
+            .take()
+            .expect("Loop entered through its stub twice.");
+        let code = lazy_loop.code.insert(compile_loop(instructions, runtime)?);
+        let address = code.function_ptr() as usize;
+        self.program
+            .as_mut()
+            .unwrap()
+            .patch(lazy_loop.target, &address.to_ne_bytes());
+
+        let memory = runtime.memory_ptr();
+        let end = runtime.run_loop(code, data_pointer as usize - memory as usize);
+        Ok(memory.wrapping_add(end))
+    }
+}
+
+// Errors can't unwind through the compiled code, so they're kept for run to
+// return, and the null data pointer has the program skip to its end.
+fn enter_inner(stub: &Stub, data_pointer: *mut u8) -> *mut u8 {
+    let program = unsafe { &mut *stub.program };
+    match program.enter(stub.index, data_pointer) {
+        Ok(data_pointer) => data_pointer,
+        Err(error) => {
+            program.error = Some(error);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+extern "C" fn enter(stub: &Stub, data_pointer: *mut u8) -> *mut u8 {
+    enter_inner(stub, data_pointer)
+}
+
+#[cfg(target_arch = "x86")]
+extern "fastcall" fn enter(stub: &Stub, data_pointer: *mut u8) -> *mut u8 {
+    enter_inner(stub, data_pointer)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{run, split, Piece},
+        crate::parser::{parse_with_passes, Instruction, Program},
+        util::{asm::Runtime, passes::PassSelection, BfError},
+    };
+
+    #[test]
+    fn split_test() {
+        let (pieces, loops) = split(vec![
+            Instruction::IncData { count: 1 },
+            Instruction::JumpBegin,
+            Instruction::JumpBegin,
+            Instruction::JumpEnd,
+            Instruction::JumpEnd,
+            Instruction::ConditionalBegin,
+            Instruction::JumpBegin,
+            Instruction::JumpEnd,
+            Instruction::ConditionalEnd,
+            Instruction::JumpBegin,
+            Instruction::JumpEnd,
+        ]);
+
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Code(vec![Instruction::IncData { count: 1 }]),
+                Piece::Loop(0),
+                Piece::Code(vec![
+                    Instruction::ConditionalBegin,
+                    Instruction::JumpBegin,
+                    Instruction::JumpEnd,
+                    Instruction::ConditionalEnd,
+                ]),
+                Piece::Loop(1),
+            ]
+        );
+        assert_eq!(
+            loops,
+            vec![
+                vec![
+                    Instruction::JumpBegin,
+                    Instruction::JumpBegin,
+                    Instruction::JumpEnd,
+                    Instruction::JumpEnd,
+                ],
+                vec![Instruction::JumpBegin, Instruction::JumpEnd],
+            ]
+        );
+    }
+
+    #[test]
+    fn run_test() {
+        // The second loop is skipped, so is never compiled.
+        let source_code = "++[>+++[>++<-]<-]>>.>[,]<<<++[>>+<<-]>>.";
+        let (program, _) = parse_with_passes(source_code, &PassSelection::Level(0)).unwrap();
+
+        let mut input = "".as_bytes();
+        let mut output = vec![];
+        let mut runtime = Runtime::new(&mut input, &mut output);
+        assert_eq!(run(program, &mut runtime).unwrap(), (2, 3));
+        assert_eq!(output, [12, 14]);
+    }
+
+    #[test]
+    fn run_error_test() {
+        // The loop only fails to compile once it's entered, after the first
+        // write.
+        let program = Program {
+            instructions: vec![
+                Instruction::IncData { count: 1 },
+                Instruction::Write { count: 1 },
+                Instruction::JumpBegin,
+                Instruction::ConditionalEnd,
+                Instruction::ConditionalBegin,
+                Instruction::ConditionalEnd,
+                Instruction::Write { count: 1 },
+            ],
+        };
+
+        let mut input = "".as_bytes();
+        let mut output = vec![];
+        let mut runtime = Runtime::new(&mut input, &mut output);
+        assert_eq!(
+            run(program, &mut runtime),
+            Err(BfError::Bf(
+                "Unmatched conditional end at position 1.".to_owned()
+            ))
+        );
+        assert_eq!(output, [1]);
+    }
+}
@@ -1,10 +1,52 @@
 use {
-    std::io::{Read, Write},
-    util::{asm::Runtime, BfResult},
+    bounds::BoundsChecks,
+    compiler::CompileOptions,
+    pgo::Hint,
+    std::{
+        fs,
+        io::{Read, Write},
+        ops::Range,
+        sync::LazyLock,
+    },
+    util::{
+        asm::Runtime,
+        gdb,
+        passes::PassSelection,
+        perf::{self, LoopMarker},
+        profile::Profile,
+        run::{report_passes, RunOptions},
+        BfError, BfResult,
+    },
+};
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use {
+    std::{io, os::unix::io::RawFd},
+    util::asm::SyscallIo,
 };
 
+mod bounds;
+mod c;
+pub mod cache;
 mod compiler;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+mod dump;
+mod lazy;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod listing;
 mod parser;
+mod pgo;
+mod rust;
+mod source;
+pub mod wasm;
+
+pub use {
+    cache::{CacheStats, ProgramCache},
+    compiler::{compile_instructions, compile_loop},
+    parser::Instruction,
+};
 
 #[cfg(not(any(
     all(target_os = "linux", target_arch = "x86_64"),
@@ -16,9 +58,442 @@ mod parser;
 compile_error!("Unsupported system.");
 
 pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
-    let program = parser::parse(source_code)?;
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    run_with_tape(source_code, options, stdin, stdout)?;
+    Ok(())
+}
+
+// The cache run_cached uses, shared by the whole process. It holds up to 64 MiB
+// of code.
+static CACHE: LazyLock<ProgramCache> = LazyLock::new(|| ProgramCache::new(64 << 20));
+
+// Like run_with_options, but reuses the compiled program when the same source
+// code was run before with the same passes. See ProgramCache::run.
+pub fn run_cached(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    CACHE.run(source_code, options, stdin, stdout)
+}
+
+pub fn cache_stats() -> CacheStats {
+    CACHE.stats()
+}
+
+// Also returns the contents of the tape once the program finishes.
+pub fn run_with_tape(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<Vec<u8>> {
+    let profile = load_profile(options)?;
+    run_profiled(source_code, options, profile.as_ref(), stdin, stdout)
+}
+
+// Like run_with_options, but optimized with the given profile, rather than one
+// loaded from options.profile. See opinterp3::run_with_profile for recording
+// one.
+pub fn run_with_profile(
+    source_code: &str,
+    options: &RunOptions,
+    profile: &Profile,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    run_profiled(source_code, options, Some(profile), stdin, stdout)?;
+    Ok(())
+}
+
+fn run_profiled(
+    source_code: &str,
+    options: &RunOptions,
+    profile: Option<&Profile>,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<Vec<u8>> {
+    let (program, spans, reports) = parser::parse_with_spans(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+    let hints = profile_hints(source_code, options, profile, &program, &spans)?;
+    if options.bounds_checks && options.lazy {
+        return Err(BfError::Bf(
+            "Bounds checks can't be combined with lazy compilation.".to_owned(),
+        ));
+    }
+
+    // The compiled code would read and write file descriptors itself, rather
+    // than the streams given here.
+    if options.syscall_io {
+        return Err(BfError::Bf(
+            "Syscall I/O reads and writes file descriptors, so it needs run_with_fds.".to_owned(),
+        ));
+    }
+
+    let mut runtime = Runtime::new(stdin, stdout);
+    if options.lazy {
+        if options.perf.enabled() || options.gdb.is_some() {
+            return Err(BfError::Bf(
+                "Lazily compiled code can't be registered with perf or GDB.".to_owned(),
+            ));
+        }
+
+        let (compiled_loops, loops) = lazy::run(program, &mut runtime)?;
+        if options.report_passes {
+            eprintln!("lazy: compiled {compiled_loops} of {loops} top-level loops");
+        }
+        return Ok(runtime.memory().to_vec());
+    }
+
+    let loops = loop_markers(&program, &spans);
+
+    let mut checks = bounds_checks(&program, &mut runtime, options);
+    let compile_options = CompileOptions {
+        checks: checks.as_mut(),
+        hints,
+    };
+    let (compiled_program, offsets) = compiler::compile(program, &mut runtime, compile_options)?;
+    let mut registration = None;
+    if options.perf.enabled() || options.gdb.is_some() {
+        let markers = loops
+            .into_iter()
+            .map(|(i, marker)| (offsets[i], marker))
+            .collect::<Vec<_>>();
+        let symbols = perf::loop_symbols(source_code, &markers, compiled_program.code().len());
+        perf::register(&options.perf, &compiled_program, &symbols)?;
+
+        if let Some(source_path) = &options.gdb {
+            let lines = offsets
+                .iter()
+                .copied()
+                .zip(spans.iter().map(|span| span.start))
+                .collect::<Vec<_>>();
+            registration = Some(gdb::register(
+                source_path,
+                source_code,
+                &compiled_program,
+                &symbols,
+                &lines,
+                *offsets.last().unwrap(),
+            )?);
+        }
+    }
+    runtime.run(compiled_program)?;
+    drop(registration);
+    check_bounds(checks.as_ref(), source_code, &spans)?;
+
+    Ok(runtime.memory().to_vec())
+}
+
+// Like run_with_options, but the compiled code reads from and writes to the
+// given file descriptors with syscalls of its own, buffering its output. See
+// SyscallIo.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub fn run_with_fds(
+    source_code: &str,
+    options: &RunOptions,
+    input_fd: RawFd,
+    output_fd: RawFd,
+) -> BfResult<()> {
+    let (program, spans, reports) = parser::parse_with_spans(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+    if options.lazy || options.perf.enabled() || options.gdb.is_some() {
+        return Err(BfError::Bf(
+            "Syscall I/O can't be combined with lazy compilation, perf or GDB.".to_owned(),
+        ));
+    }
+    let profile = load_profile(options)?;
+    let hints = profile_hints(source_code, options, profile.as_ref(), &program, &spans)?;
+
+    let (mut stdin, mut stdout) = (io::empty(), io::sink());
+    let mut runtime = Runtime::new(&mut stdin, &mut stdout);
+    let mut checks = bounds_checks(&program, &mut runtime, options);
+    let compile_options = CompileOptions {
+        checks: checks.as_mut(),
+        hints,
+    };
+    run_with_syscalls(program, &mut runtime, input_fd, output_fd, compile_options)?;
+    check_bounds(checks.as_ref(), source_code, &spans)
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn run_with_syscalls(
+    program: parser::Program,
+    runtime: &mut Runtime,
+    input_fd: RawFd,
+    output_fd: RawFd,
+    compile_options: CompileOptions,
+) -> BfResult<()> {
+    let io = SyscallIo::new(input_fd, output_fd);
+    let compiled_program = compiler::compile_with_syscalls(program, runtime, &io, compile_options)?;
+    runtime.run(compiled_program)?;
+
+    io.result()
+}
+
+fn load_profile(options: &RunOptions) -> BfResult<Option<Profile>> {
+    options
+        .profile
+        .as_ref()
+        .map(|path| Profile::decode(&fs::read_to_string(path)?))
+        .transpose()
+}
+
+// How to compile each instruction, going by the profile if there is one. Loops
+// compiled lazily are compiled on their own, without the hints.
+fn profile_hints(
+    source_code: &str,
+    options: &RunOptions,
+    profile: Option<&Profile>,
+    program: &parser::Program,
+    spans: &[Range<usize>],
+) -> BfResult<Vec<Hint>> {
+    let Some(profile) = profile else {
+        return Ok(vec![]);
+    };
+    if options.lazy {
+        return Err(BfError::Bf(
+            "A profile can't be combined with lazy compilation.".to_owned(),
+        ));
+    }
+
+    profile.check_source(source_code)?;
+    let hints = pgo::hints(program, spans, profile);
+    if options.report_passes {
+        eprintln!("{}", pgo::summary(&hints));
+    }
+    Ok(hints)
+}
+
+// The checks to compile the program with, if the options ask for any.
+fn bounds_checks(
+    program: &parser::Program,
+    runtime: &mut Runtime,
+    options: &RunOptions,
+) -> Option<BoundsChecks> {
+    if !options.bounds_checks {
+        return None;
+    }
+
+    let checks = BoundsChecks::new(&program.instructions, runtime.memory_ptr());
+    if options.report_passes {
+        let (checked, checkable) = checks.counts(&program.instructions);
+        eprintln!("bounds: checking {checked} of {checkable} instructions which move the pointer");
+    }
+    Some(checks)
+}
+
+// Once the program has run, fails with where the data pointer left memory, if
+// it did.
+fn check_bounds(
+    checks: Option<&BoundsChecks>,
+    source_code: &str,
+    spans: &[Range<usize>],
+) -> BfResult<()> {
+    let Some(index) = checks.and_then(BoundsChecks::failed_at) else {
+        return Ok(());
+    };
+
+    let span = &spans[index];
+    let snippet = source_code[span.clone()]
+        .chars()
+        .filter(|c| "><+-,.[]".contains(*c))
+        .collect::<String>();
+    Err(BfError::Bf(format!(
+        "Data pointer out of bounds in instruction {index}, '{snippet}' at index {}.",
+        span.start
+    )))
+}
+
+// Where each loop begins and ends, as the index of the instruction whose code
+// starts there. Indexes go up to the number of instructions, for the end of the
+// last one.
+fn loop_markers(program: &parser::Program, spans: &[Range<usize>]) -> Vec<(usize, LoopMarker)> {
+    program
+        .instructions
+        .iter()
+        .zip(spans)
+        .enumerate()
+        .filter_map(|(i, (instruction, span))| match instruction {
+            Instruction::JumpBegin => Some((
+                i,
+                LoopMarker::Begin {
+                    position: span.start,
+                },
+            )),
+            Instruction::JumpEnd => Some((i + 1, LoopMarker::End)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Like run_with_options, but first writes the code compiled for the program to
+// dump, disassembled and annotated with the instructions and source it came
+// from.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+pub fn run_with_dump(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+    dump: &mut dyn Write,
+) -> BfResult<()> {
+    // The whole program is dumped before it runs, which lazily compiled code
+    // and code doing its own I/O aren't compiled for.
+    if options.lazy || options.syscall_io {
+        return Err(BfError::Bf(
+            "Lazy compilation and syscall I/O can't be combined with dumping the compiled code."
+                .to_owned(),
+        ));
+    }
+
+    let (program, spans, reports) = parser::parse_with_spans(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+    let profile = load_profile(options)?;
+    let hints = profile_hints(source_code, options, profile.as_ref(), &program, &spans)?;
+
+    let descriptions = program
+        .instructions
+        .iter()
+        .map(|instruction| format!("{instruction:?}"))
+        .collect::<Vec<_>>();
+
     let mut runtime = Runtime::new(stdin, stdout);
 
-    let compiled_program = compiler::compile(program, &mut runtime)?;
-    runtime.run(compiled_program)
+    let mut checks = bounds_checks(&program, &mut runtime, options);
+    let compile_options = CompileOptions {
+        checks: checks.as_mut(),
+        hints,
+    };
+    let (compiled_program, offsets) = compiler::compile(program, &mut runtime, compile_options)?;
+    dump.write_all(
+        dump::write_dump(
+            &descriptions,
+            &spans,
+            source_code,
+            compiled_program.code(),
+            compiled_program.address(),
+            &offsets,
+        )
+        .as_bytes(),
+    )?;
+    dump.flush()?;
+    runtime.run(compiled_program)?;
+
+    check_bounds(checks.as_ref(), source_code, &spans)
+}
+
+// There's no disassembler for aarch64 code here.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+pub fn run_with_dump(
+    _source_code: &str,
+    _options: &RunOptions,
+    _stdin: &mut dyn Read,
+    _stdout: &mut dyn Write,
+    _dump: &mut dyn Write,
+) -> BfResult<()> {
+    Err(BfError::Bf(
+        "Dumping the compiled code is only supported on x86.".to_owned(),
+    ))
+}
+
+// Returns the code compiled for a program as GNU assembler source, which can
+// be built into an executable along with runtime.c.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn assembly_listing(source_code: &str, options: &RunOptions) -> BfResult<String> {
+    let (program, spans, reports) = parser::parse_with_spans(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+
+    listing::write_listing(program, &spans, source_code)
+}
+
+// The listing is disassembled from the code compiled for this machine, which
+// is only the x86-64 System V code it's written for here.
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+pub fn assembly_listing(_source_code: &str, _options: &RunOptions) -> BfResult<String> {
+    Err(BfError::Bf(
+        "Assembly listings can only be written on x86-64 Linux.".to_owned(),
+    ))
+}
+
+// The runtime assembly listings are built with, which provides main, the tape,
+// and bf_read and bf_write.
+pub const LISTING_RUNTIME: &str = include_str!("../runtime.c");
+
+// Returns the program as a self-contained C program, with the tape shaped by
+// the options rather than the JIT's fixed one.
+pub fn c_source(source_code: &str, options: &RunOptions) -> BfResult<String> {
+    let (program, reports) = parser::parse_with_passes(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+
+    c::write_c(program, &options.tape)
+}
+
+// Returns the program as Rust source for a function which runs it, with the
+// tape shaped by the options.
+pub fn rust_source(source_code: &str, options: &RunOptions) -> BfResult<String> {
+    let (program, reports) = parser::parse_with_passes(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+
+    rust::write_rust(program, &options.tape)
+}
+
+// Returns the program as a WebAssembly module, with the tape shaped by the
+// options. See the wasm module for what it imports and exports.
+pub fn wasm_module(source_code: &str, options: &RunOptions) -> BfResult<Vec<u8>> {
+    let (program, reports) = parser::parse_with_passes(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+
+    wasm::write_wasm(program, &options.tape)
+}
+
+// Compiles a program into code for an executable which runs without a JIT.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn compile_standalone(
+    source_code: &str,
+    options: &RunOptions,
+) -> BfResult<util::asm::StandaloneProgram> {
+    let (program, reports) = parser::parse_with_passes(source_code, &options.passes)?;
+    if options.report_passes {
+        report_passes(&reports);
+    }
+
+    compiler::compile_standalone(program)
+}
+
+// The names of the passes the selection resolves to, in the order they run.
+pub fn pass_names(selection: &PassSelection) -> BfResult<Vec<&'static str>> {
+    Ok(parser::Pass::pipeline(selection)?
+        .into_iter()
+        .map(parser::Pass::name)
+        .collect())
 }
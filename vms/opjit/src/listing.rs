@@ -0,0 +1,210 @@
+use {
+    crate::{compiler::compile_instructions, parser::Program},
+    dynasmrt::{dynasm, DynasmApi, DynasmLabelApi},
+    iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, IntelFormatter},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fmt::Write,
+        ops::Range,
+    },
+    util::{
+        asm::{loop_epilogue, loop_prologue, Assembler, Io, VectorWidth},
+        dasm, BfResult,
+    },
+};
+
+// Writes the code compiler.rs generates for a program as GNU assembler source
+// for x86-64 System V, in Intel syntax. The program is compiled as usual, with
+// the tape passed in as the only argument of bf_main and reads and writes
+// calling bf_read and bf_write, which runtime.c provides. The code is then
+// disassembled, so the listing is whatever the JIT would run. Scans only use
+// SSE2, so the listing builds for any x86-64 CPU. Labels are named after where
+// they are in the code, and each instruction's code is preceded by the BF it
+// was compiled from.
+pub fn write_listing(
+    program: Program,
+    spans: &[Range<usize>],
+    source_code: &str,
+) -> BfResult<String> {
+    let mut assembler = Assembler::new()?;
+    let read = assembler.new_dynamic_label();
+    let write = assembler.new_dynamic_label();
+
+    loop_prologue(&mut assembler);
+    let offsets = compile_instructions(
+        &mut assembler,
+        program.instructions,
+        &Io::Extern { read, write },
+        VectorWidth::Baseline,
+    )?;
+    let epilogue = assembler.offset().0;
+    loop_epilogue(&mut assembler);
+    let end = assembler.offset().0;
+    // Stand-ins for the functions, which aren't part of the listing.
+    let read_offset = assembler.offset().0;
+    dasm!(assembler
+        ; =>read
+        ; int3
+    );
+    let write_offset = assembler.offset().0;
+    dasm!(assembler
+        ; =>write
+        ; int3
+    );
+    let code = assembler.finalize()?;
+
+    let instructions = Decoder::new(64, &code[..end], DecoderOptions::NONE)
+        .into_iter()
+        .collect::<Vec<_>>();
+    let mut targets = BTreeMap::from([
+        (read_offset, "bf_read@PLT".to_owned()),
+        (write_offset, "bf_write@PLT".to_owned()),
+    ]);
+    let labels = instructions
+        .iter()
+        .filter_map(branch_target)
+        .filter(|target| *target < end)
+        .collect::<BTreeSet<_>>();
+    targets.extend(labels.iter().map(|target| (*target, format!(".L{target}"))));
+
+    // The BF each instruction was compiled from, by where its code starts.
+    // Instructions with no code of their own share the next one's offset.
+    let mut comments = BTreeMap::<usize, Vec<String>>::new();
+    for (offset, span) in offsets.iter().zip(spans) {
+        let snippet = source_code[span.clone()]
+            .chars()
+            .filter(|c| "><+-,.[]".contains(*c))
+            .collect::<String>();
+        comments.entry(offset.0).or_default().push(snippet);
+    }
+    comments.entry(epilogue).or_default().push("end".to_owned());
+
+    let mut formatter = IntelFormatter::new();
+    let options = formatter.options_mut();
+    options.set_hex_prefix("0x");
+    options.set_hex_suffix("");
+    options.set_uppercase_hex(false);
+    options.set_signed_immediate_operands(true);
+    options.set_space_after_operand_separator(true);
+
+    let mut listing = Listing::default();
+    listing.instruction(".intel_syntax noprefix");
+    listing.instruction(".text");
+    listing.instruction(".globl bf_main");
+    listing.instruction(".type bf_main, @function");
+    listing.label("bf_main");
+
+    let mut text = String::new();
+    for instruction in &instructions {
+        let offset = instruction.ip() as usize;
+        if labels.contains(&offset) {
+            listing.label(&targets[&offset]);
+        }
+        for comment in comments.remove(&offset).unwrap_or_default() {
+            listing.comment(&comment);
+        }
+
+        text.clear();
+        match branch_target(instruction) {
+            Some(target) => {
+                formatter.format_mnemonic(instruction, &mut text);
+                write!(text, " {}", targets[&target]).unwrap();
+            }
+            None => formatter.format(instruction, &mut text),
+        }
+        listing.instruction(&text);
+    }
+
+    listing.instruction(".size bf_main, .-bf_main");
+    listing.instruction(".section .note.GNU-stack,\"\",@progbits");
+
+    Ok(listing.text)
+}
+
+// Where a jump or call goes, as an offset into the code.
+fn branch_target(instruction: &Instruction) -> Option<usize> {
+    (instruction.is_jcc_short_or_near()
+        || instruction.is_jmp_short_or_near()
+        || instruction.is_call_near())
+    .then(|| instruction.near_branch_target() as usize)
+}
+
+#[derive(Default)]
+struct Listing {
+    text: String,
+}
+
+impl Listing {
+    fn instruction(&mut self, instruction: &str) {
+        writeln!(self.text, "    {instruction}").unwrap();
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.text, "{label}:").unwrap();
+    }
+
+    fn comment(&mut self, comment: &str) {
+        writeln!(self.text, "    # {comment}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::write_listing, crate::parser::parse_with_spans, util::passes::PassSelection};
+
+    #[test]
+    fn write_listing_test() {
+        let source_code = ",[->+<]> [-[-]]+++.";
+        let (program, spans, _reports) =
+            parse_with_spans(source_code, &PassSelection::default()).unwrap();
+
+        assert_eq!(
+            write_listing(program, &spans, source_code).unwrap(),
+            concat!(
+                "    .intel_syntax noprefix\n",
+                "    .text\n",
+                "    .globl bf_main\n",
+                "    .type bf_main, @function\n",
+                "bf_main:\n",
+                "    push r13\n",
+                "    mov r13, rdi\n",
+                "    # ,\n",
+                "    call bf_read@PLT\n",
+                "    mov [r13], al\n",
+                "    # [->+<]\n",
+                "    movzx r9d, byte ptr [r13]\n",
+                "    test r9b, r9b\n",
+                "    je .L38\n",
+                "    add [r13+1], r9b\n",
+                "    xor r9d, r9d\n",
+                ".L38:\n",
+                "    # >\n",
+                "    mov [r13], r9b\n",
+                "    add r13, 1\n",
+                "    # [\n",
+                "    movzx r9d, byte ptr [r13]\n",
+                "    test r9b, r9b\n",
+                "    je .L74\n",
+                "    # -\n",
+                "    sub r9b, 1\n",
+                "    # [-]\n",
+                "    xor r9d, r9d\n",
+                "    # ]\n",
+                "    mov [r13], r9b\n",
+                ".L74:\n",
+                "    # +++\n",
+                "    add r9b, 3\n",
+                "    # .\n",
+                "    mov [r13], r9b\n",
+                "    movzx rdi, byte ptr [r13]\n",
+                "    call bf_write@PLT\n",
+                "    # end\n",
+                "    mov rax, r13\n",
+                "    pop r13\n",
+                "    ret\n",
+                "    .size bf_main, .-bf_main\n",
+                "    .section .note.GNU-stack,\"\",@progbits\n",
+            )
+        );
+    }
+}
@@ -1,5 +1,82 @@
-use util::{run::run_main, BfResult};
+use {
+    std::{
+        env, fs,
+        io::{self, Write},
+    },
+    util::{
+        run::{check_default_tape, parse_args, RunOptions, SupportedOptions},
+        BfError, BfResult,
+    },
+};
 
 fn main() -> BfResult<()> {
-    run_main(opjit::run)
+    let mut emit_asm = None;
+    // None for no dump, and Some(None) to dump to stderr.
+    let mut dump_jit = None;
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        if let Some(path) = arg.strip_prefix("--emit-asm=") {
+            emit_asm = Some(path.to_owned());
+        } else if arg == "--dump-jit" {
+            dump_jit = Some(None);
+        } else if let Some(path) = arg.strip_prefix("--dump-jit=") {
+            dump_jit = Some(Some(path.to_owned()));
+        } else {
+            args.push(arg);
+        }
+    }
+
+    // Superinstructions are only for the interpreters.
+    let supported = SupportedOptions {
+        superinstructions: false,
+        ..SupportedOptions::ALL
+    };
+    let (filepath, options) = parse_args(args.into_iter(), &supported)?;
+    check_default_tape(&options)?;
+    let source_code = fs::read_to_string(filepath)?;
+    match (emit_asm, dump_jit) {
+        (Some(_), Some(_)) => Err(BfError::Bf(
+            "--emit-asm and --dump-jit can't be combined.".to_owned(),
+        )),
+        (Some(path), None) => {
+            fs::write(path, opjit::assembly_listing(&source_code, &options)?)?;
+            Ok(())
+        }
+        (None, Some(path)) => {
+            let mut dump: Box<dyn Write> = match path {
+                Some(path) => Box::new(fs::File::create(path)?),
+                None => Box::new(io::stderr()),
+            };
+            opjit::run_with_dump(
+                &source_code,
+                &options,
+                &mut io::stdin(),
+                &mut io::stdout(),
+                &mut dump,
+            )
+        }
+        (None, None) if options.syscall_io => run_with_syscall_io(&source_code, &options),
+        (None, None) => {
+            opjit::run_with_options(&source_code, &options, &mut io::stdin(), &mut io::stdout())
+        }
+    }
+}
+
+// The compiled code reads stdin and writes stdout itself.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn run_with_syscall_io(source_code: &str, options: &RunOptions) -> BfResult<()> {
+    opjit::run_with_fds(source_code, options, 0, 1)
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn run_with_syscall_io(_source_code: &str, _options: &RunOptions) -> BfResult<()> {
+    Err(BfError::Bf(
+        "Syscall I/O is only supported on x86-64 and aarch64 Linux.".to_owned(),
+    ))
 }
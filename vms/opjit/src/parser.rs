@@ -1,9 +1,12 @@
 use {
-    std::{iter::Peekable, slice, str::CharIndices},
-    util::{BfError, BfResult},
+    std::{iter::Peekable, ops::Range, slice, str::CharIndices},
+    util::{
+        passes::{PassReport, PassSelection},
+        BfError, BfResult,
+    },
 };
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 enum AstNode {
     IncPtr,
     DecPtr,
@@ -12,14 +15,17 @@ enum AstNode {
     Read,
     Write,
     Loop { seq: AstSeq },
+    Conditional { seq: AstSeq },
     SetDataToZero,
     MovePtrUntilZero { forward: bool, amount: u32 },
     MoveData { forward: bool, amount: u32 },
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 struct AstSeq {
     children: Vec<AstNode>,
+    // The range of the source code each child was parsed from.
+    spans: Vec<Range<usize>>,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -44,6 +50,8 @@ pub enum Instruction {
     },
     JumpBegin,
     JumpEnd,
+    ConditionalBegin,
+    ConditionalEnd,
     SetDataToZero,
     MovePtrUntilZero {
         count: u32,
@@ -62,13 +70,130 @@ pub struct Program {
     pub instructions: Vec<Instruction>,
 }
 
-pub fn parse(source_code: &str) -> BfResult<Program> {
-    let ast = create_ast(source_code)?;
-    let ast = optimize_loops(ast);
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pass {
+    CombineRepeats,
+    SetDataToZero,
+    MovePtrUntilZero,
+    MoveData,
+    ConditionalLoops,
+}
+
+impl Pass {
+    const ALL: [Pass; 5] = [
+        Pass::CombineRepeats,
+        Pass::SetDataToZero,
+        Pass::MovePtrUntilZero,
+        Pass::MoveData,
+        Pass::ConditionalLoops,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::CombineRepeats => "combine-repeats",
+            Pass::SetDataToZero => "set-data-to-zero",
+            Pass::MovePtrUntilZero => "move-ptr-until-zero",
+            Pass::MoveData => "move-data",
+            Pass::ConditionalLoops => "conditional-loops",
+        }
+    }
+
+    fn from_name(name: &str) -> BfResult<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|pass| pass.name() == name)
+            .ok_or_else(|| BfError::Bf(format!("Unknown optimization pass '{name}'.")))
+    }
+
+    fn for_level(level: u8) -> BfResult<Vec<Self>> {
+        match level {
+            0 => Ok(vec![]),
+            1 => Ok(vec![Pass::CombineRepeats]),
+            2 => Ok(vec![
+                Pass::CombineRepeats,
+                Pass::SetDataToZero,
+                Pass::MovePtrUntilZero,
+            ]),
+            3 => Ok(Self::ALL.to_vec()),
+            _ => Err(BfError::Bf(format!("Unknown optimization level {level}."))),
+        }
+    }
+
+    pub fn pipeline(selection: &PassSelection) -> BfResult<Vec<Self>> {
+        match selection {
+            PassSelection::Level(level) => Self::for_level(*level),
+            PassSelection::Passes(names) => {
+                names.iter().map(|name| Self::from_name(name)).collect()
+            }
+        }
+    }
+
+    fn run(self, seq: AstSeq) -> AstSeq {
+        match self {
+            // Repeated instructions are combined while lowering the AST rather
+            // than in the AST itself.
+            Pass::CombineRepeats => seq,
+            _ => optimize_loops(seq, self),
+        }
+    }
+}
+
+pub fn parse_with_passes(
+    source_code: &str,
+    selection: &PassSelection,
+) -> BfResult<(Program, Vec<PassReport>)> {
+    let (program, _spans, reports) = parse_with_spans(source_code, selection)?;
+    Ok((program, reports))
+}
+
+// Also returns the range of the source code each instruction was compiled
+// from. Combined repeats and optimized loops cover all of the code they
+// replaced, and the beginning and end of a loop cover its brackets.
+pub fn parse_with_spans(
+    source_code: &str,
+    selection: &PassSelection,
+) -> BfResult<(Program, Vec<Range<usize>>, Vec<PassReport>)> {
+    let passes = Pass::pipeline(selection)?;
+    let mut ast = create_ast(source_code)?;
+
+    let mut combine_repeats = false;
+    let mut instruction_count = count_instructions(&ast, combine_repeats);
+    let mut reports = vec![];
+    for pass in passes {
+        ast = pass.run(ast);
+        combine_repeats |= pass == Pass::CombineRepeats;
 
-    let instructions = compile(ast);
+        let new_instruction_count = count_instructions(&ast, combine_repeats);
+        reports.push(PassReport {
+            name: pass.name(),
+            instructions_before: instruction_count,
+            instructions_after: new_instruction_count,
+        });
+        instruction_count = new_instruction_count;
+    }
+
+    let (instructions, spans) = compile(ast, combine_repeats).into_iter().unzip();
 
-    Ok(Program { instructions })
+    Ok((Program { instructions }, spans, reports))
+}
+
+// The index of the instruction matching each bracket and conditional.
+pub fn partners(instructions: &[Instruction]) -> Vec<usize> {
+    let mut partners = vec![0; instructions.len()];
+    let mut open = vec![];
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::JumpBegin | Instruction::ConditionalBegin => open.push(index),
+            Instruction::JumpEnd | Instruction::ConditionalEnd => {
+                let begin = open.pop().expect("Unmatched end in a parsed program.");
+                partners[begin] = index;
+                partners[index] = begin;
+            }
+            _ => {}
+        }
+    }
+
+    partners
 }
 
 fn create_ast(source_code: &str) -> BfResult<AstSeq> {
@@ -85,6 +210,7 @@ fn create_ast(source_code: &str) -> BfResult<AstSeq> {
 
     fn parse_seq(chars: &mut Peekable<CharIndices>, is_in_loop: bool) -> BfResult<AstSeq> {
         let mut children = vec![];
+        let mut spans = vec![];
 
         while let Some((i, c)) = peek_meaningful_char(chars) {
             // Special handling to break without consuming the character.
@@ -94,30 +220,33 @@ fn create_ast(source_code: &str) -> BfResult<AstSeq> {
 
             chars.next();
 
-            match c {
-                '>' => children.push(AstNode::IncPtr),
-                '<' => children.push(AstNode::DecPtr),
-                '+' => children.push(AstNode::IncData),
-                '-' => children.push(AstNode::DecData),
-                ',' => children.push(AstNode::Read),
-                '.' => children.push(AstNode::Write),
+            // The last index of the source code the node covers.
+            let (node, end) = match c {
+                '>' => (AstNode::IncPtr, i),
+                '<' => (AstNode::DecPtr, i),
+                '+' => (AstNode::IncData, i),
+                '-' => (AstNode::DecData, i),
+                ',' => (AstNode::Read, i),
+                '.' => (AstNode::Write, i),
                 '[' => {
                     let seq = parse_seq(chars, true /*is_in_loop*/)?;
-                    if let Some((_, ']')) = chars.peek() {
-                        // Consume the "]".
-                        chars.next();
-                    } else {
+                    let Some(&(end, ']')) = chars.peek() else {
                         return Err(BfError::Bf(format!("Unmatched '[' at index {i}.")));
-                    }
+                    };
+                    // Consume the "]".
+                    chars.next();
 
-                    children.push(AstNode::Loop { seq });
+                    (AstNode::Loop { seq }, end)
                 }
                 ']' => return Err(BfError::Bf(format!("Unmatched ']' at index {i}."))),
                 _ => unreachable!(),
-            }
+            };
+
+            children.push(node);
+            spans.push(i..end + 1);
         }
 
-        Ok(AstSeq { children })
+        Ok(AstSeq { children, spans })
     }
 
     parse_seq(
@@ -126,7 +255,7 @@ fn create_ast(source_code: &str) -> BfResult<AstSeq> {
     )
 }
 
-fn optimize_loops(seq: AstSeq) -> AstSeq {
+fn optimize_loops(seq: AstSeq, pass: Pass) -> AstSeq {
     fn check_next_node<'a>(
         iter: &'a mut Peekable<slice::Iter<AstNode>>,
         expected_node: &AstNode,
@@ -201,43 +330,118 @@ fn optimize_loops(seq: AstSeq) -> AstSeq {
         }
     }
 
-    fn optimize_node(node: AstNode) -> AstNode {
-        if let AstNode::Loop { seq } = node {
-            if let Some((forward, amount)) = check_move_ptr_until_zero_pattern(&seq.children) {
-                AstNode::MovePtrUntilZero { forward, amount }
-            } else if check_set_data_to_zero_pattern(&seq.children) {
-                AstNode::SetDataToZero
-            } else if let Some((forward, amount)) = check_move_data_pattern(&seq.children) {
-                AstNode::MoveData { forward, amount }
-            } else {
-                AstNode::Loop {
-                    seq: optimize_seq(seq),
-                }
+    // Every loop-like node only finishes once the current cell is zero, so a
+    // loop whose body ends with one can never jump back to its start.
+    fn check_conditional_pattern(nodes: &[AstNode]) -> bool {
+        matches!(
+            nodes.last(),
+            Some(
+                AstNode::Loop { .. }
+                    | AstNode::Conditional { .. }
+                    | AstNode::SetDataToZero
+                    | AstNode::MovePtrUntilZero { .. }
+                    | AstNode::MoveData { .. }
+            )
+        )
+    }
+
+    fn optimize_loop(seq: &AstSeq, pass: Pass) -> Option<AstNode> {
+        match pass {
+            Pass::CombineRepeats | Pass::ConditionalLoops => None,
+            Pass::SetDataToZero => {
+                check_set_data_to_zero_pattern(&seq.children).then_some(AstNode::SetDataToZero)
             }
-        } else {
-            node
+            Pass::MovePtrUntilZero => check_move_ptr_until_zero_pattern(&seq.children)
+                .map(|(forward, amount)| AstNode::MovePtrUntilZero { forward, amount }),
+            Pass::MoveData => check_move_data_pattern(&seq.children)
+                .map(|(forward, amount)| AstNode::MoveData { forward, amount }),
         }
     }
 
-    fn optimize_seq(seq: AstSeq) -> AstSeq {
+    fn optimize_node(node: AstNode, pass: Pass) -> AstNode {
+        match node {
+            AstNode::Loop { seq } => optimize_loop(&seq, pass).unwrap_or_else(|| {
+                let seq = optimize_seq(seq, pass);
+                if pass == Pass::ConditionalLoops && check_conditional_pattern(&seq.children) {
+                    AstNode::Conditional { seq }
+                } else {
+                    AstNode::Loop { seq }
+                }
+            }),
+            AstNode::Conditional { seq } => AstNode::Conditional {
+                seq: optimize_seq(seq, pass),
+            },
+            _ => node,
+        }
+    }
+
+    fn optimize_seq(seq: AstSeq, pass: Pass) -> AstSeq {
         AstSeq {
-            children: seq.children.into_iter().map(optimize_node).collect(),
+            children: seq
+                .children
+                .into_iter()
+                .map(|node| optimize_node(node, pass))
+                .collect(),
+            spans: seq.spans,
         }
     }
 
-    optimize_seq(seq)
+    optimize_seq(seq, pass)
 }
 
-fn compile(seq: AstSeq) -> Vec<Instruction> {
-    fn compile_loop(seq: AstSeq, position: usize) -> Vec<Instruction> {
-        let children = compile_seq(seq, position + 1);
+// Each instruction comes with the range of the source code it was compiled
+// from.
+// The number of instructions compile would lower the AST to, counted without
+// lowering it, for reporting what each pass did.
+fn count_instructions(seq: &AstSeq, combine_repeats: bool) -> usize {
+    let mut count = 0;
+    let mut previous = None;
+    for node in &seq.children {
+        match node {
+            AstNode::Loop { seq } | AstNode::Conditional { seq } => {
+                count += 2 + count_instructions(seq, combine_repeats);
+            }
+            _ if combine_repeats && previous == Some(node) => (),
+            _ => count += 1,
+        }
+        previous = Some(node);
+    }
+
+    count
+}
+
+fn compile(seq: AstSeq, combine_repeats: bool) -> Vec<(Instruction, Range<usize>)> {
+    fn compile_loop(
+        seq: AstSeq,
+        span: Range<usize>,
+        position: usize,
+        combine_repeats: bool,
+    ) -> Vec<(Instruction, Range<usize>)> {
+        let children = compile_seq(seq, position + 1, combine_repeats);
 
         let jump_begin = Instruction::JumpBegin;
         let jump_end = Instruction::JumpEnd;
 
-        let mut instructions = vec![jump_begin];
+        let mut instructions = vec![(jump_begin, span.start..span.start + 1)];
         instructions.extend(children);
-        instructions.push(jump_end);
+        instructions.push((jump_end, span.end - 1..span.end));
+
+        instructions
+    }
+
+    // Conditionals run their body at most once, so they only need a forward
+    // branch over it and no back edge.
+    fn compile_conditional(
+        seq: AstSeq,
+        span: Range<usize>,
+        position: usize,
+        combine_repeats: bool,
+    ) -> Vec<(Instruction, Range<usize>)> {
+        let children = compile_seq(seq, position + 1, combine_repeats);
+
+        let mut instructions = vec![(Instruction::ConditionalBegin, span.start..span.start + 1)];
+        instructions.extend(children);
+        instructions.push((Instruction::ConditionalEnd, span.end - 1..span.end));
 
         instructions
     }
@@ -265,24 +469,38 @@ fn compile(seq: AstSeq) -> Vec<Instruction> {
         }
     }
 
-    fn compile_seq(seq: AstSeq, position: usize) -> Vec<Instruction> {
+    fn compile_seq(
+        seq: AstSeq,
+        position: usize,
+        combine_repeats: bool,
+    ) -> Vec<(Instruction, Range<usize>)> {
         let mut instructions = vec![];
 
-        let mut iter = seq.children.into_iter().peekable();
-        while let Some(node) = iter.next() {
+        let mut iter = seq.children.into_iter().zip(seq.spans).peekable();
+        while let Some((node, span)) = iter.next() {
             match node {
-                AstNode::Loop { seq } => {
-                    instructions.extend(compile_loop(seq, position + instructions.len()))
-                }
+                AstNode::Loop { seq } => instructions.extend(compile_loop(
+                    seq,
+                    span,
+                    position + instructions.len(),
+                    combine_repeats,
+                )),
+                AstNode::Conditional { seq } => instructions.extend(compile_conditional(
+                    seq,
+                    span,
+                    position + instructions.len(),
+                    combine_repeats,
+                )),
                 _ => {
                     let mut count = 1;
-                    while iter.peek() == Some(&node) {
+                    let mut end = span.end;
+                    while combine_repeats && iter.peek().map(|(next, _)| next) == Some(&node) {
                         count += 1;
-                        debug_assert_eq!(iter.peek(), Some(&node));
-                        iter.next();
+                        let (_, next_span) = iter.next().unwrap();
+                        end = next_span.end;
                     }
 
-                    instructions.push(compile_node(node, count));
+                    instructions.push((compile_node(node, count), span.start..end));
                 }
             }
         }
@@ -290,16 +508,27 @@ fn compile(seq: AstSeq) -> Vec<Instruction> {
         instructions
     }
 
-    compile_seq(seq, 0 /*position*/)
+    compile_seq(seq, 0 /*position*/, combine_repeats)
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        super::{parse, Instruction, Program},
-        util::BfError,
+        super::{
+            compile, count_instructions, create_ast, parse_with_passes, parse_with_spans,
+            Instruction, Pass, Program,
+        },
+        util::{
+            passes::{PassReport, PassSelection},
+            BfError, BfResult,
+        },
     };
 
+    fn parse(source_code: &str) -> BfResult<Program> {
+        let (program, _reports) = parse_with_passes(source_code, &PassSelection::default())?;
+        Ok(program)
+    }
+
     #[test]
     fn parse_test() {
         let program = parse(">a<+bcde-,_.[]._1[.]234567890か").unwrap();
@@ -624,6 +853,115 @@ mod tests {
         );
     }
 
+    #[test]
+    fn conditional_loops_test() {
+        assert_eq!(
+            parse("[>+<[-]]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin,
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::IncData { count: 1 },
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::SetDataToZero,
+                    Instruction::ConditionalEnd,
+                ],
+            }
+        );
+        assert_eq!(
+            parse("[.[>]]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin,
+                    Instruction::Write { count: 1 },
+                    Instruction::MovePtrUntilZero {
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                    Instruction::ConditionalEnd,
+                ],
+            }
+        );
+        assert_eq!(
+            parse("[[.[-]]]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin,
+                    Instruction::ConditionalBegin,
+                    Instruction::Write { count: 1 },
+                    Instruction::SetDataToZero,
+                    Instruction::ConditionalEnd,
+                    Instruction::ConditionalEnd,
+                ],
+            }
+        );
+
+        // The cell isn't necessarily zero at the end of these bodies.
+        assert_eq!(
+            parse("[-[-].]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::JumpBegin,
+                    Instruction::DecData { count: 1 },
+                    Instruction::SetDataToZero,
+                    Instruction::Write { count: 1 },
+                    Instruction::JumpEnd,
+                ],
+            }
+        );
+        assert_eq!(
+            parse("[>[-]<]").unwrap(),
+            Program {
+                instructions: vec![
+                    Instruction::JumpBegin,
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::SetDataToZero,
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::JumpEnd,
+                ],
+            }
+        );
+
+        // Loops which are still around when the pass runs end conditionals
+        // too.
+        let passes = PassSelection::Passes(vec!["conditional-loops".to_owned()]);
+        let (program, _reports) = parse_with_passes("[>+<[-]]", &passes).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::ConditionalBegin,
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::IncData { count: 1 },
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::JumpBegin,
+                    Instruction::DecData { count: 1 },
+                    Instruction::JumpEnd,
+                    Instruction::ConditionalEnd,
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn spans_test() {
+        let (program, spans, _reports) =
+            parse_with_spans("a>>b[-]\n[<+ ]", &PassSelection::default()).unwrap();
+        assert_eq!(
+            program.instructions,
+            vec![
+                Instruction::IncPtr { count: 2 },
+                Instruction::SetDataToZero,
+                Instruction::JumpBegin,
+                Instruction::DecPtr { count: 1 },
+                Instruction::IncData { count: 1 },
+                Instruction::JumpEnd,
+            ]
+        );
+        assert_eq!(spans, vec![1..3, 4..7, 8..9, 9..10, 10..11, 12..13]);
+    }
+
     #[test]
     fn parse_error_test() {
         let err = parse("..[...").unwrap_err();
@@ -632,4 +970,151 @@ mod tests {
         let err = parse("...]...").unwrap_err();
         assert_eq!(err, BfError::Bf("Unmatched ']' at index 3.".to_owned()));
     }
+
+    #[test]
+    fn opt_level_test() {
+        let source_code = ">>[-][->+<]";
+
+        let (program, reports) = parse_with_passes(source_code, &PassSelection::Level(0)).unwrap();
+        assert_eq!(program.instructions.len(), 11);
+        assert_eq!(reports, vec![]);
+
+        let (program, _reports) = parse_with_passes(source_code, &PassSelection::Level(1)).unwrap();
+        assert_eq!(program.instructions.len(), 10);
+        assert_eq!(program.instructions[0], Instruction::IncPtr { count: 2 });
+
+        let (program, _reports) = parse_with_passes(source_code, &PassSelection::Level(2)).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::IncPtr { count: 2 },
+                    Instruction::SetDataToZero,
+                    Instruction::JumpBegin,
+                    Instruction::DecData { count: 1 },
+                    Instruction::IncPtr { count: 1 },
+                    Instruction::IncData { count: 1 },
+                    Instruction::DecPtr { count: 1 },
+                    Instruction::JumpEnd,
+                ],
+            }
+        );
+
+        let (program, reports) = parse_with_passes(source_code, &PassSelection::Level(3)).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::IncPtr { count: 2 },
+                    Instruction::SetDataToZero,
+                    Instruction::MoveData {
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            reports,
+            vec![
+                PassReport {
+                    name: "combine-repeats",
+                    instructions_before: 11,
+                    instructions_after: 10,
+                },
+                PassReport {
+                    name: "set-data-to-zero",
+                    instructions_before: 10,
+                    instructions_after: 8,
+                },
+                PassReport {
+                    name: "move-ptr-until-zero",
+                    instructions_before: 8,
+                    instructions_after: 8,
+                },
+                PassReport {
+                    name: "move-data",
+                    instructions_before: 8,
+                    instructions_after: 3,
+                },
+                PassReport {
+                    name: "conditional-loops",
+                    instructions_before: 3,
+                    instructions_after: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_passes_test() {
+        let passes = PassSelection::Passes(vec!["move-data".to_owned()]);
+        let (program, reports) = parse_with_passes("[-][->+<]", &passes).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::JumpBegin,
+                    Instruction::DecData { count: 1 },
+                    Instruction::JumpEnd,
+                    Instruction::MoveData {
+                        count: 1,
+                        forward: true,
+                        amount: 1,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            reports,
+            vec![PassReport {
+                name: "move-data",
+                instructions_before: 9,
+                instructions_after: 4,
+            }]
+        );
+
+        let passes = PassSelection::Passes(vec![]);
+        let (program, reports) = parse_with_passes("++", &passes).unwrap();
+        assert_eq!(
+            program,
+            Program {
+                instructions: vec![
+                    Instruction::IncData { count: 1 },
+                    Instruction::IncData { count: 1 },
+                ],
+            }
+        );
+        assert_eq!(reports, vec![]);
+    }
+
+    #[test]
+    fn count_instructions_test() {
+        for source_code in ["++>>[-]<<", "[>+<[-]]+[.[>]]", "[[.[-]]]--[->+<]", ",[.,]"] {
+            let mut ast = create_ast(source_code).unwrap();
+            for pass in Pass::pipeline(&PassSelection::default()).unwrap() {
+                ast = pass.run(ast);
+                for combine_repeats in [false, true] {
+                    assert_eq!(
+                        count_instructions(&ast, combine_repeats),
+                        compile(ast.clone(), combine_repeats).len()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn passes_error_test() {
+        let err = parse_with_passes("+", &PassSelection::Level(4)).unwrap_err();
+        assert_eq!(err, BfError::Bf("Unknown optimization level 4.".to_owned()));
+
+        let passes = PassSelection::Passes(vec!["move-data".to_owned(), "bogus".to_owned()]);
+        let err = parse_with_passes("+", &passes).unwrap_err();
+        assert_eq!(
+            err,
+            BfError::Bf("Unknown optimization pass 'bogus'.".to_owned())
+        );
+    }
 }
@@ -0,0 +1,96 @@
+use {
+    crate::parser::{Instruction, Program},
+    std::ops::Range,
+    util::profile::Profile,
+};
+
+// Scans which go fewer cells than this on average are done one cell at a time,
+// skipping the setup vector loads need.
+const SHORT_SCAN_STEPS: f64 = 16.0;
+
+// What the compiler does differently for an instruction, going by the profile.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Hint {
+    #[default]
+    None,
+    // The scan is usually short, so it's done one cell at a time rather than
+    // with vector loads.
+    ShortScan,
+}
+
+// Works out the hint for each instruction. Instructions are matched with the
+// loops in the profile by where they start in the source, as given by spans.
+pub fn hints(program: &Program, spans: &[Range<usize>], profile: &Profile) -> Vec<Hint> {
+    program
+        .instructions
+        .iter()
+        .zip(spans)
+        .map(
+            |(instruction, span)| match (instruction, profile.get(span.start)) {
+                (Instruction::MovePtrUntilZero { .. }, Some(counts))
+                    if counts.entries > 0
+                        && (counts.iterations as f64 / counts.entries as f64)
+                            < SHORT_SCAN_STEPS =>
+                {
+                    Hint::ShortScan
+                }
+                _ => Hint::None,
+            },
+        )
+        .collect()
+}
+
+// How many scans were found to be short, for reporting.
+pub fn summary(hints: &[Hint]) -> String {
+    let short_scans = hints
+        .iter()
+        .filter(|hint| **hint == Hint::ShortScan)
+        .count();
+    format!("pgo: {short_scans} short scans")
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{hints, summary, Hint},
+        crate::parser::parse_with_spans,
+        util::{
+            passes::PassSelection,
+            profile::{LoopCounts, Profile},
+        },
+    };
+
+    fn hints_for(source_code: &str, loops: &[(usize, u64, u64, u64)]) -> Vec<Hint> {
+        let (program, spans, _) = parse_with_spans(source_code, &PassSelection::default()).unwrap();
+        let mut profile = Profile::new(source_code);
+        for &(position, entries, iterations, zero_trips) in loops {
+            profile.loops.insert(
+                position,
+                LoopCounts {
+                    entries,
+                    iterations,
+                    zero_trips,
+                },
+            );
+        }
+        hints(&program, &spans, &profile)
+    }
+
+    #[test]
+    fn short_scan_test() {
+        assert_eq!(
+            hints_for(">+>+<<[>]", &[(6, 10, 20, 0)])[5],
+            Hint::ShortScan
+        );
+        assert_eq!(hints_for(">+>+<<[>]", &[(6, 10, 200, 0)])[5], Hint::None);
+        assert_eq!(hints_for("[>]", &[]), vec![Hint::None]);
+    }
+
+    #[test]
+    fn summary_test() {
+        assert_eq!(
+            summary(&[Hint::ShortScan, Hint::None, Hint::ShortScan]),
+            "pgo: 2 short scans"
+        );
+    }
+}
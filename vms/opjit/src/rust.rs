@@ -0,0 +1,228 @@
+use {
+    crate::{
+        parser::{Instruction, Program},
+        source::Source,
+    },
+    util::{
+        run::{CellType, EofPolicy, TapeOptions},
+        BfError, BfResult,
+    },
+};
+
+// Writes a program as a Rust function named run, which takes the program's
+// input and output and returns the final tape. Loops and the optimized
+// instructions become plain Rust the same way they become C in c.rs. Paths are
+// fully qualified so the function can be dropped into any module. The tape is
+// indexed rather than pointed into, so moving off either end panics.
+pub fn write_rust(program: Program, tape: &TapeOptions) -> BfResult<String> {
+    let mut source = Source::default();
+    let cell = cell_type_name(tape.cell);
+
+    source.line("pub fn run(");
+    source.indent += 1;
+    source.line("input: &mut dyn std::io::Read,");
+    source.line("output: &mut dyn std::io::Write,");
+    source.indent -= 1;
+    source.line(&format!(") -> std::io::Result<Vec<{cell}>> {{"));
+    source.indent += 1;
+    source.line(&format!(
+        "fn read(input: &mut dyn std::io::Read, cell: &mut {cell}) -> std::io::Result<()> {{"
+    ));
+    source.indent += 1;
+    source.line("let mut byte = [0];");
+    source.line("match input.read_exact(&mut byte) {");
+    source.indent += 1;
+    source.line("Ok(()) => *cell = byte[0].into(),");
+    source.line("Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {");
+    source.indent += 1;
+    match tape.eof {
+        EofPolicy::Error => source.line("return Err(e);"),
+        EofPolicy::Zero => source.line("*cell = 0;"),
+        EofPolicy::Unchanged => {}
+        EofPolicy::MinusOne => source.line(&format!("*cell = {cell}::MAX;")),
+    }
+    source.indent -= 1;
+    source.line("}");
+    source.line("Err(e) => return Err(e),");
+    source.indent -= 1;
+    source.line("}");
+    source.line("Ok(())");
+    source.indent -= 1;
+    source.line("}");
+    source.line("");
+    source.line(&format!(
+        "let mut tape: Vec<{cell}> = vec![0; {}];",
+        tape.size
+    ));
+    source.line("let mut p: usize = 0;");
+    source.line("");
+
+    // Only the low byte of a cell is written.
+    let write = if tape.cell == CellType::U8 {
+        "output.write_all(&[tape[p]])?;"
+    } else {
+        "output.write_all(&[tape[p] as u8])?;"
+    };
+    let modulus = 1u64 << tape.cell.bits();
+    let mut open_blocks = 0usize;
+
+    for (i, instruction) in program.instructions.into_iter().enumerate() {
+        match instruction {
+            Instruction::IncPtr { count } => source.line(&format!("p += {count};")),
+            Instruction::DecPtr { count } => source.line(&format!("p -= {count};")),
+            Instruction::IncData { count } => source.line(&format!(
+                "tape[p] = tape[p].wrapping_add({});",
+                u64::from(count) % modulus
+            )),
+            Instruction::DecData { count } => source.line(&format!(
+                "tape[p] = tape[p].wrapping_sub({});",
+                u64::from(count) % modulus
+            )),
+            Instruction::Read { count } => {
+                for _ in 0..count {
+                    source.line("read(input, &mut tape[p])?;");
+                }
+            }
+            Instruction::Write { count } => {
+                for _ in 0..count {
+                    source.line(write);
+                }
+            }
+            Instruction::JumpBegin => {
+                source.line("while tape[p] != 0 {");
+                source.indent += 1;
+                open_blocks += 1;
+            }
+            Instruction::ConditionalBegin => {
+                source.line("if tape[p] != 0 {");
+                source.indent += 1;
+                open_blocks += 1;
+            }
+            Instruction::JumpEnd | Instruction::ConditionalEnd => {
+                open_blocks = open_blocks
+                    .checked_sub(1)
+                    .ok_or_else(|| BfError::Bf(format!("Unmatched block end at position {i}.")))?;
+                source.indent -= 1;
+                source.line("}");
+            }
+            Instruction::SetDataToZero => source.line("tape[p] = 0;"),
+            // As in c.rs, only the first repeat does anything.
+            Instruction::MovePtrUntilZero {
+                count: _,
+                forward,
+                amount,
+            } => {
+                let step = if forward { "+=" } else { "-=" };
+                source.line("while tape[p] != 0 {");
+                source.indent += 1;
+                source.line(&format!("p {step} {amount};"));
+                source.indent -= 1;
+                source.line("}");
+            }
+            Instruction::MoveData {
+                count: _,
+                forward,
+                amount,
+            } => {
+                // As in c.rs, nothing is touched when the current cell is zero.
+                let sign = if forward { '+' } else { '-' };
+                source.line("if tape[p] != 0 {");
+                source.indent += 1;
+                source.line(&format!(
+                    "tape[p {sign} {amount}] = tape[p {sign} {amount}].wrapping_add(tape[p]);"
+                ));
+                source.line("tape[p] = 0;");
+                source.indent -= 1;
+                source.line("}");
+            }
+        }
+    }
+
+    if open_blocks != 0 {
+        return Err(BfError::Bf("Unmatched block begin.".to_owned()));
+    }
+
+    source.line("");
+    source.line("output.flush()?;");
+    source.line("Ok(tape)");
+    source.indent -= 1;
+    source.line("}");
+
+    Ok(source.text)
+}
+
+fn cell_type_name(cell: CellType) -> &'static str {
+    match cell {
+        CellType::U8 => "u8",
+        CellType::U16 => "u16",
+        CellType::U32 => "u32",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::write_rust,
+        crate::parser::parse_with_passes,
+        util::{
+            passes::PassSelection,
+            run::{CellType, EofPolicy, TapeOptions},
+        },
+    };
+
+    #[test]
+    fn write_rust_test() {
+        let (program, _reports) =
+            parse_with_passes(",[-<+>]>[-[-]]+++[>]-.", &PassSelection::default()).unwrap();
+        let tape = TapeOptions {
+            size: 100,
+            cell: CellType::U32,
+            eof: EofPolicy::Zero,
+        };
+
+        assert_eq!(
+            write_rust(program, &tape).unwrap(),
+            concat!(
+                "pub fn run(\n",
+                "    input: &mut dyn std::io::Read,\n",
+                "    output: &mut dyn std::io::Write,\n",
+                ") -> std::io::Result<Vec<u32>> {\n",
+                "    fn read(input: &mut dyn std::io::Read, cell: &mut u32) -> std::io::Result<()> {\n",
+                "        let mut byte = [0];\n",
+                "        match input.read_exact(&mut byte) {\n",
+                "            Ok(()) => *cell = byte[0].into(),\n",
+                "            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {\n",
+                "                *cell = 0;\n",
+                "            }\n",
+                "            Err(e) => return Err(e),\n",
+                "        }\n",
+                "        Ok(())\n",
+                "    }\n",
+                "\n",
+                "    let mut tape: Vec<u32> = vec![0; 100];\n",
+                "    let mut p: usize = 0;\n",
+                "\n",
+                "    read(input, &mut tape[p])?;\n",
+                "    if tape[p] != 0 {\n",
+                "        tape[p - 1] = tape[p - 1].wrapping_add(tape[p]);\n",
+                "        tape[p] = 0;\n",
+                "    }\n",
+                "    p += 1;\n",
+                "    if tape[p] != 0 {\n",
+                "        tape[p] = tape[p].wrapping_sub(1);\n",
+                "        tape[p] = 0;\n",
+                "    }\n",
+                "    tape[p] = tape[p].wrapping_add(3);\n",
+                "    while tape[p] != 0 {\n",
+                "        p += 1;\n",
+                "    }\n",
+                "    tape[p] = tape[p].wrapping_sub(1);\n",
+                "    output.write_all(&[tape[p] as u8])?;\n",
+                "\n",
+                "    output.flush()?;\n",
+                "    Ok(tape)\n",
+                "}\n",
+            )
+        );
+    }
+}
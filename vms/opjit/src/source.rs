@@ -0,0 +1,19 @@
+use std::fmt::Write;
+
+// Source code for another language, written a line at a time at the current
+// indentation.
+#[derive(Default)]
+pub struct Source {
+    pub text: String,
+    pub indent: usize,
+}
+
+impl Source {
+    pub fn line(&mut self, line: &str) {
+        if line.is_empty() {
+            self.text.push('\n');
+        } else {
+            writeln!(self.text, "{}{line}", "    ".repeat(self.indent)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use {
+    crate::parser::{Instruction, Program},
+    util::{
+        run::{CellType, EofPolicy, TapeOptions},
+        BfError, BfResult,
+    },
+};
+
+// Opcodes and other constants from the WebAssembly binary format, for the
+// subset of it written here.
+pub mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0b;
+    pub const BR: u8 = 0x0c;
+    pub const BR_IF: u8 = 0x0d;
+    pub const CALL: u8 = 0x10;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const LOCAL_TEE: u8 = 0x22;
+    pub const I32_LOAD: u8 = 0x28;
+    pub const I32_LOAD8_U: u8 = 0x2d;
+    pub const I32_LOAD16_U: u8 = 0x2f;
+    pub const I32_STORE: u8 = 0x36;
+    pub const I32_STORE8: u8 = 0x3a;
+    pub const I32_STORE16: u8 = 0x3b;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_NE: u8 = 0x47;
+    pub const I32_ADD: u8 = 0x6a;
+    pub const I32_SUB: u8 = 0x6b;
+
+    pub const BLOCK_TYPE_EMPTY: u8 = 0x40;
+    pub const VALUE_TYPE_I32: u8 = 0x7f;
+    pub const FUNCTION_TYPE: u8 = 0x60;
+}
+
+pub const MAGIC: &[u8] = b"\0asm";
+pub const VERSION: &[u8] = &[1, 0, 0, 0];
+pub const PAGE_SIZE: usize = 65536;
+
+// The functions the module imports from the "bf" module. read returns the next
+// byte of input, or -1 at the end of it, and write is passed the current cell.
+pub const IMPORT_MODULE: &str = "bf";
+pub const READ_IMPORT: &str = "read";
+pub const WRITE_IMPORT: &str = "write";
+// What the module exports: the function running the program, and the memory
+// holding the tape, which starts at address 0.
+pub const RUN_EXPORT: &str = "run";
+pub const MEMORY_EXPORT: &str = "memory";
+
+pub const READ_FUNCTION: u32 = 0;
+pub const WRITE_FUNCTION: u32 = 1;
+pub const RUN_FUNCTION: u32 = 2;
+
+// The run function's locals. The data pointer is kept as a byte address.
+const DATA_POINTER: u32 = 0;
+const READ_RESULT: u32 = 1;
+
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const MEMORY_SECTION: u8 = 5;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+
+const IMPORT_KIND_FUNCTION: u8 = 0x00;
+const EXPORT_KIND_FUNCTION: u8 = 0x00;
+const EXPORT_KIND_MEMORY: u8 = 0x02;
+
+// Writes a program as a WebAssembly module, with the tape in linear memory
+// shaped by the given options. Loops become a block around a loop, so a zero
+// cell skips straight past it, and the optimized instructions are written out
+// as plain loads and stores. Nothing checks that the pointer stays on the tape,
+// but leaving the module's memory traps.
+pub fn write_wasm(program: Program, tape: &TapeOptions) -> BfResult<Vec<u8>> {
+    let cell_size = cell_size(tape.cell);
+    let mut code = Code {
+        bytes: vec![],
+        cell: tape.cell,
+    };
+    let mut open_blocks = 0usize;
+
+    for (i, instruction) in program.instructions.into_iter().enumerate() {
+        match instruction {
+            Instruction::IncPtr { count } => code.move_ptr(true, count, cell_size),
+            Instruction::DecPtr { count } => code.move_ptr(false, count, cell_size),
+            Instruction::IncData { count } => code.change_data(op::I32_ADD, count),
+            Instruction::DecData { count } => code.change_data(op::I32_SUB, count),
+            Instruction::Read { count } => {
+                for _ in 0..count {
+                    code.read(tape.eof);
+                }
+            }
+            Instruction::Write { count } => {
+                for _ in 0..count {
+                    code.local_get(DATA_POINTER);
+                    code.load();
+                    code.call(WRITE_FUNCTION);
+                }
+            }
+            Instruction::JumpBegin => {
+                code.bytes.extend([op::BLOCK, op::BLOCK_TYPE_EMPTY]);
+                code.load_current();
+                code.bytes.push(op::I32_EQZ);
+                code.branch(op::BR_IF, 0);
+                code.bytes.extend([op::LOOP, op::BLOCK_TYPE_EMPTY]);
+                open_blocks += 1;
+            }
+            Instruction::JumpEnd => {
+                open_blocks = close_block(open_blocks, i)?;
+                code.load_current();
+                code.branch(op::BR_IF, 0);
+                code.bytes.extend([op::END, op::END]);
+            }
+            Instruction::ConditionalBegin => {
+                code.load_current();
+                code.bytes.extend([op::IF, op::BLOCK_TYPE_EMPTY]);
+                open_blocks += 1;
+            }
+            Instruction::ConditionalEnd => {
+                open_blocks = close_block(open_blocks, i)?;
+                code.bytes.push(op::END);
+            }
+            Instruction::SetDataToZero => {
+                code.local_get(DATA_POINTER);
+                code.i32_const(0);
+                code.store();
+            }
+            // Only the first repeat does anything, as in c.rs.
+            Instruction::MovePtrUntilZero {
+                count: _,
+                forward,
+                amount,
+            } => {
+                code.bytes.extend([op::BLOCK, op::BLOCK_TYPE_EMPTY]);
+                code.bytes.extend([op::LOOP, op::BLOCK_TYPE_EMPTY]);
+                code.load_current();
+                code.bytes.push(op::I32_EQZ);
+                code.branch(op::BR_IF, 1);
+                code.move_ptr(forward, amount, cell_size);
+                code.branch(op::BR, 0);
+                code.bytes.extend([op::END, op::END]);
+            }
+            Instruction::MoveData {
+                count: _,
+                forward,
+                amount,
+            } => {
+                let add_address = |code: &mut Code| {
+                    code.local_get(DATA_POINTER);
+                    code.i32_const(offset(amount, cell_size));
+                    code.bytes
+                        .push(if forward { op::I32_ADD } else { op::I32_SUB });
+                };
+                // Nothing is touched when the current cell is zero, as in c.rs.
+                code.load_current();
+                code.bytes.extend([op::IF, op::BLOCK_TYPE_EMPTY]);
+                add_address(&mut code);
+                add_address(&mut code);
+                code.load();
+                code.load_current();
+                code.bytes.push(op::I32_ADD);
+                code.store();
+                code.local_get(DATA_POINTER);
+                code.i32_const(0);
+                code.store();
+                code.bytes.push(op::END);
+            }
+        }
+    }
+
+    if open_blocks != 0 {
+        return Err(BfError::Bf("Unmatched block begin.".to_owned()));
+    }
+    code.bytes.push(op::END);
+
+    let mut module = MAGIC.to_vec();
+    module.extend(VERSION);
+
+    // (i32) -> (), () -> i32, and () -> ().
+    let mut types = vec![];
+    write_u32(&mut types, 3);
+    types.extend([op::FUNCTION_TYPE, 1, op::VALUE_TYPE_I32, 0]);
+    types.extend([op::FUNCTION_TYPE, 0, 1, op::VALUE_TYPE_I32]);
+    types.extend([op::FUNCTION_TYPE, 0, 0]);
+    write_section(&mut module, TYPE_SECTION, &types);
+
+    let mut imports = vec![];
+    write_u32(&mut imports, 2);
+    for (name, type_index) in [(READ_IMPORT, 1), (WRITE_IMPORT, 0)] {
+        write_name(&mut imports, IMPORT_MODULE);
+        write_name(&mut imports, name);
+        imports.push(IMPORT_KIND_FUNCTION);
+        write_u32(&mut imports, type_index);
+    }
+    write_section(&mut module, IMPORT_SECTION, &imports);
+
+    let mut functions = vec![];
+    write_u32(&mut functions, 1);
+    write_u32(&mut functions, 2);
+    write_section(&mut module, FUNCTION_SECTION, &functions);
+
+    let pages = (tape.size * cell_size as usize).div_ceil(PAGE_SIZE);
+    let mut memories = vec![];
+    write_u32(&mut memories, 1);
+    // A minimum and no maximum.
+    memories.push(0);
+    write_u32(&mut memories, pages.try_into()?);
+    write_section(&mut module, MEMORY_SECTION, &memories);
+
+    let mut exports = vec![];
+    write_u32(&mut exports, 2);
+    write_name(&mut exports, RUN_EXPORT);
+    exports.push(EXPORT_KIND_FUNCTION);
+    write_u32(&mut exports, RUN_FUNCTION);
+    write_name(&mut exports, MEMORY_EXPORT);
+    exports.push(EXPORT_KIND_MEMORY);
+    write_u32(&mut exports, 0);
+    write_section(&mut module, EXPORT_SECTION, &exports);
+
+    // Both locals are i32s.
+    let mut body = vec![];
+    write_u32(&mut body, 1);
+    write_u32(&mut body, 2);
+    body.push(op::VALUE_TYPE_I32);
+    body.extend(code.bytes);
+    let mut bodies = vec![];
+    write_u32(&mut bodies, 1);
+    write_u32(&mut bodies, body.len().try_into()?);
+    bodies.extend(body);
+    write_section(&mut module, CODE_SECTION, &bodies);
+
+    Ok(module)
+}
+
+pub fn cell_size(cell: CellType) -> u32 {
+    cell.bits() / 8
+}
+
+fn close_block(open_blocks: usize, position: usize) -> BfResult<usize> {
+    open_blocks
+        .checked_sub(1)
+        .ok_or_else(|| BfError::Bf(format!("Unmatched block end at position {position}.")))
+}
+
+// The distance covered by moving the pointer by some number of cells, as the
+// operand of an i32.const. Distances past i32::MAX wrap, which is fine since
+// they're only ever added to or subtracted from the pointer.
+fn offset(amount: u32, cell_size: u32) -> i32 {
+    amount.wrapping_mul(cell_size) as i32
+}
+
+struct Code {
+    bytes: Vec<u8>,
+    cell: CellType,
+}
+
+impl Code {
+    fn local_get(&mut self, local: u32) {
+        self.bytes.push(op::LOCAL_GET);
+        write_u32(&mut self.bytes, local);
+    }
+
+    fn local_set(&mut self, local: u32) {
+        self.bytes.push(op::LOCAL_SET);
+        write_u32(&mut self.bytes, local);
+    }
+
+    fn i32_const(&mut self, value: i32) {
+        self.bytes.push(op::I32_CONST);
+        write_i32(&mut self.bytes, value);
+    }
+
+    fn call(&mut self, function: u32) {
+        self.bytes.push(op::CALL);
+        write_u32(&mut self.bytes, function);
+    }
+
+    fn branch(&mut self, opcode: u8, depth: u32) {
+        self.bytes.push(opcode);
+        write_u32(&mut self.bytes, depth);
+    }
+
+    // Loads and stores are aligned to the cell size, with no offset.
+    fn load(&mut self) {
+        let (opcode, align) = match self.cell {
+            CellType::U8 => (op::I32_LOAD8_U, 0),
+            CellType::U16 => (op::I32_LOAD16_U, 1),
+            CellType::U32 => (op::I32_LOAD, 2),
+        };
+        self.bytes.extend([opcode, align, 0]);
+    }
+
+    fn store(&mut self) {
+        let (opcode, align) = match self.cell {
+            CellType::U8 => (op::I32_STORE8, 0),
+            CellType::U16 => (op::I32_STORE16, 1),
+            CellType::U32 => (op::I32_STORE, 2),
+        };
+        self.bytes.extend([opcode, align, 0]);
+    }
+
+    fn load_current(&mut self) {
+        self.local_get(DATA_POINTER);
+        self.load();
+    }
+
+    fn move_ptr(&mut self, forward: bool, amount: u32, cell_size: u32) {
+        self.local_get(DATA_POINTER);
+        self.i32_const(offset(amount, cell_size));
+        self.bytes
+            .push(if forward { op::I32_ADD } else { op::I32_SUB });
+        self.local_set(DATA_POINTER);
+    }
+
+    // Narrow stores truncate, so the data wraps to the cell size by itself.
+    fn change_data(&mut self, opcode: u8, count: u32) {
+        self.local_get(DATA_POINTER);
+        self.load_current();
+        self.i32_const(count as i32);
+        self.bytes.push(opcode);
+        self.store();
+    }
+
+    fn read(&mut self, eof: EofPolicy) {
+        self.call(READ_FUNCTION);
+        self.bytes.push(op::LOCAL_TEE);
+        write_u32(&mut self.bytes, READ_RESULT);
+        self.i32_const(-1);
+        self.bytes
+            .extend([op::I32_NE, op::IF, op::BLOCK_TYPE_EMPTY]);
+        self.local_get(DATA_POINTER);
+        self.local_get(READ_RESULT);
+        self.store();
+        match eof {
+            EofPolicy::Error => self.bytes.extend([op::ELSE, op::UNREACHABLE]),
+            EofPolicy::Zero => {
+                self.bytes.push(op::ELSE);
+                self.local_get(DATA_POINTER);
+                self.i32_const(0);
+                self.store();
+            }
+            EofPolicy::Unchanged => {}
+            // Stores truncate -1 to the cell size.
+            EofPolicy::MinusOne => {
+                self.bytes.push(op::ELSE);
+                self.local_get(DATA_POINTER);
+                self.i32_const(-1);
+                self.store();
+            }
+        }
+        self.bytes.push(op::END);
+    }
+}
+
+fn write_section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    // Sections are always far smaller than 4 GiB.
+    write_u32(module, contents.len() as u32);
+    module.extend(contents);
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_u32(bytes, name.len() as u32);
+    bytes.extend(name.as_bytes());
+}
+
+// Unsigned LEB128.
+pub fn write_u32(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// Signed LEB128.
+pub fn write_i32(bytes: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{write_i32, write_u32, write_wasm},
+        crate::parser::parse_with_passes,
+        util::{passes::PassSelection, run::TapeOptions},
+    };
+
+    #[test]
+    fn leb128_test() {
+        let mut bytes = vec![];
+        write_u32(&mut bytes, 624485);
+        assert_eq!(bytes, [0xe5, 0x8e, 0x26]);
+
+        let mut bytes = vec![];
+        write_i32(&mut bytes, -123456);
+        assert_eq!(bytes, [0xc0, 0xbb, 0x78]);
+
+        let mut bytes = vec![];
+        write_i32(&mut bytes, 64);
+        assert_eq!(bytes, [0xc0, 0x00]);
+    }
+
+    #[test]
+    fn write_wasm_test() {
+        let (program, _reports) = parse_with_passes("+[-].", &PassSelection::default()).unwrap();
+
+        let expected: [&[u8]; 12] = [
+            // Header.
+            b"\0asm\x01\0\0\0",
+            // Types.
+            &[
+                0x01, 0x0c, 0x03, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x00, 0x00,
+            ],
+            // Imports.
+            &[0x02, 0x16, 0x02],
+            b"\x02bf\x04read\x00\x01",
+            b"\x02bf\x05write\x00\x00",
+            // Functions.
+            &[0x03, 0x02, 0x01, 0x02],
+            // One page of memory.
+            &[0x05, 0x03, 0x01, 0x00, 0x01],
+            // Exports.
+            b"\x07\x10\x02\x03run\x00\x02\x06memory\x02\x00",
+            // Code, with two i32 locals.
+            &[0x0a, 0x21, 0x01, 0x1f, 0x01, 0x02, 0x7f],
+            // +
+            &[
+                0x20, 0x00, 0x20, 0x00, 0x2d, 0x00, 0x00, 0x41, 0x01, 0x6a, 0x3a, 0x00, 0x00,
+            ],
+            // [-]
+            &[0x20, 0x00, 0x41, 0x00, 0x3a, 0x00, 0x00],
+            // . and the end of the function.
+            &[0x20, 0x00, 0x2d, 0x00, 0x00, 0x10, 0x01, 0x0b],
+        ];
+        assert_eq!(
+            write_wasm(program, &TapeOptions::default()).unwrap(),
+            expected.concat()
+        );
+    }
+}
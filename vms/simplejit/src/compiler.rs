@@ -1,24 +1,32 @@
 use {
     crate::parser::{Instruction, Program},
-    dynasmrt::{dynasm, DynasmApi},
+    dynasmrt::{dynasm, AssemblyOffset, DynasmApi},
     util::{
         asm::{
             call_read, call_write, epilogue, jump_begin, jump_end, prologue, Assembler,
-            CompiledProgram, Runtime,
+            CellLocation, CompiledProgram, Io, Runtime,
         },
         dasm, BfResult,
     },
 };
 
-pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProgram> {
+// Also returns where each instruction's code starts, followed by where the
+// epilogue starts.
+pub fn compile(
+    program: Program,
+    runtime: &mut Runtime,
+) -> BfResult<(CompiledProgram, Vec<AssemblyOffset>)> {
     let mut assembler = Assembler::new()?;
     let start = assembler.offset();
 
     prologue(&mut assembler, runtime);
 
+    let io = Io::Runtime(runtime);
     let mut open_bracket_stack = vec![];
+    let mut offsets = Vec::with_capacity(program.instructions.len() + 1);
 
     for (i, instruction) in program.instructions.into_iter().enumerate() {
+        offsets.push(assembler.offset());
         match instruction {
             Instruction::IncPtr => {
                 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
@@ -65,21 +73,31 @@ pub fn compile(program: Program, runtime: &mut Runtime) -> BfResult<CompiledProg
                 );
             }
             Instruction::Read => {
-                call_read(&mut assembler, runtime);
+                call_read(&mut assembler, &io);
             }
             Instruction::Write => {
-                call_write(&mut assembler, runtime);
+                call_write(&mut assembler, &io);
             }
             Instruction::JumpIfZero => {
-                jump_begin(&mut assembler, &mut open_bracket_stack);
+                jump_begin(
+                    &mut assembler,
+                    &mut open_bracket_stack,
+                    CellLocation::Memory,
+                );
             }
             Instruction::JumpIfNotZero => {
-                jump_end(&mut assembler, &mut open_bracket_stack, i)?;
+                jump_end(
+                    &mut assembler,
+                    &mut open_bracket_stack,
+                    CellLocation::Memory,
+                    i,
+                )?;
             }
         }
     }
 
+    offsets.push(assembler.offset());
     epilogue(&mut assembler);
 
-    Ok(CompiledProgram::new(assembler.finalize()?, start))
+    Ok((CompiledProgram::new(assembler.finalize()?, start), offsets))
 }
@@ -1,6 +1,13 @@
 use {
+    parser::Instruction,
     std::io::{Read, Write},
-    util::{asm::Runtime, BfResult},
+    util::{
+        asm::Runtime,
+        gdb,
+        perf::{self, LoopMarker},
+        run::RunOptions,
+        BfResult,
+    },
 };
 
 mod compiler;
@@ -16,9 +23,55 @@ mod parser;
 compile_error!("Unsupported system.");
 
 pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
-    let program = parser::parse(source_code)?;
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+// Only the perf and GDB options make a difference, since there's nothing to
+// optimize.
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    let (program, positions) = parser::parse(source_code)?;
+    let loops = program
+        .instructions
+        .iter()
+        .zip(positions.iter().copied())
+        .enumerate()
+        .filter_map(|(i, (instruction, position))| match instruction {
+            Instruction::JumpIfZero => Some((i, LoopMarker::Begin { position })),
+            Instruction::JumpIfNotZero => Some((i + 1, LoopMarker::End)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
     let mut runtime = Runtime::new(stdin, stdout);
 
-    let compiled_program = compiler::compile(program, &mut runtime)?;
-    runtime.run(compiled_program)
+    let (compiled_program, offsets) = compiler::compile(program, &mut runtime)?;
+    let mut registration = None;
+    if options.perf.enabled() || options.gdb.is_some() {
+        let markers = loops
+            .into_iter()
+            .map(|(i, marker)| (offsets[i], marker))
+            .collect::<Vec<_>>();
+        let symbols = perf::loop_symbols(source_code, &markers, compiled_program.code().len());
+        perf::register(&options.perf, &compiled_program, &symbols)?;
+
+        if let Some(source_path) = &options.gdb {
+            let lines = offsets.iter().copied().zip(positions).collect::<Vec<_>>();
+            registration = Some(gdb::register(
+                source_path,
+                source_code,
+                &compiled_program,
+                &symbols,
+                &lines,
+                *offsets.last().unwrap(),
+            )?);
+        }
+    }
+    runtime.run(compiled_program)?;
+    drop(registration);
+
+    Ok(())
 }
@@ -1,5 +1,15 @@
-use util::{run::run_main, BfResult};
+use util::{
+    run::{run_main_with_options, SupportedOptions},
+    BfResult,
+};
 
 fn main() -> BfResult<()> {
-    run_main(simplejit::run)
+    run_main_with_options(
+        simplejit::run_with_options,
+        &SupportedOptions {
+            perf: true,
+            gdb: true,
+            ..SupportedOptions::NONE
+        },
+    )
 }
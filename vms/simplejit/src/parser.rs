@@ -17,24 +17,28 @@ pub struct Program {
     pub instructions: Vec<Instruction>,
 }
 
-pub fn parse(source_code: &str) -> BfResult<Program> {
+// Also returns where in the source code each instruction is.
+pub fn parse(source_code: &str) -> BfResult<(Program, Vec<usize>)> {
     let mut instructions = vec![];
+    let mut positions = vec![];
 
-    for c in source_code.chars() {
-        match c {
-            '>' => instructions.push(Instruction::IncPtr),
-            '<' => instructions.push(Instruction::DecPtr),
-            '+' => instructions.push(Instruction::IncData),
-            '-' => instructions.push(Instruction::DecData),
-            ',' => instructions.push(Instruction::Read),
-            '.' => instructions.push(Instruction::Write),
-            '[' => instructions.push(Instruction::JumpIfZero),
-            ']' => instructions.push(Instruction::JumpIfNotZero),
-            _ => (),
-        }
+    for (i, c) in source_code.char_indices() {
+        let instruction = match c {
+            '>' => Instruction::IncPtr,
+            '<' => Instruction::DecPtr,
+            '+' => Instruction::IncData,
+            '-' => Instruction::DecData,
+            ',' => Instruction::Read,
+            '.' => Instruction::Write,
+            '[' => Instruction::JumpIfZero,
+            ']' => Instruction::JumpIfNotZero,
+            _ => continue,
+        };
+        instructions.push(instruction);
+        positions.push(i);
     }
 
-    Ok(Program { instructions })
+    Ok((Program { instructions }, positions))
 }
 
 #[cfg(test)]
@@ -43,7 +47,7 @@ mod tests {
 
     #[test]
     fn parse_works() {
-        let program = parse(">a<+bcde-,_.[]_1234567890か").unwrap();
+        let (program, _positions) = parse(">a<+bcde-,_.[]_1234567890か").unwrap();
         assert_eq!(
             program,
             Program {
@@ -60,4 +64,10 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn parse_positions_works() {
+        let (_program, positions) = parse("a+é[\n]").unwrap();
+        assert_eq!(positions, vec![1, 4, 6]);
+    }
 }
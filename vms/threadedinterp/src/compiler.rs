@@ -0,0 +1,191 @@
+use {
+    crate::vm::Machine,
+    opinterp3::{Instruction, Program},
+    util::{
+        math::{unbalanced_wrapping_add, unbalanced_wrapping_sub},
+        scan::move_ptr_until_zero,
+    },
+};
+
+// Runs a single instruction given the data pointer, and returns the position
+// of the next handler to run along with the new data pointer. Passing the data
+// pointer in and out rather than keeping it in the machine lets it stay in a
+// register between handlers.
+pub type Handler = Box<dyn Fn(&mut Machine, usize) -> (usize, usize)>;
+
+pub fn compile(program: Program) -> Vec<Handler> {
+    program
+        .instructions
+        .into_iter()
+        .enumerate()
+        .map(|(pc, instruction)| compile_instruction(instruction, pc + 1))
+        .collect()
+}
+
+// Jump destinations point at the instruction before the one to carry on from,
+// since the match-based interpreter always steps forward after a jump. Here
+// the handler goes straight to the right place instead.
+fn compile_instruction(instruction: Instruction, next: usize) -> Handler {
+    match instruction {
+        Instruction::IncPtr { count } => Box::new(move |_machine, mut data_pointer| {
+            data_pointer += count;
+            (next, data_pointer)
+        }),
+        Instruction::DecPtr { count } => Box::new(move |_machine, mut data_pointer| {
+            data_pointer -= count;
+            (next, data_pointer)
+        }),
+        Instruction::IncData { count } => Box::new(move |machine, data_pointer| {
+            let cell = &mut machine.memory[data_pointer];
+            *cell = unbalanced_wrapping_add(*cell, count);
+            (next, data_pointer)
+        }),
+        Instruction::DecData { count } => Box::new(move |machine, data_pointer| {
+            let cell = &mut machine.memory[data_pointer];
+            *cell = unbalanced_wrapping_sub(*cell, count);
+            (next, data_pointer)
+        }),
+        Instruction::Read { count } => {
+            Box::new(
+                move |machine, data_pointer| match machine.read(data_pointer, count) {
+                    Ok(()) => (next, data_pointer),
+                    Err(err) => (machine.halt(err), data_pointer),
+                },
+            )
+        }
+        Instruction::Write { count } => {
+            Box::new(
+                move |machine, data_pointer| match machine.write(data_pointer, count) {
+                    Ok(()) => (next, data_pointer),
+                    Err(err) => (machine.halt(err), data_pointer),
+                },
+            )
+        }
+        Instruction::JumpBegin { destination } | Instruction::ConditionalBegin { destination } => {
+            let after = destination + 1;
+            Box::new(move |machine, data_pointer| {
+                if machine.memory[data_pointer] == 0 {
+                    (after, data_pointer)
+                } else {
+                    (next, data_pointer)
+                }
+            })
+        }
+        Instruction::JumpEnd { destination } => {
+            let after = destination + 1;
+            Box::new(move |machine, data_pointer| {
+                if machine.memory[data_pointer] != 0 {
+                    (after, data_pointer)
+                } else {
+                    (next, data_pointer)
+                }
+            })
+        }
+        Instruction::SetDataToZero => Box::new(move |machine, data_pointer| {
+            machine.memory[data_pointer] = 0;
+            (next, data_pointer)
+        }),
+        Instruction::MovePtrUntilZero {
+            count,
+            forward,
+            amount,
+        } => Box::new(move |machine, mut data_pointer| {
+            for _ in 0..count {
+                data_pointer = move_ptr_until_zero(&machine.memory, data_pointer, forward, amount);
+            }
+            (next, data_pointer)
+        }),
+        Instruction::MoveData {
+            count,
+            forward,
+            amount,
+        } => Box::new(move |machine, data_pointer| {
+            machine.move_data(data_pointer, count, forward, amount);
+            (next, data_pointer)
+        }),
+        Instruction::MovePtrAddData { offset, delta } => {
+            Box::new(move |machine, mut data_pointer| {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                let cell = &mut machine.memory[data_pointer];
+                *cell = cell.wrapping_add(delta);
+                (next, data_pointer)
+            })
+        }
+        Instruction::AddDataMovePtr { delta, offset } => {
+            Box::new(move |machine, mut data_pointer| {
+                let cell = &mut machine.memory[data_pointer];
+                *cell = cell.wrapping_add(delta);
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                (next, data_pointer)
+            })
+        }
+        Instruction::MovePtrAddDataMovePtr {
+            offset,
+            delta,
+            second_offset,
+        } => Box::new(move |machine, mut data_pointer| {
+            data_pointer = data_pointer.wrapping_add_signed(offset);
+            let cell = &mut machine.memory[data_pointer];
+            *cell = cell.wrapping_add(delta);
+            data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            (next, data_pointer)
+        }),
+        Instruction::MovePtrSetDataToZero { offset } => {
+            Box::new(move |machine, mut data_pointer| {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                machine.memory[data_pointer] = 0;
+                (next, data_pointer)
+            })
+        }
+        Instruction::MovePtrMoveData {
+            offset,
+            count,
+            forward,
+            amount,
+        } => Box::new(move |machine, mut data_pointer| {
+            data_pointer = data_pointer.wrapping_add_signed(offset);
+            machine.move_data(data_pointer, count, forward, amount);
+            (next, data_pointer)
+        }),
+        Instruction::MovePtrMoveDataMovePtr {
+            offset,
+            count,
+            forward,
+            amount,
+            second_offset,
+        } => Box::new(move |machine, mut data_pointer| {
+            data_pointer = data_pointer.wrapping_add_signed(offset);
+            machine.move_data(data_pointer, count, forward, amount);
+            data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            (next, data_pointer)
+        }),
+        Instruction::MovePtrJumpIfZero {
+            offset,
+            destination,
+        } => {
+            let after = destination + 1;
+            Box::new(move |machine, mut data_pointer| {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                if machine.memory[data_pointer] == 0 {
+                    (after, data_pointer)
+                } else {
+                    (next, data_pointer)
+                }
+            })
+        }
+        Instruction::MovePtrJumpEnd {
+            offset,
+            destination,
+        } => {
+            let after = destination + 1;
+            Box::new(move |machine, mut data_pointer| {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                if machine.memory[data_pointer] != 0 {
+                    (after, data_pointer)
+                } else {
+                    (next, data_pointer)
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use {
+    std::io::{Read, Write},
+    util::{run::RunOptions, BfResult},
+};
+
+mod compiler;
+mod vm;
+
+pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    let program = opinterp3::compile(source_code, options)?;
+    let handlers = compiler::compile(program);
+
+    vm::run(&handlers, stdin, stdout)
+}
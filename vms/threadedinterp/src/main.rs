@@ -0,0 +1,8 @@
+use util::{run::run_main_with_options, BfResult};
+
+fn main() -> BfResult<()> {
+    run_main_with_options(
+        threadedinterp::run_with_options,
+        &opinterp3::SUPPORTED_OPTIONS,
+    )
+}
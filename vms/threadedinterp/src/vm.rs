@@ -0,0 +1,82 @@
+use {
+    crate::compiler::Handler,
+    std::io::{Read, Write},
+    util::{BfError, BfResult},
+};
+
+const MEMORY_SIZE: usize = 30000;
+
+// Handlers return this instead of the position of the next handler to stop
+// the program early, after storing the error which caused it.
+pub const HALT: usize = usize::MAX;
+
+pub struct Machine<'a> {
+    pub memory: [u8; MEMORY_SIZE],
+    stdin: &'a mut dyn Read,
+    stdout: &'a mut dyn Write,
+    error: Option<BfError>,
+}
+
+impl Machine<'_> {
+    pub fn read(&mut self, data_pointer: usize, count: usize) -> BfResult<()> {
+        for _ in 0..count {
+            let mut c = [0; 1];
+            self.stdin.read_exact(&mut c)?;
+            self.memory[data_pointer] = c[0];
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, data_pointer: usize, count: usize) -> BfResult<()> {
+        for _ in 0..count {
+            self.stdout.write_all(&[self.memory[data_pointer]])?;
+            self.stdout.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn halt(&mut self, err: BfError) -> usize {
+        self.error = Some(err);
+        HALT
+    }
+
+    pub fn move_data(&mut self, data_pointer: usize, count: usize, forward: bool, amount: usize) {
+        for _ in 0..count {
+            if self.memory[data_pointer] != 0 {
+                let move_to_ptr = if forward {
+                    data_pointer + amount
+                } else {
+                    data_pointer - amount
+                };
+
+                self.memory[move_to_ptr] =
+                    self.memory[move_to_ptr].wrapping_add(self.memory[data_pointer]);
+                self.memory[data_pointer] = 0;
+            }
+        }
+    }
+}
+
+pub fn run(handlers: &[Handler], stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    let mut machine = Machine {
+        memory: [0; MEMORY_SIZE],
+        stdin,
+        stdout,
+        error: None,
+    };
+
+    // Every handler has already worked out where to go next, so all that's
+    // left is to call them one after the other.
+    let mut pc = 0;
+    let mut data_pointer = 0;
+    while pc < handlers.len() {
+        (pc, data_pointer) = handlers[pc](&mut machine, data_pointer);
+    }
+
+    match machine.error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,45 @@
+use {
+    std::io::{Read, Write},
+    util::{asm::Runtime, run::RunOptions, BfResult},
+};
+
+mod lower;
+mod vm;
+
+pub use lower::{is_loop_begin, lower_conditional, lower_loop, lower_straight_line};
+
+// The number of iterations after which a loop is compiled. Compiling a loop
+// takes a few microseconds, so this only needs to be enough to skip loops
+// which barely run.
+pub const DEFAULT_THRESHOLD: u32 = 1000;
+
+pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    run_with_threshold(source_code, options, DEFAULT_THRESHOLD, stdin, stdout)
+}
+
+pub fn run_with_threshold(
+    source_code: &str,
+    options: &RunOptions,
+    threshold: u32,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    let program = opinterp3::compile(source_code, options)?;
+    let mut runtime = Runtime::new(stdin, stdout);
+
+    let compiled_loops = vm::run(&program, &mut runtime, threshold)?;
+    if options.report_passes {
+        eprintln!("tiering: compiled {compiled_loops} hot loops");
+    }
+
+    Ok(())
+}
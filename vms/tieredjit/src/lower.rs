@@ -0,0 +1,385 @@
+use {
+    opinterp3::Instruction,
+    opjit::Instruction as JitInstruction,
+    std::ops::Range,
+    util::{BfError, BfResult},
+};
+
+// Translates the loop between `begin` and `end` (inclusive) into instructions
+// for opjit's code generator. The compiled loop is entered at its first
+// instruction once the interpreter has done any pointer move fused into it, so
+// that move is left out.
+pub fn lower_loop(
+    instructions: &[Instruction],
+    begin: usize,
+    end: usize,
+) -> BfResult<Vec<JitInstruction>> {
+    let mut lowered = vec![JitInstruction::JumpBegin];
+    // The number of conditionals which end after each instruction.
+    let mut conditional_ends = vec![0; end + 1];
+    lower_range(
+        instructions,
+        begin + 1..end,
+        &mut conditional_ends,
+        &mut lowered,
+    )?;
+
+    match instructions[end] {
+        Instruction::JumpEnd { .. } => (),
+        Instruction::MovePtrJumpEnd { offset, .. } => move_ptr(offset, &mut lowered)?,
+        instruction => {
+            return Err(BfError::Bf(format!(
+                "Expected the end of a loop at position {end}, found {instruction:?}."
+            )))
+        }
+    }
+    lowered.push(JitInstruction::JumpEnd);
+
+    Ok(lowered)
+}
+
+// Translates the conditional starting at `begin`, along with any pointer move
+// fused into it.
+pub fn lower_conditional(
+    instructions: &[Instruction],
+    begin: usize,
+) -> BfResult<Vec<JitInstruction>> {
+    let mut lowered = vec![];
+    let end = match instructions[begin] {
+        Instruction::ConditionalBegin { destination } => destination,
+        Instruction::MovePtrJumpIfZero { destination, .. }
+            if !is_loop_begin(instructions, begin) =>
+        {
+            destination
+        }
+        instruction => {
+            return Err(BfError::Bf(format!(
+                "Expected the start of a conditional at position {begin}, found {instruction:?}."
+            )))
+        }
+    };
+
+    let mut conditional_ends = vec![0; end + 1];
+    lower_range(
+        instructions,
+        begin..end + 1,
+        &mut conditional_ends,
+        &mut lowered,
+    )?;
+
+    Ok(lowered)
+}
+
+fn lower_range(
+    instructions: &[Instruction],
+    range: Range<usize>,
+    conditional_ends: &mut [usize],
+    lowered: &mut Vec<JitInstruction>,
+) -> BfResult<()> {
+    for position in range {
+        lower(
+            instructions,
+            position,
+            instructions[position],
+            conditional_ends,
+            lowered,
+        )?;
+        for _ in 0..conditional_ends[position] {
+            lowered.push(JitInstruction::ConditionalEnd);
+        }
+    }
+
+    Ok(())
+}
+
+// Whether the instruction at `position` starts a loop rather than a
+// conditional, since a fused pointer move and jump can be either.
+pub fn is_loop_begin(instructions: &[Instruction], position: usize) -> bool {
+    match instructions[position] {
+        Instruction::JumpBegin { .. } => true,
+        Instruction::MovePtrJumpIfZero { destination, .. } => matches!(
+            instructions[destination],
+            Instruction::JumpEnd { destination: begin }
+                | Instruction::MovePtrJumpEnd { destination: begin, .. } if begin == position
+        ),
+        _ => false,
+    }
+}
+
+fn lower(
+    instructions: &[Instruction],
+    position: usize,
+    instruction: Instruction,
+    conditional_ends: &mut [usize],
+    lowered: &mut Vec<JitInstruction>,
+) -> BfResult<()> {
+    lower_straight_line(instruction, lowered)?;
+    match instruction {
+        Instruction::JumpBegin { .. } => lowered.push(JitInstruction::JumpBegin),
+        Instruction::JumpEnd { .. } | Instruction::MovePtrJumpEnd { .. } => {
+            lowered.push(JitInstruction::JumpEnd)
+        }
+        Instruction::ConditionalBegin { destination } => {
+            conditional_begin(destination, conditional_ends, lowered)
+        }
+        Instruction::MovePtrJumpIfZero { destination, .. } => {
+            if is_loop_begin(instructions, position) {
+                lowered.push(JitInstruction::JumpBegin);
+            } else {
+                conditional_begin(destination, conditional_ends, lowered);
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+// Lowers everything an instruction does apart from branching, which for a
+// fused pointer move and jump leaves just the move.
+pub fn lower_straight_line(
+    instruction: Instruction,
+    lowered: &mut Vec<JitInstruction>,
+) -> BfResult<()> {
+    match instruction {
+        Instruction::IncPtr { count } => lowered.push(JitInstruction::IncPtr {
+            count: count.try_into()?,
+        }),
+        Instruction::DecPtr { count } => lowered.push(JitInstruction::DecPtr {
+            count: count.try_into()?,
+        }),
+        Instruction::IncData { count } => lowered.push(JitInstruction::IncData {
+            count: (count % 256) as u32,
+        }),
+        Instruction::DecData { count } => lowered.push(JitInstruction::DecData {
+            count: (count % 256) as u32,
+        }),
+        Instruction::Read { count } => lowered.push(JitInstruction::Read {
+            count: count.try_into()?,
+        }),
+        Instruction::Write { count } => lowered.push(JitInstruction::Write {
+            count: count.try_into()?,
+        }),
+        Instruction::JumpBegin { .. }
+        | Instruction::JumpEnd { .. }
+        | Instruction::ConditionalBegin { .. } => (),
+        Instruction::SetDataToZero => lowered.push(JitInstruction::SetDataToZero),
+        Instruction::MovePtrUntilZero {
+            count,
+            forward,
+            amount,
+        } => lowered.push(JitInstruction::MovePtrUntilZero {
+            count: count.try_into()?,
+            forward,
+            amount: amount.try_into()?,
+        }),
+        Instruction::MoveData {
+            count,
+            forward,
+            amount,
+        } => move_data(count, forward, amount, lowered)?,
+        Instruction::MovePtrAddData { offset, delta } => {
+            move_ptr(offset, lowered)?;
+            lowered.push(JitInstruction::IncData {
+                count: delta.into(),
+            });
+        }
+        Instruction::AddDataMovePtr { delta, offset } => {
+            lowered.push(JitInstruction::IncData {
+                count: delta.into(),
+            });
+            move_ptr(offset, lowered)?;
+        }
+        Instruction::MovePtrAddDataMovePtr {
+            offset,
+            delta,
+            second_offset,
+        } => {
+            move_ptr(offset, lowered)?;
+            lowered.push(JitInstruction::IncData {
+                count: delta.into(),
+            });
+            move_ptr(second_offset, lowered)?;
+        }
+        Instruction::MovePtrSetDataToZero { offset } => {
+            move_ptr(offset, lowered)?;
+            lowered.push(JitInstruction::SetDataToZero);
+        }
+        Instruction::MovePtrMoveData {
+            offset,
+            count,
+            forward,
+            amount,
+        } => {
+            move_ptr(offset, lowered)?;
+            move_data(count, forward, amount, lowered)?;
+        }
+        Instruction::MovePtrMoveDataMovePtr {
+            offset,
+            count,
+            forward,
+            amount,
+            second_offset,
+        } => {
+            move_ptr(offset, lowered)?;
+            move_data(count, forward, amount, lowered)?;
+            move_ptr(second_offset, lowered)?;
+        }
+        Instruction::MovePtrJumpIfZero { offset, .. }
+        | Instruction::MovePtrJumpEnd { offset, .. } => move_ptr(offset, lowered)?,
+    }
+
+    Ok(())
+}
+
+// A conditional's destination is the last instruction of its body.
+fn conditional_begin(
+    destination: usize,
+    conditional_ends: &mut [usize],
+    lowered: &mut Vec<JitInstruction>,
+) {
+    conditional_ends[destination] += 1;
+    lowered.push(JitInstruction::ConditionalBegin);
+}
+
+fn move_ptr(offset: isize, lowered: &mut Vec<JitInstruction>) -> BfResult<()> {
+    let count = offset.unsigned_abs().try_into()?;
+    if offset > 0 {
+        lowered.push(JitInstruction::IncPtr { count });
+    } else if offset < 0 {
+        lowered.push(JitInstruction::DecPtr { count });
+    }
+
+    Ok(())
+}
+
+fn move_data(
+    count: usize,
+    forward: bool,
+    amount: usize,
+    lowered: &mut Vec<JitInstruction>,
+) -> BfResult<()> {
+    lowered.push(JitInstruction::MoveData {
+        count: count.try_into()?,
+        forward,
+        amount: amount.try_into()?,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{is_loop_begin, lower_conditional, lower_loop},
+        opinterp3::Instruction,
+        opjit::Instruction as JitInstruction,
+    };
+
+    #[test]
+    fn lower_loop_test() {
+        let instructions = [
+            Instruction::IncData { count: 258 },
+            Instruction::MovePtrJumpIfZero {
+                offset: 2,
+                destination: 6,
+            },
+            Instruction::MovePtrAddDataMovePtr {
+                offset: -1,
+                delta: 255,
+                second_offset: 1,
+            },
+            Instruction::ConditionalBegin { destination: 4 },
+            Instruction::MovePtrSetDataToZero { offset: 3 },
+            Instruction::MovePtrMoveData {
+                offset: -3,
+                count: 1,
+                forward: false,
+                amount: 2,
+            },
+            Instruction::MovePtrJumpEnd {
+                offset: 0,
+                destination: 1,
+            },
+        ];
+
+        assert!(!is_loop_begin(&instructions, 0));
+        assert!(is_loop_begin(&instructions, 1));
+        assert!(!is_loop_begin(&instructions, 3));
+
+        // The pointer move fused into the start of the loop is left to the
+        // interpreter.
+        assert_eq!(
+            lower_loop(&instructions, 1, 6).unwrap(),
+            vec![
+                JitInstruction::JumpBegin,
+                JitInstruction::DecPtr { count: 1 },
+                JitInstruction::IncData { count: 255 },
+                JitInstruction::IncPtr { count: 1 },
+                JitInstruction::ConditionalBegin,
+                JitInstruction::IncPtr { count: 3 },
+                JitInstruction::SetDataToZero,
+                JitInstruction::ConditionalEnd,
+                JitInstruction::DecPtr { count: 3 },
+                JitInstruction::MoveData {
+                    count: 1,
+                    forward: false,
+                    amount: 2,
+                },
+                JitInstruction::JumpEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_test() {
+        // [+[-[>]]] with the inner loop and both conditionals ending together.
+        let instructions = [
+            Instruction::JumpBegin { destination: 6 },
+            Instruction::IncData { count: 1 },
+            Instruction::ConditionalBegin { destination: 5 },
+            Instruction::DecData { count: 1 },
+            Instruction::ConditionalBegin { destination: 5 },
+            Instruction::MovePtrUntilZero {
+                count: 1,
+                forward: true,
+                amount: 1,
+            },
+            Instruction::JumpEnd { destination: 0 },
+        ];
+
+        assert_eq!(
+            lower_loop(&instructions, 0, 6).unwrap(),
+            vec![
+                JitInstruction::JumpBegin,
+                JitInstruction::IncData { count: 1 },
+                JitInstruction::ConditionalBegin,
+                JitInstruction::DecData { count: 1 },
+                JitInstruction::ConditionalBegin,
+                JitInstruction::MovePtrUntilZero {
+                    count: 1,
+                    forward: true,
+                    amount: 1,
+                },
+                JitInstruction::ConditionalEnd,
+                JitInstruction::ConditionalEnd,
+                JitInstruction::JumpEnd,
+            ]
+        );
+        assert_eq!(
+            lower_conditional(&instructions, 2).unwrap(),
+            vec![
+                JitInstruction::ConditionalBegin,
+                JitInstruction::DecData { count: 1 },
+                JitInstruction::ConditionalBegin,
+                JitInstruction::MovePtrUntilZero {
+                    count: 1,
+                    forward: true,
+                    amount: 1,
+                },
+                JitInstruction::ConditionalEnd,
+                JitInstruction::ConditionalEnd,
+            ]
+        );
+    }
+}
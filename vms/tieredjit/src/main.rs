@@ -0,0 +1,5 @@
+use util::{run::run_main_with_options, BfResult};
+
+fn main() -> BfResult<()> {
+    run_main_with_options(tieredjit::run_with_options, &opinterp3::SUPPORTED_OPTIONS)
+}
@@ -0,0 +1,209 @@
+use {
+    crate::lower::lower_loop,
+    opinterp3::{Instruction, Program},
+    util::{
+        asm::{CompiledProgram, Runtime},
+        math::{unbalanced_wrapping_add, unbalanced_wrapping_sub},
+        scan::move_ptr_until_zero,
+        BfResult,
+    },
+};
+
+// Interprets the program like opinterp3, counting the iterations of each loop.
+// Once a loop has run `threshold` iterations it's compiled with opjit's code
+// generator, and from then on the whole loop runs as native code whenever it's
+// reached. Both tiers work on the runtime's memory, so the only state to hand
+// over is the data pointer. Returns the number of loops compiled.
+pub fn run(program: &Program, runtime: &mut Runtime, threshold: u32) -> BfResult<usize> {
+    let instructions = &program.instructions;
+    let mut loops = Loops {
+        threshold,
+        iterations: vec![0; instructions.len()],
+        compiled: instructions.iter().map(|_| None).collect(),
+    };
+
+    let mut pc = 0;
+    let mut data_pointer = 0;
+
+    while pc < instructions.len() {
+        match instructions[pc] {
+            Instruction::IncPtr { count } => data_pointer += count,
+            Instruction::DecPtr { count } => data_pointer -= count,
+            Instruction::IncData { count } => {
+                let cell = &mut runtime.memory_mut()[data_pointer];
+                *cell = unbalanced_wrapping_add(*cell, count);
+            }
+            Instruction::DecData { count } => {
+                let cell = &mut runtime.memory_mut()[data_pointer];
+                *cell = unbalanced_wrapping_sub(*cell, count);
+            }
+            Instruction::Read { count } => {
+                for _ in 0..count {
+                    runtime.memory_mut()[data_pointer] = runtime.read_byte()?;
+                }
+            }
+            Instruction::Write { count } => {
+                for _ in 0..count {
+                    runtime.write_byte(runtime.memory()[data_pointer])?;
+                }
+            }
+            Instruction::JumpBegin { destination } => {
+                if let Some(compiled_loop) = &loops.compiled[pc] {
+                    data_pointer = runtime.run_loop(compiled_loop, data_pointer);
+                    pc = destination;
+                } else if runtime.memory()[data_pointer] == 0 {
+                    pc = destination;
+                }
+            }
+            Instruction::JumpEnd { destination } => {
+                if runtime.memory()[data_pointer] != 0 {
+                    (pc, data_pointer) =
+                        loops.loop_back(instructions, runtime, data_pointer, destination, pc)?;
+                }
+            }
+            Instruction::ConditionalBegin { destination } => {
+                if runtime.memory()[data_pointer] == 0 {
+                    pc = destination;
+                }
+            }
+            Instruction::SetDataToZero => runtime.memory_mut()[data_pointer] = 0,
+            Instruction::MovePtrUntilZero {
+                count,
+                forward,
+                amount,
+            } => {
+                for _ in 0..count {
+                    data_pointer =
+                        move_ptr_until_zero(runtime.memory(), data_pointer, forward, amount);
+                }
+            }
+            Instruction::MoveData {
+                count,
+                forward,
+                amount,
+            } => move_data(runtime.memory_mut(), data_pointer, count, forward, amount),
+            Instruction::MovePtrAddData { offset, delta } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                add(runtime.memory_mut(), data_pointer, delta);
+            }
+            Instruction::AddDataMovePtr { delta, offset } => {
+                add(runtime.memory_mut(), data_pointer, delta);
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+            }
+            Instruction::MovePtrAddDataMovePtr {
+                offset,
+                delta,
+                second_offset,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                add(runtime.memory_mut(), data_pointer, delta);
+                data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            }
+            Instruction::MovePtrSetDataToZero { offset } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                runtime.memory_mut()[data_pointer] = 0;
+            }
+            Instruction::MovePtrMoveData {
+                offset,
+                count,
+                forward,
+                amount,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                move_data(runtime.memory_mut(), data_pointer, count, forward, amount);
+            }
+            Instruction::MovePtrMoveDataMovePtr {
+                offset,
+                count,
+                forward,
+                amount,
+                second_offset,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                move_data(runtime.memory_mut(), data_pointer, count, forward, amount);
+                data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            }
+            Instruction::MovePtrJumpIfZero {
+                offset,
+                destination,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                // The pointer move isn't part of the compiled loop, since
+                // it's skipped when jumping back to the start of the loop.
+                if let Some(compiled_loop) = &loops.compiled[pc] {
+                    data_pointer = runtime.run_loop(compiled_loop, data_pointer);
+                    pc = destination;
+                } else if runtime.memory()[data_pointer] == 0 {
+                    pc = destination;
+                }
+            }
+            Instruction::MovePtrJumpEnd {
+                offset,
+                destination,
+            } => {
+                data_pointer = data_pointer.wrapping_add_signed(offset);
+                if runtime.memory()[data_pointer] != 0 {
+                    (pc, data_pointer) =
+                        loops.loop_back(instructions, runtime, data_pointer, destination, pc)?;
+                }
+            }
+        }
+
+        pc += 1;
+    }
+
+    Ok(loops.compiled.iter().flatten().count())
+}
+
+struct Loops {
+    threshold: u32,
+    // Both indexed by the position of the start of each loop.
+    iterations: Vec<u32>,
+    compiled: Vec<Option<CompiledProgram>>,
+}
+
+impl Loops {
+    // Takes the back edge of the loop between `begin` and `end`, compiling the
+    // loop if it has just become hot. A compiled loop is run straight away for
+    // the rest of its iterations, after which execution carries on past its
+    // end. Returns the new pc and data pointer.
+    fn loop_back(
+        &mut self,
+        instructions: &[Instruction],
+        runtime: &mut Runtime,
+        data_pointer: usize,
+        begin: usize,
+        end: usize,
+    ) -> BfResult<(usize, usize)> {
+        self.iterations[begin] = self.iterations[begin].saturating_add(1);
+        if self.iterations[begin] >= self.threshold {
+            let lowered = lower_loop(instructions, begin, end)?;
+            let compiled_loop = opjit::compile_loop(lowered, runtime)?;
+            let data_pointer = runtime.run_loop(&compiled_loop, data_pointer);
+            self.compiled[begin] = Some(compiled_loop);
+
+            Ok((end, data_pointer))
+        } else {
+            Ok((begin, data_pointer))
+        }
+    }
+}
+
+fn add(memory: &mut [u8], data_pointer: usize, delta: u8) {
+    memory[data_pointer] = memory[data_pointer].wrapping_add(delta);
+}
+
+fn move_data(memory: &mut [u8], data_pointer: usize, count: usize, forward: bool, amount: usize) {
+    for _ in 0..count {
+        if memory[data_pointer] != 0 {
+            let move_to_ptr = if forward {
+                data_pointer + amount
+            } else {
+                data_pointer - amount
+            };
+
+            memory[move_to_ptr] = memory[move_to_ptr].wrapping_add(memory[data_pointer]);
+            memory[data_pointer] = 0;
+        }
+    }
+}
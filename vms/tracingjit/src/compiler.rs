@@ -0,0 +1,80 @@
+use {
+    crate::trace::TraceOp,
+    dynasmrt::{dynasm, DynasmApi, DynasmLabelApi},
+    std::cell::Cell,
+    util::{
+        asm::{
+            guard, jump, loop_epilogue, loop_prologue, store_u32, Assembler, CompiledProgram, Io,
+            Runtime, VectorWidth,
+        },
+        dasm, BfResult,
+    },
+};
+
+pub struct CompiledTrace {
+    program: CompiledProgram,
+    // Where the compiled code stores the pc for the interpreter to carry on
+    // from when a guard fails.
+    exit: Box<Cell<u32>>,
+}
+
+impl CompiledTrace {
+    // Runs the trace until a guard fails, returning the pc to carry on from.
+    pub fn run(&self, runtime: &mut Runtime, data_pointer: &mut usize) -> usize {
+        *data_pointer = runtime.run_loop(&self.program, *data_pointer);
+        self.exit.get() as usize
+    }
+}
+
+// Compiles a trace into a loop which keeps running it for as long as its
+// guards hold. Each guard gets its own exit, which records where the
+// interpreter should carry on before leaving through the shared epilogue.
+pub fn compile_trace(trace: Vec<TraceOp>, runtime: &mut Runtime) -> BfResult<CompiledTrace> {
+    let exit = Box::new(Cell::new(0));
+    let mut assembler = Assembler::new()?;
+    let start = assembler.offset();
+    let top = assembler.new_dynamic_label();
+    let leave = assembler.new_dynamic_label();
+    let io = Io::Runtime(runtime);
+    let vectors = VectorWidth::host();
+
+    loop_prologue(&mut assembler);
+    dasm!(assembler
+        ; =>top
+    );
+
+    let mut exits = vec![];
+    let mut code = vec![];
+    for op in trace {
+        match op {
+            TraceOp::Instruction(instruction) => code.push(instruction),
+            TraceOp::Guard { zero, exit } => {
+                opjit::compile_instructions(&mut assembler, code.split_off(0), &io, vectors)?;
+
+                let label = assembler.new_dynamic_label();
+                guard(&mut assembler, zero, label);
+                exits.push((label, exit));
+            }
+        }
+    }
+    opjit::compile_instructions(&mut assembler, code, &io, vectors)?;
+    jump(&mut assembler, top);
+
+    for (label, pc) in exits {
+        dasm!(assembler
+            ; =>label
+        );
+        store_u32(&mut assembler, exit.as_ptr(), pc.try_into()?);
+        jump(&mut assembler, leave);
+    }
+
+    dasm!(assembler
+        ; =>leave
+    );
+    loop_epilogue(&mut assembler);
+
+    Ok(CompiledTrace {
+        program: CompiledProgram::new(assembler.finalize()?, start),
+        exit,
+    })
+}
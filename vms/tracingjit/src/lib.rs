@@ -0,0 +1,47 @@
+use {
+    std::io::{Read, Write},
+    util::{asm::Runtime, run::RunOptions, BfResult},
+};
+
+mod compiler;
+mod trace;
+mod vm;
+
+// The number of iterations after which a loop is traced. Tracing a loop
+// specializes it to the path taken through one iteration, so this is high
+// enough to skip loops which haven't settled down yet.
+pub const DEFAULT_THRESHOLD: u32 = 1000;
+
+pub fn run(source_code: &str, stdin: &mut dyn Read, stdout: &mut dyn Write) -> BfResult<()> {
+    run_with_options(source_code, &RunOptions::default(), stdin, stdout)
+}
+
+pub fn run_with_options(
+    source_code: &str,
+    options: &RunOptions,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    run_with_threshold(source_code, options, DEFAULT_THRESHOLD, stdin, stdout)
+}
+
+pub fn run_with_threshold(
+    source_code: &str,
+    options: &RunOptions,
+    threshold: u32,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> BfResult<()> {
+    let program = opinterp3::compile(source_code, options)?;
+    let mut runtime = Runtime::new(stdin, stdout);
+
+    let stats = vm::run(&program, &mut runtime, threshold)?;
+    if options.report_passes {
+        eprintln!(
+            "tracing: compiled {} traces and {} whole loops",
+            stats.traces, stats.whole_loops
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use {
+    opinterp3::Instruction,
+    opjit::Instruction as JitInstruction,
+    tieredjit::{is_loop_begin, lower_conditional, lower_loop, lower_straight_line},
+    util::{BfError, BfResult},
+};
+
+// Traces longer than this are given up on, since they mostly come from loops
+// which take a different path every iteration.
+const MAX_TRACE_LENGTH: usize = 1000;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TraceOp {
+    Instruction(JitInstruction),
+    // Checks that the current cell is zero, or that it isn't, like it was when
+    // the trace was recorded. If not, the trace is left and the interpreter
+    // carries on from `exit`.
+    Guard { zero: bool, exit: usize },
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Progress {
+    Recording,
+    // Code has been added to the trace as a whole rather than as the path
+    // taken through it, so the interpreter should run it without recording
+    // until it reaches `until`.
+    Skip { until: usize },
+    Finished(Vec<TraceOp>),
+    // The traced loop ended before finishing the iteration.
+    Exited,
+    TooLong,
+}
+
+// Records the instructions run through one iteration of the loop between
+// `begin` and `end`, starting from the first instruction of its body. Inner
+// loops which run zero times or once become part of the trace, guarded by
+// which way they went. Ones which run more than that are compiled as loops.
+// Conditionals are always compiled as they are, since which way they go tends
+// to change from one iteration to the next, and every failed guard means going
+// back to the interpreter.
+pub struct Recorder {
+    begin: usize,
+    end: usize,
+    trace: Vec<TraceOp>,
+    // The start of each inner loop currently being recorded, along with the
+    // length of the trace just before the guard for entering it.
+    inner_loops: Vec<(usize, usize)>,
+}
+
+impl Recorder {
+    pub fn new(begin: usize, end: usize) -> Self {
+        Self {
+            begin,
+            end,
+            trace: vec![],
+            inner_loops: vec![],
+        }
+    }
+
+    // Records the instruction at `pc` once it's been run, given the pc
+    // execution went on to and whether the current cell is now zero.
+    pub fn record(
+        &mut self,
+        instructions: &[Instruction],
+        pc: usize,
+        next: usize,
+        cell_zero: bool,
+    ) -> BfResult<Progress> {
+        let instruction = instructions[pc];
+
+        if is_conditional_begin(instructions, pc) {
+            self.trace.extend(
+                lower_conditional(instructions, pc)?
+                    .into_iter()
+                    .map(TraceOp::Instruction),
+            );
+            return Ok(match branch_destination(instruction) {
+                Some(destination) if next != destination + 1 => Progress::Skip {
+                    until: destination + 1,
+                },
+                _ => self.check_length(),
+            });
+        }
+
+        let mut lowered = vec![];
+        lower_straight_line(instruction, &mut lowered)?;
+        self.trace
+            .extend(lowered.into_iter().map(TraceOp::Instruction));
+
+        if let Some(destination) = branch_destination(instruction) {
+            let mark = self.trace.len();
+            // Jump destinations point at the instruction before the one
+            // execution carries on from.
+            self.trace.push(TraceOp::Guard {
+                zero: cell_zero,
+                exit: if next == pc + 1 {
+                    destination + 1
+                } else {
+                    pc + 1
+                },
+            });
+
+            if pc == self.end {
+                return Ok(if next == self.begin + 1 {
+                    Progress::Finished(std::mem::take(&mut self.trace))
+                } else {
+                    Progress::Exited
+                });
+            }
+
+            if is_loop_begin(instructions, pc) {
+                if next == pc + 1 {
+                    self.inner_loops.push((pc, mark));
+                }
+            } else if is_loop_end(instruction) {
+                let Some((begin, mark)) = self.inner_loops.pop() else {
+                    return Err(BfError::Bf(format!(
+                        "Reached the end of a loop at position {pc} without entering it."
+                    )));
+                };
+
+                if next == begin + 1 {
+                    self.trace.truncate(mark);
+                    self.trace.extend(
+                        lower_loop(instructions, begin, pc)?
+                            .into_iter()
+                            .map(TraceOp::Instruction),
+                    );
+                    return Ok(Progress::Skip { until: pc + 1 });
+                }
+            }
+        }
+
+        Ok(self.check_length())
+    }
+
+    fn check_length(&self) -> Progress {
+        if self.trace.len() > MAX_TRACE_LENGTH {
+            Progress::TooLong
+        } else {
+            Progress::Recording
+        }
+    }
+}
+
+pub fn branch_destination(instruction: Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::JumpBegin { destination }
+        | Instruction::JumpEnd { destination }
+        | Instruction::ConditionalBegin { destination }
+        | Instruction::MovePtrJumpIfZero { destination, .. }
+        | Instruction::MovePtrJumpEnd { destination, .. } => Some(destination),
+        _ => None,
+    }
+}
+
+fn is_conditional_begin(instructions: &[Instruction], position: usize) -> bool {
+    match instructions[position] {
+        Instruction::ConditionalBegin { .. } => true,
+        Instruction::MovePtrJumpIfZero { .. } => !is_loop_begin(instructions, position),
+        _ => false,
+    }
+}
+
+fn is_loop_end(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpEnd { .. } | Instruction::MovePtrJumpEnd { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Progress, Recorder, TraceOp},
+        opinterp3::Instruction,
+        opjit::Instruction as JitInstruction,
+    };
+
+    // [>[-]<[-]<] without merging the inner loop away.
+    fn instructions() -> Vec<Instruction> {
+        vec![
+            Instruction::JumpBegin { destination: 6 },
+            Instruction::MovePtrJumpIfZero {
+                offset: 1,
+                destination: 3,
+            },
+            Instruction::DecData { count: 1 },
+            Instruction::JumpEnd { destination: 1 },
+            Instruction::ConditionalBegin { destination: 5 },
+            Instruction::SetDataToZero,
+            Instruction::MovePtrJumpEnd {
+                offset: -1,
+                destination: 0,
+            },
+        ]
+    }
+
+    fn record(recorder: &mut Recorder, steps: &[(usize, usize, bool)]) -> Progress {
+        let instructions = instructions();
+        let (last, steps) = steps.split_last().unwrap();
+        for &(pc, next, cell_zero) in steps {
+            assert_eq!(
+                recorder.record(&instructions, pc, next, cell_zero).unwrap(),
+                Progress::Recording
+            );
+        }
+
+        let &(pc, next, cell_zero) = last;
+        recorder.record(&instructions, pc, next, cell_zero).unwrap()
+    }
+
+    #[test]
+    fn record_test() {
+        // The inner loop runs once, and the conditional is compiled as it is
+        // even though it was skipped.
+        assert_eq!(
+            record(
+                &mut Recorder::new(0, 6),
+                &[
+                    (1, 2, false),
+                    (2, 3, true),
+                    (3, 4, true),
+                    (4, 6, true),
+                    (6, 1, false)
+                ]
+            ),
+            Progress::Finished(vec![
+                TraceOp::Instruction(JitInstruction::IncPtr { count: 1 }),
+                TraceOp::Guard {
+                    zero: false,
+                    exit: 4,
+                },
+                TraceOp::Instruction(JitInstruction::DecData { count: 1 }),
+                TraceOp::Guard {
+                    zero: true,
+                    exit: 2
+                },
+                TraceOp::Instruction(JitInstruction::ConditionalBegin),
+                TraceOp::Instruction(JitInstruction::SetDataToZero),
+                TraceOp::Instruction(JitInstruction::ConditionalEnd),
+                TraceOp::Instruction(JitInstruction::DecPtr { count: 1 }),
+                TraceOp::Guard {
+                    zero: false,
+                    exit: 7,
+                },
+            ])
+        );
+
+        // The inner loop is skipped and the traced loop ends.
+        assert_eq!(
+            record(
+                &mut Recorder::new(0, 6),
+                &[(1, 4, true), (4, 6, true), (6, 7, true)]
+            ),
+            Progress::Exited
+        );
+    }
+
+    #[test]
+    fn inner_loop_test() {
+        let mut recorder = Recorder::new(0, 6);
+
+        // The inner loop goes round again, so it replaces the guard for
+        // entering it.
+        assert_eq!(
+            record(
+                &mut recorder,
+                &[(1, 2, false), (2, 3, false), (3, 2, false)]
+            ),
+            Progress::Skip { until: 4 }
+        );
+        // Running the conditional means skipping it too.
+        assert_eq!(
+            record(&mut recorder, &[(4, 5, false)]),
+            Progress::Skip { until: 6 }
+        );
+        assert_eq!(
+            record(&mut recorder, &[(6, 1, false)]),
+            Progress::Finished(vec![
+                TraceOp::Instruction(JitInstruction::IncPtr { count: 1 }),
+                TraceOp::Instruction(JitInstruction::JumpBegin),
+                TraceOp::Instruction(JitInstruction::DecData { count: 1 }),
+                TraceOp::Instruction(JitInstruction::JumpEnd),
+                TraceOp::Instruction(JitInstruction::ConditionalBegin),
+                TraceOp::Instruction(JitInstruction::SetDataToZero),
+                TraceOp::Instruction(JitInstruction::ConditionalEnd),
+                TraceOp::Instruction(JitInstruction::DecPtr { count: 1 }),
+                TraceOp::Guard {
+                    zero: false,
+                    exit: 7,
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use {
+    crate::{
+        compiler::{compile_trace, CompiledTrace},
+        trace::{branch_destination, Progress, Recorder},
+    },
+    opinterp3::{Instruction, Program},
+    tieredjit::{is_loop_begin, lower_loop},
+    util::{
+        asm::{CompiledProgram, Runtime},
+        math::{unbalanced_wrapping_add, unbalanced_wrapping_sub},
+        scan::move_ptr_until_zero,
+        BfResult,
+    },
+};
+
+// How many times recording a loop can end with the loop itself ending, and
+// how many times a loop's trace can be left through a failed guard, before the
+// loop is compiled whole instead.
+const MAX_EXITED_RECORDINGS: u32 = 10;
+const MAX_SIDE_EXITS: u32 = 100;
+
+pub struct Stats {
+    pub traces: usize,
+    pub whole_loops: usize,
+}
+
+enum LoopState {
+    Counting {
+        iterations: u32,
+        exited: u32,
+    },
+    Traced {
+        trace: CompiledTrace,
+        side_exits: u32,
+    },
+    Compiled(CompiledProgram),
+}
+
+// Interprets the program like opinterp3, counting the iterations of each loop.
+// Once a loop has run `threshold` iterations the next one is traced, and the
+// trace compiled and run in place of the loop's body from then on. A trace
+// only covers the path taken through the iteration it was recorded from, so it
+// hands back to the interpreter whenever a different path is taken. Loops
+// which can't be traced, or whose traces keep being left that way, are
+// compiled whole like tieredjit does. Both work on the runtime's memory, so
+// the only state to hand over is the data pointer.
+pub fn run(program: &Program, runtime: &mut Runtime, threshold: u32) -> BfResult<Stats> {
+    let instructions = &program.instructions;
+    let loop_begins: Vec<_> = (0..instructions.len())
+        .map(|position| is_loop_begin(instructions, position))
+        .collect();
+    let mut loops: Vec<_> = instructions
+        .iter()
+        .map(|_| LoopState::Counting {
+            iterations: 0,
+            exited: 0,
+        })
+        .collect();
+    let mut vm = Vm {
+        instructions,
+        runtime,
+        data_pointer: 0,
+    };
+
+    let mut pc = 0;
+    while pc < instructions.len() {
+        let next = vm.step(pc)?;
+        // The only way to reach the instruction after the start of a loop is
+        // to enter it or to go round it again.
+        pc = if next > 0 && loop_begins[next - 1] {
+            vm.enter_loop(next - 1, &mut loops[next - 1], threshold)?
+        } else {
+            next
+        };
+    }
+
+    Ok(Stats {
+        traces: loops
+            .iter()
+            .filter(|state| matches!(state, LoopState::Traced { .. }))
+            .count(),
+        whole_loops: loops
+            .iter()
+            .filter(|state| matches!(state, LoopState::Compiled(_)))
+            .count(),
+    })
+}
+
+struct Vm<'a, 'b, 'c> {
+    instructions: &'a [Instruction],
+    runtime: &'b mut Runtime<'c>,
+    data_pointer: usize,
+}
+
+impl Vm<'_, '_, '_> {
+    // Runs an iteration of the loop starting at `begin`, which the current cell
+    // has already been checked for, returning the pc to carry on from.
+    fn enter_loop(
+        &mut self,
+        begin: usize,
+        state: &mut LoopState,
+        threshold: u32,
+    ) -> BfResult<usize> {
+        match state {
+            LoopState::Traced { trace, side_exits } => {
+                let exit = trace.run(self.runtime, &mut self.data_pointer);
+                if exit != self.end(begin) + 1 {
+                    *side_exits += 1;
+                    if *side_exits >= MAX_SIDE_EXITS {
+                        *state = LoopState::Compiled(self.compile_loop(begin)?);
+                    }
+                }
+                Ok(exit)
+            }
+            LoopState::Compiled(compiled_loop) => {
+                self.data_pointer = self.runtime.run_loop(compiled_loop, self.data_pointer);
+                Ok(self.end(begin) + 1)
+            }
+            LoopState::Counting { iterations, exited } => {
+                *iterations = iterations.saturating_add(1);
+                if *iterations >= threshold {
+                    let exited = *exited;
+                    self.record(begin, state, exited)
+                } else {
+                    Ok(begin + 1)
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, begin: usize, state: &mut LoopState, exited: u32) -> BfResult<usize> {
+        let mut recorder = Recorder::new(begin, self.end(begin));
+
+        let mut pc = begin + 1;
+        loop {
+            let next = self.step(pc)?;
+            let cell_zero = self.runtime.memory()[self.data_pointer] == 0;
+
+            match recorder.record(self.instructions, pc, next, cell_zero)? {
+                Progress::Recording => pc = next,
+                Progress::Skip { until } => pc = self.run_until(next, until)?,
+                Progress::Finished(trace) => {
+                    let trace = compile_trace(trace, self.runtime)?;
+                    let exit = trace.run(self.runtime, &mut self.data_pointer);
+                    *state = LoopState::Traced {
+                        trace,
+                        side_exits: 0,
+                    };
+                    return Ok(exit);
+                }
+                // Loops which keep going round the same number of times
+                // resonate with the threshold, so rather than counting up to
+                // it again, the next iteration is recorded instead.
+                Progress::Exited if exited + 1 < MAX_EXITED_RECORDINGS => {
+                    *state = LoopState::Counting {
+                        iterations: u32::MAX,
+                        exited: exited + 1,
+                    };
+                    return Ok(next);
+                }
+                Progress::Exited | Progress::TooLong => {
+                    *state = LoopState::Compiled(self.compile_loop(begin)?);
+                    return Ok(next);
+                }
+            }
+        }
+    }
+
+    fn compile_loop(&mut self, begin: usize) -> BfResult<CompiledProgram> {
+        let lowered = lower_loop(self.instructions, begin, self.end(begin))?;
+        opjit::compile_loop(lowered, self.runtime)
+    }
+
+    // The start of a loop jumps to its end.
+    fn end(&self, begin: usize) -> usize {
+        branch_destination(self.instructions[begin]).unwrap_or(begin)
+    }
+
+    // Interprets from `pc` until execution reaches `until`, which has to be
+    // just past the end of the loop or conditional being run.
+    fn run_until(&mut self, mut pc: usize, until: usize) -> BfResult<usize> {
+        while pc != until {
+            pc = self.step(pc)?;
+        }
+
+        Ok(pc)
+    }
+
+    // Runs the instruction at `pc`, returning the pc to run next.
+    fn step(&mut self, pc: usize) -> BfResult<usize> {
+        let memory = self.runtime.memory_mut();
+        let data_pointer = &mut self.data_pointer;
+
+        match self.instructions[pc] {
+            Instruction::IncPtr { count } => *data_pointer += count,
+            Instruction::DecPtr { count } => *data_pointer -= count,
+            Instruction::IncData { count } => {
+                let cell = &mut memory[*data_pointer];
+                *cell = unbalanced_wrapping_add(*cell, count);
+            }
+            Instruction::DecData { count } => {
+                let cell = &mut memory[*data_pointer];
+                *cell = unbalanced_wrapping_sub(*cell, count);
+            }
+            Instruction::Read { count } => {
+                for _ in 0..count {
+                    let byte = self.runtime.read_byte()?;
+                    self.runtime.memory_mut()[*data_pointer] = byte;
+                }
+            }
+            Instruction::Write { count } => {
+                for _ in 0..count {
+                    self.runtime
+                        .write_byte(self.runtime.memory()[*data_pointer])?;
+                }
+            }
+            Instruction::JumpBegin { destination }
+            | Instruction::ConditionalBegin { destination } => {
+                if memory[*data_pointer] == 0 {
+                    return Ok(destination + 1);
+                }
+            }
+            Instruction::JumpEnd { destination } => {
+                if memory[*data_pointer] != 0 {
+                    return Ok(destination + 1);
+                }
+            }
+            Instruction::SetDataToZero => memory[*data_pointer] = 0,
+            Instruction::MovePtrUntilZero {
+                count,
+                forward,
+                amount,
+            } => {
+                for _ in 0..count {
+                    *data_pointer = move_ptr_until_zero(memory, *data_pointer, forward, amount);
+                }
+            }
+            Instruction::MoveData {
+                count,
+                forward,
+                amount,
+            } => move_data(memory, *data_pointer, count, forward, amount),
+            Instruction::MovePtrAddData { offset, delta } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                add(memory, *data_pointer, delta);
+            }
+            Instruction::AddDataMovePtr { delta, offset } => {
+                add(memory, *data_pointer, delta);
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+            }
+            Instruction::MovePtrAddDataMovePtr {
+                offset,
+                delta,
+                second_offset,
+            } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                add(memory, *data_pointer, delta);
+                *data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            }
+            Instruction::MovePtrSetDataToZero { offset } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                memory[*data_pointer] = 0;
+            }
+            Instruction::MovePtrMoveData {
+                offset,
+                count,
+                forward,
+                amount,
+            } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                move_data(memory, *data_pointer, count, forward, amount);
+            }
+            Instruction::MovePtrMoveDataMovePtr {
+                offset,
+                count,
+                forward,
+                amount,
+                second_offset,
+            } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                move_data(memory, *data_pointer, count, forward, amount);
+                *data_pointer = data_pointer.wrapping_add_signed(second_offset);
+            }
+            Instruction::MovePtrJumpIfZero {
+                offset,
+                destination,
+            } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                if memory[*data_pointer] == 0 {
+                    return Ok(destination + 1);
+                }
+            }
+            Instruction::MovePtrJumpEnd {
+                offset,
+                destination,
+            } => {
+                *data_pointer = data_pointer.wrapping_add_signed(offset);
+                if memory[*data_pointer] != 0 {
+                    return Ok(destination + 1);
+                }
+            }
+        }
+
+        Ok(pc + 1)
+    }
+}
+
+fn add(memory: &mut [u8], data_pointer: usize, delta: u8) {
+    memory[data_pointer] = memory[data_pointer].wrapping_add(delta);
+}
+
+fn move_data(memory: &mut [u8], data_pointer: usize, count: usize, forward: bool, amount: usize) {
+    for _ in 0..count {
+        if memory[data_pointer] != 0 {
+            let move_to_ptr = if forward {
+                data_pointer + amount
+            } else {
+                data_pointer - amount
+            };
+
+            memory[move_to_ptr] = memory[move_to_ptr].wrapping_add(memory[data_pointer]);
+            memory[data_pointer] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{run, Stats},
+        util::{asm::Runtime, run::RunOptions},
+    };
+
+    fn run_with_threshold(source_code: &str, threshold: u32) -> (Stats, Vec<u8>) {
+        let program = opinterp3::compile(source_code, &RunOptions::default()).unwrap();
+        let (mut stdin, mut stdout) = ("".as_bytes(), vec![]);
+        let mut runtime = Runtime::new(&mut stdin, &mut stdout);
+        let stats = run(&program, &mut runtime, threshold).unwrap();
+        (stats, runtime.memory()[..4].to_vec())
+    }
+
+    #[test]
+    fn exited_recording_test() {
+        // The inner loop goes round five times each time, so its fifth
+        // iteration is always its last.
+        let (stats, memory) = run_with_threshold("++++++++++++[>+++++[>+<-]<-]", 5);
+        assert_eq!((stats.traces, stats.whole_loops), (2, 0));
+        assert_eq!(memory, [0, 0, 60, 0]);
+    }
+
+    #[test]
+    fn side_exits_test() {
+        // The inner loop runs on every other iteration of the outer one, so
+        // the outer loop's trace keeps failing the guard for entering it.
+        let source_code = "+".repeat(250) + "[>>+<[->-<]>[-<+>]<<-]";
+        let (stats, memory) = run_with_threshold(&source_code, 10);
+        assert_eq!((stats.traces, stats.whole_loops), (0, 1));
+        assert_eq!(memory, [0, 0, 0, 0]);
+    }
+}